@@ -15,7 +15,11 @@ use crossterm::{
 };
 use fastnum::UD64;
 use futures::StreamExt;
-use perpl_sdk::{Chain, state::Exchange, stream, types};
+use perpl_sdk::{
+    Chain,
+    state::{Account, Exchange},
+    stream, types,
+};
 use tabled::{Table, Tabled, settings::Style};
 use tokio_util::sync::CancellationToken;
 
@@ -23,10 +27,13 @@ pub(crate) async fn render<P: Provider + Clone>(
     chain: Chain,
     provider: P,
     mut exchange: Exchange,
+    account: types::AccountAddressOrID,
     num_blocks: Option<u64>,
     num_trades: usize,
     cancellation_token: CancellationToken,
 ) -> anyhow::Result<()> {
+    let account_id = find_account(&exchange, &account)?.id();
+
     let stream = stream::raw(&chain, provider, exchange.instant().next(), tokio::time::sleep);
     let mut stream = pin!(stream);
 
@@ -50,7 +57,10 @@ pub(crate) async fn render<P: Provider + Clone>(
         let block_events = res?;
         let state_events = exchange.apply_events(&block_events)?;
 
-        let account = exchange.accounts().values().last().unwrap();
+        let account = exchange
+            .accounts()
+            .get(&account_id)
+            .ok_or_else(|| anyhow::anyhow!("account {account_id} is no longer tracked"))?;
         let mut perpetuals: Vec<_> = exchange.perpetuals().values().collect();
         perpetuals.sort_by_key(|p| p.id());
 
@@ -65,21 +75,12 @@ pub(crate) async fn render<P: Provider + Clone>(
                                 block: block_events.instant().block_number(),
                                 tx_hash: events.tx_hash(),
                                 liquidity_side: "Taker".yellow().to_string(),
-                                side: if trade.taker_side == types::OrderSide::Ask {
-                                    trade.taker_side.to_string().red().to_string()
-                                } else {
-                                    trade.taker_side.to_string().green().to_string()
-                                },
-                                price: if trade.taker_side == types::OrderSide::Ask {
-                                    trade.avg_price().unwrap().to_string().red().to_string()
-                                } else {
-                                    trade.avg_price().unwrap().to_string().green().to_string()
-                                },
-                                size: if trade.taker_side == types::OrderSide::Ask {
-                                    trade.total_size().to_string().red().to_string()
-                                } else {
-                                    trade.total_size().to_string().green().to_string()
-                                },
+                                side: colored_by_side(trade.taker_side, trade.taker_side),
+                                price: colored_by_side(
+                                    trade.taker_side,
+                                    trade.avg_price().unwrap(),
+                                ),
+                                size: colored_by_side(trade.taker_side, trade.total_size()),
                                 fees: trade.taker_fee,
                             })
                         } else if let Some((avg_price, size, fees)) =
@@ -90,21 +91,9 @@ pub(crate) async fn render<P: Provider + Clone>(
                                 block: block_events.instant().block_number(),
                                 tx_hash: events.tx_hash(),
                                 liquidity_side: "Maker".blue().to_string(),
-                                side: if maker_side == types::OrderSide::Ask {
-                                    maker_side.to_string().red().to_string()
-                                } else {
-                                    maker_side.to_string().green().to_string()
-                                },
-                                price: if maker_side == types::OrderSide::Ask {
-                                    avg_price.to_string().red().to_string()
-                                } else {
-                                    avg_price.to_string().green().to_string()
-                                },
-                                size: if maker_side == types::OrderSide::Ask {
-                                    size.to_string().red().to_string()
-                                } else {
-                                    size.to_string().green().to_string()
-                                },
+                                side: colored_by_side(maker_side, maker_side),
+                                price: colored_by_side(maker_side, avg_price),
+                                size: colored_by_side(maker_side, size),
                                 fees,
                             })
                         };
@@ -122,8 +111,9 @@ pub(crate) async fn render<P: Provider + Clone>(
         // Exchange summary
         stdout.queue(Print(format!("{}", exchange)))?;
 
-        // Account with positions
-        stdout.queue(Print(format!("{:#}", account)))?;
+        // Account with positions, amounts rendered to the collateral token precision
+        let rendered = exchange.display_config().scoped(|| format!("{:#}", account));
+        stdout.queue(Print(rendered))?;
 
         // Account orders for all perpetuals
         for perp in perpetuals {
@@ -173,6 +163,30 @@ pub(crate) async fn render<P: Provider + Clone>(
     Ok(())
 }
 
+/// Finds the account requested with `--account` in the snapshot.
+fn find_account<'a>(
+    exchange: &'a Exchange,
+    account: &types::AccountAddressOrID,
+) -> anyhow::Result<&'a Account> {
+    match account {
+        types::AccountAddressOrID::ID(id) => exchange.accounts().get(id),
+        types::AccountAddressOrID::Address(address) => exchange
+            .accounts()
+            .values()
+            .find(|acc| acc.address() == *address),
+    }
+    .map(|acc| &**acc)
+    .ok_or_else(|| anyhow::anyhow!("account {account} not found"))
+}
+
+/// Renders trade detail in red for asks and green for bids.
+fn colored_by_side(side: types::OrderSide, value: impl std::fmt::Display) -> String {
+    match side {
+        types::OrderSide::Ask => value.to_string().red().to_string(),
+        types::OrderSide::Bid => value.to_string().green().to_string(),
+    }
+}
+
 #[derive(Tabled)]
 struct TradeDetails {
     #[tabled(rename = "Block")]
@@ -190,3 +204,22 @@ struct TradeDetails {
     #[tabled(rename = "Fees")]
     fees: UD64,
 }
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::Address;
+
+    use super::*;
+
+    #[test]
+    fn test_find_missing_account() {
+        let exchange = Exchange::for_test(6, vec![]);
+        let err = find_account(&exchange, &types::AccountAddressOrID::ID(1)).unwrap_err();
+        assert_eq!(err.to_string(), "account 1 not found");
+
+        let address = Address::repeat_byte(1);
+        let err =
+            find_account(&exchange, &types::AccountAddressOrID::Address(address)).unwrap_err();
+        assert_eq!(err.to_string(), format!("account {address} not found"));
+    }
+}
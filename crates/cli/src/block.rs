@@ -1,10 +1,9 @@
-use alloy::{eips::BlockId, providers::Provider, sol_types::SolEventInterface};
+use alloy::{eips::BlockId, providers::Provider};
 use colored::Colorize;
 use perpl_sdk::{
     Chain,
-    abi::dex::Exchange::ExchangeEvents,
     error::{DexError, ProviderError},
-    stream::RawEvent,
+    stream::{self, RawEvent, RawExchangeEvent},
 };
 
 pub(crate) async fn render<P: Provider + Clone>(
@@ -27,14 +26,16 @@ pub(crate) async fn render<P: Provider + Clone>(
             .iter()
             .filter(|log| log.address() == exchange)
         {
-            events.push(RawEvent::new(
-                log.transaction_hash.unwrap_or_default(),
-                log.transaction_index.unwrap_or_default(),
-                log.log_index.unwrap_or_default(),
-                ExchangeEvents::decode_log(&log.inner)
-                    .map_err(|err| DexError::Provider(err.into()))?
-                    .data,
-            ));
+            events.push(
+                RawEvent::new(
+                    log.transaction_hash.unwrap_or_default(),
+                    log.transaction_index.unwrap_or_default(),
+                    log.log_index.unwrap_or_default(),
+                    RawExchangeEvent::decode_log(&log.inner)
+                        .map_err(|err| DexError::Provider(err.into()))?,
+                )
+                .with_block(block_number),
+            );
         }
     }
 
@@ -53,28 +54,8 @@ pub(crate) async fn render<P: Provider + Clone>(
         }
         prev_tx = Some(event.tx_index());
 
-        match event.event() {
-            ExchangeEvents::OrderRequest { .. } => {
-                println!("{}", format!("  {}: {:?}", event.log_index(), event.event()).cyan());
-                order_request = true;
-            },
-            ExchangeEvents::OrderBatchCompleted { .. } => {
-                println!("{}", format!("  {}: {:?}", event.log_index(), event.event()).cyan());
-                order_request = false;
-            },
-            _ => {
-                println!(
-                    "{}",
-                    format!(
-                        "  {}{}: {:?}",
-                        if order_request { "   ↳ " } else { "" },
-                        event.log_index(),
-                        event.event()
-                    )
-                    .bright_cyan()
-                );
-            },
-        }
+        println!("{}", stream::format_event(&event, order_request));
+        order_request = stream::order_request_scope(&event, order_request);
     }
 
     println!();
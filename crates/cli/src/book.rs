@@ -9,7 +9,7 @@ use crossterm::{
     terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use futures::StreamExt;
-use perpl_sdk::{Chain, state::Exchange, stream};
+use perpl_sdk::{Chain, num::DisplayConfig, state::Exchange, stream};
 use tokio_util::sync::CancellationToken;
 
 #[allow(clippy::too_many_arguments)]
@@ -51,13 +51,17 @@ pub(crate) async fn render<P: Provider + Clone>(
         // Perpetual summary
         stdout.queue(Print(format!("{}", perpetual)))?;
 
-        // Book
+        // Book, with prices and sizes rendered to the perpetual's precision
         let book_view = perpetual.l3_book().view(
             if depth > 0 { Some(depth) } else { None },
             if orders_per_level > 0 { Some(orders_per_level) } else { None },
             show_expired,
         );
-        stdout.queue(Print(format!("{:#}", book_view)))?;
+        let rendered = DisplayConfig::new()
+            .with_price_decimals(perpetual.price_converter().decimals())
+            .with_size_decimals(perpetual.size_converter().decimals())
+            .scoped(|| format!("{:#}", book_view));
+        stdout.queue(Print(rendered))?;
 
         stdout.flush()?;
 
@@ -7,13 +7,7 @@ mod trace;
 mod trades;
 mod tx;
 
-use std::time::Duration;
-
-use alloy::{
-    providers::{Provider, ProviderBuilder},
-    rpc::{client::RpcClient, types::BlockId},
-    transports::layers::{RetryBackoffLayer, ThrottleLayer},
-};
+use alloy::{providers::Provider, rpc::types::BlockId};
 use anyhow::Context;
 use args::Cli;
 use perpl_sdk::{Chain, state::SnapshotBuilder};
@@ -28,23 +22,13 @@ pub async fn run(cli: Cli) -> anyhow::Result<()> {
     } else {
         (args::DEFAULT_MAINNET_RPC_PROVIDER.to_string(), true)
     });
-    let client = if default || cli.rpc_throttle.is_some() {
-        // Apply throttling with default RPC
-        RpcClient::builder()
-            .layer(ThrottleLayer::new(cli.rpc_throttle.unwrap_or(args::DEFAULT_RPC_THROTTLING)))
-            .layer(RetryBackoffLayer::new(10, 100, 200))
-            .connect(&rpc)
-            .await
-            .context("connecting to RPC")?
-    } else {
-        RpcClient::builder()
-            .layer(RetryBackoffLayer::new(10, 100, 200))
-            .connect(&rpc)
-            .await
-            .context("connecting to RPC")?
-    };
-    client.set_poll_interval(Duration::from_millis(100));
-    let provider = ProviderBuilder::new().connect_client(client);
+    // Apply throttling with default RPC
+    let throttle = cli
+        .rpc_throttle
+        .or(default.then_some(args::DEFAULT_RPC_THROTTLING));
+    let provider = perpl_sdk::provider::recommended(&rpc, throttle)
+        .await
+        .context("connecting to RPC")?;
 
     if let Some(unknown_perp) = cli
         .perp
@@ -60,6 +44,7 @@ pub async fn run(cli: Cli) -> anyhow::Result<()> {
         chain.deployed_at_block(),
         cli.exchange.unwrap_or(chain.exchange()),
         if !cli.perp.is_empty() { cli.perp.clone() } else { chain.perpetuals().to_vec() },
+        chain.block_time(),
     );
 
     let mut builder = SnapshotBuilder::new(&chain, provider.clone());
@@ -127,6 +112,7 @@ pub async fn run(cli: Cli) -> anyhow::Result<()> {
                     chain,
                     provider,
                     exchange.unwrap(),
+                    cli.account[0],
                     cli.num_blocks,
                     *num_trades,
                     cancellation_token,
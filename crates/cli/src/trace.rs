@@ -3,7 +3,7 @@ use std::pin::pin;
 use alloy::providers::Provider;
 use colored::Colorize;
 use futures::StreamExt;
-use perpl_sdk::{Chain, abi::dex::Exchange::ExchangeEvents, state::Exchange, stream};
+use perpl_sdk::{Chain, state::Exchange, stream};
 use tokio_util::sync::CancellationToken;
 
 pub(crate) async fn render<P: Provider + Clone>(
@@ -52,34 +52,8 @@ pub(crate) async fn render<P: Provider + Clone>(
                 order_request = false;
             }
             prev_tx = Some(block_event.tx_index());
-            match block_event.event() {
-                ExchangeEvents::OrderRequest { .. } => {
-                    println!(
-                        "{}",
-                        format!("  {}: {:?}", block_event.log_index(), block_event.event()).cyan()
-                    );
-                    order_request = true;
-                },
-                ExchangeEvents::OrderBatchCompleted { .. } => {
-                    println!(
-                        "{}",
-                        format!("  {}: {:?}", block_event.log_index(), block_event.event()).cyan()
-                    );
-                    order_request = false;
-                },
-                _ => {
-                    println!(
-                        "{}",
-                        format!(
-                            "  {}{}: {:?}",
-                            if order_request { "   ↳ " } else { "" },
-                            block_event.log_index(),
-                            block_event.event()
-                        )
-                        .bright_cyan()
-                    );
-                },
-            }
+            println!("{}", stream::format_event(block_event, order_request));
+            order_request = stream::order_request_scope(block_event, order_request);
 
             // State events produced from exchange events
             while let Some(state_events) = state_event_iter.peek()
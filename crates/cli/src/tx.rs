@@ -1,9 +1,8 @@
-use alloy::{primitives::TxHash, providers::Provider, sol_types::SolEventInterface};
+use alloy::{primitives::TxHash, providers::Provider};
 use colored::Colorize;
 use perpl_sdk::{
-    abi::dex::Exchange::ExchangeEvents,
     error::{DexError, ProviderError},
-    stream::RawEvent,
+    stream::{self, RawEvent, RawExchangeEvent},
 };
 
 pub(crate) async fn render<P: Provider + Clone>(
@@ -20,42 +19,24 @@ pub(crate) async fn render<P: Provider + Clone>(
 
     let mut events = Vec::with_capacity(receipt.inner.logs().len());
     for log in receipt.inner.logs() {
-        events.push(RawEvent::new(
-            log.transaction_hash.unwrap_or_default(),
-            log.transaction_index.unwrap_or_default(),
-            log.log_index.unwrap_or_default(),
-            ExchangeEvents::decode_log(&log.inner)
-                .map_err(|err| DexError::Provider(err.into()))?
-                .data,
-        ));
+        events.push(
+            RawEvent::new(
+                log.transaction_hash.unwrap_or_default(),
+                log.transaction_index.unwrap_or_default(),
+                log.log_index.unwrap_or_default(),
+                RawExchangeEvent::decode_log(&log.inner)
+                    .map_err(|err| DexError::Provider(err.into()))?,
+            )
+            .with_block(log.block_number.unwrap_or_default()),
+        );
     }
 
     println!("\n{}\n", format!("**** Tx {}", tx_hash).bright_blue());
 
     let mut order_request = false;
     for event in events {
-        match event.event() {
-            ExchangeEvents::OrderRequest { .. } => {
-                println!("{}", format!("  {}: {:?}", event.log_index(), event.event()).cyan());
-                order_request = true;
-            },
-            ExchangeEvents::OrderBatchCompleted { .. } => {
-                println!("{}", format!("  {}: {:?}", event.log_index(), event.event()).cyan());
-                order_request = false;
-            },
-            _ => {
-                println!(
-                    "{}",
-                    format!(
-                        "  {}{}: {:?}",
-                        if order_request { "   ↳ " } else { "" },
-                        event.log_index(),
-                        event.event()
-                    )
-                    .bright_cyan()
-                );
-            },
-        }
+        println!("{}", stream::format_event(&event, order_request));
+        order_request = stream::order_request_scope(&event, order_request);
     }
 
     println!();
@@ -0,0 +1,338 @@
+//! Benchmarks of applying blocks of exchange events to the state snapshot,
+//! the hot path of indexers, of the order book on its own, and of cloning
+//! the snapshot.
+//!
+//! Run with `cargo bench -p perpl-sdk --features test-utils`.
+
+use std::{collections::HashMap, hash::Hash, hint::black_box, num::NonZeroU16, sync::Arc};
+
+use alloy::primitives::{I256, TxHash, U256};
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use fastnum::{UD64, udec64};
+use perpl_sdk::{
+    abi::dex::Exchange::{
+        AccountCreated, CollateralDeposit, ExchangeEvents, MakerOrderFilled, OrderCancelled,
+        OrderPlaced, OrderRequest, PositionOpened, TakerOrderFilled,
+    },
+    num,
+    state::{Exchange, OrderBook, Perpetual, Tracking},
+    stream::{RawBlockEvents, RawEvent},
+    types::{self, RequestType},
+};
+
+const PERP_ID: types::PerpetualId = 1;
+const MAKER: u64 = 1;
+const TAKER: u64 = 2;
+const ORDERS_PER_BLOCK: u64 = 500;
+
+fn order_request(
+    account_id: u64,
+    request_id: u64,
+    order_id: u64,
+    request_type: RequestType,
+    price: u64,
+) -> ExchangeEvents {
+    ExchangeEvents::OrderRequest(OrderRequest {
+        perpId: U256::from(PERP_ID),
+        accountId: U256::from(account_id),
+        orderDescId: U256::from(request_id),
+        orderId: U256::from(order_id),
+        orderType: request_type as u8,
+        pricePNS: U256::from(price),
+        lotLNS: U256::from(1),
+        expiryBlock: U256::ZERO,
+        postOnly: false,
+        fillOrKill: false,
+        immediateOrCancel: false,
+        maxMatches: U256::ZERO,
+        leverageHdths: U256::from(500),
+        lastExecutionBlock: U256::ZERO,
+        amountCNS: U256::ZERO,
+        maxNegPnlCollatBPS: U256::ZERO,
+        gasLeft: U256::ZERO,
+    })
+}
+
+fn order_placed(order_id: u64) -> ExchangeEvents {
+    ExchangeEvents::OrderPlaced(OrderPlaced {
+        orderId: U256::from(order_id),
+        lotLNS: U256::from(1),
+        lockedBalanceCNS: U256::ZERO,
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::from(1_000_000),
+    })
+}
+
+fn order_cancelled() -> ExchangeEvents {
+    ExchangeEvents::OrderCancelled(OrderCancelled {
+        lockedBalanceCNS: U256::ZERO,
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::from(1_000_000),
+    })
+}
+
+/// Taker order of one lot filling maker order 1, opening positions of both.
+fn taker_fill(request_id: u64) -> Vec<ExchangeEvents> {
+    let position_opened = |account_id: u64, position_type: u8| {
+        ExchangeEvents::PositionOpened(PositionOpened {
+            perpId: U256::from(PERP_ID),
+            accountId: U256::from(account_id),
+            positionType: position_type,
+            leverageHdths: U256::from(500),
+            depositCNS: U256::from(202_000),
+            pnlCollateralizedCNS: I256::ZERO,
+            pricePNS: U256::from(101),
+            lotLNS: U256::from(1),
+            insFeeCNS: U256::ZERO,
+            protFeeCNS: U256::ZERO,
+        })
+    };
+    vec![
+        order_request(TAKER, request_id, 0, RequestType::OpenLong, 101),
+        position_opened(MAKER, 1),
+        ExchangeEvents::MakerOrderFilled(MakerOrderFilled {
+            perpId: U256::from(PERP_ID),
+            accountId: U256::from(MAKER),
+            orderId: U256::from(1),
+            pricePNS: U256::from(101),
+            lotLNS: U256::from(1),
+            feeCNS: U256::from(10),
+            lockedBalanceCNS: U256::ZERO,
+            amountCNS: I256::ZERO,
+            balanceCNS: U256::from(797_990),
+        }),
+        position_opened(TAKER, 0),
+        ExchangeEvents::TakerOrderFilled(TakerOrderFilled {
+            entryPricePNS: U256::from(101),
+            collatPricePNS: U256::from(101),
+            pnlPricePNS: U256::from(101),
+            lotLNS: U256::from(1),
+            feeCNS: U256::from(30),
+            amountCNS: I256::ZERO,
+            balanceCNS: U256::from(797_970),
+        }),
+    ]
+}
+
+/// Block of transactions, each one a list of events.
+fn block(block_number: u64, txs: Vec<Vec<ExchangeEvents>>) -> RawBlockEvents {
+    let mut log_index = 0;
+    let events = txs
+        .into_iter()
+        .enumerate()
+        .flat_map(|(tx_index, events)| events.into_iter().map(move |e| (tx_index as u64, e)))
+        .map(|(tx_index, event)| {
+            log_index += 1;
+            RawEvent::new(TxHash::ZERO, tx_index, log_index, event.into()).with_block(block_number)
+        })
+        .collect();
+    RawBlockEvents::new(types::StateInstant::new(block_number, block_number), events)
+}
+
+fn deposit(account_id: u64) -> ExchangeEvents {
+    ExchangeEvents::CollateralDeposit(CollateralDeposit {
+        accountId: U256::from(account_id),
+        amountCNS: U256::from(1_000_000),
+        balanceCNS: U256::from(1_000_000),
+    })
+}
+
+fn setup_block() -> RawBlockEvents {
+    let account = |id: u64| {
+        vec![
+            ExchangeEvents::AccountCreated(AccountCreated {
+                account: Default::default(),
+                id: U256::from(id),
+            }),
+            deposit(id),
+        ]
+    };
+    block(1, vec![account(MAKER), account(TAKER)])
+}
+
+/// Maker asks with IDs starting from `first_order_id`, one per transaction.
+fn place_txs(first_order_id: u64, num_orders: u64) -> Vec<Vec<ExchangeEvents>> {
+    (first_order_id..first_order_id + num_orders)
+        .map(|id| {
+            vec![
+                order_request(MAKER, id, 0, RequestType::OpenShort, 100 + id % 50),
+                order_placed(id),
+            ]
+        })
+        .collect()
+}
+
+fn cancel_txs(order_ids: impl Iterator<Item = u64>) -> Vec<Vec<ExchangeEvents>> {
+    order_ids
+        .map(|id| {
+            vec![
+                order_request(MAKER, 1_000_000 + id, id, RequestType::Cancel, 0),
+                order_cancelled(),
+            ]
+        })
+        .collect()
+}
+
+fn exchange(blocks: &[&RawBlockEvents]) -> Exchange { exchange_tracking(blocks, Tracking::ALL) }
+
+fn exchange_tracking(blocks: &[&RawBlockEvents], tracking: Tracking) -> Exchange {
+    let mut exchange =
+        Exchange::for_test_with_tracking(4, vec![Perpetual::for_test(PERP_ID)], tracking);
+    for block in blocks {
+        exchange.apply_events(block).unwrap();
+    }
+    exchange
+}
+
+fn bench_apply_events(c: &mut Criterion) {
+    let setup = setup_block();
+    let place = block(2, place_txs(1, ORDERS_PER_BLOCK));
+    let cancel = block(3, cancel_txs(1..=ORDERS_PER_BLOCK));
+
+    // Half of the book cancelled and replaced, along with a taker fill
+    let half = ORDERS_PER_BLOCK / 2;
+    let mut mixed_txs = cancel_txs(2..=half);
+    mixed_txs.extend(place_txs(ORDERS_PER_BLOCK + 1, half));
+    mixed_txs.push(taker_fill(10_000_000));
+    let mixed = block(3, mixed_txs);
+
+    let mut group = c.benchmark_group("apply_events");
+    group.bench_function("place_orders", |b| {
+        b.iter_batched_ref(
+            || exchange(&[&setup]),
+            |exchange| black_box(exchange.apply_events(&place).unwrap()),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("cancel_orders", |b| {
+        b.iter_batched_ref(
+            || exchange(&[&setup, &place]),
+            |exchange| black_box(exchange.apply_events(&cancel).unwrap()),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("mixed", |b| {
+        b.iter_batched_ref(
+            || exchange(&[&setup, &place]),
+            |exchange| black_box(exchange.apply_events(&mixed).unwrap()),
+            BatchSize::LargeInput,
+        )
+    });
+
+    // Same blocks, skipping the order books
+    let book_free = Tracking { books: false, ..Tracking::ALL };
+    group.bench_function("place_orders_without_books", |b| {
+        b.iter_batched_ref(
+            || exchange_tracking(&[&setup], book_free),
+            |exchange| black_box(exchange.apply_events(&place).unwrap()),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("mixed_without_books", |b| {
+        b.iter_batched_ref(
+            || exchange_tracking(&[&setup, &place], book_free),
+            |exchange| black_box(exchange.apply_events(&mixed).unwrap()),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// Book of `ORDERS_PER_BLOCK` orders, asks and bids alternating over 50
+/// price levels on each side.
+fn book() -> OrderBook {
+    let mut book = OrderBook::for_test();
+    for id in 1..=ORDERS_PER_BLOCK {
+        let (r#type, price) = if id % 2 == 0 {
+            (types::OrderType::OpenShort, 150 + id % 50)
+        } else {
+            (types::OrderType::OpenLong, 50 + id % 50)
+        };
+        book.add_test_order(
+            r#type,
+            order_id(id),
+            num::Price::new(UD64::from(price)),
+            num::Size::new(UD64::ONE),
+        )
+        .unwrap();
+    }
+    book
+}
+
+fn order_id(id: u64) -> types::OrderId { NonZeroU16::new(id as u16).unwrap() }
+
+fn bench_order_book(c: &mut Criterion) {
+    let book = book();
+    let mut group = c.benchmark_group("order_book");
+    group.bench_function("add_orders", |b| b.iter_with_large_drop(|| black_box(self::book())));
+    group.bench_function("update_orders", |b| {
+        b.iter_batched_ref(
+            || book.clone(),
+            |book| {
+                for id in 1..=ORDERS_PER_BLOCK {
+                    book.update_test_order(order_id(id), num::Size::new(udec64!(0.5)))
+                        .unwrap();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("remove_orders", |b| {
+        b.iter_batched_ref(
+            || book.clone(),
+            |book| {
+                for id in 1..=ORDERS_PER_BLOCK {
+                    black_box(book.remove_test_order(order_id(id)).unwrap());
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_clone(c: &mut Criterion) {
+    let exchange = exchange(&[&setup_block(), &block(2, place_txs(1, ORDERS_PER_BLOCK))]);
+    let deposit = block(3, vec![vec![deposit(MAKER)]]);
+    let place = block(3, place_txs(ORDERS_PER_BLOCK + 1, 1));
+
+    // Copy of the entire state, as cloning did before sharing it copy-on-write
+    let mut group = c.benchmark_group("clone");
+    group.bench_function("deep_copy", |b| {
+        b.iter(|| {
+            let books = exchange
+                .perpetuals()
+                .values()
+                .map(|perp| perp.l3_book().clone())
+                .collect::<Vec<_>>();
+            black_box((deep_copy(exchange.perpetuals()), books, deep_copy(exchange.accounts())))
+        })
+    });
+    group.bench_function("exchange", |b| b.iter(|| black_box(exchange.clone())));
+
+    // Clone followed by a block, copying only the entries it mutates
+    group.bench_function("exchange_then_deposit", |b| {
+        b.iter_with_large_drop(|| {
+            let mut exchange = exchange.clone();
+            black_box(exchange.apply_events(&deposit).unwrap());
+            exchange
+        })
+    });
+    group.bench_function("exchange_then_place_order", |b| {
+        b.iter_with_large_drop(|| {
+            let mut exchange = exchange.clone();
+            black_box(exchange.apply_events(&place).unwrap());
+            exchange
+        })
+    });
+    group.finish();
+}
+
+fn deep_copy<K: Copy + Eq + Hash, V: Clone>(map: &HashMap<K, Arc<V>>) -> HashMap<K, V> {
+    map.iter()
+        .map(|(key, value)| (*key, V::clone(value)))
+        .collect()
+}
+
+criterion_group!(benches, bench_apply_events, bench_order_book, bench_clone);
+criterion_main!(benches);
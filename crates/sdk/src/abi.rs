@@ -20,6 +20,19 @@ pub mod erc1967_proxy {
     );
 }
 
+pub mod erc20 {
+    alloy::sol!(
+        /// Metadata extension of ERC-20 tokens, such as the collateral token.
+        #[derive(Debug)]
+        #[sol(rpc)]
+        interface IERC20Metadata {
+            function name() external view returns (string memory);
+            function symbol() external view returns (string memory);
+            function decimals() external view returns (uint8);
+        }
+    );
+}
+
 #[allow(clippy::too_many_arguments)]
 pub mod errors {
     alloy::sol!(
@@ -55,8 +55,8 @@ pub enum DexError {
     #[error("provider error: {0}")]
     Provider(#[from] ProviderError<ExchangeErrors>),
 
-    #[error("block out of order, expected: {0}, got: {1}")]
-    BlockOutOfOrder(u64, u64),
+    #[error("block gap, expected: {expected}, got: {got}")]
+    BlockGap { expected: u64, got: u64 },
 
     #[error("order context expected, tx: {0}, log: {1}")]
     OrderContextExpected(u64, u64),
@@ -75,6 +75,27 @@ pub enum DexError {
 
     #[error("invalid argument: {0}")]
     InvalidArgument(String),
+
+    #[error("exchange contract does not match SDK revision {0}, {1} selectors missing")]
+    RevisionMismatch(&'static str, usize),
+
+    #[error("inconsistent position pnl, acc: {0}, perp: {1}")]
+    InconsistentPnl(types::AccountId, types::PerpetualId),
+
+    #[error("perp {0} order book is crossed")]
+    CrossedBook(types::PerpetualId),
+
+    #[error("event buffer overflow, max blocks: {0}")]
+    BufferOverflow(usize),
+
+    #[error("no rollback checkpoint for block {0}")]
+    RollbackUnavailable(u64),
+
+    #[error("exchange contract code changed, detected at block {0}")]
+    ContractChanged(u64),
+
+    #[error("malformed chain data: {0}")]
+    MalformedData(String),
 }
 
 impl<R: SolInterface> From<contract::Error> for ProviderError<R> {
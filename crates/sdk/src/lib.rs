@@ -4,9 +4,13 @@
 //!
 //! Convenient in-memory cache of on-chain exchange state.
 //!
+//! Use [`provider::recommended`] to connect to the RPC endpoint with retries
+//! and throttling.
+//!
 //! Use [`state::SnapshotBuilder`] to capture initial state snapshot, then
 //! [`stream::raw`] to catch up with the recent state and keep snapshot
-//! up to date.
+//! up to date, or [`stream::catch_up_then_follow`] if the snapshot is far
+//! behind the chain tip.
 //!
 //! Use [`types::OrderRequest`] to prepare order requests to send them with
 //! [`crate::abi::dex::Exchange::ExchangeInstance::execOrders`].
@@ -27,7 +31,7 @@
 //!
 //! | Feature | Default | Description |
 //! | --- | --- | --- |
-//! | `display` | yes | Enables [`std::fmt::Display`] implementation for state types. |
+//! | `display` | yes | Enables [`std::fmt::Display`] implementation for state types, see [`num::set_colors`]. |
 //! | `testing` | yes | Enables [`testing`] module. |
 //!
 //! # Testing
@@ -42,6 +46,7 @@
 pub mod abi;
 pub mod error;
 pub mod num;
+pub mod provider;
 pub mod state;
 pub mod stream;
 #[cfg(feature = "testing")]
@@ -50,9 +55,25 @@ pub mod testing;
 mod tests;
 pub mod types;
 
-use alloy::primitives::{Address, address};
+use std::time::Duration;
+
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{Address, U256, address},
+    providers::Provider,
+    rpc::types::Filter,
+    sol_types::{SolEvent, SolEventInterface},
+};
 
-#[derive(Clone, Debug)]
+use crate::{
+    abi::{
+        dex::Exchange::{ContractAdded, ContractRemoved, ExchangeEvents},
+        erc20::IERC20Metadata,
+    },
+    error::DexError,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 /// Chain the exchange is operating on.
 pub struct Chain {
     chain_id: u64,
@@ -60,9 +81,13 @@ pub struct Chain {
     deployed_at_block: u64,
     exchange: Address,
     perpetuals: Vec<types::PerpetualId>,
+    block_time: Duration,
 }
 
 impl Chain {
+    /// Target block time of Monad.
+    pub const DEFAULT_BLOCK_TIME: Duration = Duration::from_millis(400);
+
     pub fn mainnet() -> Self {
         Self {
             chain_id: 143,
@@ -70,6 +95,7 @@ impl Chain {
             deployed_at_block: 54773010,
             exchange: address!("0x34B6552d57a35a1D042CcAe1951BD1C370112a6F"),
             perpetuals: vec![1, 10, 20, 31, 40, 50],
+            block_time: Self::DEFAULT_BLOCK_TIME,
         }
     }
 
@@ -80,6 +106,7 @@ impl Chain {
             deployed_at_block: 62953,
             exchange: address!("0x1964C32f0bE608E7D29302AFF5E61268E72080cc"),
             perpetuals: vec![16, 32, 48, 64, 256],
+            block_time: Self::DEFAULT_BLOCK_TIME,
         }
     }
 
@@ -89,8 +116,9 @@ impl Chain {
         deployed_at_block: u64,
         exchange: Address,
         perpetuals: Vec<types::PerpetualId>,
+        block_time: Duration,
     ) -> Self {
-        Self { chain_id, collateral_token, deployed_at_block, exchange, perpetuals }
+        Self { chain_id, collateral_token, deployed_at_block, exchange, perpetuals, block_time }
     }
 
     pub fn chain_id(&self) -> u64 { self.chain_id }
@@ -102,4 +130,80 @@ impl Chain {
     pub fn exchange(&self) -> Address { self.exchange }
 
     pub fn perpetuals(&self) -> &[types::PerpetualId] { &self.perpetuals }
+
+    /// Fetches IDs of perpetual contracts listed by the exchange, so markets
+    /// listed after the SDK release are not missed, see
+    /// [`state::SnapshotBuilder::with_onchain_perpetuals`].
+    ///
+    /// Replays `ContractAdded`/`ContractRemoved` events since
+    /// [`Self::deployed_at_block`] with a single log query, which may exceed
+    /// the block range limit of some RPC providers.
+    pub async fn fetch_perpetuals<P: Provider>(
+        &self,
+        provider: &P,
+    ) -> Result<Vec<types::PerpetualId>, DexError> {
+        self.fetch_perpetuals_at(provider, BlockNumberOrTag::Latest)
+            .await
+    }
+
+    /// Same as [`Self::fetch_perpetuals`], but as of the `block`.
+    pub(crate) async fn fetch_perpetuals_at<P: Provider>(
+        &self,
+        provider: &P,
+        block: BlockNumberOrTag,
+    ) -> Result<Vec<types::PerpetualId>, DexError> {
+        let filter = Filter::new()
+            .address(self.exchange)
+            .event_signature(vec![ContractAdded::SIGNATURE_HASH, ContractRemoved::SIGNATURE_HASH])
+            .from_block(self.deployed_at_block)
+            .to_block(block);
+        let mut logs = provider
+            .get_logs(&filter)
+            .await
+            .map_err(|err| DexError::Provider(err.into()))?;
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+
+        let mut perpetuals = vec![];
+        for log in &logs {
+            let event = ExchangeEvents::decode_log(&log.inner)
+                .map_err(|err| DexError::Provider(err.into()))?;
+            match event.data {
+                ExchangeEvents::ContractAdded(e) => perpetuals.push(e.perpId.to()),
+                ExchangeEvents::ContractRemoved(e) => {
+                    perpetuals.retain(|id| U256::from(*id) != e.perpId);
+                },
+                _ => {},
+            }
+        }
+        perpetuals.sort_unstable();
+        perpetuals.dedup();
+        Ok(perpetuals)
+    }
+
+    /// Fetches symbol, name and decimals of the [`Self::collateral_token`]
+    /// directly from the ERC-20 token contract, e.g. to label amounts in UI.
+    pub async fn fetch_collateral_metadata<P: Provider>(
+        &self,
+        provider: &P,
+    ) -> Result<types::CollateralMeta, DexError> {
+        let token = IERC20Metadata::new(self.collateral_token, provider);
+        let (symbol_call, name_call, decimals_call) =
+            (token.symbol(), token.name(), token.decimals());
+        let (symbol, name, decimals) = futures::try_join!(
+            symbol_call.call().into_future(),
+            name_call.call().into_future(),
+            decimals_call.call().into_future(),
+        )
+        .map_err(|err| DexError::Provider(err.into()))?;
+        Ok(types::CollateralMeta { symbol, name, decimals })
+    }
+
+    /// Estimated duration of a block, used to convert blocks to wall-clock
+    /// time.
+    pub fn block_time(&self) -> Duration { self.block_time }
+
+    /// Estimated wall-clock duration of the number of `blocks`.
+    pub fn blocks_duration(&self, blocks: u64) -> Duration {
+        u32::try_from(blocks).map_or(Duration::MAX, |blocks| self.block_time.saturating_mul(blocks))
+    }
 }
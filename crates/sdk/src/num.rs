@@ -1,11 +1,21 @@
+use std::{
+    cell::Cell,
+    fmt::Display,
+    iter::Sum,
+    ops::{Add, AddAssign, Mul, Sub, SubAssign},
+    str::FromStr,
+};
+
 use alloy::primitives::{I256, U256};
 use fastnum::{
-    bint,
+    UD64, UD128, bint,
     decimal::{Context, Decimal, RoundingMode, UnsignedDecimal},
 };
 
+use crate::error::DexError;
+
 /// Fixed-point to decimal converter.
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Converter {
     decimals: i32,
 }
@@ -26,13 +36,47 @@ impl Converter {
     }
 
     pub fn from_unsigned<const N: usize>(&self, value: U256) -> UnsignedDecimal<N> {
+        self.try_from_unsigned(value)
+            .expect("Converter: U256 -> UInt::<N>")
+    }
+
+    /// Converts fixed-point `value` decoded from the exchange data.
+    ///
+    /// Fails with [`DexError::MalformedData`] if the value does not fit into
+    /// the decimal, instead of panicking as [`Self::from_unsigned`] does.
+    pub fn try_from_unsigned<const N: usize>(
+        &self,
+        value: U256,
+    ) -> Result<UnsignedDecimal<N>, DexError> {
         let unscaled = bint::UInt::<N>::from_le_slice(value.as_le_slice())
-            .expect("Converter: U256 -> UInt::<N>");
-        UnsignedDecimal::<N>::from_parts(
+            .ok_or_else(|| DexError::MalformedData(format!("value out of range: {value}")))?;
+        Ok(UnsignedDecimal::<N>::from_parts(
             unscaled,
             -self.decimals,
             Context::default().with_rounding_mode(RoundingMode::Floor),
-        )
+        ))
+    }
+
+    /// Converts all the `values`, e.g. a decoded array of prices or sizes.
+    pub fn from_unsigned_all<const N: usize>(&self, values: &[U256]) -> Vec<UnsignedDecimal<N>> {
+        values
+            .iter()
+            .map(|value| self.from_unsigned(*value))
+            .collect()
+    }
+
+    /// Converts all the `values` decoded from the exchange data.
+    ///
+    /// Fails with [`DexError::MalformedData`] on the first value that does
+    /// not fit into the decimal, see [`Self::try_from_unsigned`].
+    pub fn try_from_unsigned_all<const N: usize>(
+        &self,
+        values: &[U256],
+    ) -> Result<Vec<UnsignedDecimal<N>>, DexError> {
+        values
+            .iter()
+            .map(|value| self.try_from_unsigned(*value))
+            .collect()
     }
 
     pub fn from_u64<const N: usize>(&self, value: u64) -> UnsignedDecimal<N> {
@@ -44,9 +88,18 @@ impl Converter {
     }
 
     pub fn from_signed<const N: usize>(&self, value: I256) -> Decimal<N> {
+        self.try_from_signed(value)
+            .expect("Converter: abs(I256) -> UInt::<N>")
+    }
+
+    /// Converts signed fixed-point `value` decoded from the exchange data.
+    ///
+    /// Fails with [`DexError::MalformedData`] if the value does not fit into
+    /// the decimal, instead of panicking as [`Self::from_signed`] does.
+    pub fn try_from_signed<const N: usize>(&self, value: I256) -> Result<Decimal<N>, DexError> {
         let unscaled = bint::UInt::<N>::from_le_slice(value.unsigned_abs().as_le_slice())
-            .expect("Converter: abs(I256) -> UInt::<N>");
-        Decimal::<N>::from_parts(
+            .ok_or_else(|| DexError::MalformedData(format!("value out of range: {value}")))?;
+        Ok(Decimal::<N>::from_parts(
             unscaled,
             -self.decimals,
             match value.sign() {
@@ -54,7 +107,7 @@ impl Converter {
                 alloy::primitives::Sign::Positive => fastnum::decimal::Sign::Plus,
             },
             Context::default().with_rounding_mode(RoundingMode::Floor),
-        )
+        ))
     }
 
     pub fn from_i64<const N: usize>(&self, value: i64) -> Decimal<N> {
@@ -66,6 +119,22 @@ impl Converter {
         )
     }
 
+    /// Parses decimal string, e.g. user input, ensuring it has no more
+    /// decimal places than the converter supports.
+    pub fn parse<const N: usize>(&self, value: &str) -> Result<UnsignedDecimal<N>, DexError> {
+        let parsed = <UnsignedDecimal<N> as FromStr>::from_str(value.trim())
+            .ok()
+            .filter(|parsed| parsed.is_finite())
+            .ok_or_else(|| DexError::InvalidArgument(format!("invalid decimal: {value}")))?;
+        if parsed.reduce().fractional_digits_count() > self.decimals as i16 {
+            return Err(DexError::InvalidArgument(format!(
+                "decimal {value} exceeds {} decimal places",
+                self.decimals
+            )));
+        }
+        Ok(parsed)
+    }
+
     pub fn to_unsigned<const N: usize>(&self, value: UnsignedDecimal<N>) -> U256 {
         let rescaled = value.rescale(self.decimals as i16);
         U256::from_le_slice(rescaled.digits().to_radix_le(256).as_slice())
@@ -82,12 +151,457 @@ impl Converter {
     }
 }
 
+/// Notional amount in collateral token of `lot_lns` lots at `price_pns`,
+/// fixed-point values of `size_converter` and `price_converter` respectively.
+///
+/// The product is rescaled to `collateral_converter` decimals in fixed-point,
+/// truncating extra decimal places the same way the exchange does, so the
+/// amount matches the one used for fees and margins on-chain.
+///
+/// # Panics
+///
+/// If the amount does not fit into [`Collateral`].
+pub fn notional(
+    price_pns: U256,
+    lot_lns: U256,
+    price_converter: PriceConverter,
+    size_converter: SizeConverter,
+    collateral_converter: CollateralConverter,
+) -> Collateral {
+    let product = price_pns.saturating_mul(lot_lns);
+    let shift =
+        price_converter.0.decimals + size_converter.0.decimals - collateral_converter.0.decimals;
+    let scale = U256::from(10).pow(U256::from(shift.unsigned_abs()));
+    let amount_cns = if shift >= 0 { product / scale } else { product.saturating_mul(scale) };
+    collateral_converter.from_unsigned(amount_cns)
+}
+
+/// Narrows the `value` of the `field` decoded from the exchange data.
+///
+/// Fails with [`DexError::MalformedData`] if the value does not fit.
+pub(crate) fn narrow<T: TryFrom<U256>>(value: U256, field: &str) -> Result<T, DexError> {
+    T::try_from(value)
+        .map_err(|_| DexError::MalformedData(format!("{field} out of range: {value}")))
+}
+
+/// Declares a newtype of decimal number in a specific unit, arithmetic of
+/// which is restricted to values of the same unit.
+macro_rules! unit {
+    ($(#[$attr:meta])* $name:ident($inner:ty)) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, derive_more::Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[debug("{_0}")]
+        pub struct $name($inner);
+
+        impl $name {
+            /// Zero value.
+            pub const ZERO: Self = Self(<$inner>::ZERO);
+
+            /// Wraps decimal number in this unit.
+            pub const fn new(value: $inner) -> Self { Self(value) }
+
+            pub fn is_zero(&self) -> bool { self.0.is_zero() }
+
+            /// Underlying decimal number.
+            pub fn get(self) -> $inner { self.0 }
+        }
+
+        impl From<$inner> for $name {
+            fn from(value: $inner) -> Self { Self(value) }
+        }
+
+        impl From<$name> for $inner {
+            fn from(value: $name) -> Self { value.0 }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { self.0.fmt(f) }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self { Self(self.0 + rhs.0) }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self { Self(self.0 - rhs.0) }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: Self) { self.0 += rhs.0 }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, rhs: Self) { self.0 -= rhs.0 }
+        }
+
+        impl Sum for $name {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self { Self(iter.map(|value| value.0).sum()) }
+        }
+    };
+}
+
+/// Declares a [`Converter`] of fixed-point values in a specific unit.
+macro_rules! unit_converter {
+    ($(#[$attr:meta])* $name:ident($unit:ident)) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+        pub struct $name(Converter);
+
+        impl $name {
+            pub fn new(decimals: u8) -> Self { Self(Converter::new(decimals)) }
+
+            pub fn decimals(&self) -> u8 { self.0.decimals() }
+
+            pub fn scale<const N: usize>(&self) -> UnsignedDecimal<N> { self.0.scale() }
+
+            pub fn from_unsigned(&self, value: U256) -> $unit { $unit(self.0.from_unsigned(value)) }
+
+            /// Converts fixed-point `value` decoded from the exchange data.
+            ///
+            /// Fails with [`DexError::MalformedData`] if the value does not
+            /// fit into the unit.
+            pub fn try_from_unsigned(&self, value: U256) -> Result<$unit, DexError> {
+                self.0.try_from_unsigned(value).map($unit)
+            }
+
+            /// Converts all the `values`, e.g. a decoded array of prices or
+            /// sizes.
+            pub fn from_unsigned_all(&self, values: &[U256]) -> Vec<$unit> {
+                values.iter().map(|value| self.from_unsigned(*value)).collect()
+            }
+
+            /// Converts all the `values` decoded from the exchange data.
+            ///
+            /// Fails with [`DexError::MalformedData`] on the first value that
+            /// does not fit into the unit.
+            pub fn try_from_unsigned_all(&self, values: &[U256]) -> Result<Vec<$unit>, DexError> {
+                values.iter().map(|value| self.try_from_unsigned(*value)).collect()
+            }
+
+            pub fn from_u64(&self, value: u64) -> $unit { $unit(self.0.from_u64(value)) }
+
+            pub fn to_unsigned(&self, value: $unit) -> U256 { self.0.to_unsigned(value.0) }
+
+            /// Parses decimal string, e.g. user input, see [`Converter::parse`].
+            pub fn parse(&self, value: &str) -> Result<$unit, DexError> {
+                self.0.parse(value).map($unit)
+            }
+        }
+    };
+}
+
+unit! {
+    /// Price of a perpetual contract in collateral token per unit of size,
+    /// see [`crate::state::Perpetual::price_converter`].
+    ///
+    /// Cannot be mixed up with values in other units:
+    ///
+    /// ```compile_fail
+    /// use perpl_sdk::num::{Price, Size};
+    ///
+    /// let _ = Price::ZERO + Size::ZERO;
+    /// ```
+    ///
+    /// ```compile_fail
+    /// use perpl_sdk::num::{Collateral, Price};
+    ///
+    /// let _ = Price::ZERO * Collateral::ZERO;
+    /// ```
+    Price(UD64)
+}
+
+unit! {
+    /// Size of an order or a position in a perpetual contract,
+    /// see [`crate::state::Perpetual::size_converter`].
+    ///
+    /// Cannot be mixed up with values in other units:
+    ///
+    /// ```compile_fail
+    /// use perpl_sdk::num::{Price, Size};
+    ///
+    /// let mut size = Size::ZERO;
+    /// size += Price::ZERO;
+    /// ```
+    Size(UD64)
+}
+
+unit! {
+    /// Amount in collateral token, see
+    /// [`crate::state::Exchange::collateral_converter`].
+    ///
+    /// Only results from the product of [`Price`] and [`Size`] or explicit
+    /// conversion:
+    ///
+    /// ```compile_fail
+    /// use perpl_sdk::num::{Collateral, Size};
+    ///
+    /// let _: Collateral = Size::ZERO * Size::ZERO;
+    /// ```
+    Collateral(UD128)
+}
+
+unit_converter! {
+    /// Converter of perpetual contract prices, see [`Price`].
+    PriceConverter(Price)
+}
+
+unit_converter! {
+    /// Converter of perpetual contract sizes, see [`Size`].
+    SizeConverter(Size)
+}
+
+unit_converter! {
+    /// Converter of collateral token amounts, see [`Collateral`].
+    CollateralConverter(Collateral)
+}
+
+impl CollateralConverter {
+    /// Converts signed fixed-point amount, e.g. PnL, which has no unit.
+    pub fn from_signed<const N: usize>(&self, value: I256) -> Decimal<N> {
+        self.0.from_signed(value)
+    }
+
+    /// Converts signed fixed-point amount decoded from the exchange data,
+    /// see [`Converter::try_from_signed`].
+    pub fn try_from_signed<const N: usize>(&self, value: I256) -> Result<Decimal<N>, DexError> {
+        self.0.try_from_signed(value)
+    }
+
+    pub fn from_i64<const N: usize>(&self, value: i64) -> Decimal<N> { self.0.from_i64(value) }
+
+    pub fn to_signed<const N: usize>(&self, value: Decimal<N>) -> I256 { self.0.to_signed(value) }
+}
+
+impl Mul<Size> for Price {
+    type Output = Collateral;
+
+    fn mul(self, rhs: Size) -> Collateral { Collateral(self.0.resize() * rhs.0.resize()) }
+}
+
+impl Mul<Price> for Size {
+    type Output = Collateral;
+
+    fn mul(self, rhs: Price) -> Collateral { rhs * self }
+}
+
+/// Precision of decimal numbers rendered by `Display`/`Tabled`
+/// implementations of the state entities.
+///
+/// Configuration is thread-local and applied with [`Self::scoped`], so nested
+/// renderings (e.g. orders within an order book view) pick it up as well.
+/// Unset precision renders numbers as is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DisplayConfig {
+    price_decimals: Option<u8>,
+    size_decimals: Option<u8>,
+    amount_decimals: Option<u8>,
+}
+
+thread_local! {
+    static DISPLAY_CONFIG: Cell<DisplayConfig> = const { Cell::new(DisplayConfig::new()) };
+}
+
+impl DisplayConfig {
+    /// Configuration rendering numbers with full precision.
+    pub const fn new() -> Self {
+        Self { price_decimals: None, size_decimals: None, amount_decimals: None }
+    }
+
+    /// Decimal places to render prices with.
+    pub fn with_price_decimals(self, decimals: u8) -> Self {
+        Self { price_decimals: Some(decimals), ..self }
+    }
+
+    /// Decimal places to render order and position sizes with.
+    pub fn with_size_decimals(self, decimals: u8) -> Self {
+        Self { size_decimals: Some(decimals), ..self }
+    }
+
+    /// Decimal places to render collateral token amounts with.
+    pub fn with_amount_decimals(self, decimals: u8) -> Self {
+        Self { amount_decimals: Some(decimals), ..self }
+    }
+
+    /// Configuration currently in effect on this thread.
+    pub fn current() -> Self { DISPLAY_CONFIG.get() }
+
+    /// Runs `f` with this configuration in effect on the current thread,
+    /// restoring the previous one afterwards.
+    pub fn scoped<R>(self, f: impl FnOnce() -> R) -> R {
+        struct Restore(DisplayConfig);
+        impl Drop for Restore {
+            fn drop(&mut self) { DISPLAY_CONFIG.set(self.0) }
+        }
+
+        let _restore = Restore(DISPLAY_CONFIG.replace(self));
+        f()
+    }
+
+    pub(crate) fn price<T>(&self, value: T) -> Precision<T> {
+        Precision(value, self.price_decimals)
+    }
+
+    pub(crate) fn size<T>(&self, value: T) -> Precision<T> { Precision(value, self.size_decimals) }
+
+    #[cfg(feature = "display")]
+    pub(crate) fn amount<T>(&self, value: T) -> Precision<T> {
+        Precision(value, self.amount_decimals)
+    }
+}
+
+/// Enables or disables ANSI colors in `Display`/`Tabled` output of the state
+/// entities process-wide.
+///
+/// By default colors follow `NO_COLOR`/`CLICOLOR`/`CLICOLOR_FORCE` environment
+/// variables and are disabled if stdout is not a terminal. Explicit setting
+/// takes precedence over the environment.
+#[cfg(feature = "display")]
+pub fn set_colors(enabled: bool) { colored::control::set_override(enabled) }
+
+/// Renders signed collateral token amount, e.g. PnL, with explicit sign,
+/// green if positive and red if negative.
+///
+/// Zero is rendered without sign and color. Precision follows
+/// [`DisplayConfig::with_amount_decimals`] currently in effect.
+#[cfg(feature = "display")]
+pub fn fmt_signed(value: fastnum::D256) -> String {
+    use colored::Colorize;
+
+    let amount = DisplayConfig::current().amount(value);
+    if value.is_zero() {
+        amount.to_string()
+    } else if value.is_negative() {
+        amount.to_string().red().to_string()
+    } else {
+        format!("+{amount}").green().to_string()
+    }
+}
+
+/// Serde representation of decimals as JSON strings, which is lossless and
+/// the default one, e.g. `#[serde(with = "perpl_sdk::num::as_string")]`.
+#[cfg(feature = "serde")]
+pub mod as_string {
+    use std::{fmt::Display, str::FromStr};
+
+    pub fn serialize<T: Display, S: serde::Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr<Err: Display>,
+        D: serde::Deserializer<'de>,
+    {
+        <String as serde::Deserialize>::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serde representation of decimals as JSON numbers, e.g.
+/// `#[serde(with = "perpl_sdk::num::as_number")]`.
+///
+/// Numbers are limited to the `f64` precision: values of up to 15
+/// significant digits within the normal `f64` range are always serialized,
+/// others only if they round-trip through `f64` exactly, failing otherwise
+/// rather than silently losing precision. Strings in the [`as_string`]
+/// format are accepted on deserialization as well.
+#[cfg(feature = "serde")]
+pub mod as_number {
+    use std::{fmt::Display, marker::PhantomData, str::FromStr};
+
+    use serde::ser::Error as _;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display + FromStr + PartialEq,
+        S: serde::Serializer,
+    {
+        let number = value.to_string().parse::<f64>().map_err(S::Error::custom)?;
+        let round_trip = format!("{number:e}").parse::<T>();
+        if number.is_finite() && round_trip.is_ok_and(|v| v == *value) {
+            serializer.serialize_f64(number)
+        } else {
+            Err(S::Error::custom(format_args!("{value} cannot be represented exactly as a number")))
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr<Err: Display>,
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(Visitor(PhantomData))
+    }
+
+    struct Visitor<T>(PhantomData<T>);
+
+    impl<T: FromStr<Err: Display>> serde::de::Visitor<'_> for Visitor<T> {
+        type Value = T;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("decimal number or string")
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<T, E> {
+            self.visit_str(&v.to_string())
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<T, E> {
+            self.visit_str(&v.to_string())
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<T, E> {
+            if !v.is_finite() {
+                return Err(E::custom(format_args!("{v} is not a decimal number")));
+            }
+            self.visit_str(&format!("{v:e}"))
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<T, E> {
+            v.parse().map_err(E::custom)
+        }
+    }
+}
+
+/// Number rendered with optional fixed precision.
+pub(crate) struct Precision<T>(T, Option<u8>);
+
+impl<T: Display> Display for Precision<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.1 {
+            Some(decimals) => write!(f, "{:.*}", decimals as usize, self.0),
+            None => self.0.fmt(f),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use fastnum::{dec256, udec256};
+    use fastnum::{dec256, udec64, udec128, udec256};
 
     use super::*;
 
+    #[test]
+    #[cfg(feature = "display")]
+    fn test_fmt_signed() {
+        set_colors(false);
+        assert_eq!(fmt_signed(dec256!(1.5)), "+1.5");
+        assert_eq!(fmt_signed(dec256!(-1.5)), "-1.5");
+        assert_eq!(fmt_signed(dec256!(0)), "0");
+
+        let config = DisplayConfig::new().with_amount_decimals(2);
+        assert_eq!(config.scoped(|| fmt_signed(dec256!(12.3))), "+12.30");
+        assert_eq!(config.scoped(|| fmt_signed(dec256!(-0.1))), "-0.10");
+    }
+
     #[test]
     fn test_numeric_converter_from_unsigned() {
         assert_eq!(Converter::new(0).from_unsigned(U256::from(1234567890)), udec256!(1234567890));
@@ -98,6 +612,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_numeric_converter_from_unsigned_all() {
+        let converter = Converter::new(6);
+        let values = [U256::ZERO, U256::from(1), U256::from(1234567890), U256::from(u64::MAX)];
+        let converted: Vec<UD64> = converter.from_unsigned_all(&values);
+        assert_eq!(converted.len(), values.len());
+        for (value, converted) in values.iter().zip(converted) {
+            assert_eq!(converted, converter.from_unsigned(*value));
+        }
+        assert!(converter.from_unsigned_all::<1>(&[]).is_empty());
+
+        // Fallible variant fails on values out of range
+        assert_eq!(
+            converter.try_from_unsigned_all::<1>(&values).unwrap(),
+            converter.from_unsigned_all::<1>(&values)
+        );
+        assert!(matches!(
+            converter.try_from_unsigned_all::<1>(&[U256::ONE, U256::MAX]),
+            Err(DexError::MalformedData(_))
+        ));
+
+        // Unit converters
+        let (pc, sc) = (PriceConverter::new(1), SizeConverter::new(5));
+        let (prices, sizes) = (pc.from_unsigned_all(&values), sc.from_unsigned_all(&values));
+        for ((value, price), size) in values.iter().zip(prices).zip(sizes) {
+            assert_eq!(price, pc.from_unsigned(*value));
+            assert_eq!(size, sc.from_unsigned(*value));
+        }
+        assert_eq!(pc.try_from_unsigned_all(&values).unwrap(), pc.from_unsigned_all(&values));
+        assert!(matches!(
+            sc.try_from_unsigned_all(&[U256::MAX]),
+            Err(DexError::MalformedData(_))
+        ));
+    }
+
     #[test]
     fn test_numeric_converter_from_signed() {
         assert_eq!(
@@ -162,4 +711,148 @@ mod tests {
             I256::try_from(-1234567890).unwrap(),
         );
     }
+
+    #[test]
+    fn test_notional() {
+        let (pc, sc) = (PriceConverter::new(1), SizeConverter::new(5));
+        let price = pc.to_unsigned(Price::new(udec64!(100.5)));
+        let lot = sc.to_unsigned(Size::new(udec64!(0.00125)));
+
+        // Price and size decimals add up to collateral decimals
+        let cc = CollateralConverter::new;
+        assert_eq!(notional(price, lot, pc, sc, cc(6)), Collateral(udec128!(0.125625)));
+
+        // Collateral has less decimals, extra ones are truncated
+        assert_eq!(notional(price, lot, pc, sc, cc(4)), Collateral(udec128!(0.1256)));
+        assert_eq!(notional(price, lot, pc, sc, cc(0)), Collateral::ZERO);
+
+        // Collateral has more decimals
+        assert_eq!(notional(price, lot, pc, sc, cc(18)), Collateral(udec128!(0.125625)));
+        let (pc, sc) = (PriceConverter::new(0), SizeConverter::new(0));
+        assert_eq!(
+            notional(U256::from(100), U256::from(2), pc, sc, cc(6)),
+            Collateral(udec128!(200))
+        );
+    }
+
+    #[test]
+    fn test_numeric_converter_parse() {
+        let converter = Converter::new(2);
+        assert_eq!(converter.parse("123.45").unwrap(), udec64!(123.45));
+        assert_eq!(converter.parse("123.4").unwrap(), udec64!(123.4));
+        assert_eq!(converter.parse("123.450").unwrap(), udec64!(123.45));
+        assert_eq!(converter.parse(" 7 ").unwrap(), udec64!(7));
+        assert_eq!(converter.parse("0").unwrap(), udec64!(0));
+
+        // Excess precision
+        assert!(matches!(converter.parse::<1>("123.456"), Err(DexError::InvalidArgument(_))));
+        assert!(matches!(Converter::new(0).parse::<1>("0.5"), Err(DexError::InvalidArgument(_))));
+
+        // Malformed
+        for value in ["", "abc", "1.2.3", "-1", "1,5", "1e"] {
+            assert!(
+                matches!(converter.parse::<1>(value), Err(DexError::InvalidArgument(_))),
+                "{value}"
+            );
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json<T: Display + FromStr + PartialEq>(
+        value: T,
+        as_number: bool,
+    ) -> Result<String, serde_json::Error> {
+        let mut json = vec![];
+        let mut serializer = serde_json::Serializer::new(&mut json);
+        if as_number {
+            super::as_number::serialize(&value, &mut serializer)?;
+        } else {
+            super::as_string::serialize(&value, &mut serializer)?;
+        }
+        Ok(String::from_utf8(json).unwrap())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_as_string() {
+        use fastnum::{D256, UD128, udec128};
+
+        assert_eq!(to_json(udec128!(1234.5), false).unwrap(), r#""1234.5""#);
+        let huge: D256 = "-1e400".parse().unwrap();
+        let json = to_json(huge, false).unwrap();
+        let parsed: D256 =
+            super::as_string::deserialize(&mut serde_json::Deserializer::from_str(&json)).unwrap();
+        assert_eq!(parsed, huge);
+
+        // Numbers are not accepted
+        let result: Result<UD128, _> =
+            super::as_string::deserialize(&mut serde_json::Deserializer::from_str("1234.5"));
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_as_number() {
+        use fastnum::{D256, UD128, udec128};
+
+        assert_eq!(to_json(udec128!(1234.5), true).unwrap(), "1234.5");
+        assert_eq!(to_json(dec256!(-0.1), true).unwrap(), "-0.1");
+        assert_eq!(to_json(udec128!(123456789012345), true).unwrap(), "123456789012345.0");
+
+        // Values not representable exactly fail rather than lose precision
+        assert!(to_json(udec128!(0.123456789012345678), true).is_err());
+        assert!(to_json("1e400".parse::<D256>().unwrap(), true).is_err());
+
+        let from_json = |json: &str| -> Result<D256, serde_json::Error> {
+            super::as_number::deserialize(&mut serde_json::Deserializer::from_str(json))
+        };
+        assert_eq!(from_json("-0.1").unwrap(), dec256!(-0.1));
+        assert_eq!(from_json("42").unwrap(), dec256!(42));
+        assert_eq!(from_json("-42").unwrap(), dec256!(-42));
+        assert_eq!(from_json(r#""0.123456789012345678""#).unwrap(), dec256!(0.123456789012345678));
+        assert!(from_json("1e400").is_err());
+        let negative: Result<UD128, _> =
+            super::as_number::deserialize(&mut serde_json::Deserializer::from_str("-1"));
+        assert!(negative.is_err());
+    }
+
+    #[test]
+    fn test_units() {
+        let (price, size) = (Price(udec64!(100.5)), Size(udec64!(2)));
+        assert_eq!(price * size, Collateral(udec128!(201)));
+        assert_eq!(size * price, price * size);
+
+        let mut total = Size::ZERO;
+        total += size;
+        total -= Size(udec64!(0.5));
+        assert_eq!(total + size, Size(udec64!(3.5)));
+        assert_eq!(UD64::from(total), udec64!(1.5));
+        assert_eq!(format!("{price} {price:?}"), "100.5 100.5");
+
+        // Typed conversion of fixed-point values
+        let (pc, sc, cc) =
+            (PriceConverter::new(1), SizeConverter::new(5), CollateralConverter::new(6));
+        let price = pc.from_unsigned(U256::from(1005));
+        let size = sc.from_unsigned(U256::from(125));
+        assert_eq!((price, size), (Price(udec64!(100.5)), Size(udec64!(0.00125))));
+        assert_eq!(cc.from_unsigned(U256::from(125_625)), price * size);
+        assert_eq!(cc.parse("0.125625").unwrap(), price * size);
+        assert_eq!(pc.to_unsigned(price), U256::from(1005));
+        assert_eq!(sc.parse("0.00125").unwrap(), size);
+        assert!(matches!(sc.try_from_unsigned(U256::MAX), Err(DexError::MalformedData(_))));
+    }
+
+    #[test]
+    fn test_display_config_scoped() {
+        let config = DisplayConfig::new()
+            .with_price_decimals(2)
+            .with_size_decimals(0);
+        let rendered = config.scoped(|| {
+            let current = DisplayConfig::current();
+            format!("{} {}", current.price(udec64!(101.256)), current.size(udec64!(3)))
+        });
+        assert_eq!(rendered, "101.26 3");
+        assert_eq!(DisplayConfig::current(), DisplayConfig::new());
+        assert_eq!(DisplayConfig::current().price(udec64!(101.256)).to_string(), "101.256");
+    }
 }
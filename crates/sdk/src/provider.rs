@@ -0,0 +1,57 @@
+//! RPC provider setup recommended for the SDK.
+//!
+//! Streams in [`crate::stream`] poll logs continuously and snapshots issue
+//! bursts of calls, so public RPC endpoints quickly start rate limiting them.
+//! [`recommended`] assembles the provider with retries and optional
+//! throttling, so consumers do not have to replicate it.
+
+use std::time::Duration;
+
+use alloy::{
+    providers::{Provider, ProviderBuilder},
+    rpc::client::RpcClient,
+    transports::layers::{RetryBackoffLayer, ThrottleLayer},
+};
+
+use crate::error::DexError;
+
+/// Max number of retries of rate limited requests.
+pub const MAX_RATE_LIMIT_RETRIES: u32 = 10;
+
+/// Initial backoff of retried requests, in milliseconds.
+pub const INITIAL_BACKOFF_MS: u64 = 100;
+
+/// Compute units per second assumed by the retry backoff.
+pub const COMPUTE_UNITS_PER_SECOND: u64 = 200;
+
+/// Interval of polling for new blocks/logs.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Connects to the `rpc_url` with retries of rate limited requests and
+/// optional throttling to `throttle` requests per second, see
+/// [`ThrottleLayer`].
+///
+/// Public RPC endpoints usually require throttling, while dedicated ones can
+/// be used without it.
+pub async fn recommended(
+    rpc_url: &str,
+    throttle: Option<u32>,
+) -> Result<impl Provider + Clone, DexError> {
+    let retry = RetryBackoffLayer::new(
+        MAX_RATE_LIMIT_RETRIES,
+        INITIAL_BACKOFF_MS,
+        COMPUTE_UNITS_PER_SECOND,
+    );
+    let client = if let Some(requests_per_second) = throttle {
+        RpcClient::builder()
+            .layer(ThrottleLayer::new(requests_per_second))
+            .layer(retry)
+            .connect(rpc_url)
+            .await
+    } else {
+        RpcClient::builder().layer(retry).connect(rpc_url).await
+    }
+    .map_err(|err| DexError::Provider(err.into()))?;
+    client.set_poll_interval(POLL_INTERVAL);
+    Ok(ProviderBuilder::new().connect_client(client))
+}
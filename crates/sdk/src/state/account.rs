@@ -1,5 +1,7 @@
+use std::{collections::VecDeque, sync::Arc};
+
 use alloy::primitives::{Address, U256};
-use fastnum::{D256, UD128};
+use fastnum::D256;
 
 use super::*;
 use crate::{
@@ -9,16 +11,73 @@ use crate::{
 
 /// Exchange account.
 #[derive(Clone, derive_more::Debug)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
 pub struct Account {
     instant: types::StateInstant,
     id: types::AccountId,
     address: Address,
     #[debug("{balance}")]
-    balance: UD128, // SC allocates 80 bits
+    balance: num::Collateral, // SC allocates 80 bits
     #[debug("{locked_balance}")]
-    locked_balance: UD128, // SC allocates 80 bits
+    locked_balance: num::Collateral, // SC allocates 80 bits
     frozen: bool,
     positions: HashMap<types::PerpetualId, Position>,
+    balance_history_capacity: usize,
+    balance_history: VecDeque<BalanceChange>,
+}
+
+/// Raw fixed-point values of the account state as used by the exchange
+/// contract, see [`Account::raw`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawAccount {
+    /// Balance in collateral token units, `balanceCNS`.
+    pub balance_cns: U256,
+
+    /// Locked balance in collateral token units, `lockedBalanceCNS`.
+    pub locked_balance_cns: U256,
+}
+
+/// Change of the account balance, see [`Account::with_balance_history`].
+#[derive(Clone, Copy, derive_more::Debug, PartialEq, Eq)]
+pub struct BalanceChange {
+    /// Instant the balance was changed at.
+    pub instant: types::StateInstant,
+
+    /// Balance increase, negative if decreased.
+    #[debug("{delta}")]
+    pub delta: D256,
+
+    /// Reason of the change.
+    pub reason: BalanceChangeReason,
+}
+
+/// Reason of the account balance change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalanceChangeReason {
+    /// Collateral deposited to the account.
+    Deposit,
+
+    /// Collateral withdrawn from the account.
+    Withdrawal,
+
+    /// Order fill settled, including trading fee and realized PnL.
+    Settlement,
+
+    /// Recycle fee charged on order placement or change, refunded on order
+    /// cancellation, or received for clearing orders of other accounts.
+    Fee,
+
+    /// Collateral moved to a position.
+    PositionCollateral,
+
+    /// Position liquidated, deleveraged or unwound.
+    Liquidation,
+
+    /// Collateral transferred between the account and the protocol.
+    ///
+    /// The exchange has no direct transfers between accounts, so moving
+    /// collateral between accounts shows up as a pair of such transfers.
+    Transfer,
 }
 
 impl Account {
@@ -27,7 +86,7 @@ impl Account {
         id: types::AccountId,
         info: &AccountInfo,
         positions: HashMap<types::PerpetualId, Position>,
-        collateral_converter: num::Converter,
+        collateral_converter: num::CollateralConverter,
     ) -> Self {
         Self {
             instant,
@@ -37,6 +96,8 @@ impl Account {
             locked_balance: collateral_converter.from_unsigned(info.lockedBalanceCNS),
             frozen: info.frozen != 0,
             positions,
+            balance_history_capacity: 0,
+            balance_history: VecDeque::new(),
         }
     }
 
@@ -49,10 +110,12 @@ impl Account {
             instant,
             id,
             address,
-            balance: UD128::ZERO,
-            locked_balance: UD128::ZERO,
+            balance: num::Collateral::ZERO,
+            locked_balance: num::Collateral::ZERO,
             frozen: false,
             positions: HashMap::new(),
+            balance_history_capacity: 0,
+            balance_history: VecDeque::new(),
         }
     }
 
@@ -64,13 +127,22 @@ impl Account {
             instant,
             id: account_id,
             address: Address::ZERO,
-            balance: UD128::ZERO,
-            locked_balance: UD128::ZERO,
+            balance: num::Collateral::ZERO,
+            locked_balance: num::Collateral::ZERO,
             frozen: false,
             positions,
+            balance_history_capacity: 0,
+            balance_history: VecDeque::new(),
         }
     }
 
+    /// Retains up to `capacity` most recent balance changes, see
+    /// [`Self::balance_history`]. Zero capacity disables the history.
+    pub fn with_balance_history(mut self, capacity: usize) -> Self {
+        self.set_balance_history_capacity(capacity);
+        self
+    }
+
     /// Instant the account state is consistent with or was last updated at.
     pub fn instant(&self) -> types::StateInstant { self.instant }
 
@@ -82,19 +154,30 @@ impl Account {
 
     /// The current balance of collateral tokens in this account,
     /// not including any open positions.
-    pub fn balance(&self) -> UD128 { self.balance }
+    pub fn balance(&self) -> num::Collateral { self.balance }
 
     /// The balance of collateral tokens locked by existing orders for this
     /// account.
     /// If this value exceeds [`Self::balance`], new Open* orders cannot be
     /// placed.
-    pub fn locked_balance(&self) -> UD128 { self.locked_balance }
+    pub fn locked_balance(&self) -> num::Collateral { self.locked_balance }
+
+    /// Raw fixed-point values of [`Self::balance`] and
+    /// [`Self::locked_balance`] as reported by the exchange contract, for
+    /// precise accounting without fetching them from the chain.
+    pub fn raw(&self, exchange: &Exchange) -> RawAccount {
+        let cc = exchange.collateral_converter();
+        RawAccount {
+            balance_cns: cc.to_unsigned(self.balance),
+            locked_balance_cns: cc.to_unsigned(self.locked_balance),
+        }
+    }
 
     /// The balance of collateral tokens available for trading.
-    pub fn available_balance(&self) -> UD128 {
+    pub fn available_balance(&self) -> num::Collateral {
         if self.locked_balance > self.balance {
             // Valid scenario from the smart contract perspective
-            return UD128::ZERO;
+            return num::Collateral::ZERO;
         }
         self.balance - self.locked_balance
     }
@@ -102,18 +185,93 @@ impl Account {
     /// Total unrealized PnL of all positions of the account.
     pub fn unrealized_pnl(&self) -> D256 { self.positions.values().map(|p| p.pnl()).sum() }
 
+    /// Total notional of all positions of the account at the mark prices of
+    /// `perpetuals`, regardless of position direction.
+    ///
+    /// Positions in perpetual contracts missing from `perpetuals` are skipped.
+    pub fn gross_notional(
+        &self,
+        perpetuals: &HashMap<types::PerpetualId, Arc<Perpetual>>,
+    ) -> num::Collateral {
+        self.position_notionals(perpetuals)
+            .map(|(_, notional)| notional)
+            .sum()
+    }
+
+    /// Same as [`Self::gross_notional`], but with notional of short
+    /// positions taken as negative.
+    pub fn net_notional(&self, perpetuals: &HashMap<types::PerpetualId, Arc<Perpetual>>) -> D256 {
+        self.position_notionals(perpetuals)
+            .map(|(pos, notional)| {
+                let notional: D256 = notional.get().resize().to_signed();
+                if pos.r#type().is_long() { notional } else { -notional }
+            })
+            .sum()
+    }
+
     /// Indicator of the account being frozen.
     pub fn frozen(&self) -> bool { self.frozen }
 
     /// Positions the account has, up to one per each perpetual contract.
     pub fn positions(&self) -> &HashMap<types::PerpetualId, position::Position> { &self.positions }
 
+    /// Position the account has in the perpetual contract with the given
+    /// symbol, resolved via the perpetual contracts tracked by the exchange.
+    pub fn position_by_symbol(&self, exchange: &Exchange, symbol: &str) -> Option<&Position> {
+        exchange
+            .perpetuals()
+            .values()
+            .find(|perp| perp.symbol() == symbol)
+            .and_then(|perp| self.positions.get(&perp.id()))
+    }
+
+    /// Most recent balance changes applied since the history was enabled with
+    /// [`Self::with_balance_history`], oldest first.
+    pub fn balance_history(&self) -> &VecDeque<BalanceChange> { &self.balance_history }
+
+    fn position_notionals<'a>(
+        &'a self,
+        perpetuals: &'a HashMap<types::PerpetualId, Arc<Perpetual>>,
+    ) -> impl Iterator<Item = (&'a Position, num::Collateral)> {
+        self.positions.values().filter_map(|pos| {
+            let perp = perpetuals.get(&pos.perpetual_id())?;
+            Some((pos, perp.mark_price() * pos.size()))
+        })
+    }
+
+    /// Approximate heap memory used by the account state, in bytes.
+    pub(crate) fn approximate_heap_bytes(&self) -> usize {
+        self.positions.capacity() * std::mem::size_of::<(types::PerpetualId, Position)>()
+            + self.balance_history.capacity() * std::mem::size_of::<BalanceChange>()
+    }
+
+    pub(crate) fn set_balance_history_capacity(&mut self, capacity: usize) {
+        self.balance_history_capacity = capacity;
+        let excess = self.balance_history.len().saturating_sub(capacity);
+        self.balance_history.drain(..excess);
+        self.balance_history.shrink_to(capacity);
+    }
+
     pub(crate) fn update_frozen(&mut self, instant: types::StateInstant, frozen: bool) {
         self.frozen = frozen;
         self.instant = instant;
     }
 
-    pub(crate) fn update_balance(&mut self, instant: types::StateInstant, balance: UD128) {
+    pub(crate) fn update_balance(
+        &mut self,
+        instant: types::StateInstant,
+        balance: num::Collateral,
+        reason: BalanceChangeReason,
+    ) {
+        if self.balance_history_capacity > 0 && balance != self.balance {
+            if self.balance_history.len() == self.balance_history_capacity {
+                self.balance_history.pop_front();
+            }
+            let delta =
+                balance.get().to_signed().resize() - self.balance.get().to_signed().resize();
+            self.balance_history
+                .push_back(BalanceChange { instant, delta, reason });
+        }
         self.balance = balance;
         self.instant = instant;
     }
@@ -121,7 +279,7 @@ impl Account {
     pub(crate) fn update_locked_balance(
         &mut self,
         instant: types::StateInstant,
-        locked_balance: UD128,
+        locked_balance: num::Collateral,
     ) {
         self.locked_balance = locked_balance;
         self.instant = instant;
@@ -140,17 +298,17 @@ impl std::fmt::Display for Account {
 
         if !self.address.is_zero() {
             // Full account state is known
-            let pnl = self.unrealized_pnl();
+            let config = num::DisplayConfig::current();
             writeln!(
                 f,
                 "{} ({}) {}\n    Balance: {} | Available: {} | Locked: {} | Unrealized PnL: {}",
                 format!("Account #{}", self.id).blue(),
                 self.address,
                 if self.frozen { "FROZEN ".bright_red() } else { Default::default() },
-                self.balance,
-                self.available_balance().to_string().green(),
-                self.locked_balance,
-                if pnl.is_negative() { pnl.to_string().red() } else { pnl.to_string().green() },
+                config.amount(self.balance),
+                config.amount(self.available_balance()).to_string().green(),
+                config.amount(self.locked_balance),
+                num::fmt_signed(self.unrealized_pnl()),
             )?;
         } else {
             // Only ID is known
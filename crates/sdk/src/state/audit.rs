@@ -0,0 +1,601 @@
+#[cfg(feature = "serde")]
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write as _},
+    path::Path,
+    sync::Mutex,
+};
+
+use alloy::primitives::TxHash;
+
+use super::*;
+
+/// Receiver of the state changes applied to [`Exchange`] snapshot, see
+/// [`Exchange::set_audit_sink`].
+///
+/// Called synchronously for each state change while events are applied, so
+/// implementations should be cheap or hand records off to a background task.
+pub trait StateAuditSink: Send + Sync {
+    fn record(&self, record: &StateAuditRecord<'_>);
+}
+
+/// Single state change along with its provenance.
+#[derive(Clone, Copy, Debug)]
+pub struct StateAuditRecord<'a> {
+    /// Instant of the block the change was applied at.
+    pub instant: types::StateInstant,
+
+    /// Hash of the transaction emitted the originating raw event.
+    ///
+    /// Zero for block-level changes not originating from any raw event, e.g.
+    /// funding payments or perpetual contract parameter changes propagated
+    /// to positions.
+    pub tx_hash: TxHash,
+
+    /// Index of the transaction within the block.
+    pub tx_index: u64,
+
+    /// Index of the originating raw event within the block.
+    pub log_index: u64,
+
+    /// The state change.
+    pub event: &'a StateEvents,
+}
+
+impl StateAuditRecord<'_> {
+    /// Kind of the state changed.
+    pub fn kind(&self) -> &'static str {
+        match self.event {
+            StateEvents::Account(e) => match e.r#type {
+                AccountEventType::BalanceUpdated(_) | AccountEventType::LockedBalanceUpdated(_) => {
+                    "balance"
+                },
+                _ => "account",
+            },
+            StateEvents::Error(_) => "error",
+            StateEvents::Exchange(_) => "exchange",
+            StateEvents::Order(_) => "order",
+            StateEvents::Perpetual(_) => "perpetual",
+            StateEvents::Position(_) => "position",
+            StateEvents::Trade(_) => "trade",
+        }
+    }
+
+    /// Renders the record as a single line JSON object.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("audit record serialization is infallible")
+    }
+}
+
+/// Serialized as a flat map, with the instant as `block` and `timestamp`,
+/// the [`StateAuditRecord::kind`] as `kind` and the change as `change`.
+///
+/// The change is a flat map of the IDs of the affected entities, the event
+/// `type` in snake case, e.g. `balance_updated`, and the event fields. IDs
+/// and amounts are serialized as strings, see [`num::as_string`], order and
+/// position types and sides in their [`std::fmt::Display`] format.
+#[cfg(feature = "serde")]
+impl serde::Serialize for StateAuditRecord<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("StateAuditRecord", 7)?;
+        state.serialize_field("block", &self.instant.block_number())?;
+        state.serialize_field("timestamp", &self.instant.block_timestamp())?;
+        state.serialize_field("tx_hash", &self.tx_hash)?;
+        state.serialize_field("tx_index", &self.tx_index)?;
+        state.serialize_field("log_index", &self.log_index)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("change", &Change(self.event))?;
+        state.end()
+    }
+}
+
+/// [`StateAuditSink`] appending records to a file in JSON lines format.
+///
+/// Each record is written as a separate line once received, failed writes
+/// are logged as errors.
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub struct JsonLinesAuditSink(Mutex<File>);
+
+#[cfg(feature = "serde")]
+impl JsonLinesAuditSink {
+    /// Opens the file at `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl StateAuditSink for JsonLinesAuditSink {
+    fn record(&self, record: &StateAuditRecord<'_>) {
+        let mut line = record.to_json();
+        line.push('\n');
+        let mut file = self.0.lock().expect("audit file lock poisoned");
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            tracing::error!(
+                block = record.instant.block_number(),
+                log_index = record.log_index,
+                %err,
+                "failed to write state audit record",
+            );
+        }
+    }
+}
+
+/// Structured [`StateAuditRecord::event`] serialization.
+#[cfg(feature = "serde")]
+struct Change<'a>(&'a StateEvents);
+
+/// Value serialized as string, see [`num::as_string`].
+#[cfg(feature = "serde")]
+struct Str<T>(T);
+
+#[cfg(feature = "serde")]
+impl<T: std::fmt::Display> serde::Serialize for Str<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        num::as_string::serialize(&self.0, serializer)
+    }
+}
+
+/// Serializes `$key => $value` entries into the `$map`, with values wrapped
+/// into [`Str`] unless marked as `raw`.
+#[cfg(feature = "serde")]
+macro_rules! entries {
+    ($map:ident; $($key:literal => $(@$raw:ident)? $value:expr),* $(,)?) => {
+        $(entries!(@entry $map, $key, $($raw)? $value);)*
+    };
+    (@entry $map:ident, $key:literal, raw $value:expr) => {
+        $map.serialize_entry($key, &$value)?
+    };
+    (@entry $map:ident, $key:literal, $value:expr) => {
+        $map.serialize_entry($key, &Str($value))?
+    };
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Change<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        match self.0 {
+            StateEvents::Account(e) => {
+                entries!(map;
+                    "account_id" => e.account_id,
+                    "request_id" => @raw e.request_id.map(Str),
+                );
+                match e.r#type {
+                    AccountEventType::Created(id) => {
+                        entries!(map; "type" => @raw "created", "id" => id);
+                    },
+                    AccountEventType::Frozen(frozen) => {
+                        entries!(map; "type" => @raw "frozen", "frozen" => @raw frozen);
+                    },
+                    AccountEventType::BalanceUpdated(balance) => {
+                        entries!(map; "type" => @raw "balance_updated", "balance" => balance);
+                    },
+                    AccountEventType::LockedBalanceUpdated(balance) => {
+                        entries!(map;
+                            "type" => @raw "locked_balance_updated",
+                            "locked_balance" => balance,
+                        );
+                    },
+                }
+            },
+            StateEvents::Error(e) => {
+                entries!(map;
+                    "perpetual_id" => e.perpetual_id,
+                    "account_id" => e.account_id,
+                    "request_id" => e.request_id,
+                    "order_id" => @raw e.order_id.map(Str),
+                    "type" => @raw order_error_type(&e.r#type),
+                );
+                match e.r#type {
+                    OrderErrorType::AmountExceedsAvailableBalance(amount, available) => {
+                        entries!(map; "amount" => amount, "available_balance" => available);
+                    },
+                    OrderErrorType::OrderPostFailed(status) => {
+                        entries!(map; "status" => @raw status);
+                    },
+                    _ => {},
+                }
+            },
+            StateEvents::Exchange(e) => match e {
+                ExchangeEvent::Halted(halted) => {
+                    entries!(map; "type" => @raw "halted", "halted" => @raw halted);
+                },
+                ExchangeEvent::MinPostUpdated(amount) => {
+                    entries!(map; "type" => @raw "min_post_updated", "min_post" => amount);
+                },
+                ExchangeEvent::MinSettleUpdated(amount) => {
+                    entries!(map; "type" => @raw "min_settle_updated", "min_settle" => amount);
+                },
+                ExchangeEvent::RecycleFeeUpdated(amount) => {
+                    entries!(map; "type" => @raw "recycle_fee_updated", "recycle_fee" => amount);
+                },
+            },
+            StateEvents::Order(e) => {
+                entries!(map;
+                    "perpetual_id" => e.perpetual_id,
+                    "account_id" => e.account_id,
+                    "request_id" => @raw e.request_id.map(Str),
+                    "client_order_id" => @raw e.client_order_id.map(Str),
+                    "order_id" => @raw e.order_id.map(Str),
+                );
+                match e.r#type {
+                    OrderEventType::Filled {
+                        fill_price,
+                        fill_size,
+                        remaining_size,
+                        fee,
+                        is_maker,
+                    } => {
+                        entries!(map;
+                            "type" => @raw "filled",
+                            "fill_price" => fill_price,
+                            "fill_size" => fill_size,
+                            "remaining_size" => @raw remaining_size.map(Str),
+                            "fee" => fee,
+                            "is_maker" => @raw is_maker,
+                        );
+                    },
+                    OrderEventType::Placed {
+                        r#type,
+                        price,
+                        size,
+                        expiry_block,
+                        leverage,
+                        post_only,
+                        fill_or_kill,
+                        immediate_or_cancel,
+                    } => {
+                        entries!(map;
+                            "type" => @raw "placed",
+                            "order_type" => r#type,
+                            "price" => price,
+                            "size" => size,
+                            "expiry_block" => expiry_block,
+                            "leverage" => leverage,
+                            "post_only" => @raw post_only,
+                            "fill_or_kill" => @raw fill_or_kill,
+                            "immediate_or_cancel" => @raw immediate_or_cancel,
+                        );
+                    },
+                    OrderEventType::Removed => {
+                        entries!(map; "type" => @raw "removed");
+                    },
+                    OrderEventType::Updated { price, size, expiry_block } => {
+                        entries!(map;
+                            "type" => @raw "updated",
+                            "price" => @raw price.map(Str),
+                            "size" => @raw size.map(Str),
+                            "expiry_block" => @raw expiry_block.map(Str),
+                        );
+                    },
+                }
+            },
+            StateEvents::Perpetual(e) => {
+                entries!(map; "perpetual_id" => e.perpetual_id);
+                serialize_perpetual_event(&mut map, &e.r#type)?;
+            },
+            StateEvents::Position(e) => {
+                entries!(map;
+                    "perpetual_id" => e.perpetual_id,
+                    "account_id" => e.account_id,
+                    "request_id" => @raw e.request_id.map(Str),
+                );
+                serialize_position_event(&mut map, &e.r#type)?;
+            },
+            StateEvents::Trade(t) => {
+                let maker_fills: Vec<_> = t.maker_fills.iter().map(MakerFill).collect();
+                entries!(map;
+                    "perpetual_id" => t.perpetual_id,
+                    "taker_account_id" => t.taker_account_id,
+                    "taker_request_id" => t.taker_request_id,
+                    "taker_side" => t.taker_side,
+                    "taker_fee" => t.taker_fee,
+                    "maker_fills" => @raw maker_fills,
+                );
+            },
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+fn serialize_perpetual_event<M: serde::ser::SerializeMap>(
+    map: &mut M,
+    r#type: &PerpetualEventType,
+) -> Result<(), M::Error> {
+    match *r#type {
+        PerpetualEventType::Added => {
+            entries!(map; "type" => @raw "added");
+        },
+        PerpetualEventType::BookNearCapacity(num_orders) => {
+            entries!(map; "type" => @raw "book_near_capacity", "num_orders" => @raw num_orders);
+        },
+        PerpetualEventType::FundingEvent { rate, payment_per_unit } => {
+            entries!(map;
+                "type" => @raw "funding_event",
+                "rate" => rate,
+                "payment_per_unit" => payment_per_unit,
+            );
+        },
+        PerpetualEventType::FundingSumScalingExpUpdated(exp) => {
+            entries!(map; "type" => @raw "funding_sum_scaling_exp_updated", "exp" => @raw exp);
+        },
+        PerpetualEventType::InitialMarginFractionUpdated(value) => {
+            entries!(map; "type" => @raw "initial_margin_fraction_updated", "value" => value);
+        },
+        PerpetualEventType::LastPriceUpdated(price) => {
+            entries!(map; "type" => @raw "last_price_updated", "price" => price);
+        },
+        PerpetualEventType::MaintenanceMarginFractionUpdated(value) => {
+            entries!(map; "type" => @raw "maintenance_margin_fraction_updated", "value" => value);
+        },
+        PerpetualEventType::MarkPriceUpdated(price) => {
+            entries!(map; "type" => @raw "mark_price_updated", "price" => price);
+        },
+        PerpetualEventType::MakerFeeUpdated(fee) => {
+            entries!(map; "type" => @raw "maker_fee_updated", "fee" => fee);
+        },
+        PerpetualEventType::OpenInterestUpdated(size) => {
+            entries!(map; "type" => @raw "open_interest_updated", "open_interest" => size);
+        },
+        PerpetualEventType::OracleConfigurationUpdated { is_used, feed_id } => {
+            entries!(map;
+                "type" => @raw "oracle_configuration_updated",
+                "is_used" => @raw is_used,
+                "feed_id" => feed_id,
+            );
+        },
+        PerpetualEventType::OraclePriceUpdated(price) => {
+            entries!(map; "type" => @raw "oracle_price_updated", "price" => price);
+        },
+        PerpetualEventType::ParamChanged { param, old, new } => {
+            let param = match param {
+                PerpetualParam::MakerFee => "maker_fee",
+                PerpetualParam::TakerFee => "taker_fee",
+                PerpetualParam::InitialMargin => "initial_margin",
+                PerpetualParam::MaintenanceMargin => "maintenance_margin",
+                PerpetualParam::PriceMaxAge => "price_max_age",
+            };
+            entries!(map;
+                "type" => @raw "param_changed",
+                "param" => @raw param,
+                "old" => old,
+                "new" => new,
+            );
+        },
+        PerpetualEventType::Paused(paused) => {
+            entries!(map; "type" => @raw "paused", "paused" => @raw paused);
+        },
+        PerpetualEventType::TakerFeeUpdated(fee) => {
+            entries!(map; "type" => @raw "taker_fee_updated", "fee" => fee);
+        },
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn serialize_position_event<M: serde::ser::SerializeMap>(
+    map: &mut M,
+    r#type: &PositionEventType,
+) -> Result<(), M::Error> {
+    match *r#type {
+        PositionEventType::Closed {
+            r#type,
+            entry_price,
+            exit_price,
+            size,
+            delta_pnl,
+            premium_pnl,
+        } => {
+            entries!(map;
+                "type" => @raw "closed",
+                "position_type" => r#type,
+                "entry_price" => entry_price,
+                "exit_price" => exit_price,
+                "size" => size,
+                "delta_pnl" => delta_pnl,
+                "premium_pnl" => premium_pnl,
+            );
+        },
+        PositionEventType::CollateralDecreased { prev_entry_price, new_entry_price, deposit } => {
+            entries!(map;
+                "type" => @raw "collateral_decreased",
+                "prev_entry_price" => prev_entry_price,
+                "new_entry_price" => new_entry_price,
+                "deposit" => deposit,
+            );
+        },
+        PositionEventType::Decreased { prev_size, new_size, deposit, delta_pnl, premium_pnl } => {
+            entries!(map;
+                "type" => @raw "decreased",
+                "prev_size" => prev_size,
+                "new_size" => new_size,
+                "deposit" => deposit,
+                "delta_pnl" => delta_pnl,
+                "premium_pnl" => premium_pnl,
+            );
+        },
+        PositionEventType::Deleveraged {
+            force_close,
+            r#type,
+            entry_price,
+            exit_price,
+            prev_size,
+            new_size,
+            deposit,
+            delta_pnl,
+            premium_pnl,
+        } => {
+            entries!(map;
+                "type" => @raw "deleveraged",
+                "force_close" => @raw force_close,
+                "position_type" => r#type,
+                "entry_price" => entry_price,
+                "exit_price" => exit_price,
+                "prev_size" => prev_size,
+                "new_size" => new_size,
+                "deposit" => deposit,
+                "delta_pnl" => delta_pnl,
+                "premium_pnl" => premium_pnl,
+            );
+        },
+        PositionEventType::DepositUpdated(deposit) => {
+            entries!(map; "type" => @raw "deposit_updated", "deposit" => deposit);
+        },
+        PositionEventType::Increased { entry_price, prev_size, new_size, deposit } => {
+            entries!(map;
+                "type" => @raw "increased",
+                "entry_price" => entry_price,
+                "prev_size" => prev_size,
+                "new_size" => new_size,
+                "deposit" => deposit,
+            );
+        },
+        PositionEventType::Inverted {
+            r#type,
+            entry_price,
+            prev_size,
+            new_size,
+            deposit,
+            delta_pnl,
+            premium_pnl,
+        } => {
+            entries!(map;
+                "type" => @raw "inverted",
+                "position_type" => r#type,
+                "entry_price" => entry_price,
+                "prev_size" => prev_size,
+                "new_size" => new_size,
+                "deposit" => deposit,
+                "delta_pnl" => delta_pnl,
+                "premium_pnl" => premium_pnl,
+            );
+        },
+        PositionEventType::Liquidated {
+            r#type,
+            entry_price,
+            exit_price,
+            prev_size,
+            liquidated_size,
+            new_size,
+            deposit,
+            delta_pnl,
+            premium_pnl,
+        } => {
+            entries!(map;
+                "type" => @raw "liquidated",
+                "position_type" => r#type,
+                "entry_price" => entry_price,
+                "exit_price" => exit_price,
+                "prev_size" => prev_size,
+                "liquidated_size" => liquidated_size,
+                "new_size" => new_size,
+                "deposit" => deposit,
+                "delta_pnl" => delta_pnl,
+                "premium_pnl" => premium_pnl,
+            );
+        },
+        PositionEventType::MaintenanceMarginUpdated(requirement) => {
+            entries!(map;
+                "type" => @raw "maintenance_margin_updated",
+                "maintenance_margin_requirement" => requirement,
+            );
+        },
+        PositionEventType::Opened { r#type, entry_price, size, deposit } => {
+            entries!(map;
+                "type" => @raw "opened",
+                "position_type" => r#type,
+                "entry_price" => entry_price,
+                "size" => size,
+                "deposit" => deposit,
+            );
+        },
+        PositionEventType::UnrealizedPnLUpdated { pnl, delta_pnl, premium_pnl } => {
+            entries!(map;
+                "type" => @raw "unrealized_pnl_updated",
+                "pnl" => pnl,
+                "delta_pnl" => delta_pnl,
+                "premium_pnl" => premium_pnl,
+            );
+        },
+        PositionEventType::Unwound {
+            r#type,
+            entry_price,
+            exit_price,
+            size,
+            fair_market_value,
+            payment,
+        } => {
+            entries!(map;
+                "type" => @raw "unwound",
+                "position_type" => r#type,
+                "entry_price" => entry_price,
+                "exit_price" => exit_price,
+                "size" => size,
+                "fair_market_value" => fair_market_value,
+                "payment" => payment,
+            );
+        },
+    }
+    Ok(())
+}
+
+/// Maker fill of a trade, serialized as a flat map.
+#[cfg(feature = "serde")]
+struct MakerFill<'a>(&'a types::MakerFill);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for MakerFill<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let fill = self.0;
+        let mut map = serializer.serialize_map(Some(6))?;
+        entries!(map;
+            "log_index" => @raw fill.log_index,
+            "maker_account_id" => fill.maker_account_id,
+            "maker_order_id" => fill.maker_order_id,
+            "price" => fill.price,
+            "size" => fill.size,
+            "fee" => fill.fee,
+        );
+        map.end()
+    }
+}
+
+/// Snake case name of the order request failure.
+#[cfg(feature = "serde")]
+fn order_error_type(r#type: &OrderErrorType) -> &'static str {
+    match r#type {
+        OrderErrorType::AccountFrozen => "account_frozen",
+        OrderErrorType::AmountExceedsAvailableBalance(..) => "amount_exceeds_available_balance",
+        OrderErrorType::CancelExistingInvalidCloseOrders => "cancel_existing_invalid_close_orders",
+        OrderErrorType::CantChangeCloseOrder => "cant_change_close_order",
+        OrderErrorType::ChangeExpiredOrderNeedsNewExpiry => "change_expired_order_needs_new_expiry",
+        OrderErrorType::CloseOrderExceedsPosition => "close_order_exceeds_position",
+        OrderErrorType::CloseOrderPositionMismatch => "close_order_position_mismatch",
+        OrderErrorType::ContractNotOperational => "contract_not_operational",
+        OrderErrorType::CrossesBook => "crosses_book",
+        OrderErrorType::ExceedsLastExecutionBlock => "exceeds_last_execution_block",
+        OrderErrorType::ImmediateOrCancelExecuted => "immediate_or_cancel_executed",
+        OrderErrorType::InsuficientFundsForRecycleFee => "insufficient_funds_for_recycle_fee",
+        OrderErrorType::InvalidExpiryBlock => "invalid_expiry_block",
+        OrderErrorType::InvalidOrderId => "invalid_order_id",
+        OrderErrorType::MakerOrderSettlementFailed => "maker_order_settlement_failed",
+        OrderErrorType::MaxMatchesReached => "max_matches_reached",
+        OrderErrorType::MaximumAccountOrders => "maximum_account_orders",
+        OrderErrorType::OrderDoesNotExist => "order_does_not_exist",
+        OrderErrorType::OrderPostFailed(_) => "order_post_failed",
+        OrderErrorType::OrderSettlementImpliesInsolvent => "order_settlement_implies_insolvent",
+        OrderErrorType::OrderSizeExceedsAvailableSize => "order_size_exceeds_available_size",
+        OrderErrorType::PostOrderUnderMinimum => "post_order_under_minimum",
+        OrderErrorType::PriceOutOfRange => "price_out_of_range",
+        OrderErrorType::SizeOutOfRange => "size_out_of_range",
+        OrderErrorType::ValueExceedsMaximum => "value_exceeds_maximum",
+        OrderErrorType::WrongAccountForOrder => "wrong_account_for_order",
+    }
+}
@@ -2,7 +2,7 @@ use alloy::primitives::{B256, U256};
 use fastnum::{D64, D256, UD64, UD128};
 
 use super::{account, order, perpetual, position};
-use crate::{abi::dex::Exchange::OrderRequest, types};
+use crate::{abi::dex::Exchange::OrderRequest, error::DexError, num, types};
 
 /// Exchange state processing events.
 ///
@@ -212,6 +212,10 @@ pub enum OrderEventType {
         fill_price: UD64,
         #[debug("{fill_size}")]
         fill_size: UD64,
+        /// Size left in the book after the fill, zero if the order was removed.
+        /// Known only for maker orders.
+        #[debug("{:?}", remaining_size.map(|v| format!("{v}")))]
+        remaining_size: Option<UD64>,
         #[debug("{fee}")]
         fee: UD64, // Precision of SC calculations is limited to 5 decimals.
         is_maker: bool,
@@ -261,6 +265,11 @@ pub enum PerpetualEventType {
     /// Perpetual contract being added
     Added,
 
+    /// Order book got filled past the near capacity threshold, see
+    /// [`super::Exchange::set_book_capacity_threshold`], with the number of
+    /// orders in the book.
+    BookNearCapacity(usize),
+
     /// Funding event occured and rate updated.
     FundingEvent {
         #[debug("{rate}")]
@@ -298,6 +307,17 @@ pub enum PerpetualEventType {
     /// Oracle price updated.
     OraclePriceUpdated(#[debug("{_0}")] UD64),
 
+    /// Risk or fee parameter of the contract changed, emitted along with the
+    /// parameter-specific event if any, so consumers can re-evaluate positions
+    /// depending on the parameter.
+    ParamChanged {
+        param: PerpetualParam,
+        #[debug("{old}")]
+        old: UD64,
+        #[debug("{new}")]
+        new: UD64,
+    },
+
     /// Perpetual contract paused/unpaused.
     Paused(bool),
 
@@ -305,6 +325,23 @@ pub enum PerpetualEventType {
     TakerFeeUpdated(#[debug("{_0}")] UD64),
 }
 
+/// Risk or fee parameter of perpetual contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PerpetualParam {
+    /// Maker fee, see [`super::Perpetual::maker_fee`].
+    MakerFee,
+    /// Taker fee, see [`super::Perpetual::taker_fee`].
+    TakerFee,
+    /// Initial margin fraction, see [`super::Perpetual::initial_margin`].
+    InitialMargin,
+    /// Maintenance margin fraction, see
+    /// [`super::Perpetual::maintenance_margin`].
+    MaintenanceMargin,
+    /// Max age of oracle/mark prices in seconds, see
+    /// [`super::Perpetual::price_max_age_sec`].
+    PriceMaxAge,
+}
+
 /// Position state mutation event.
 #[derive(Clone, derive_more::Debug)]
 pub struct PositionEvent {
@@ -595,12 +632,12 @@ impl StateEvents {
         })
     }
 
-    pub(crate) fn trade(ctx: &OrderContext, taker_fee: UD64) -> Self {
+    pub(crate) fn trade(ctx: &OrderContext, taker_side: types::OrderSide, taker_fee: UD64) -> Self {
         Self::Trade(types::Trade {
             perpetual_id: ctx.perpetual_id,
             taker_account_id: ctx.account_id,
             taker_request_id: ctx.request_id,
-            taker_side: ctx.r#type.try_side().expect("order type with side"),
+            taker_side,
             taker_fee,
             maker_fills: ctx.maker_fills.clone(),
         })
@@ -608,7 +645,7 @@ impl StateEvents {
 }
 
 /// Order request context.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct OrderContext {
     pub(crate) perpetual_id: types::PerpetualId,
     pub(crate) account_id: types::AccountId,
@@ -616,6 +653,7 @@ pub(crate) struct OrderContext {
     pub(crate) order_id: Option<types::OrderId>,
     pub(crate) r#type: types::RequestType,
     pub(crate) price: U256,
+    pub(crate) lot: U256,
     pub(crate) expiry_block: u64,
     pub(crate) leverage: U256,
     pub(crate) post_only: bool,
@@ -626,22 +664,23 @@ pub(crate) struct OrderContext {
     pub(crate) position_closed_at_log_index: Option<u64>,
 }
 
-impl From<&OrderRequest> for OrderContext {
-    fn from(value: &OrderRequest) -> Self {
-        Self {
-            perpetual_id: value.perpId.to(),
-            account_id: value.accountId.to(),
-            request_id: value.orderDescId.to(),
-            order_id: if value.orderId <= U256::from(u16::MAX) {
-                std::num::NonZeroU16::new(value.orderId.to::<u16>())
-            } else {
-                // Trigger order requests might have order_id > u16::MAX, but they are not
-                // supported by the SDK yet.
-                None
-            },
-            r#type: value.orderType.into(),
+impl TryFrom<&OrderRequest> for OrderContext {
+    type Error = DexError;
+
+    fn try_from(value: &OrderRequest) -> Result<Self, Self::Error> {
+        Ok(Self {
+            perpetual_id: num::narrow(value.perpId, "perpetual id")?,
+            account_id: num::narrow(value.accountId, "account id")?,
+            request_id: num::narrow(value.orderDescId, "request id")?,
+            // Trigger order requests might have order_id > u16::MAX, but they are not
+            // supported by the SDK yet.
+            order_id: u16::try_from(value.orderId)
+                .ok()
+                .and_then(std::num::NonZeroU16::new),
+            r#type: value.orderType.try_into()?,
             price: value.pricePNS,
-            expiry_block: value.expiryBlock.to(),
+            lot: value.lotLNS,
+            expiry_block: num::narrow(value.expiryBlock, "expiry block")?,
             leverage: value.leverageHdths,
             post_only: value.postOnly,
             fill_or_kill: value.fillOrKill,
@@ -649,6 +688,6 @@ impl From<&OrderRequest> for OrderContext {
             maker_fills: vec![],
             clearing_remaining_order: false,
             position_closed_at_log_index: None,
-        }
+        })
     }
 }
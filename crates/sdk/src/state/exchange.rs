@@ -1,39 +1,265 @@
-use std::iter;
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use alloy::primitives::uint;
 use fastnum::{D64, D256, UD64, UD128};
 use itertools::chain;
 
 use super::*;
 use crate::{
     Chain,
-    abi::dex::Exchange::ExchangeEvents,
+    abi::dex::Exchange::{ExchangeCalls, ExchangeEvents},
     stream,
-    types::{EventContext, OrderType},
+    types::{EventContext, OrderType, RequestType},
 };
 
+/// Default fraction of order book capacity to warn about, see
+/// [`Exchange::set_book_capacity_threshold`].
+const DEFAULT_BOOK_CAPACITY_THRESHOLD: f64 = 0.9;
+
+/// ERC-1967 storage slot holding the proxy implementation address.
+const ERC1967_IMPLEMENTATION_SLOT: U256 =
+    uint!(0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc_U256);
+
 pub type StateBlockEvents = types::BlockEvents<types::EventContext<Vec<StateEvents>>>;
 
+/// State events of the block, each paired with the raw event that caused it,
+/// see [`Exchange::apply_events_paired`].
+#[derive(Clone, Debug)]
+pub struct ApplyResult {
+    /// Instant of the applied block.
+    pub instant: types::StateInstant,
+
+    /// Raw events of the block in order, along with state events produced
+    /// from each of them, if any.
+    pub per_event: Vec<(stream::RawEvent, Vec<StateEvents>)>,
+
+    /// State events not caused by any particular raw event: funding settled
+    /// at the start of the block, followed by perpetual contract parameter
+    /// changes propagated to positions at the end of the block.
+    pub block_events: Vec<StateEvents>,
+}
+
 /// Exchange state snapshot.
 ///
 /// [`super::SnapshotBuilder`] can be used to create the snapshot at
 /// specified/latest block, which can then be kept up to date by
 /// calling [`Self::apply_events`] with events from [`crate::stream::raw`].
+///
+/// Cloning is cheap, perpetual contracts and accounts are shared between
+/// clones and copied on the first mutation of a shared clone, so handing
+/// a copy to each of many consumers per block costs at most one copy of
+/// the state.
 #[derive(Clone, derive_more::Debug)]
 pub struct Exchange {
     chain: Chain,
     instant: types::StateInstant,
-    collateral_converter: num::Converter,
+    collateral_converter: num::CollateralConverter,
     funding_interval_blocks: u32,
     #[debug("{min_post}")]
-    min_post: UD128,
+    min_post: num::Collateral,
     #[debug("{min_settle}")]
-    min_settle: UD128,
+    min_settle: num::Collateral,
     #[debug("{recycle_fee}")]
-    recycle_fee: UD128,
-    perpetuals: HashMap<types::PerpetualId, Perpetual>,
-    accounts: HashMap<types::AccountId, Account>,
+    recycle_fee: num::Collateral,
+    perpetuals: CowMap<types::PerpetualId, Perpetual>,
+    accounts: CowMap<types::AccountId, Account>,
+    /// Addresses of accounts removed by [`Self::prune`], restored once the
+    /// accounts are tracked again.
+    pruned_addresses: HashMap<types::AccountId, Address>,
     is_halted: bool,
     track_all_accounts: bool,
+    /// Block being applied event by event, see [`Self::apply_event`].
+    partial_block: Option<PartialBlock>,
+    max_skipped_blocks: u64,
+    balance_history_capacity: usize,
+    book_journal_capacity: usize,
+    book_capacity_threshold: f64,
+    verify_books: bool,
+    tracking: Tracking,
+    #[debug(skip)]
+    audit_sink: Option<Arc<dyn StateAuditSink>>,
+    #[debug(skip)]
+    observers: Vec<Arc<Mutex<StateObserver>>>,
+    rollback_capacity: usize,
+    /// Undo entries of the most recent blocks, oldest first, see
+    /// [`Self::rollback_to`].
+    #[debug(skip)]
+    undo_log: VecDeque<Arc<UndoEntry>>,
+}
+
+/// State preceding an applied block, retained for [`Exchange::rollback_to`].
+#[derive(Clone)]
+struct UndoEntry {
+    /// State preceding the block, without perpetual contracts and accounts.
+    state: Exchange,
+    /// Prior values of the perpetual contracts mutated by the block.
+    perpetuals: Arc<Journal<types::PerpetualId, Perpetual>>,
+    /// Prior values of the accounts mutated by the block.
+    accounts: Arc<Journal<types::AccountId, Account>>,
+    /// Completion of the block by the perpetual contracts it did not mutate
+    /// otherwise.
+    completions: Vec<Completion>,
+}
+
+/// Completion of a block by a perpetual contract, retained instead of its
+/// prior value, see [`Exchange::complete_perpetuals`].
+#[derive(Clone)]
+struct Completion {
+    perpetual_id: types::PerpetualId,
+    /// State instant preceding the block.
+    instant: types::StateInstant,
+    /// Prior state of the orders expired as of the block.
+    expired: Vec<Order>,
+}
+
+/// Callback registered with [`Exchange::on_state_event`].
+type StateObserver = dyn FnMut(&StateEvents) + Send;
+
+/// Progress of the block applied event by event.
+#[derive(Clone, Debug)]
+struct PartialBlock {
+    instant: types::StateInstant,
+    order_context: Option<OrderContext>,
+    tx_index: Option<u64>,
+}
+
+/// Outcome of [`Exchange::simulate_order`].
+#[derive(Clone, Copy, derive_more::Debug, PartialEq, Eq)]
+pub struct OrderSimulation {
+    /// Resulting position size, positive for long and negative for short.
+    #[debug("{position_size}")]
+    pub position_size: D64,
+
+    /// Resulting position deposit.
+    #[debug("{position_deposit}")]
+    pub position_deposit: UD128,
+
+    /// Leverage of the resulting position at the order price.
+    #[debug("{effective_leverage}")]
+    pub effective_leverage: UD64,
+
+    /// Max leverage permitted by the perpetual contract initial margin.
+    #[debug("{max_leverage}")]
+    pub max_leverage: UD64,
+
+    /// Order leverage exceeds [`Self::max_leverage`].
+    pub exceeds_max_leverage: bool,
+
+    /// Resulting position size exceeds the provided limit.
+    pub exceeds_position_limit: bool,
+
+    /// Order price crosses the best price of the opposite side of the book,
+    /// so the order would be matched immediately rather than rest, see
+    /// [`OrderBook::crosses`]. Always `false` if books are not tracked.
+    pub crosses_book: bool,
+}
+
+impl OrderSimulation {
+    /// Indicates if the order should be rejected by any of the checks.
+    pub fn is_rejected(&self) -> bool { self.exceeds_max_leverage || self.exceeds_position_limit }
+}
+
+/// Inconsistency of the account state found by [`Exchange::verify_account`].
+#[derive(Clone, Copy, derive_more::Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// Account is not tracked, nothing else is checked.
+    UnknownAccount,
+
+    /// Balance is locked while the account has no resting orders.
+    LockedWithoutOrders {
+        #[debug("{locked_balance}")]
+        locked_balance: num::Collateral,
+    },
+
+    /// Locked balance does not match the collateral locked by the resting
+    /// orders, see [`Exchange::verify_account`].
+    LockedMismatch {
+        #[debug("{locked_balance}")]
+        locked_balance: num::Collateral,
+        #[debug("{expected}")]
+        expected: num::Collateral,
+    },
+
+    /// Position is held under another account or perpetual contract ID.
+    PositionMismatch(types::PerpetualId),
+
+    /// Position of zero size.
+    EmptyPosition(types::PerpetualId),
+
+    /// Position without deposit.
+    ZeroDeposit(types::PerpetualId),
+}
+
+/// Parts of the exchange state maintained by [`Exchange::apply_events`],
+/// see [`super::SnapshotBuilder::track`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tracking {
+    /// Order books of perpetual contracts, along with order events.
+    ///
+    /// Most expensive part to maintain, consumers only interested in
+    /// positions and balances can skip it. Without books, fills of `Change`
+    /// requests crossing the spread are not reported as trades.
+    pub books: bool,
+
+    /// Accounts and their positions.
+    pub positions: bool,
+
+    /// Trades, see [`StateEvents::Trade`].
+    pub trades: bool,
+}
+
+impl Tracking {
+    /// Tracks the entire state.
+    pub const ALL: Self = Self { books: true, positions: true, trades: true };
+}
+
+impl Default for Tracking {
+    fn default() -> Self { Self::ALL }
+}
+
+/// Compact status of the exchange state, see [`Exchange::summary`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExchangeSummary {
+    /// Instant the state is up to date with.
+    pub instant: types::StateInstant,
+
+    /// Number of tracked perpetual contracts.
+    pub num_perpetuals: usize,
+
+    /// Number of tracked accounts.
+    pub num_accounts: usize,
+
+    /// Number of orders in the books of all perpetual contracts.
+    pub num_orders: usize,
+
+    /// Indicates if exchange is being halted.
+    pub is_halted: bool,
+
+    /// Wall-clock time elapsed since the block of [`Self::instant`].
+    pub lag: Duration,
+}
+
+/// Serialized as a flat map, with the instant as `block_number` and
+/// `block_timestamp` and the lag as `lag_secs`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ExchangeSummary {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("ExchangeSummary", 7)?;
+        state.serialize_field("block_number", &self.instant.block_number())?;
+        state.serialize_field("block_timestamp", &self.instant.block_timestamp())?;
+        state.serialize_field("num_perpetuals", &self.num_perpetuals)?;
+        state.serialize_field("num_accounts", &self.num_accounts)?;
+        state.serialize_field("num_orders", &self.num_orders)?;
+        state.serialize_field("is_halted", &self.is_halted)?;
+        state.serialize_field("lag_secs", &self.lag.as_secs())?;
+        state.end()
+    }
 }
 
 impl Exchange {
@@ -41,11 +267,11 @@ impl Exchange {
     pub(crate) fn new(
         chain: Chain,
         instant: types::StateInstant,
-        collateral_converter: num::Converter,
+        collateral_converter: num::CollateralConverter,
         funding_interval_blocks: u32,
-        min_post: UD128,
-        min_settle: UD128,
-        recycle_fee: UD128,
+        min_post: num::Collateral,
+        min_settle: num::Collateral,
+        recycle_fee: num::Collateral,
         perpetuals: HashMap<types::PerpetualId, Perpetual>,
         accounts: HashMap<types::AccountId, Account>,
         is_halted: bool,
@@ -59,25 +285,317 @@ impl Exchange {
             min_post,
             min_settle,
             recycle_fee,
-            perpetuals,
-            accounts,
+            perpetuals: CowMap::new(perpetuals),
+            accounts: CowMap::new(accounts),
+            pruned_addresses: HashMap::new(),
             is_halted,
             track_all_accounts,
+            partial_block: None,
+            max_skipped_blocks: 0,
+            balance_history_capacity: 0,
+            book_journal_capacity: 0,
+            book_capacity_threshold: DEFAULT_BOOK_CAPACITY_THRESHOLD,
+            verify_books: false,
+            tracking: Tracking::ALL,
+            audit_sink: None,
+            observers: vec![],
+            rollback_capacity: 0,
+            undo_log: VecDeque::new(),
         }
     }
 
     /// Revision of the exchange smart contract the SDK targeted at.
     pub const fn revision() -> &'static str { crate::abi::DEX_REVISION }
 
+    /// Checks that the exchange contract deployed on `chain` matches the ABI
+    /// of [`Self::revision`] the SDK was built against.
+    ///
+    /// The contract does not expose its revision, so the check resolves the
+    /// ERC-1967 implementation behind the exchange proxy and verifies its
+    /// code dispatches every function selector known to the SDK. A mismatch
+    /// is logged as a warning and reported as [`DexError::RevisionMismatch`].
+    pub async fn check_revision<P: Provider>(chain: &Chain, provider: &P) -> Result<(), DexError> {
+        let (implementation, code) =
+            Self::implementation_code(chain, provider, BlockId::latest()).await?;
+        let missing = ExchangeCalls::SELECTORS
+            .iter()
+            .filter(|sel| !code.windows(sel.len()).any(|w| w == sel.as_slice()))
+            .count();
+        if missing > 0 {
+            tracing::warn!(
+                revision = Self::revision(),
+                %implementation,
+                missing,
+                "deployed exchange contract does not match SDK ABI revision",
+            );
+            return Err(DexError::RevisionMismatch(Self::revision(), missing));
+        }
+        Ok(())
+    }
+
+    /// Address and code of the exchange contract implementation as of the
+    /// `block`, resolving the ERC-1967 proxy if any.
+    pub(crate) async fn implementation_code<P: Provider>(
+        chain: &Chain,
+        provider: &P,
+        block: BlockId,
+    ) -> Result<(Address, alloy::primitives::Bytes), DexError> {
+        let slot = provider
+            .get_storage_at(chain.exchange(), ERC1967_IMPLEMENTATION_SLOT)
+            .block_id(block)
+            .await
+            .map_err(ProviderError::from)?;
+        let implementation = if slot.is_zero() {
+            chain.exchange()
+        } else {
+            Address::from_word(slot.into())
+        };
+        let code = provider
+            .get_code_at(implementation)
+            .block_id(block)
+            .await
+            .map_err(ProviderError::from)?;
+        Ok((implementation, code))
+    }
+
     /// Chain the snapshot collected from.
     pub fn chain(&self) -> &Chain { &self.chain }
 
     /// Instant the snapshot is consistent with or was last updated at.
     pub fn instant(&self) -> types::StateInstant { self.instant }
 
+    /// Immutable point-in-time copy of the snapshot, see
+    /// [`SharedExchange::freeze`] for shared snapshots.
+    ///
+    /// The snapshot is copied only once it gets mutated while the view is
+    /// alive, clones of the returned view are cheap.
+    pub fn freeze(&self) -> FrozenExchange { FrozenExchange(Arc::new(self.clone())) }
+
+    /// Sets the sink receiving each state change applied by
+    /// [`Self::apply_events`] and [`Self::apply_event`] along with its
+    /// provenance, or removes it with `None`.
+    ///
+    /// Order request errors and trades are not state changes and are not
+    /// passed to the sink.
+    pub fn set_audit_sink(&mut self, sink: Option<Arc<dyn StateAuditSink>>) {
+        self.audit_sink = sink;
+    }
+
+    /// Registers the `observer` called synchronously with each state event
+    /// produced by [`Self::apply_events`] and [`Self::apply_event`], in the
+    /// order events are returned, including order request errors and trades.
+    ///
+    /// Multiple observers are called in the order of registration. Observers
+    /// are shared with clones of the snapshot.
+    pub fn on_state_event(&mut self, observer: impl FnMut(&StateEvents) + Send + 'static) {
+        self.observers.push(Arc::new(Mutex::new(observer)));
+    }
+
+    /// Number of blocks allowed to be skipped between consecutive blocks
+    /// applied by [`Self::apply_events`] and [`Self::apply_event`].
+    pub fn max_skipped_blocks(&self) -> u64 { self.max_skipped_blocks }
+
+    /// Sets the number of blocks allowed to be skipped between consecutive
+    /// blocks applied, zero by default.
+    ///
+    /// [`crate::stream::raw`] produces contiguous blocks, so skipped blocks
+    /// indicate missed events unless the consumer drops blocks known to be
+    /// empty, in which case the tolerance should cover the longest run of
+    /// such blocks.
+    pub fn set_max_skipped_blocks(&mut self, max_skipped_blocks: u64) {
+        self.max_skipped_blocks = max_skipped_blocks;
+    }
+
+    /// Number of the most recent balance changes retained by each account,
+    /// see [`Account::balance_history`].
+    pub fn balance_history_capacity(&self) -> usize { self.balance_history_capacity }
+
+    /// Sets the number of the most recent balance changes retained by each
+    /// account, including accounts created later, zero (disabled) by default.
+    pub fn set_balance_history_capacity(&mut self, capacity: usize) {
+        self.balance_history_capacity = capacity;
+        for acc in self.accounts.values_mut() {
+            acc.set_balance_history_capacity(capacity);
+        }
+    }
+
+    /// Number of the most recent changes retained by the order book journal
+    /// of each perpetual contract, see [`OrderBook::as_of`].
+    pub fn book_journal_capacity(&self) -> usize { self.book_journal_capacity }
+
+    /// Sets the number of the most recent changes retained by the order book
+    /// journal of each perpetual contract, including contracts added later,
+    /// zero (disabled) by default.
+    ///
+    /// Enabled journal keeps a copy of the book preceding the retained
+    /// changes, so [`OrderBook::as_of`] can reconstruct the book as of any
+    /// block within them.
+    pub fn set_book_journal_capacity(&mut self, capacity: usize) {
+        self.book_journal_capacity = capacity;
+        for perp in self.perpetuals.values_mut() {
+            perp.set_book_journal_capacity(capacity);
+        }
+    }
+
+    /// Fraction of order book capacity [`PerpetualEventType::BookNearCapacity`]
+    /// is emitted at.
+    pub fn book_capacity_threshold(&self) -> f64 { self.book_capacity_threshold }
+
+    /// Sets the fraction of [`OrderBook::CAPACITY`] (default: 0.9) to emit
+    /// [`PerpetualEventType::BookNearCapacity`] at, once the order book of a
+    /// perpetual contract gets filled past it.
+    pub fn set_book_capacity_threshold(&mut self, threshold: f64) {
+        self.book_capacity_threshold = threshold;
+    }
+
+    /// Indicates if cached order book level sizes are verified after each
+    /// applied block.
+    pub fn verify_books(&self) -> bool { self.verify_books }
+
+    /// Enables verification of cached order book level sizes after each
+    /// applied block in debug builds, see [`OrderBook::verify_sizes`],
+    /// disabled by default.
+    ///
+    /// Diagnostic, walks all orders of all books per block, so it is meant
+    /// for tests rather than production.
+    pub fn set_verify_books(&mut self, verify_books: bool) { self.verify_books = verify_books; }
+
+    /// Number of the most recent blocks [`Self::rollback_to`] can revert.
+    pub fn rollback_capacity(&self) -> usize { self.rollback_capacity }
+
+    /// Sets the number of the most recent blocks [`Self::rollback_to`] can
+    /// revert, zero (disabled) by default.
+    ///
+    /// Rather than copying the state, each applied block retains the prior
+    /// values of the perpetual contracts and accounts it mutated. Perpetual
+    /// contracts only advanced to the block retain their prior state instant
+    /// and the orders expired as of the block.
+    pub fn set_rollback_capacity(&mut self, capacity: usize) {
+        self.rollback_capacity = capacity;
+        while self.undo_log.len() > capacity {
+            self.undo_log.pop_front();
+        }
+        if capacity == 0 {
+            self.perpetuals.journal = None;
+            self.accounts.journal = None;
+        }
+    }
+
+    /// Reverts the snapshot to the state as of the block at `instant`, e.g.
+    /// the last block common with the new fork once a reorg is detected, so
+    /// the blocks of the new fork can be applied on top of it.
+    ///
+    /// No-op if the snapshot is not past the block. Fails with
+    /// [`DexError::RollbackUnavailable`] if the block is older than the
+    /// blocks retained, see [`Self::set_rollback_capacity`].
+    pub fn rollback_to(&mut self, instant: types::StateInstant) -> Result<(), DexError> {
+        let block_number = instant.block_number();
+        if self.instant.block_number() <= block_number {
+            return Ok(());
+        }
+        let Some(idx) = self
+            .undo_log
+            .iter()
+            .rposition(|entry| entry.state.instant.block_number() <= block_number)
+        else {
+            return Err(DexError::RollbackUnavailable(block_number));
+        };
+        let mut undo_log = std::mem::take(&mut self.undo_log);
+        if let Some(entry) = undo_log.back_mut().map(Arc::make_mut) {
+            entry.perpetuals = self.perpetuals.restart_journal().unwrap_or_default();
+            entry.accounts = self.accounts.restart_journal().unwrap_or_default();
+        }
+        let mut state = loop {
+            let entry = Arc::unwrap_or_clone(undo_log.pop_back().unwrap());
+            // Completions follow the mutations of the block, so revert first
+            for completion in entry.completions.iter().rev() {
+                if let Some(perp) = self
+                    .perpetuals
+                    .get_mut_unjournaled(&completion.perpetual_id)
+                {
+                    perp.revert_state_instant(completion.instant, &completion.expired);
+                }
+            }
+            self.perpetuals.revert(entry.perpetuals);
+            self.accounts.revert(entry.accounts);
+            if undo_log.len() == idx {
+                break entry.state;
+            }
+        };
+        // The restored state follows the block of the preceding entry, so
+        // its journals resume recording the mutations
+        if let Some(entry) = undo_log.back() {
+            self.perpetuals.journal = Some(entry.perpetuals.clone());
+            self.accounts.journal = Some(entry.accounts.clone());
+        }
+        state.perpetuals = std::mem::take(&mut self.perpetuals);
+        state.accounts = std::mem::take(&mut self.accounts);
+        state.undo_log = undo_log;
+        state.max_skipped_blocks = self.max_skipped_blocks;
+        state.book_capacity_threshold = self.book_capacity_threshold;
+        state.verify_books = self.verify_books;
+        state.tracking = self.tracking;
+        state.rollback_capacity = self.rollback_capacity;
+        state.audit_sink = self.audit_sink.take();
+        state.observers = std::mem::take(&mut self.observers);
+        if state.balance_history_capacity != self.balance_history_capacity {
+            state.set_balance_history_capacity(self.balance_history_capacity);
+        }
+        if state.book_journal_capacity != self.book_journal_capacity {
+            state.set_book_journal_capacity(self.book_journal_capacity);
+        }
+        *self = state;
+        Ok(())
+    }
+
+    /// Numbers of perpetual contracts and accounts journaled for
+    /// [`Self::rollback_to`], along with the number of orders retained to
+    /// revert their expiry.
+    #[cfg(test)]
+    pub(crate) fn undo_log_len(&self) -> (usize, usize, usize) {
+        let current = (
+            self.perpetuals
+                .journal
+                .as_ref()
+                .map_or(0, |journal| journal.len()),
+            self.accounts
+                .journal
+                .as_ref()
+                .map_or(0, |journal| journal.len()),
+        );
+        let (perpetuals, accounts) =
+            self.undo_log
+                .iter()
+                .fold(current, |(perpetuals, accounts), entry| {
+                    (perpetuals + entry.perpetuals.len(), accounts + entry.accounts.len())
+                });
+        let expired = self
+            .undo_log
+            .iter()
+            .flat_map(|entry| &entry.completions)
+            .map(|completion| completion.expired.len())
+            .sum();
+        (perpetuals, accounts, expired)
+    }
+
+    /// Parts of the state maintained while applying events.
+    pub fn tracking(&self) -> Tracking { self.tracking }
+
+    pub(crate) fn with_tracking(mut self, tracking: Tracking) -> Self {
+        self.tracking = tracking;
+        self
+    }
+
     /// Converter of fixed-point <-> decimal numbers for collateral token
     /// amounts.
-    pub fn collateral_converter(&self) -> num::Converter { self.collateral_converter }
+    pub fn collateral_converter(&self) -> num::CollateralConverter { self.collateral_converter }
+
+    /// Display configuration rendering collateral token amounts, e.g. account
+    /// balances, with the decimals of the collateral token, to be applied
+    /// with [`num::DisplayConfig::scoped`].
+    pub fn display_config(&self) -> num::DisplayConfig {
+        num::DisplayConfig::current().with_amount_decimals(self.collateral_converter.decimals())
+    }
 
     /// Funding interval in blocks.
     ///
@@ -86,36 +604,284 @@ impl Exchange {
     pub fn funding_interval_blocks(&self) -> u32 { self.funding_interval_blocks }
 
     /// Minimal amount in collateral token that can be posted to the book.
-    pub fn min_post(&self) -> UD128 { self.min_post }
+    pub fn min_post(&self) -> num::Collateral { self.min_post }
 
     /// Minimal amount in collateral token that can be settled.
-    pub fn min_settle(&self) -> UD128 { self.min_settle }
+    pub fn min_settle(&self) -> num::Collateral { self.min_settle }
 
     /// Amount in collateral token locked with each posted order to
     /// pay the account that cleans it up:
     /// * When cancelled/changed by the original poster -> the original poster
     /// * When filled -> the original poster
     /// * In all other cases -> the one that performed the recycling
-    pub fn recycle_fee(&self) -> UD128 { self.recycle_fee }
+    pub fn recycle_fee(&self) -> num::Collateral { self.recycle_fee }
 
     /// Perpetual contracts state tracked within the exchange, according to
     /// initial snapshot building configuration.
-    pub fn perpetuals(&self) -> &HashMap<types::PerpetualId, Perpetual> { &self.perpetuals }
+    ///
+    /// Shared with the clones of the exchange until mutated.
+    pub fn perpetuals(&self) -> &HashMap<types::PerpetualId, Arc<Perpetual>> { &self.perpetuals }
 
     /// Accounts state tracked within the exchange, according to initial
     /// snapshot building configuration.
-    pub fn accounts(&self) -> &HashMap<types::AccountId, Account> { &self.accounts }
+    ///
+    /// Shared with the clones of the exchange until mutated.
+    pub fn accounts(&self) -> &HashMap<types::AccountId, Arc<Account>> { &self.accounts }
+
+    /// Realized PnL of the account from the trade, see
+    /// [`types::Trade::realized_pnl`], taking the account position tracked by
+    /// the snapshot as the position before the trade.
+    ///
+    /// Trades are applied to positions along with the rest of the block
+    /// events, so it should be called before the block is applied.
+    pub fn trade_pnl(&self, trade: &types::Trade, account_id: types::AccountId) -> Option<D256> {
+        let position = self.accounts.get(&account_id)?.positions().get(&trade.perpetual_id)?;
+        trade.realized_pnl(account_id, position)
+    }
+
+    /// Simulates full fill of the order request of the account at the order
+    /// price, checking it against the max leverage permitted by the
+    /// perpetual contract initial margin and the optional position size
+    /// limit.
+    ///
+    /// Reducing the position releases its deposit proportionally, while
+    /// increasing or flipping it deposits margin at the order leverage.
+    /// Book liquidity, fees and funding are not taken into account.
+    pub fn simulate_order(
+        &self,
+        account_id: types::AccountId,
+        request: &types::OrderRequest,
+        max_position_size: Option<UD64>,
+    ) -> Result<OrderSimulation, DexError> {
+        let perpetual_id = request.perpetual_id();
+        let perp = self.perpetuals.get(&perpetual_id).ok_or_else(|| {
+            DexError::InvalidArgument(format!("unknown perpetual: {perpetual_id}"))
+        })?;
+        let side = request.r#type().try_side().ok_or_else(|| {
+            DexError::InvalidArgument(format!("not an order request: {}", request.r#type()))
+        })?;
+
+        let (price, leverage) = (request.price(), request.leverage());
+        let (size, deposit) = self
+            .accounts
+            .get(&account_id)
+            .and_then(|acc| acc.positions().get(&perpetual_id))
+            .map_or((D64::ZERO, UD128::ZERO), |pos| {
+                let size = pos.size().get().to_signed();
+                (if pos.r#type().is_long() { size } else { -size }, pos.deposit().get())
+            });
+        let delta = match side {
+            types::OrderSide::Bid => request.size().get().to_signed(),
+            types::OrderSide::Ask => -request.size().get().to_signed(),
+        };
+        let new_size = size + delta;
+
+        let increases = size.is_zero() || size.is_negative() == delta.is_negative();
+        let flips =
+            !increases && !new_size.is_zero() && new_size.is_negative() != size.is_negative();
+        if (increases || flips) && leverage.is_zero() {
+            return Err(DexError::InvalidArgument("zero order leverage".to_string()));
+        }
+        let notional = |size: UD64| -> UD128 { price.get().resize() * size.resize() };
+        let new_deposit = if increases {
+            deposit + notional(delta.unsigned_abs()) / leverage.resize()
+        } else if flips {
+            notional(new_size.unsigned_abs()) / leverage.resize()
+        } else {
+            let (new_size, size): (UD128, UD128) =
+                (new_size.unsigned_abs().resize(), size.unsigned_abs().resize());
+            deposit * new_size / size
+        };
+
+        let max_leverage = perp.initial_margin();
+        let effective_leverage = if new_deposit.is_zero() {
+            UD64::ZERO
+        } else {
+            (notional(new_size.unsigned_abs()) / new_deposit).resize()
+        };
+        Ok(OrderSimulation {
+            position_size: new_size,
+            position_deposit: new_deposit,
+            effective_leverage,
+            max_leverage,
+            exceeds_max_leverage: (increases || flips) && leverage > max_leverage,
+            exceeds_position_limit: max_position_size
+                .is_some_and(|limit| new_size.unsigned_abs() > limit),
+            crosses_book: perp.l3_book().crosses(side, price),
+        })
+    }
+
+    /// Checks the state of the account for inconsistencies caused by bugs in
+    /// event handling, e.g. locked balance not matching resting orders.
+    ///
+    /// Each resting order is expected to lock the recycle fee, along with the
+    /// margin of `price * size / leverage` for opening orders, give or take a
+    /// unit of the collateral token precision per order for rounding.
+    ///
+    /// Diagnostic, scans order books of all perpetual contracts. Orders are
+    /// checked only if books are tracked, see [`Self::tracking`], and excess
+    /// of the locked balance only if all perpetual contracts are tracked, as
+    /// the rest of them could hold orders of the account.
+    pub fn verify_account(&self, account_id: types::AccountId) -> Vec<Inconsistency> {
+        let Some(acc) = self.accounts.get(&account_id) else {
+            return vec![Inconsistency::UnknownAccount];
+        };
+        let mut inconsistencies = vec![];
+
+        if self.tracking.books {
+            let (num_orders, expected) = self
+                .perpetuals
+                .values()
+                .flat_map(|perp| perp.l3_book().all_orders().values())
+                .filter(|order| order.account_id() == account_id)
+                .fold((0u64, num::Collateral::ZERO), |(count, locked), order| {
+                    (count + 1, locked + self.order_locked(order))
+                });
+            let locked_balance = acc.locked_balance();
+            let all_tracked = self
+                .chain
+                .perpetuals()
+                .iter()
+                .all(|perp_id| self.perpetuals.contains_key(perp_id));
+            let tolerance = self.collateral_converter.from_u64(num_orders);
+            if num_orders == 0 {
+                if !locked_balance.is_zero() && all_tracked {
+                    inconsistencies.push(Inconsistency::LockedWithoutOrders { locked_balance });
+                }
+            } else if locked_balance + tolerance < expected
+                || (all_tracked && locked_balance > expected + tolerance)
+            {
+                inconsistencies.push(Inconsistency::LockedMismatch { locked_balance, expected });
+            }
+        }
+
+        for (perp_id, pos) in acc.positions() {
+            if pos.account_id() != account_id || pos.perpetual_id() != *perp_id {
+                inconsistencies.push(Inconsistency::PositionMismatch(*perp_id));
+            }
+            if pos.size().is_zero() {
+                inconsistencies.push(Inconsistency::EmptyPosition(*perp_id));
+            } else if pos.deposit().is_zero() {
+                inconsistencies.push(Inconsistency::ZeroDeposit(*perp_id));
+            }
+        }
+        inconsistencies
+    }
+
+    /// Collateral locked by the resting `order`, see [`Self::verify_account`].
+    fn order_locked(&self, order: &Order) -> num::Collateral {
+        let opens =
+            matches!(order.r#type(), types::OrderType::OpenLong | types::OrderType::OpenShort);
+        let margin = if opens && !order.leverage().is_zero() {
+            num::Collateral::new((order.price() * order.size()).get() / order.leverage().resize())
+        } else {
+            num::Collateral::ZERO
+        };
+        self.recycle_fee + margin
+    }
 
     /// Indicates if exchange is being halted.
     pub fn is_halted(&self) -> bool { self.is_halted }
 
+    /// Compact status of the state, e.g. for health endpoints, with the lag
+    /// measured as of the `clock`.
+    ///
+    /// Cheap to compute, aggregates counts of the tracked entities without
+    /// traversing the books.
+    pub fn summary(&self, clock: &impl types::Clock) -> ExchangeSummary {
+        ExchangeSummary {
+            instant: self.instant,
+            num_perpetuals: self.perpetuals.len(),
+            num_accounts: self.accounts.len(),
+            num_orders: self.perpetuals.values().map(|perp| perp.total_orders()).sum(),
+            is_halted: self.is_halted,
+            lag: Duration::from_secs(clock.now().saturating_sub(self.instant.block_timestamp())),
+        }
+    }
+
+    /// Approximate memory footprint of the snapshot, in bytes.
+    ///
+    /// Accounts for the snapshot itself, tracked accounts with their
+    /// positions, perpetual contracts with their orders and book levels.
+    /// Not exact, but cheap to compute and proportional to the actual memory
+    /// usage, so can be used for monitoring.
+    pub fn approximate_memory_bytes(&self) -> usize {
+        use std::mem::{size_of, size_of_val};
+        size_of::<Self>()
+            + size_of_val(self.chain.perpetuals())
+            + self.perpetuals.capacity() * size_of::<(types::PerpetualId, Arc<Perpetual>)>()
+            + self
+                .perpetuals
+                .values()
+                .map(|perp| size_of::<Perpetual>() + perp.approximate_heap_bytes())
+                .sum::<usize>()
+            + self.accounts.capacity() * size_of::<(types::AccountId, Arc<Account>)>()
+            + self
+                .accounts
+                .values()
+                .map(|acc| size_of::<Account>() + acc.approximate_heap_bytes())
+                .sum::<usize>()
+            + self.pruned_addresses.capacity() * size_of::<(types::AccountId, Address)>()
+    }
+
+    /// Removes empty entries accumulated by a long-running snapshot:
+    /// * Accounts with zero balance, no positions and no orders, unless
+    ///   accounts were explicitly requested via
+    ///   [`SnapshotBuilder::with_accounts`]. Pruned accounts get tracked again
+    ///   once they are referenced by future events, only their address is
+    ///   kept in the meantime.
+    /// * Empty order book price levels.
+    ///
+    /// Tracked perpetual contracts are never removed.
+    ///
+    /// # Returns
+    ///
+    /// Number of pruned accounts and book levels.
+    pub fn prune(&mut self) -> (usize, usize) {
+        let num_accounts = if self.track_all_accounts {
+            let with_orders: HashSet<types::AccountId> = self
+                .perpetuals
+                .values()
+                .flat_map(|perp| perp.l3_book().all_orders().values())
+                .map(|order| order.account_id())
+                .collect();
+            let num_accounts = self.accounts.len();
+            let pruned_addresses = &mut self.pruned_addresses;
+            self.accounts.retain(|id, acc| {
+                let keep = !acc.balance().is_zero()
+                    || !acc.locked_balance().is_zero()
+                    || !acc.positions().is_empty()
+                    || with_orders.contains(id);
+                if !keep {
+                    pruned_addresses.insert(*id, acc.address());
+                }
+                keep
+            });
+            let num_pruned = num_accounts - self.accounts.len();
+            if num_pruned > 0 {
+                self.accounts.shrink_to_fit();
+            }
+            num_pruned
+        } else {
+            0
+        };
+        let num_levels = self
+            .perpetuals
+            .values_mut()
+            .map(Perpetual::compact_book)
+            .sum();
+        (num_accounts, num_levels)
+    }
+
     /// Updates state snapshot by applying raw exchange events from the
     /// specific block.
     ///
     /// Blocks expected to arrive strictly in-order, with already applied blocks
     /// being ignored, to enforce state consistency as most raw events
     /// provide only incremental state update information rather than full
-    /// piece of state snapshot.
+    /// piece of state snapshot. Skipping a block fails with
+    /// [`DexError::BlockGap`], unless allowed with
+    /// [`Self::set_max_skipped_blocks`].
     ///
     /// Exchange emits two categories of events:
     /// * State mutation events
@@ -147,23 +913,70 @@ impl Exchange {
     ///
     /// On failure, the corresponding [`DexError`], any of which indicates some
     /// inconsistency in event sequence or event handling logic and should
-    /// not be ignored as it may lead to state inconsistency.
+    /// not be ignored as it may lead to state inconsistency. Malformed event
+    /// data, e.g. unknown order type, zero or out of range ID, is reported as
+    /// [`DexError::MalformedData`] rather than panicking.
     pub fn apply_events(
         &mut self,
         events: &stream::RawBlockEvents,
     ) -> Result<Option<StateBlockEvents>, DexError> {
+        let applied = self.apply_block(events)?;
+        Ok(applied.map(|(state_events, _)| state_events))
+    }
+
+    /// Same as [`Self::apply_events`], but pairs each raw event with the
+    /// state events it produced, so consumers don't have to correlate them
+    /// by transaction and log indexes.
+    pub fn apply_events_paired(
+        &mut self,
+        events: &stream::RawBlockEvents,
+    ) -> Result<Option<ApplyResult>, DexError> {
+        let Some((state_events, raw_range)) = self.apply_block(events)? else {
+            return Ok(None);
+        };
+        let mut block_events = vec![];
+        let mut by_raw_event = vec![];
+        for (idx, ctx) in state_events.events().iter().enumerate() {
+            if raw_range.contains(&idx) {
+                by_raw_event.push(ctx);
+            } else {
+                block_events.extend(ctx.event().iter().cloned());
+            }
+        }
+
+        let mut by_raw_event = by_raw_event.into_iter().peekable();
+        let per_event = events
+            .events()
+            .iter()
+            .map(|event| {
+                let produced = by_raw_event
+                    .next_if(|ctx| {
+                        ctx.tx_index() == event.tx_index() && ctx.log_index() == event.log_index()
+                    })
+                    .map(|ctx| ctx.event().clone())
+                    .unwrap_or_default();
+                (event.clone(), produced)
+            })
+            .collect();
+        Ok(Some(ApplyResult { instant: state_events.instant(), per_event, block_events }))
+    }
+
+    /// Applies the block, returning its state events along with the range of
+    /// them produced from raw events, as opposed to funding and fan-out.
+    fn apply_block(
+        &mut self,
+        events: &stream::RawBlockEvents,
+    ) -> Result<Option<(StateBlockEvents, std::ops::Range<usize>)>, DexError> {
+        // Finalize block applied event by event, if any, so it is not applied again
+        self.complete_block();
+
         let next_instant = events.instant();
         if self.instant >= next_instant {
             // Block already applied
             return Ok(None);
         }
-        if self.instant.block_number() + 1 < next_instant.block_number() {
-            // Block arrived out of order
-            return Err(DexError::BlockOutOfOrder(
-                self.instant.block_number() + 1,
-                next_instant.block_number(),
-            ));
-        }
+        self.check_block_gap(next_instant)?;
+        self.save_undo_state();
 
         // apply_events runs three passes over the block:
         //   Pass 1 — funding:    settle the block's scheduled funding on each position's
@@ -175,48 +988,17 @@ impl Exchange {
         //                        maintenance-margin-fraction change) out to every tracked position.
         let mut order_context: Option<OrderContext> = None;
         let mut prev_tx_index: Option<u64> = None;
-        let mut state_events = vec![];
         let mut perp_events = vec![];
 
-        // Pass 1 — funding: the contract settles a funding-event block at the new funding sum
-        // regardless of same-block decreases, so funding must land on each position's PRE-event
-        // size, before the block's size-changing events. This is the only place funding is
-        // applied.
-        let funding_due: Vec<(types::PerpetualId, D64, D256)> = self
-            .perpetuals
-            .values_mut()
-            .filter_map(|perp| {
-                perp.take_funding_payment(next_instant)
-                    .map(|(rate, payment)| (perp.id(), rate, payment))
-            })
-            .collect();
-        for (perp_id, rate, payment) in funding_due {
-            let mut funding_events = vec![];
-            if let Some(perp) = self.perpetuals.get(&perp_id) {
-                funding_events.push(StateEvents::perpetual(
-                    perp,
-                    PerpetualEventType::FundingEvent { rate, payment_per_unit: payment },
-                ));
-            }
-            for acc in self.accounts.values_mut() {
-                if let Some(pos) = acc.positions_mut().get_mut(&perp_id)
-                    && pos.apply_funding_payment(next_instant, payment)
-                {
-                    funding_events.push(StateEvents::position(
-                        pos,
-                        &None,
-                        PositionEventType::UnrealizedPnLUpdated {
-                            pnl: pos.pnl(),
-                            delta_pnl: pos.delta_pnl(),
-                            premium_pnl: pos.premium_pnl(),
-                        },
-                    ));
-                }
-            }
-            if !funding_events.is_empty() {
-                state_events.push(EventContext::empty(funding_events));
-            }
-        }
+        // Pass 1 — funding, see `apply_funding`.
+        let mut state_events = self
+            .apply_funding(next_instant)
+            .into_iter()
+            .map(|events| EventContext::empty(events).with_block(next_instant.block_number()))
+            .collect::<Vec<_>>();
+        let num_funding_events = state_events.len();
+        // At most one context per raw event
+        state_events.reserve(events.events().len());
 
         // Pass 2 — raw events: apply the block's on-chain events in order, keeping incremental
         // order context across events within a transaction.
@@ -236,27 +1018,305 @@ impl Exchange {
                 if !block_perp_events.is_empty() {
                     perp_events.push(block_perp_events);
                 }
-                state_events.push(event.pass(result));
+                state_events.push(event.pass(result).with_block(next_instant.block_number()));
             }
             prev_tx_index = Some(event.tx_index());
         }
 
+        let raw_range = num_funding_events..state_events.len();
+
         // Commit the instant: advance each perpetual's state instant and expire stale orders.
         self.instant = events.instant();
-        for perp in self.perpetuals.values_mut() {
-            perp.update_state_instant(self.instant);
-        }
+        self.complete_perpetuals(self.instant);
 
         // Pass 3 — fan-out: apply the perpetual-parameter changes set aside in Pass 2 (e.g. a
         // maintenance-margin-fraction change) to every tracked position.
         for event in perp_events.iter().flatten() {
             let result = self.apply_state_event(self.instant, event)?;
             if !result.is_empty() {
-                state_events.push(EventContext::empty(result));
+                state_events.push(
+                    EventContext::empty(result).with_block(self.instant.block_number()),
+                );
             }
         }
 
-        Ok(Some(StateBlockEvents::new(self.instant, state_events)))
+        debug_assert!(
+            !self.verify_books
+                || self.perpetuals.values().all(|perp| perp.l3_book().verify_sizes()),
+            "cached book level sizes drifted at {}",
+            self.instant
+        );
+
+        for ctx_events in &state_events {
+            self.audit(self.instant, ctx_events, ctx_events.event());
+            self.notify(ctx_events.event());
+        }
+
+        Ok(Some((StateBlockEvents::new(self.instant, state_events), raw_range)))
+    }
+
+    /// Updates state snapshot by applying a single raw exchange event from
+    /// the block at `instant`, ahead of the whole block being available.
+    ///
+    /// Intended for low-latency consumers processing logs as they arrive,
+    /// e.g. via WebSocket subscriptions. Events are expected to be applied in
+    /// their exact on-chain order, without gaps, which is the responsibility
+    /// of the caller. Events from already applied blocks are ignored, and
+    /// blocks are expected to arrive strictly in-order, same as with
+    /// [`Self::apply_events`].
+    ///
+    /// Funding scheduled for the block is applied along with its first event,
+    /// and [`Self::instant`] moves to the block right away. Perpetual contract
+    /// parameter changes are propagated to the tracked positions immediately
+    /// rather than after the whole block.
+    ///
+    /// # Partial block state
+    ///
+    /// Until all events of the block are applied the snapshot reflects only
+    /// a part of the block, which may be inconsistent with any state observed
+    /// on-chain, e.g. a taker order could be matched against part of the makers
+    /// only. Once the last event of the block is applied, call
+    /// [`Self::complete_block`] to finalize it; it is also finalized implicitly
+    /// by events of the next block or by [`Self::apply_events`], which ignores
+    /// the block already applied event by event. Missing any event of the
+    /// block leaves the snapshot inconsistent with no way to recover it other
+    /// than taking a new snapshot.
+    pub fn apply_event(
+        &mut self,
+        event: &stream::RawEvent,
+        instant: types::StateInstant,
+    ) -> Result<Vec<StateEvents>, DexError> {
+        let mut state_events = vec![];
+        let mut block = match self.partial_block.take() {
+            Some(block) if block.instant == instant => block,
+            partial_block => {
+                self.partial_block = partial_block;
+                if self.instant >= instant {
+                    // Block already applied
+                    return Ok(vec![]);
+                }
+                self.check_block_gap(instant)?;
+                self.complete_block();
+                self.save_undo_state();
+                state_events.extend(self.apply_funding(instant).into_iter().flatten());
+                self.audit(instant, &EventContext::empty(()), &state_events);
+                self.notify(&state_events);
+                self.instant = instant;
+                PartialBlock { instant, order_context: None, tx_index: None }
+            },
+        };
+
+        if block.tx_index.is_some_and(|idx| idx < event.tx_index()) {
+            // Reset order context at the transaction boundary
+            block.order_context.take();
+        }
+        block.tx_index = Some(event.tx_index());
+        let result = self.apply_raw_event(instant, event, &mut block.order_context);
+        self.partial_block = Some(block);
+
+        let num_funding_events = state_events.len();
+        for state_event in result? {
+            let fan_out = self.apply_state_event(instant, &state_event)?;
+            state_events.push(state_event);
+            state_events.extend(fan_out);
+        }
+        self.audit(instant, event, &state_events[num_funding_events..]);
+        self.notify(&state_events[num_funding_events..]);
+        Ok(state_events)
+    }
+
+    /// Finalizes the block applied event by event with [`Self::apply_event`],
+    /// expiring orders as of the block.
+    ///
+    /// No-op if there is no such block.
+    pub fn complete_block(&mut self) {
+        if let Some(block) = self.partial_block.take() {
+            self.complete_perpetuals(block.instant);
+        }
+    }
+
+    /// Advances all perpetual contracts to the completed block at `instant`,
+    /// expiring their orders.
+    ///
+    /// Perpetual contracts not mutated by the block otherwise are not
+    /// journaled, instead the undo entry of the block retains their prior
+    /// state instant and orders expired, so rollback doesn't copy every
+    /// contract on each block.
+    fn complete_perpetuals(&mut self, instant: types::StateInstant) {
+        let journaling = self.perpetuals.journal.is_some();
+        let completions = self
+            .perpetuals
+            .iter_mut_unjournaled()
+            .filter_map(|(id, perp, journaled)| {
+                let prior = perp.state_instant();
+                let expired = perp.update_state_instant(instant);
+                (journaling && !journaled).then_some(Completion {
+                    perpetual_id: *id,
+                    instant: prior,
+                    expired,
+                })
+            })
+            .collect::<Vec<_>>();
+        if !completions.is_empty()
+            && let Some(entry) = self.undo_log.back_mut().map(Arc::make_mut)
+        {
+            entry.completions.extend(completions);
+        }
+    }
+
+    /// Sets externally sourced mark price of the perpetual contract at
+    /// `instant`, recomputing unrealized PnL of its positions, e.g. to replay
+    /// historical prices in deterministic backtests.
+    ///
+    /// Bypasses [`Self::apply_events`], so the snapshot no longer matches the
+    /// on-chain state. Available with the `backtest` feature only.
+    #[cfg(any(test, feature = "backtest"))]
+    pub fn set_mark_price(
+        &mut self,
+        perpetual_id: types::PerpetualId,
+        price: num::Price,
+        instant: types::StateInstant,
+    ) -> Result<Vec<StateEvents>, DexError> {
+        if !self.perpetuals.contains_key(&perpetual_id) {
+            return Err(DexError::InvalidArgument(format!("unknown perpetual: {perpetual_id}")));
+        }
+        Ok(self.update_mark_price(instant, perpetual_id, price))
+    }
+
+    /// Retains the current state for [`Self::rollback_to`] ahead of applying
+    /// the next block, if enabled.
+    ///
+    /// Perpetual contracts and accounts are not copied, instead their prior
+    /// values get journaled on the first mutation by the block, see also
+    /// [`Self::complete_perpetuals`].
+    fn save_undo_state(&mut self) {
+        if self.rollback_capacity == 0 {
+            return;
+        }
+        let perpetuals = self.perpetuals.restart_journal().unwrap_or_default();
+        let accounts = self.accounts.restart_journal().unwrap_or_default();
+        if let Some(entry) = self.undo_log.back_mut().map(Arc::make_mut) {
+            entry.perpetuals = perpetuals;
+            entry.accounts = accounts;
+        }
+
+        let (perpetuals, accounts, undo_log) = (
+            std::mem::take(&mut self.perpetuals),
+            std::mem::take(&mut self.accounts),
+            std::mem::take(&mut self.undo_log),
+        );
+        let state = self.clone();
+        (self.perpetuals, self.accounts, self.undo_log) = (perpetuals, accounts, undo_log);
+
+        if self.undo_log.len() == self.rollback_capacity {
+            self.undo_log.pop_front();
+        }
+        self.undo_log.push_back(Arc::new(UndoEntry {
+            state,
+            perpetuals: Arc::default(),
+            accounts: Arc::default(),
+            completions: vec![],
+        }));
+    }
+
+    /// Fails with [`DexError::BlockGap`] if more than
+    /// [`Self::max_skipped_blocks`] blocks are missing before the block at
+    /// `instant`.
+    fn check_block_gap(&self, instant: types::StateInstant) -> Result<(), DexError> {
+        let expected = self.instant.block_number() + 1;
+        if expected + self.max_skipped_blocks < instant.block_number() {
+            return Err(DexError::BlockGap { expected, got: instant.block_number() });
+        }
+        Ok(())
+    }
+
+    /// Passes state changes among `events` to the audit sink, if any, with
+    /// provenance of the event `context`.
+    fn audit<T>(
+        &self,
+        instant: types::StateInstant,
+        context: &EventContext<T>,
+        events: &[StateEvents],
+    ) {
+        let Some(sink) = &self.audit_sink else {
+            return;
+        };
+        for event in events {
+            if matches!(event, StateEvents::Error(_) | StateEvents::Trade(_)) {
+                continue;
+            }
+            sink.record(&StateAuditRecord {
+                instant,
+                tx_hash: context.tx_hash(),
+                tx_index: context.tx_index(),
+                log_index: context.log_index(),
+                event,
+            });
+        }
+    }
+
+    /// Passes `events` to the observers registered with
+    /// [`Self::on_state_event`].
+    fn notify(&self, events: &[StateEvents]) {
+        for observer in &self.observers {
+            // Observer panicked on one of the previous events
+            let Ok(mut observer) = observer.lock() else {
+                continue;
+            };
+            for event in events {
+                observer(event);
+            }
+        }
+    }
+
+    /// Pass 1 — funding: the contract settles a funding-event block at the new
+    /// funding sum regardless of same-block decreases, so funding must land on
+    /// each position's PRE-event size, before the block's size-changing
+    /// events. This is the only place funding is applied.
+    ///
+    /// Returns state events grouped per perpetual contract.
+    fn apply_funding(&mut self, instant: types::StateInstant) -> Vec<Vec<StateEvents>> {
+        let mut state_events = vec![];
+        let funding_due: Vec<(types::PerpetualId, D64, D256)> = self
+            .perpetuals
+            .values_mut_where(|perp| perp.has_funding_payment(instant))
+            .filter_map(|perp| {
+                perp.take_funding_payment(instant)
+                    .map(|(rate, payment)| (perp.id(), rate, payment))
+            })
+            .collect();
+        for (perp_id, rate, payment) in funding_due {
+            let mut funding_events = vec![];
+            if let Some(perp) = self.perpetuals.get(&perp_id) {
+                funding_events.push(StateEvents::perpetual(
+                    perp,
+                    PerpetualEventType::FundingEvent { rate, payment_per_unit: payment },
+                ));
+            }
+            for acc in self
+                .accounts
+                .values_mut_where(|acc| acc.positions().contains_key(&perp_id))
+            {
+                if let Some(pos) = acc.positions_mut().get_mut(&perp_id)
+                    && pos.apply_funding_payment(instant, payment)
+                {
+                    funding_events.push(StateEvents::position(
+                        pos,
+                        &None,
+                        PositionEventType::UnrealizedPnLUpdated {
+                            pnl: pos.pnl(),
+                            delta_pnl: pos.delta_pnl(),
+                            premium_pnl: pos.premium_pnl(),
+                        },
+                    ));
+                }
+            }
+            if !funding_events.is_empty() {
+                state_events.push(funding_events);
+            }
+        }
+
+        state_events
     }
 
     pub(crate) fn apply_raw_event(
@@ -266,28 +1326,36 @@ impl Exchange {
         ctx: &mut Option<OrderContext>,
     ) -> Result<Vec<StateEvents>, DexError> {
         let cc = self.collateral_converter;
+        let tracking = self.tracking;
 
         let must_ctx = || {
             ctx.as_ref()
                 .ok_or(DexError::OrderContextExpected(event.tx_index(), event.log_index()))
         };
 
-        Ok(match event.event() {
+        let Some(exchange_event) = event.event().known() else {
+            // Events unknown to the SDK cannot be applied to the state
+            return Ok(vec![]);
+        };
+
+        Ok(match exchange_event {
             ExchangeEvents::AccountCreated(e) => {
                 if self.track_all_accounts {
-                    self.accounts
-                        .insert(e.id.to(), Account::from_event(instant, e.id.to(), e.account));
+                    let account_id = num::narrow(e.id, "account id")?;
+                    let account = Account::from_event(instant, account_id, e.account)
+                        .with_balance_history(self.balance_history_capacity);
+                    self.accounts.insert(account_id, account);
                     vec![StateEvents::Account(AccountEvent {
-                        account_id: e.id.to(),
+                        account_id,
                         request_id: None,
-                        r#type: AccountEventType::Created(e.id.to()),
+                        r#type: AccountEventType::Created(account_id),
                     })]
                 } else {
                     vec![]
                 }
             },
             ExchangeEvents::AccountFreeze(e) => self
-                .account(e.accountId)
+                .account(e.accountId)?
                 .map(|acc| {
                     acc.update_frozen(instant, e.status > 0);
                     StateEvents::account(acc, ctx, AccountEventType::Frozen(acc.frozen()))
@@ -299,29 +1367,38 @@ impl Exchange {
                 .map(|ctx| StateEvents::order_error(ctx, OrderErrorType::AccountFrozen))
                 .into_iter()
                 .collect(),
-            ExchangeEvents::AccountLiquidationCredit(e) => self
-                .account(e.accountId)
-                .map(|acc| {
-                    acc.update_balance(instant, cc.from_unsigned(e.endBalanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                })
-                .into_iter()
-                .collect(),
+            ExchangeEvents::AccountLiquidationCredit(e) => {
+                let end_balance = cc.try_from_unsigned(e.endBalanceCNS)?;
+                self.account(e.accountId)?
+                    .map(|acc| {
+                        acc.update_balance(instant, end_balance, BalanceChangeReason::Liquidation);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    })
+                    .into_iter()
+                    .collect()
+            },
             ExchangeEvents::AdminChanged(_) => vec![],
             ExchangeEvents::AdministratorUpdated(_) => vec![],
-            ExchangeEvents::AmountExceedsAvailableBalance(e) => self
-                .err_ctx(ctx, event)?
-                .map(|ctx| {
-                    StateEvents::order_error(
-                        ctx,
-                        OrderErrorType::AmountExceedsAvailableBalance(
-                            cc.from_unsigned(e.amountCNS),
-                            cc.from_unsigned(e.availableBalanceCNS),
-                        ),
-                    )
-                })
-                .into_iter()
-                .collect(),
+            ExchangeEvents::AmountExceedsAvailableBalance(e) => {
+                let amount = cc.try_from_unsigned(e.amountCNS)?;
+                let available_balance = cc.try_from_unsigned(e.availableBalanceCNS)?;
+                self.err_ctx(ctx, event)?
+                    .map(|ctx| {
+                        StateEvents::order_error(
+                            ctx,
+                            OrderErrorType::AmountExceedsAvailableBalance(
+                                amount.get(),
+                                available_balance.get(),
+                            ),
+                        )
+                    })
+                    .into_iter()
+                    .collect()
+            },
             ExchangeEvents::BankruptcyPricePreventsDeleverage(_) => vec![],
             ExchangeEvents::BeaconUpgraded(_) => vec![],
             ExchangeEvents::BlockStatusChanged(_) => vec![],
@@ -356,110 +1433,120 @@ impl Exchange {
                 })
                 .into_iter()
                 .collect(),
-            ExchangeEvents::ClearingExpiredOrder(e) => chain!(
-                if let Some(perp) = self.perpetual(e.perpId) {
-                    let order_id = std::num::NonZeroU16::new(e.orderId.to::<u16>())
-                        .expect("orderId in event cannot be 0");
-                    let order = perp.remove_order(order_id)?;
-                    Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
-                } else {
-                    None
-                },
-                self.account(e.accountId).map(|acc| {
-                    acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                    StateEvents::account(
-                        acc,
-                        ctx,
-                        AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                    )
-                }),
-                if !e.recyclerAmountCNS.is_zero() {
-                    self.account(e.recyclerAccountId).map(|acc| {
-                        acc.update_balance(instant, cc.from_unsigned(e.recyclerBalanceCNS));
+            ExchangeEvents::ClearingExpiredOrder(e) => {
+                let locked_balance = cc.try_from_unsigned(e.lockedBalanceCNS)?;
+                let recycler_balance = cc.try_from_unsigned(e.recyclerBalanceCNS)?;
+                chain!(
+                    if let Some(perp) = self.book(num::narrow(e.perpId, "perpetual id")?) {
+                        let order_id = event_order_id(e.orderId)?;
+                        let order = perp.remove_order(order_id)?;
+                        Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
+                    } else {
+                        None
+                    },
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_locked_balance(instant, locked_balance);
                         StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance()),
+                            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
                         )
-                    })
-                } else {
-                    None
-                },
-            )
-            .collect(),
-            ExchangeEvents::ClearingFrozenAccountOrder(e) => chain!(
-                if let Some(perp) = self.perpetual(e.perpId) {
-                    let order_id = std::num::NonZeroU16::new(e.orderId.to::<u16>())
-                        .expect("orderId in event cannot be 0");
-                    let order = perp.remove_order(order_id)?;
-                    Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
-                } else {
-                    None
-                },
-                self.account(e.accountId).map(|acc| {
-                    acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                    StateEvents::account(
-                        acc,
-                        ctx,
-                        AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                    )
-                }),
-                if !e.recyclerAmountCNS.is_zero() {
-                    self.account(e.recyclerAccountId).map(|acc| {
-                        acc.update_balance(instant, cc.from_unsigned(e.recyclerBalanceCNS));
+                    }),
+                    if !e.recyclerAmountCNS.is_zero() {
+                        self.account(e.recyclerAccountId)?.map(|acc| {
+                            acc.update_balance(instant, recycler_balance, BalanceChangeReason::Fee);
+                            StateEvents::account(
+                                acc,
+                                ctx,
+                                AccountEventType::BalanceUpdated(acc.balance().get()),
+                            )
+                        })
+                    } else {
+                        None
+                    },
+                )
+                .collect()
+            },
+            ExchangeEvents::ClearingFrozenAccountOrder(e) => {
+                let locked_balance = cc.try_from_unsigned(e.lockedBalanceCNS)?;
+                let recycler_balance = cc.try_from_unsigned(e.recyclerBalanceCNS)?;
+                chain!(
+                    if let Some(perp) = self.book(num::narrow(e.perpId, "perpetual id")?) {
+                        let order_id = event_order_id(e.orderId)?;
+                        let order = perp.remove_order(order_id)?;
+                        Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
+                    } else {
+                        None
+                    },
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_locked_balance(instant, locked_balance);
                         StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance()),
+                            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
                         )
-                    })
-                } else {
-                    None
-                },
-            )
-            .collect(),
-            ExchangeEvents::ClearingInvalidCloseOrder(e) => chain!(
-                if let Some(perp) = self.perpetual(e.perpId) {
-                    let order_id = std::num::NonZeroU16::new(e.orderId.to::<u16>())
-                        .expect("orderId in event cannot be 0");
-                    let order = perp.remove_order(order_id)?;
-                    Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
-                } else {
-                    None
-                },
-                self.account(e.accountId).map(|acc| {
-                    acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                    StateEvents::account(
-                        acc,
-                        ctx,
-                        AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                    )
-                }),
-                if !e.recyclerAmountCNS.is_zero() {
-                    self.account(e.recyclerAccountId).map(|acc| {
-                        acc.update_balance(instant, cc.from_unsigned(e.recyclerBalanceCNS));
+                    }),
+                    if !e.recyclerAmountCNS.is_zero() {
+                        self.account(e.recyclerAccountId)?.map(|acc| {
+                            acc.update_balance(instant, recycler_balance, BalanceChangeReason::Fee);
+                            StateEvents::account(
+                                acc,
+                                ctx,
+                                AccountEventType::BalanceUpdated(acc.balance().get()),
+                            )
+                        })
+                    } else {
+                        None
+                    },
+                )
+                .collect()
+            },
+            ExchangeEvents::ClearingInvalidCloseOrder(e) => {
+                let locked_balance = cc.try_from_unsigned(e.lockedBalanceCNS)?;
+                let recycler_balance = cc.try_from_unsigned(e.recyclerBalanceCNS)?;
+                chain!(
+                    if let Some(perp) = self.book(num::narrow(e.perpId, "perpetual id")?) {
+                        let order_id = event_order_id(e.orderId)?;
+                        let order = perp.remove_order(order_id)?;
+                        Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
+                    } else {
+                        None
+                    },
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_locked_balance(instant, locked_balance);
                         StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance()),
+                            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
                         )
-                    })
-                } else {
-                    None
-                },
-            )
-            .collect(),
+                    }),
+                    if !e.recyclerAmountCNS.is_zero() {
+                        self.account(e.recyclerAccountId)?.map(|acc| {
+                            acc.update_balance(instant, recycler_balance, BalanceChangeReason::Fee);
+                            StateEvents::account(
+                                acc,
+                                ctx,
+                                AccountEventType::BalanceUpdated(acc.balance().get()),
+                            )
+                        })
+                    } else {
+                        None
+                    },
+                )
+                .collect()
+            },
             ExchangeEvents::ClearingRemainingOrderLockBeyondBalance(e) => {
+                let recycler_balance = cc.try_from_unsigned(e.recyclerBalanceCNS)?;
                 if let Some(ctx) = ctx {
                     ctx.clearing_remaining_order = true;
                 }
                 chain!(if !e.recyclerAmountCNS.is_zero() {
-                    self.account(e.recyclerAccountId).map(|acc| {
-                        acc.update_balance(instant, cc.from_unsigned(e.recyclerBalanceCNS));
+                    self.account(e.recyclerAccountId)?.map(|acc| {
+                        acc.update_balance(instant, recycler_balance, BalanceChangeReason::Fee);
                         StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance()),
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
                         )
                     })
                 } else {
@@ -467,37 +1554,40 @@ impl Exchange {
                 },)
                 .collect()
             },
-            ExchangeEvents::ClearingSelfMatchingOrder(e) => chain!(
-                if let Some(perp) = self.perpetual(e.perpId) {
-                    let order_id = std::num::NonZeroU16::new(e.orderId.to::<u16>())
-                        .expect("orderId in event cannot be 0");
-                    let order = perp.remove_order(order_id)?;
-                    Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
-                } else {
-                    None
-                },
-                self.account(e.accountId).map(|acc| {
-                    acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                    StateEvents::account(
-                        acc,
-                        ctx,
-                        AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                    )
-                }),
-                if !e.recyclerAmountCNS.is_zero() {
-                    self.account(e.recyclerAccountId).map(|acc| {
-                        acc.update_balance(instant, cc.from_unsigned(e.recyclerBalanceCNS));
+            ExchangeEvents::ClearingSelfMatchingOrder(e) => {
+                let locked_balance = cc.try_from_unsigned(e.lockedBalanceCNS)?;
+                let recycler_balance = cc.try_from_unsigned(e.recyclerBalanceCNS)?;
+                chain!(
+                    if let Some(perp) = self.book(num::narrow(e.perpId, "perpetual id")?) {
+                        let order_id = event_order_id(e.orderId)?;
+                        let order = perp.remove_order(order_id)?;
+                        Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
+                    } else {
+                        None
+                    },
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_locked_balance(instant, locked_balance);
                         StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance()),
+                            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
                         )
-                    })
-                } else {
-                    None
-                },
-            )
-            .collect(),
+                    }),
+                    if !e.recyclerAmountCNS.is_zero() {
+                        self.account(e.recyclerAccountId)?.map(|acc| {
+                            acc.update_balance(instant, recycler_balance, BalanceChangeReason::Fee);
+                            StateEvents::account(
+                                acc,
+                                ctx,
+                                AccountEventType::BalanceUpdated(acc.balance().get()),
+                            )
+                        })
+                    } else {
+                        None
+                    },
+                )
+                .collect()
+            },
             ExchangeEvents::CloseOrderExceedsPosition(_) => self
                 .err_ctx(ctx, event)?
                 .map(|ctx| StateEvents::order_error(ctx, OrderErrorType::CloseOrderExceedsPosition))
@@ -514,37 +1604,50 @@ impl Exchange {
             ExchangeEvents::CollateralDecreaseRequestCancelled(_) => vec![],
             ExchangeEvents::CollateralDecreaseRequested(_) => vec![],
             ExchangeEvents::CollateralDecreaseRequestExpired(_) => vec![],
-            ExchangeEvents::CollateralDeposit(e) => self
-                .account(e.accountId)
-                .map(|acc| {
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                })
-                .into_iter()
-                .collect(),
-            ExchangeEvents::CollateralWithdrawal(e) => self
-                .account(e.accountId)
-                .map(|acc| {
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                })
-                .into_iter()
-                .collect(),
+            ExchangeEvents::CollateralDeposit(e) => {
+                let balance = cc.try_from_unsigned(e.balanceCNS)?;
+                self.account(e.accountId)?
+                    .map(|acc| {
+                        acc.update_balance(instant, balance, BalanceChangeReason::Deposit);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    })
+                    .into_iter()
+                    .collect()
+            },
+            ExchangeEvents::CollateralWithdrawal(e) => {
+                let balance = cc.try_from_unsigned(e.balanceCNS)?;
+                self.account(e.accountId)?
+                    .map(|acc| {
+                        acc.update_balance(instant, balance, BalanceChangeReason::Withdrawal);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    })
+                    .into_iter()
+                    .collect()
+            },
             ExchangeEvents::ContractAdded(e) => {
-                let perp = Perpetual::added(
+                let mut perp = Perpetual::added(
                     instant,
-                    e.perpId.to(),
+                    num::narrow(e.perpId, "perpetual id")?,
                     e.name.clone(),
                     e.symbol.clone(),
                     e.status == 0, // PerpStatusEnum::Paused
-                    e.priceDecimals.to(),
-                    e.lotDecimals.to(),
+                    num::narrow(e.priceDecimals, "price decimals")?,
+                    num::narrow(e.lotDecimals, "lot decimals")?,
                     e.basePricePNS,
                     e.makerFeePer100K,
                     e.takerFeePer100K,
                     e.initMarginFracHdths,
                     e.maintMarginFracHdths,
                 );
+                perp.set_book_journal_capacity(self.book_journal_capacity);
                 let event = StateEvents::perpetual(&perp, PerpetualEventType::Added);
                 self.perpetuals.insert(perp.id(), perp);
                 vec![event]
@@ -555,7 +1658,7 @@ impl Exchange {
                 .into_iter()
                 .collect(),
             ExchangeEvents::ContractLinkFeedUpdated(e) => self
-                .perpetual(e.perpId)
+                .perpetual(e.perpId)?
                 .map(|perp| {
                     perp.update_oracle_feed_id(instant, e.feedId);
                     StateEvents::perpetual(
@@ -569,7 +1672,7 @@ impl Exchange {
                 .into_iter()
                 .collect(),
             ExchangeEvents::ContractPaused(e) => self
-                .perpetual(e.perpId)
+                .perpetual(e.perpId)?
                 .map(|perp| {
                     perp.update_paused(instant, e.paused);
                     StateEvents::perpetual(perp, PerpetualEventType::Paused(perp.is_paused()))
@@ -577,7 +1680,7 @@ impl Exchange {
                 .into_iter()
                 .collect(),
             ExchangeEvents::ContractRemoved(e) => self
-                .perpetual(e.perpId)
+                .perpetual(e.perpId)?
                 .map(|perp| {
                     perp.update_paused(instant, true);
                     StateEvents::perpetual(perp, PerpetualEventType::Paused(perp.is_paused()))
@@ -605,14 +1708,14 @@ impl Exchange {
             ExchangeEvents::FeeParamsUpdated(_) => vec![],
             ExchangeEvents::FundingClampPctUpdated(_) => vec![],
             ExchangeEvents::FundingEventCompleted(e) => {
-                if let Some(perp) = self.perpetual(e.perpId) {
+                if let Some(perp) = self.perpetual(e.perpId)? {
                     perp.update_funding(
                         instant,
                         perp.funding_rate_converter()
-                            .from_signed(e.actualRatePct100k),
+                            .try_from_signed(e.actualRatePct100k)?,
                         perp.funding_sum_converter()
                             .from_i64(e.fundingPaymentPNS.as_i64()),
-                        e.fundingEventBlock.to(),
+                        num::narrow(e.fundingEventBlock, "funding event block")?,
                     );
                 }
                 vec![]
@@ -620,19 +1723,21 @@ impl Exchange {
             ExchangeEvents::FundingEventSetTooEarly(_) => vec![],
             ExchangeEvents::FundingPriceExceedsTol(_) => vec![],
             ExchangeEvents::FundingSumAlreadySet(_) => vec![],
-            ExchangeEvents::FundingSumScalingExpUpdated(e) => self
-                .perpetual(e.perpId)
-                .map(|perp| {
-                    perp.update_funding_sum_scaling_exp(instant, e.newExp.to());
-                    StateEvents::perpetual(
-                        perp,
-                        PerpetualEventType::FundingSumScalingExpUpdated(e.newExp.to()),
-                    )
-                })
-                .into_iter()
-                .collect(),
+            ExchangeEvents::FundingSumScalingExpUpdated(e) => {
+                let new_exp = num::narrow(e.newExp, "funding sum scaling exponent")?;
+                self.perpetual(e.perpId)?
+                    .map(|perp| {
+                        perp.update_funding_sum_scaling_exp(instant, new_exp);
+                        StateEvents::perpetual(
+                            perp,
+                            PerpetualEventType::FundingSumScalingExpUpdated(new_exp),
+                        )
+                    })
+                    .into_iter()
+                    .collect()
+            },
             ExchangeEvents::IgnoreOracleUpdated(e) => self
-                .perpetual(e.perpId)
+                .perpetual(e.perpId)?
                 .map(|perp| {
                     perp.update_is_oracle_used(instant, !e.ignOracle);
                     StateEvents::perpetual(
@@ -650,37 +1755,54 @@ impl Exchange {
                 .map(|ctx| StateEvents::order_error(ctx, OrderErrorType::ImmediateOrCancelExecuted))
                 .into_iter()
                 .collect(),
-            ExchangeEvents::IncreasePositionCollateral(e) => chain!(
-                self.position(e.accountId, e.perpId)?.map(|(pos, _)| {
-                    pos.update_deposit(instant, cc.from_unsigned(e.positionDepositCNS));
-                    StateEvents::position(
-                        pos,
-                        ctx,
-                        PositionEventType::DepositUpdated(pos.deposit()),
-                    )
-                }),
-                self.account(e.accountId).map(|acc| {
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                }),
-            )
-            .collect(),
+            ExchangeEvents::IncreasePositionCollateral(e) => {
+                let position_deposit = cc.try_from_unsigned(e.positionDepositCNS)?;
+                let balance = cc.try_from_unsigned(e.balanceCNS)?;
+                chain!(
+                    self.position(e.accountId, e.perpId)?.map(|(pos, _)| {
+                        pos.update_deposit(instant, position_deposit.get());
+                        StateEvents::position(
+                            pos,
+                            ctx,
+                            PositionEventType::DepositUpdated(pos.deposit().get()),
+                        )
+                    }),
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_balance(
+                            instant,
+                            balance,
+                            BalanceChangeReason::PositionCollateral,
+                        );
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    }),
+                )
+                .collect()
+            },
             ExchangeEvents::Initialized(_) => vec![],
-            ExchangeEvents::InitialMarginFractionUpdated(e) => self
-                .perpetual(e.perpId)
-                .map(|perp| {
-                    perp.update_initial_margin(
-                        instant,
-                        perp.leverage_converter()
-                            .from_unsigned(e.initMarginFracHdths),
-                    );
-                    StateEvents::perpetual(
-                        perp,
-                        PerpetualEventType::InitialMarginFractionUpdated(perp.initial_margin()),
+            ExchangeEvents::InitialMarginFractionUpdated(e) => {
+                if let Some(perp) = self.perpetual(e.perpId)? {
+                    let margin = perp
+                        .leverage_converter()
+                        .try_from_unsigned(e.initMarginFracHdths)?;
+                    let old = perp.initial_margin();
+                    perp.update_initial_margin(instant, margin);
+                    let new = perp.initial_margin();
+                    chain!(
+                        [StateEvents::perpetual(
+                            perp,
+                            PerpetualEventType::InitialMarginFractionUpdated(new),
+                        )],
+                        param_changed(perp, PerpetualParam::InitialMargin, old, new),
                     )
-                })
-                .into_iter()
-                .collect(),
+                    .collect()
+                } else {
+                    vec![]
+                }
+            },
             ExchangeEvents::InsolventPositionCannotBeForcedClose(_) => vec![],
             ExchangeEvents::InsuficientFundsForRecycleFee(_) => self
                 .err_ctx(ctx, event)?
@@ -712,220 +1834,245 @@ impl Exchange {
             ExchangeEvents::LinkDsError_0(_) => vec![],
             ExchangeEvents::LinkDsError_1(_) => vec![],
             ExchangeEvents::LinkDsPanic(_) => vec![],
-            ExchangeEvents::LinkPriceUpdated(e) => self
-                .perpetual(e.perpId)
-                .map(|perp| {
-                    perp.update_oracle_price(
-                        instant,
-                        perp.price_converter().from_unsigned(e.oraclePricePNS),
-                    );
-                    StateEvents::perpetual(
+            ExchangeEvents::LinkPriceUpdated(e) => {
+                if let Some(perp) = self.perpetual(e.perpId)? {
+                    let price = perp.price_converter().try_from_unsigned(e.oraclePricePNS)?;
+                    perp.update_oracle_price(instant, price);
+                    vec![StateEvents::perpetual(
                         perp,
-                        PerpetualEventType::OraclePriceUpdated(perp.oracle_price()),
-                    )
-                })
-                .into_iter()
-                .collect(),
+                        PerpetualEventType::OraclePriceUpdated(perp.oracle_price().get()),
+                    )]
+                } else {
+                    vec![]
+                }
+            },
             ExchangeEvents::LiquidationBuyerUpdated(_) => vec![],
             ExchangeEvents::LiquidationParamsUpdated(_) => vec![],
             ExchangeEvents::LotOutOfRange(_) => self
                 .err_ctx(ctx, event)?
-                .map(|ctx| StateEvents::order_error(ctx, OrderErrorType::SizeOutOfRange))
-                .into_iter()
-                .collect(),
-            ExchangeEvents::MaintenanceMarginFractionUpdated(e) => self
-                .perpetual(e.perpId)
-                .map(|perp| {
-                    perp.update_maintenance_margin(
-                        instant,
-                        perp.leverage_converter()
-                            .from_unsigned(e.maintMarginFracHdths),
-                    );
-                    StateEvents::perpetual(
-                        perp,
-                        PerpetualEventType::MaintenanceMarginFractionUpdated(
-                            perp.maintenance_margin(),
-                        ),
-                    )
-                })
-                .into_iter()
-                .collect(),
-            ExchangeEvents::MakerFeeUpdated(e) => self
-                .perpetual(e.perpId)
-                .map(|perp| {
-                    perp.update_maker_fee(
-                        instant,
-                        perp.fee_converter().from_unsigned(e.makerFeePer100K),
-                    );
-                    StateEvents::perpetual(
-                        perp,
-                        PerpetualEventType::MakerFeeUpdated(perp.maker_fee()),
-                    )
-                })
-                .into_iter()
-                .collect(),
-            ExchangeEvents::MakerOrderFilled(e) => chain!(
-                if let Some((perp, order)) = self.order(e.perpId, e.orderId)? {
-                    let fill_price = perp.price_converter().from_unsigned(e.pricePNS);
-                    let fill_size = perp.size_converter().from_unsigned(e.lotLNS);
-                    let fee = cc.from_unsigned(e.feeCNS);
-                    perp.update_last_price(instant, fill_price);
-                    let clearing_remaining_order = if let Some(ctx) = ctx {
-                        ctx.maker_fills.push(types::MakerFill {
-                            log_index: event.log_index(),
-                            maker_account_id: order.account_id(),
-                            maker_order_id: order.order_id(),
-                            price: fill_price,
-                            size: fill_size,
-                            fee,
-                        });
-                        let position_closed_by_smart_contract = if event.log_index() > 0 {
-                            // Smart contract explicitly removes Close* order if position was
-                            // closed, between `PositionClosed` and `MakerOrderFilled` events
-                            // there can be a `RecycleFeeToAccount` event as well
-                            match order.r#type() {
-                                OrderType::CloseLong | OrderType::CloseShort => {
-                                    Some(event.log_index() - 1) == ctx.position_closed_at_log_index
-                                        || Some(event.log_index() - 2)
-                                            == ctx.position_closed_at_log_index
-                                },
-                                _ => false,
-                            }
-                        } else {
-                            false
-                        };
-                        ctx.clearing_remaining_order | position_closed_by_smart_contract
-                    } else {
-                        false
-                    };
-                    vec![
-                        if order.size() > fill_size && !clearing_remaining_order {
-                            let new_size = order.size() - fill_size;
-                            perp.update_order(order.updated(
-                                instant,
-                                ctx,
-                                None,
-                                Some(new_size),
-                                None,
-                                None,
-                            ))
-                            .expect("order exists");
-                            StateEvents::order(
-                                perp,
-                                &order,
-                                ctx,
-                                OrderEventType::Updated {
-                                    price: None,
-                                    size: Some(new_size),
-                                    expiry_block: None,
-                                },
-                            )
-                        } else {
-                            perp.remove_order(order.order_id()).expect("order exists");
-                            StateEvents::order(perp, &order, ctx, OrderEventType::Removed)
-                        },
-                        StateEvents::order(
-                            perp,
-                            &order,
-                            ctx,
-                            OrderEventType::Filled { fill_price, fill_size, fee, is_maker: true },
-                        ),
-                        StateEvents::perpetual(
+                .map(|ctx| StateEvents::order_error(ctx, OrderErrorType::SizeOutOfRange))
+                .into_iter()
+                .collect(),
+            ExchangeEvents::MaintenanceMarginFractionUpdated(e) => {
+                if let Some(perp) = self.perpetual(e.perpId)? {
+                    let margin = perp
+                        .leverage_converter()
+                        .try_from_unsigned(e.maintMarginFracHdths)?;
+                    let old = perp.maintenance_margin();
+                    perp.update_maintenance_margin(instant, margin);
+                    let new = perp.maintenance_margin();
+                    chain!(
+                        [StateEvents::perpetual(
                             perp,
-                            PerpetualEventType::LastPriceUpdated(perp.last_price()),
-                        ),
-                    ]
+                            PerpetualEventType::MaintenanceMarginFractionUpdated(new),
+                        )],
+                        param_changed(perp, PerpetualParam::MaintenanceMargin, old, new),
+                    )
+                    .collect()
                 } else {
                     vec![]
-                },
-                self.account(e.accountId).map(|acc| {
-                    acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                    StateEvents::account(
-                        acc,
-                        ctx,
-                        AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                    )
-                }),
-                self.account(e.accountId).map(|acc| {
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                }),
-            )
-            .collect(),
-            ExchangeEvents::MakerOrderSettlementFailed(e) => chain!(
-                if let Some(perp) = self.perpetual(e.perpId) {
-                    let order_id = std::num::NonZeroU16::new(e.orderId.to::<u16>())
-                        .expect("orderId in event cannot be 0");
-                    let order = perp.remove_order(order_id)?;
+                }
+            },
+            ExchangeEvents::MakerFeeUpdated(e) => {
+                if let Some(perp) = self.perpetual(e.perpId)? {
+                    let fee = perp.fee_converter().try_from_unsigned(e.makerFeePer100K)?;
+                    let old = perp.maker_fee();
+                    perp.update_maker_fee(instant, fee);
                     chain!(
-                        Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed)),
-                        self.err_ctx(ctx, event)?
-                            .map(|ctx| StateEvents::affected_order_error(
-                                ctx,
-                                &order,
-                                OrderErrorType::MakerOrderSettlementFailed
-                            ))
+                        [StateEvents::perpetual(
+                            perp,
+                            PerpetualEventType::MakerFeeUpdated(perp.maker_fee()),
+                        )],
+                        param_changed(perp, PerpetualParam::MakerFee, old, perp.maker_fee()),
                     )
                     .collect()
                 } else {
                     vec![]
-                },
-                self.account(e.accountId).map(|acc| {
-                    acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                    StateEvents::account(
-                        acc,
-                        ctx,
-                        AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                    )
-                }),
-                if !e.recyclerAmountCNS.is_zero() {
-                    self.account(e.recyclerAccountId).map(|acc| {
-                        acc.update_balance(instant, cc.from_unsigned(e.recyclerBalanceCNS));
+                }
+            },
+            ExchangeEvents::MakerOrderFilled(e) => {
+                let locked_balance = cc.try_from_unsigned(e.lockedBalanceCNS)?;
+                let balance = cc.try_from_unsigned(e.balanceCNS)?;
+                chain!(
+                    if let Some((perp, order)) = self.order(e.perpId, e.orderId)? {
+                        let fill_price = perp.price_converter().try_from_unsigned(e.pricePNS)?;
+                        let fill_size = perp.size_converter().try_from_unsigned(e.lotLNS)?;
+                        let fee = cc.try_from_unsigned(e.feeCNS)?.get().resize();
+                        perp.update_last_price(instant, fill_price);
+                        let clearing_remaining_order = if let Some(ctx) = ctx {
+                            if tracking.trades {
+                                ctx.maker_fills.push(types::MakerFill {
+                                    log_index: event.log_index(),
+                                    maker_account_id: order.account_id(),
+                                    maker_order_id: order.order_id(),
+                                    price: fill_price.get(),
+                                    size: fill_size.get(),
+                                    fee,
+                                });
+                            }
+                            let position_closed_by_smart_contract = if event.log_index() > 0 {
+                                // Smart contract explicitly removes Close* order if position was
+                                // closed, between `PositionClosed` and `MakerOrderFilled` events
+                                // there can be a `RecycleFeeToAccount` event as well
+                                match order.r#type() {
+                                    OrderType::CloseLong | OrderType::CloseShort => {
+                                        Some(event.log_index() - 1)
+                                            == ctx.position_closed_at_log_index
+                                            || Some(event.log_index() - 2)
+                                                == ctx.position_closed_at_log_index
+                                    },
+                                    _ => false,
+                                }
+                            } else {
+                                false
+                            };
+                            ctx.clearing_remaining_order | position_closed_by_smart_contract
+                        } else {
+                            false
+                        };
+                        let remaining_size =
+                            if order.size() > fill_size && !clearing_remaining_order {
+                                (order.size() - fill_size).get()
+                            } else {
+                                UD64::ZERO
+                            };
+                        vec![
+                            if !remaining_size.is_zero() {
+                                perp.update_order(order.updated(
+                                    instant,
+                                    ctx,
+                                    None,
+                                    Some(remaining_size),
+                                    None,
+                                    None,
+                                ))
+                                .expect("order exists");
+                                StateEvents::order(
+                                    perp,
+                                    &order,
+                                    ctx,
+                                    OrderEventType::Updated {
+                                        price: None,
+                                        size: Some(remaining_size),
+                                        expiry_block: None,
+                                    },
+                                )
+                            } else {
+                                perp.remove_order(order.order_id()).expect("order exists");
+                                StateEvents::order(perp, &order, ctx, OrderEventType::Removed)
+                            },
+                            StateEvents::order(
+                                perp,
+                                &order,
+                                ctx,
+                                OrderEventType::Filled {
+                                    fill_price: fill_price.get(),
+                                    fill_size: fill_size.get(),
+                                    remaining_size: Some(remaining_size),
+                                    fee,
+                                    is_maker: true,
+                                },
+                            ),
+                            StateEvents::perpetual(
+                                perp,
+                                PerpetualEventType::LastPriceUpdated(perp.last_price().get()),
+                            ),
+                        ]
+                    } else if !tracking.books
+                        && let Some(perp) = self.perpetual(e.perpId)?
+                    {
+                        // No book to look the order up in, fill is taken as is
+                        let fill_price = perp.price_converter().try_from_unsigned(e.pricePNS)?;
+                        perp.update_last_price(instant, fill_price);
+                        if tracking.trades
+                            && let Some(ctx) = ctx
+                        {
+                            ctx.maker_fills.push(types::MakerFill {
+                                log_index: event.log_index(),
+                                maker_account_id: num::narrow(e.accountId, "account id")?,
+                                maker_order_id: event_order_id(e.orderId)?,
+                                price: fill_price.get(),
+                                size: perp.size_converter().try_from_unsigned(e.lotLNS)?.get(),
+                                fee: cc.try_from_unsigned(e.feeCNS)?.get().resize(),
+                            });
+                        }
+                        vec![StateEvents::perpetual(
+                            perp,
+                            PerpetualEventType::LastPriceUpdated(perp.last_price().get()),
+                        )]
+                    } else {
+                        vec![]
+                    },
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_locked_balance(instant, locked_balance);
                         StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance()),
+                            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
                         )
-                    })
-                } else {
-                    None
-                },
-            )
-            .collect(),
+                    }),
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_balance(instant, balance, BalanceChangeReason::Settlement);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    }),
+                )
+                .collect()
+            },
+            ExchangeEvents::MakerOrderSettlementFailed(e) => {
+                let locked_balance = cc.try_from_unsigned(e.lockedBalanceCNS)?;
+                let recycler_balance = cc.try_from_unsigned(e.recyclerBalanceCNS)?;
+                chain!(
+                    if let Some(perp) = self.book(num::narrow(e.perpId, "perpetual id")?) {
+                        let order_id = event_order_id(e.orderId)?;
+                        let order = perp.remove_order(order_id)?;
+                        chain!(
+                            Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed)),
+                            self.err_ctx(ctx, event)?
+                                .map(|ctx| StateEvents::affected_order_error(
+                                    ctx,
+                                    &order,
+                                    OrderErrorType::MakerOrderSettlementFailed
+                                ))
+                        )
+                        .collect()
+                    } else {
+                        vec![]
+                    },
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_locked_balance(instant, locked_balance);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
+                        )
+                    }),
+                    if !e.recyclerAmountCNS.is_zero() {
+                        self.account(e.recyclerAccountId)?.map(|acc| {
+                            acc.update_balance(instant, recycler_balance, BalanceChangeReason::Fee);
+                            StateEvents::account(
+                                acc,
+                                ctx,
+                                AccountEventType::BalanceUpdated(acc.balance().get()),
+                            )
+                        })
+                    } else {
+                        None
+                    },
+                )
+                .collect()
+            },
             ExchangeEvents::MarginTolUpdated(_) => vec![],
             ExchangeEvents::MarkExceedsTol(_) => vec![],
             ExchangeEvents::MarkPriceAgeExceedsMax(_) => vec![],
             ExchangeEvents::MarkUpdated(e) => {
-                let perp_mark = self.perpetual(e.perpId).map(|perp| {
-                    perp.update_mark_price(
-                        instant,
-                        perp.price_converter().from_unsigned(e.pricePNS),
-                    );
-                    (perp.id(), perp.mark_price())
-                });
-                if let Some((perp_id, mark_price)) = perp_mark {
-                    chain!(
-                        Some(StateEvents::Perpetual(PerpetualEvent {
-                            perpetual_id: perp_id,
-                            r#type: PerpetualEventType::MarkPriceUpdated(mark_price),
-                        })),
-                        // Applying updated mark to all tracked positions
-                        self.accounts.values_mut().filter_map(|acc| {
-                            acc.positions_mut().get_mut(&perp_id).map(|pos| {
-                                pos.apply_mark_price(instant, mark_price);
-                                StateEvents::position(
-                                    pos,
-                                    &None,
-                                    PositionEventType::UnrealizedPnLUpdated {
-                                        pnl: pos.pnl(),
-                                        delta_pnl: pos.delta_pnl(),
-                                        premium_pnl: pos.premium_pnl(),
-                                    },
-                                )
-                            })
-                        }),
-                    )
-                    .collect()
+                if let Some(perp) = self.perpetual(e.perpId)? {
+                    let perp_id = perp.id();
+                    let mark_price = perp.price_converter().try_from_unsigned(e.pricePNS)?;
+                    self.update_mark_price(instant, perp_id, mark_price)
                 } else {
                     vec![]
                 }
@@ -943,12 +2090,12 @@ impl Exchange {
                 .collect(),
             ExchangeEvents::MinAccountOpenAmountUpdated(_) => vec![],
             ExchangeEvents::MinPostUpdated(e) => {
-                self.min_post = cc.from_unsigned(e.minPostCNS);
-                vec![StateEvents::Exchange(ExchangeEvent::MinPostUpdated(self.min_post))]
+                self.min_post = cc.try_from_unsigned(e.minPostCNS)?;
+                vec![StateEvents::Exchange(ExchangeEvent::MinPostUpdated(self.min_post.get()))]
             },
             ExchangeEvents::MinSettleUpdated(e) => {
-                self.min_settle = cc.from_unsigned(e.minSettleCNS);
-                vec![StateEvents::Exchange(ExchangeEvent::MinSettleUpdated(self.min_settle))]
+                self.min_settle = cc.try_from_unsigned(e.minSettleCNS)?;
+                vec![StateEvents::Exchange(ExchangeEvent::MinSettleUpdated(self.min_settle.get()))]
             },
             ExchangeEvents::MonitorAdministratorUpdated(_) => vec![],
             ExchangeEvents::MonitorPauseAttempted(_) => vec![],
@@ -961,81 +2108,87 @@ impl Exchange {
             },
             ExchangeEvents::OrderCancelled(e) => {
                 let c = must_ctx()?;
-                let order_id = c.order_id.expect("order_id required for OrderCancelled");
+                let order_id = c.order_id.ok_or_else(|| {
+                    DexError::MalformedData("order id required for OrderCancelled".to_string())
+                })?;
+                let mut events = Vec::with_capacity(3);
+                if let Some(perp) = self.book(c.perpetual_id) {
+                    let order = perp.remove_order(order_id)?;
+                    events.push(StateEvents::order(perp, &order, ctx, OrderEventType::Removed));
+                }
+                if let Some(acc) = self.accounts.get_mut(&c.account_id) {
+                    let (locked_balance, balance) = (
+                        cc.try_from_unsigned(e.lockedBalanceCNS)?,
+                        cc.try_from_unsigned(e.balanceCNS)?,
+                    );
+                    let updated =
+                        order_balances_updated(acc, instant, locked_balance, balance, ctx);
+                    events.extend(updated);
+                }
+                events
+            },
+            ExchangeEvents::OrderCancelledByAdmin(e) => {
+                let locked_balance = cc.try_from_unsigned(e.lockedBalanceCNS)?;
                 chain!(
-                    if let Some(perp) = self.perpetuals.get_mut(&c.perpetual_id) {
-                        let order = perp.remove_order(order_id)?;
-                        Some(StateEvents::order(perp, &order, ctx, OrderEventType::Removed))
-                    } else {
-                        None
-                    },
-                    if let Some(acc) = self.accounts.get_mut(&c.account_id) {
-                        acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                        acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                        vec![
-                            StateEvents::account(
-                                acc,
-                                ctx,
-                                AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                            ),
-                            StateEvents::account(
-                                acc,
-                                ctx,
-                                AccountEventType::BalanceUpdated(acc.balance()),
-                            ),
-                        ]
-                    } else {
-                        vec![]
-                    },
+                    self.order(e.perpId, e.orderId)?.map(|(perp, order)| {
+                        perp.remove_order(order.order_id()).expect("order exists");
+                        StateEvents::order(perp, &order, ctx, OrderEventType::Removed)
+                    }),
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_locked_balance(instant, locked_balance);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
+                        )
+                    }),
+                )
+                .collect()
+            },
+            ExchangeEvents::OrderCancelledByLiquidator(e) => {
+                let locked_balance = cc.try_from_unsigned(e.lockedBalanceCNS)?;
+                chain!(
+                    self.order(e.perpId, e.orderId)?.map(|(perp, order)| {
+                        perp.remove_order(order.order_id()).expect("order exists");
+                        StateEvents::order(perp, &order, ctx, OrderEventType::Removed)
+                    }),
+                    self.account(e.accountId)?.map(|acc| {
+                        acc.update_locked_balance(instant, locked_balance);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
+                        )
+                    }),
                 )
                 .collect()
             },
-            ExchangeEvents::OrderCancelledByAdmin(e) => chain!(
-                self.order(e.perpId, e.orderId)?.map(|(perp, order)| {
-                    perp.remove_order(order.order_id()).expect("order exists");
-                    StateEvents::order(perp, &order, ctx, OrderEventType::Removed)
-                }),
-                self.account(e.accountId).map(|acc| {
-                    acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                    StateEvents::account(
-                        acc,
-                        ctx,
-                        AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                    )
-                }),
-            )
-            .collect(),
-            ExchangeEvents::OrderCancelledByLiquidator(e) => chain!(
-                self.order(e.perpId, e.orderId)?.map(|(perp, order)| {
-                    perp.remove_order(order.order_id()).expect("order exists");
-                    StateEvents::order(perp, &order, ctx, OrderEventType::Removed)
-                }),
-                self.account(e.accountId).map(|acc| {
-                    acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                    StateEvents::account(
-                        acc,
-                        ctx,
-                        AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                    )
-                }),
-            )
-            .collect(),
             ExchangeEvents::OrderChanged(e) => {
                 let c = must_ctx()?;
-                let order_id = c.order_id.expect("order_id required for OrderChanged");
+                let order_id = c.order_id.ok_or_else(|| {
+                    DexError::MalformedData("order id required for OrderChanged".to_string())
+                })?;
                 chain!(
-                    if let Some(perp) = self.perpetuals.get_mut(&c.perpetual_id) {
+                    if let Some(perp) = self.book(c.perpetual_id)
+                        && e.lotLNS.is_zero()
+                    {
+                        // Changed order got completely filled crossing the spread, and could
+                        // have been removed already by `TakerOrderFilled`
+                        perp.get_order(order_id).copied().map(|order| {
+                            perp.remove_order(order_id).expect("order exists");
+                            StateEvents::order(perp, &order, ctx, OrderEventType::Removed)
+                        })
+                    } else if let Some(perp) = self.book(c.perpetual_id) {
                         let order = perp
                             .get_order(order_id)
                             .copied()
                             .ok_or(DexError::OrderNotFound(perp.id(), order_id))?;
-                        let new_price = perp.price_converter().from_unsigned(e.pricePNS);
-                        let new_size = perp.size_converter().from_unsigned(e.lotLNS);
-                        let new_expiry_block = e.expiryBlock.to();
+                        let new_price = perp.price_converter().try_from_unsigned(e.pricePNS)?;
+                        let new_size = perp.size_converter().try_from_unsigned(e.lotLNS)?;
+                        let new_expiry_block = num::narrow(e.expiryBlock, "expiry block")?;
                         let price_update =
-                            if order.price() != new_price { Some(new_price) } else { None };
-                        let size_update =
-                            if order.size() != new_size { Some(new_size) } else { None };
+                            (order.price() != new_price).then_some(new_price.get());
+                        let size_update = (order.size() != new_size).then_some(new_size.get());
                         let expiry_block_update = if order.expiry_block() != new_expiry_block {
                             Some(new_expiry_block)
                         } else {
@@ -1064,18 +2217,25 @@ impl Exchange {
                         None
                     },
                     if let Some(acc) = self.accounts.get_mut(&c.account_id) {
-                        acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                        acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
+                        acc.update_locked_balance(
+                            instant,
+                            cc.try_from_unsigned(e.lockedBalanceCNS)?,
+                        );
+                        acc.update_balance(
+                            instant,
+                            cc.try_from_unsigned(e.balanceCNS)?,
+                            BalanceChangeReason::Fee,
+                        );
                         vec![
                             StateEvents::account(
                                 acc,
                                 ctx,
-                                AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
+                                AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
                             ),
                             StateEvents::account(
                                 acc,
                                 ctx,
-                                AccountEventType::BalanceUpdated(acc.balance()),
+                                AccountEventType::BalanceUpdated(acc.balance().get()),
                             ),
                         ]
                     } else {
@@ -1094,65 +2254,71 @@ impl Exchange {
             ExchangeEvents::OrderForwardingUpdated(_) => vec![],
             ExchangeEvents::OrderPlaced(e) => {
                 let c = must_ctx()?;
-                let order_id = std::num::NonZeroU16::new(e.orderId.to::<u16>())
-                    .expect("orderId in OrderPlaced event cannot be 0");
-                chain!(
-                    if let Some(perp) = self.perpetuals.get_mut(&c.perpetual_id) {
-                        let order = Order::placed(
-                            instant,
-                            c,
-                            order_id,
-                            perp.size_converter().from_unsigned(e.lotLNS),
-                            perp.price_converter(),
-                            perp.leverage_converter(),
+                let order_id = event_order_id(e.orderId)?;
+                let capacity_threshold = self.book_capacity_threshold;
+                let mut events = Vec::with_capacity(4);
+                if let Some(perp) = self.book(c.perpetual_id) {
+                    let order = Order::placed(
+                        instant,
+                        c,
+                        order_id,
+                        perp.size_converter().try_from_unsigned(e.lotLNS)?.get(),
+                        perp.price_converter(),
+                        perp.leverage_converter(),
+                    )?;
+                    let event = OrderEventType::Placed {
+                        r#type: order.r#type(),
+                        price: order.price().get(),
+                        size: order.size().get(),
+                        expiry_block: order.expiry_block(),
+                        leverage: order.leverage(),
+                        post_only: order.post_only().unwrap_or_default(),
+                        fill_or_kill: order.fill_or_kill().unwrap_or_default(),
+                        immediate_or_cancel: order.immediate_or_cancel().unwrap_or_default(),
+                    };
+                    let was_near_capacity = perp.l3_book().is_near_capacity(capacity_threshold);
+                    perp.add_order(order)?;
+                    let near_capacity =
+                        !was_near_capacity && perp.l3_book().is_near_capacity(capacity_threshold);
+                    if near_capacity {
+                        tracing::warn!(
+                            perp_id = perp.id(),
+                            orders = perp.total_orders(),
+                            "order book is near capacity"
                         );
-                        let event = OrderEventType::Placed {
-                            r#type: order.r#type(),
-                            price: order.price(),
-                            size: order.size(),
-                            expiry_block: order.expiry_block(),
-                            leverage: order.leverage(),
-                            post_only: order.post_only().unwrap_or_default(),
-                            fill_or_kill: order.fill_or_kill().unwrap_or_default(),
-                            immediate_or_cancel: order.immediate_or_cancel().unwrap_or_default(),
-                        };
-                        perp.add_order(order)?;
-                        Some(StateEvents::order(perp, &order, ctx, event))
-                    } else {
-                        None
-                    },
-                    if let Some(acc) = self.accounts.get_mut(&c.account_id) {
-                        acc.update_locked_balance(instant, cc.from_unsigned(e.lockedBalanceCNS));
-                        acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                        vec![
-                            StateEvents::account(
-                                acc,
-                                ctx,
-                                AccountEventType::LockedBalanceUpdated(acc.locked_balance()),
-                            ),
-                            StateEvents::account(
-                                acc,
-                                ctx,
-                                AccountEventType::BalanceUpdated(acc.balance()),
-                            ),
-                        ]
-                    } else {
-                        vec![]
-                    },
-                )
-                .collect()
+                    }
+                    events.push(StateEvents::order(perp, &order, ctx, event));
+                    if near_capacity {
+                        events.push(StateEvents::perpetual(
+                            perp,
+                            PerpetualEventType::BookNearCapacity(perp.total_orders()),
+                        ));
+                    }
+                }
+                if let Some(acc) = self.accounts.get_mut(&c.account_id) {
+                    let (locked_balance, balance) = (
+                        cc.try_from_unsigned(e.lockedBalanceCNS)?,
+                        cc.try_from_unsigned(e.balanceCNS)?,
+                    );
+                    let updated =
+                        order_balances_updated(acc, instant, locked_balance, balance, ctx);
+                    events.extend(updated);
+                }
+                events
+            },
+            ExchangeEvents::OrderPostFailed(e) => {
+                let reason = num::narrow(e.reason, "order post failure reason")?;
+                self.err_ctx(ctx, event)?
+                    .map(|ctx| {
+                        StateEvents::order_error(ctx, OrderErrorType::OrderPostFailed(reason))
+                    })
+                    .into_iter()
+                    .collect()
             },
-            ExchangeEvents::OrderPostFailed(e) => self
-                .err_ctx(ctx, event)?
-                .map(|ctx| {
-                    StateEvents::order_error(ctx, OrderErrorType::OrderPostFailed(e.reason.to()))
-                })
-                .into_iter()
-                .collect(),
             ExchangeEvents::OrderRequest(e) => {
                 // Store order request context as it is required to handle
                 // future events
-                ctx.replace(OrderContext::from(e));
+                ctx.replace(OrderContext::try_from(e)?);
                 vec![]
             },
             ExchangeEvents::OrderSettlementImpliesInsolvent(_) => self
@@ -1180,7 +2346,7 @@ impl Exchange {
                     ctx.position_closed_at_log_index = Some(event.log_index());
                 }
 
-                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId) {
+                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId)? {
                     let pos = acc
                         .positions_mut()
                         .remove(&perp.id())
@@ -1192,15 +2358,15 @@ impl Exchange {
                             ctx,
                             PositionEventType::Closed {
                                 r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                exit_price: perp.price_converter().from_unsigned(e.pricePNS),
-                                size: pos.size(),
-                                delta_pnl: cc.from_signed(e.deltaPnlCNS),
-                                premium_pnl: cc.from_signed(e.fundingCNS),
+                                entry_price: pos.entry_price().get(),
+                                exit_price: perp.price_converter().try_from_unsigned(e.pricePNS)?.get(),
+                                size: pos.size().get(),
+                                delta_pnl: cc.try_from_signed(e.deltaPnlCNS)?,
+                                premium_pnl: cc.try_from_signed(e.fundingCNS)?,
                             }
                         )),
-                        if PositionType::from(e.positionType) == PositionType::Long {
-                            perp.update_open_interest(instant, pos.size(), UD64::ZERO);
+                        if PositionType::try_from(e.positionType)? == PositionType::Long {
+                            perp.update_open_interest(instant, pos.size().get(), UD64::ZERO);
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1217,17 +2383,22 @@ impl Exchange {
             ExchangeEvents::PositionCollateralDecreased(e) => {
                 if let Some((pos, perp)) = self.position(e.accountId, e.perpId)? {
                     let prev_entry_price = pos.entry_price();
-                    pos.update_entry_price(instant, e.endEntryPricePNS, 0, perp.price_converter());
-                    pos.update_deposit(instant, cc.from_unsigned(e.endDepositCNS));
+                    pos.update_entry_price(
+                        instant,
+                        num::narrow(e.endEntryPricePNS, "entry price")?,
+                        0,
+                        perp.price_converter(),
+                    );
+                    pos.update_deposit(instant, cc.try_from_unsigned(e.endDepositCNS)?.get());
                     pos.apply_mark_price(instant, perp.mark_price());
                     pos.apply_maintenance_margin(instant, perp.maintenance_margin());
                     vec![StateEvents::position(
                         pos,
                         ctx,
                         PositionEventType::CollateralDecreased {
-                            prev_entry_price,
-                            new_entry_price: pos.entry_price(),
-                            deposit: pos.deposit(),
+                            prev_entry_price: prev_entry_price.get(),
+                            new_entry_price: pos.entry_price().get(),
+                            deposit: pos.deposit().get(),
                         },
                     )]
                 } else {
@@ -1237,12 +2408,12 @@ impl Exchange {
             ExchangeEvents::PositionDecreased(e) => {
                 if let Some((pos, perp)) = self.position(e.accountId, e.perpId)? {
                     let prev_size = pos.size();
-                    pos.update_size(instant, perp.size_converter().from_unsigned(e.endLotLNS));
-                    pos.update_deposit(instant, cc.from_unsigned(e.endDepositCNS));
+                    pos.update_size(instant, perp.size_converter().try_from_unsigned(e.endLotLNS)?.get());
+                    pos.update_deposit(instant, cc.try_from_unsigned(e.endDepositCNS)?.get());
                     pos.apply_mark_price(instant, perp.mark_price());
                     pos.update_premium_pnl(
                         instant,
-                        pos.premium_pnl().sub(cc.from_signed(e.fundingCNS)),
+                        pos.premium_pnl().sub(cc.try_from_signed(e.fundingCNS)?),
                     );
                     pos.apply_maintenance_margin(instant, perp.maintenance_margin());
                     chain!(
@@ -1250,15 +2421,15 @@ impl Exchange {
                             pos,
                             ctx,
                             PositionEventType::Decreased {
-                                prev_size,
-                                new_size: pos.size(),
-                                deposit: pos.deposit(),
+                                prev_size: prev_size.get(),
+                                new_size: pos.size().get(),
+                                deposit: pos.deposit().get(),
                                 delta_pnl: pos.delta_pnl(),
                                 premium_pnl: pos.premium_pnl(),
                             }
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, prev_size, pos.size());
+                            perp.update_open_interest(instant, prev_size.get(), pos.size().get());
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1272,119 +2443,146 @@ impl Exchange {
                     vec![]
                 }
             },
-            ExchangeEvents::PositionDeleveraged(e) => chain!(
-                if let Some((pos, perp)) = self.position(e.accountId, e.perpId)? {
-                    let prev_size = pos.size();
-                    pos.update_size(instant, perp.size_converter().from_unsigned(e.endLotLNS));
-                    pos.update_deposit(instant, cc.from_unsigned(e.endDepositCNS));
-                    pos.apply_mark_price(instant, perp.mark_price());
-                    pos.update_premium_pnl(
-                        instant,
-                        pos.premium_pnl().sub(cc.from_signed(e.fundingCNS)),
-                    );
-                    pos.apply_maintenance_margin(instant, perp.maintenance_margin());
-                    chain!(
-                        Some(StateEvents::position(
-                            pos,
+            ExchangeEvents::PositionDeleveraged(e) => {
+                let balance = cc.try_from_unsigned(e.balanceCNS)?;
+                let perp_id = num::narrow(e.perpId, "perpetual id")?;
+                chain!(
+                    if let Some((pos, perp)) = self.position(e.accountId, e.perpId)? {
+                        let prev_size = pos.size();
+                        pos.update_size(
+                            instant,
+                            perp.size_converter().try_from_unsigned(e.endLotLNS)?.get(),
+                        );
+                        pos.update_deposit(instant, cc.try_from_unsigned(e.endDepositCNS)?.get());
+                        pos.apply_mark_price(instant, perp.mark_price());
+                        pos.update_premium_pnl(
+                            instant,
+                            pos.premium_pnl().sub(cc.try_from_signed(e.fundingCNS)?),
+                        );
+                        pos.apply_maintenance_margin(instant, perp.maintenance_margin());
+                        chain!(
+                            Some(StateEvents::position(
+                                pos,
+                                ctx,
+                                PositionEventType::Deleveraged {
+                                    force_close: e.forceClose,
+                                    r#type: pos.r#type(),
+                                    entry_price: pos.entry_price().get(),
+                                    exit_price: perp
+                                        .price_converter()
+                                        .try_from_unsigned(e.deleveragePricePNS)?.get(),
+                                    prev_size: prev_size.get(),
+                                    new_size: pos.size().get(),
+                                    deposit: pos.deposit().get(),
+                                    delta_pnl: pos.delta_pnl(),
+                                    premium_pnl: pos.premium_pnl(),
+                                }
+                            )),
+                            if pos.r#type() == PositionType::Long {
+                                perp.update_open_interest(instant, prev_size.get(), pos.size().get());
+                                Some(StateEvents::perpetual(
+                                    perp,
+                                    PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
+                                ))
+                            } else {
+                                None
+                            },
+                        )
+                        .collect()
+                    } else {
+                        vec![]
+                    },
+                    self.account(e.accountId)?.map(|acc| {
+                        if e.endLotLNS == U256::ZERO {
+                            acc.positions_mut()
+                                .remove(&perp_id);
+                        }
+                        acc.update_balance(instant, balance, BalanceChangeReason::Liquidation);
+                        StateEvents::account(
+                            acc,
                             ctx,
-                            PositionEventType::Deleveraged {
-                                force_close: e.forceClose,
-                                r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                exit_price: perp
-                                    .price_converter()
-                                    .from_unsigned(e.deleveragePricePNS),
-                                prev_size,
-                                new_size: pos.size(),
-                                deposit: pos.deposit(),
-                                delta_pnl: pos.delta_pnl(),
-                                premium_pnl: pos.premium_pnl(),
-                            }
-                        )),
-                        if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, prev_size, pos.size());
-                            Some(StateEvents::perpetual(
-                                perp,
-                                PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
-                            ))
-                        } else {
-                            None
-                        },
-                    )
-                    .collect()
-                } else {
-                    vec![]
-                },
-                self.account(e.accountId).map(|acc| {
-                    if e.endLotLNS == U256::ZERO {
-                        acc.positions_mut()
-                            .remove(&e.perpId.to::<types::PerpetualId>());
-                    }
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                }),
-            )
-            .collect(),
-            ExchangeEvents::PositionDeleveragedV2(e) => chain!(
-                if let Some((pos, perp)) = self.position(e.accountId, e.perpId)? {
-                    let prev_size = pos.size();
-                    pos.update_size(instant, perp.size_converter().from_unsigned(e.endLotLNS));
-                    pos.update_deposit(instant, cc.from_unsigned(e.endDepositCNS));
-                    pos.apply_mark_price(instant, perp.mark_price());
-                    pos.update_premium_pnl(
-                        instant,
-                        pos.premium_pnl().sub(cc.from_signed(e.fundingCNS)),
-                    );
-                    pos.apply_maintenance_margin(instant, perp.maintenance_margin());
-                    chain!(
-                        Some(StateEvents::position(
-                            pos,
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    }),
+                )
+                .collect()
+            },
+            ExchangeEvents::PositionDeleveragedV2(e) => {
+                let balance = cc.try_from_unsigned(e.balanceCNS)?;
+                let perp_id = num::narrow(e.perpId, "perpetual id")?;
+                chain!(
+                    if let Some((pos, perp)) = self.position(e.accountId, e.perpId)? {
+                        let prev_size = pos.size();
+                        pos.update_size(
+                            instant,
+                            perp.size_converter().try_from_unsigned(e.endLotLNS)?.get(),
+                        );
+                        pos.update_deposit(instant, cc.try_from_unsigned(e.endDepositCNS)?.get());
+                        pos.apply_mark_price(instant, perp.mark_price());
+                        pos.update_premium_pnl(
+                            instant,
+                            pos.premium_pnl().sub(cc.try_from_signed(e.fundingCNS)?),
+                        );
+                        pos.apply_maintenance_margin(instant, perp.maintenance_margin());
+                        chain!(
+                            Some(StateEvents::position(
+                                pos,
+                                ctx,
+                                PositionEventType::Deleveraged {
+                                    force_close: e.forceClose,
+                                    r#type: pos.r#type(),
+                                    entry_price: pos.entry_price().get(),
+                                    exit_price: perp
+                                        .price_converter()
+                                        .try_from_unsigned(e.deleveragePricePNS)?.get(),
+                                    prev_size: prev_size.get(),
+                                    new_size: pos.size().get(),
+                                    deposit: pos.deposit().get(),
+                                    delta_pnl: pos.delta_pnl(),
+                                    premium_pnl: pos.premium_pnl(),
+                                }
+                            )),
+                            if pos.r#type() == PositionType::Long {
+                                perp.update_open_interest(instant, prev_size.get(), pos.size().get());
+                                Some(StateEvents::perpetual(
+                                    perp,
+                                    PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
+                                ))
+                            } else {
+                                None
+                            },
+                        )
+                        .collect()
+                    } else {
+                        vec![]
+                    },
+                    self.account(e.accountId)?.map(|acc| {
+                        if e.endLotLNS == U256::ZERO {
+                            acc.positions_mut()
+                                .remove(&perp_id);
+                        }
+                        acc.update_balance(instant, balance, BalanceChangeReason::Liquidation);
+                        StateEvents::account(
+                            acc,
                             ctx,
-                            PositionEventType::Deleveraged {
-                                force_close: e.forceClose,
-                                r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                exit_price: perp
-                                    .price_converter()
-                                    .from_unsigned(e.deleveragePricePNS),
-                                prev_size,
-                                new_size: pos.size(),
-                                deposit: pos.deposit(),
-                                delta_pnl: pos.delta_pnl(),
-                                premium_pnl: pos.premium_pnl(),
-                            }
-                        )),
-                        if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, prev_size, pos.size());
-                            Some(StateEvents::perpetual(
-                                perp,
-                                PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
-                            ))
-                        } else {
-                            None
-                        },
-                    )
-                    .collect()
-                } else {
-                    vec![]
-                },
-                self.account(e.accountId).map(|acc| {
-                    if e.endLotLNS == U256::ZERO {
-                        acc.positions_mut()
-                            .remove(&e.perpId.to::<types::PerpetualId>());
-                    }
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                }),
-            )
-            .collect(),
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    }),
+                )
+                .collect()
+            },
             ExchangeEvents::PositionDoesNotExist(_) => vec![],
             ExchangeEvents::PositionIncreased(e) => {
                 if let Some((pos, perp)) = self.position(e.accountId, e.perpId)? {
                     let prev_size = pos.size();
-                    pos.update_entry_price(instant, e.pricePNS, 0, perp.price_converter());
-                    pos.update_size(instant, perp.size_converter().from_unsigned(e.endLotLNS));
-                    pos.update_deposit(instant, cc.from_unsigned(e.endDepositCNS));
+                    pos.update_entry_price(
+                        instant,
+                        num::narrow(e.pricePNS, "entry price")?,
+                        0,
+                        perp.price_converter(),
+                    );
+                    pos.update_size(instant, perp.size_converter().try_from_unsigned(e.endLotLNS)?.get());
+                    pos.update_deposit(instant, cc.try_from_unsigned(e.endDepositCNS)?.get());
                     pos.apply_mark_price(instant, perp.mark_price());
                     pos.update_premium_pnl(instant, D256::ZERO);
                     pos.apply_maintenance_margin(instant, perp.maintenance_margin());
@@ -1394,14 +2592,14 @@ impl Exchange {
                             pos,
                             ctx,
                             PositionEventType::Increased {
-                                entry_price: pos.entry_price(),
-                                prev_size,
-                                new_size: pos.size(),
-                                deposit: pos.deposit(),
+                                entry_price: pos.entry_price().get(),
+                                prev_size: prev_size.get(),
+                                new_size: pos.size().get(),
+                                deposit: pos.deposit().get(),
                             }
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, prev_size, pos.size());
+                            perp.update_open_interest(instant, prev_size.get(), pos.size().get());
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1420,12 +2618,12 @@ impl Exchange {
                     let prev_size = pos.size();
                     pos.update_entry_price(
                         instant,
-                        e.pricePNS,
-                        e.priceResiduePNSQ16.to(),
+                        num::narrow(e.pricePNS, "entry price")?,
+                        num::narrow(e.priceResiduePNSQ16, "price residue")?,
                         perp.price_converter(),
                     );
-                    pos.update_size(instant, perp.size_converter().from_unsigned(e.endLotLNS));
-                    pos.update_deposit(instant, cc.from_unsigned(e.endDepositCNS));
+                    pos.update_size(instant, perp.size_converter().try_from_unsigned(e.endLotLNS)?.get());
+                    pos.update_deposit(instant, cc.try_from_unsigned(e.endDepositCNS)?.get());
                     pos.apply_mark_price(instant, perp.mark_price());
                     pos.update_premium_pnl(instant, D256::ZERO);
                     pos.apply_maintenance_margin(instant, perp.maintenance_margin());
@@ -1435,14 +2633,14 @@ impl Exchange {
                             pos,
                             ctx,
                             PositionEventType::Increased {
-                                entry_price: pos.entry_price(),
-                                prev_size,
-                                new_size: pos.size(),
-                                deposit: pos.deposit(),
+                                entry_price: pos.entry_price().get(),
+                                prev_size: prev_size.get(),
+                                new_size: pos.size().get(),
+                                deposit: pos.deposit().get(),
                             }
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, prev_size, pos.size());
+                            perp.update_open_interest(instant, prev_size.get(), pos.size().get());
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1461,17 +2659,22 @@ impl Exchange {
                     let prev_type = pos.r#type();
                     let prev_entry_price = pos.entry_price();
                     let prev_size = pos.size();
-                    pos.update_type(instant, PositionType::from(e.positionType));
-                    pos.update_entry_price(instant, e.pricePNS, 0, perp.price_converter());
-                    pos.update_size(instant, perp.size_converter().from_unsigned(e.endLotLNS));
-                    pos.update_deposit(instant, cc.from_unsigned(e.endDepositCNS));
+                    pos.update_type(instant, PositionType::try_from(e.positionType)?);
+                    pos.update_entry_price(
+                        instant,
+                        num::narrow(e.pricePNS, "entry price")?,
+                        0,
+                        perp.price_converter(),
+                    );
+                    pos.update_size(instant, perp.size_converter().try_from_unsigned(e.endLotLNS)?.get());
+                    pos.update_deposit(instant, cc.try_from_unsigned(e.endDepositCNS)?.get());
                     pos.apply_mark_price(instant, perp.mark_price());
                     pos.update_premium_pnl(instant, D256::ZERO);
                     pos.apply_maintenance_margin(instant, perp.maintenance_margin());
                     if pos.r#type() == PositionType::Long {
-                        perp.update_open_interest(instant, UD64::ZERO, pos.size());
+                        perp.update_open_interest(instant, UD64::ZERO, pos.size().get());
                     } else {
-                        perp.update_open_interest(instant, prev_size, UD64::ZERO);
+                        perp.update_open_interest(instant, prev_size.get(), UD64::ZERO);
                     }
                     vec![
                         StateEvents::position(
@@ -1479,11 +2682,11 @@ impl Exchange {
                             ctx,
                             PositionEventType::Closed {
                                 r#type: prev_type,
-                                entry_price: prev_entry_price,
-                                exit_price: pos.entry_price(),
-                                size: prev_size,
-                                delta_pnl: cc.from_signed(e.deltaPnlCNS),
-                                premium_pnl: cc.from_signed(e.fundingCNS),
+                                entry_price: prev_entry_price.get(),
+                                exit_price: pos.entry_price().get(),
+                                size: prev_size.get(),
+                                delta_pnl: cc.try_from_signed(e.deltaPnlCNS)?,
+                                premium_pnl: cc.try_from_signed(e.fundingCNS)?,
                             },
                         ),
                         StateEvents::position(
@@ -1491,10 +2694,10 @@ impl Exchange {
                             ctx,
                             PositionEventType::Inverted {
                                 r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                prev_size,
-                                new_size: pos.size(),
-                                deposit: pos.deposit(),
+                                entry_price: pos.entry_price().get(),
+                                prev_size: prev_size.get(),
+                                new_size: pos.size().get(),
+                                deposit: pos.deposit().get(),
                                 delta_pnl: pos.delta_pnl(),
                                 premium_pnl: pos.premium_pnl(),
                             },
@@ -1508,81 +2711,98 @@ impl Exchange {
                     vec![]
                 }
             },
-            ExchangeEvents::PositionLiquidated(e) => chain!(
-                if let Some((pos, perp)) = self.position(e.posAccountId, e.perpId)? {
-                    let prev_size = pos.size();
-                    pos.update_size(instant, perp.size_converter().from_unsigned(e.posLotLNS));
-                    pos.update_deposit(instant, cc.from_unsigned(e.posDepositCNS));
-                    pos.apply_mark_price(instant, perp.mark_price());
-                    pos.update_premium_pnl(
-                        instant,
-                        pos.premium_pnl().sub(cc.from_signed(e.fundingCNS)),
-                    );
-                    pos.apply_maintenance_margin(instant, perp.maintenance_margin());
-                    chain!(
-                        Some(StateEvents::position(
+            ExchangeEvents::PositionLiquidated(e) => {
+                let acc_balance = cc.try_from_unsigned(e.accBalanceCNS)?;
+                let perp_id = num::narrow(e.perpId, "perpetual id")?;
+                chain!(
+                    if let Some((pos, perp)) = self.position(e.posAccountId, e.perpId)? {
+                        let prev_size = pos.size();
+                        pos.update_size(
+                            instant,
+                            perp.size_converter().try_from_unsigned(e.posLotLNS)?.get(),
+                        );
+                        pos.update_deposit(instant, cc.try_from_unsigned(e.posDepositCNS)?.get());
+                        pos.apply_mark_price(instant, perp.mark_price());
+                        pos.update_premium_pnl(
+                            instant,
+                            pos.premium_pnl().sub(cc.try_from_signed(e.fundingCNS)?),
+                        );
+                        pos.apply_maintenance_margin(instant, perp.maintenance_margin());
+                        chain!(
+                            Some(StateEvents::position(
+                                pos,
+                                ctx,
+                                PositionEventType::Liquidated {
+                                    r#type: pos.r#type(),
+                                    entry_price: pos.entry_price().get(),
+                                    exit_price: perp
+                                        .price_converter()
+                                        .try_from_unsigned(e.liqPricePNS)?.get(),
+                                    prev_size: prev_size.get(),
+                                    liquidated_size: perp
+                                        .size_converter()
+                                        .try_from_unsigned(e.liqLotLNS)?.get(),
+                                    new_size: pos.size().get(),
+                                    deposit: pos.deposit().get(),
+                                    delta_pnl: pos.delta_pnl(),
+                                    premium_pnl: pos.premium_pnl(),
+                                }
+                            )),
+                            if pos.r#type() == PositionType::Long {
+                                perp.update_open_interest(instant, prev_size.get(), pos.size().get());
+                                Some(StateEvents::perpetual(
+                                    perp,
+                                    PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
+                                ))
+                            } else {
+                                None
+                            },
+                        )
+                        .collect()
+                    } else {
+                        vec![]
+                    },
+                    self.account(e.posAccountId)?.map(|acc| {
+                        if e.posLotLNS == U256::ZERO {
+                            acc.positions_mut()
+                                .remove(&perp_id);
+                        }
+                        acc.update_balance(instant, acc_balance, BalanceChangeReason::Liquidation);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    }),
+                )
+                .collect()
+            },
+            ExchangeEvents::PositionLiquidationCredit(e) => {
+                let end_deposit = cc.try_from_unsigned(e.endDepositCNS)?;
+                self.position(e.accountId, e.perpId)?
+                    .map(|(pos, _)| {
+                        pos.update_deposit(instant, end_deposit.get());
+                        StateEvents::position(
                             pos,
                             ctx,
-                            PositionEventType::Liquidated {
-                                r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                exit_price: perp.price_converter().from_unsigned(e.liqPricePNS),
-                                prev_size,
-                                liquidated_size: perp.size_converter().from_unsigned(e.liqLotLNS),
-                                new_size: pos.size(),
-                                deposit: pos.deposit(),
-                                delta_pnl: pos.delta_pnl(),
-                                premium_pnl: pos.premium_pnl(),
-                            }
-                        )),
-                        if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, prev_size, pos.size());
-                            Some(StateEvents::perpetual(
-                                perp,
-                                PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
-                            ))
-                        } else {
-                            None
-                        },
-                    )
+                            PositionEventType::DepositUpdated(pos.deposit().get()),
+                        )
+                    })
+                    .into_iter()
                     .collect()
-                } else {
-                    vec![]
-                },
-                self.account(e.posAccountId).map(|acc| {
-                    if e.posLotLNS == U256::ZERO {
-                        acc.positions_mut()
-                            .remove(&e.perpId.to::<types::PerpetualId>());
-                    }
-                    acc.update_balance(instant, cc.from_unsigned(e.accBalanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                }),
-            )
-            .collect(),
-            ExchangeEvents::PositionLiquidationCredit(e) => self
-                .position(e.accountId, e.perpId)?
-                .map(|(pos, _)| {
-                    pos.update_deposit(instant, cc.from_unsigned(e.endDepositCNS));
-                    StateEvents::position(
-                        pos,
-                        ctx,
-                        PositionEventType::DepositUpdated(pos.deposit()),
-                    )
-                })
-                .into_iter()
-                .collect(),
+            },
             ExchangeEvents::PositionOpened(e) => {
-                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId) {
+                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId)? {
                     let pos = Position::opened(
                         instant,
                         perp.id(),
                         acc.id(),
-                        PositionType::from(e.positionType),
-                        e.pricePNS,
+                        PositionType::try_from(e.positionType)?,
+                        num::narrow(e.pricePNS, "entry price")?,
                         0,
                         perp.price_converter(),
-                        perp.size_converter().from_unsigned(e.lotLNS),
-                        cc.from_unsigned(e.depositCNS),
+                        perp.size_converter().try_from_unsigned(e.lotLNS)?.get(),
+                        cc.try_from_unsigned(e.depositCNS)?.get(),
                         perp.maintenance_margin(),
                     );
                     let events = chain!(
@@ -1591,13 +2811,13 @@ impl Exchange {
                             ctx,
                             PositionEventType::Opened {
                                 r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                size: pos.size(),
-                                deposit: pos.deposit(),
+                                entry_price: pos.entry_price().get(),
+                                size: pos.size().get(),
+                                deposit: pos.deposit().get(),
                             }
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, UD64::ZERO, pos.size());
+                            perp.update_open_interest(instant, UD64::ZERO, pos.size().get());
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1614,17 +2834,17 @@ impl Exchange {
                 }
             },
             ExchangeEvents::PositionOpenedV2(e) => {
-                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId) {
+                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId)? {
                     let pos = Position::opened(
                         instant,
                         perp.id(),
                         acc.id(),
-                        PositionType::from(e.positionType),
-                        e.pricePNS,
-                        e.priceResiduePNSQ16.to(),
+                        PositionType::try_from(e.positionType)?,
+                        num::narrow(e.pricePNS, "entry price")?,
+                        num::narrow(e.priceResiduePNSQ16, "price residue")?,
                         perp.price_converter(),
-                        perp.size_converter().from_unsigned(e.lotLNS),
-                        cc.from_unsigned(e.depositCNS),
+                        perp.size_converter().try_from_unsigned(e.lotLNS)?.get(),
+                        cc.try_from_unsigned(e.depositCNS)?.get(),
                         perp.maintenance_margin(),
                     );
                     let events = chain!(
@@ -1633,13 +2853,13 @@ impl Exchange {
                             ctx,
                             PositionEventType::Opened {
                                 r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                size: pos.size(),
-                                deposit: pos.deposit(),
+                                entry_price: pos.entry_price().get(),
+                                size: pos.size().get(),
+                                deposit: pos.deposit().get(),
                             }
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, UD64::ZERO, pos.size());
+                            perp.update_open_interest(instant, UD64::ZERO, pos.size().get());
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1656,32 +2876,36 @@ impl Exchange {
                 }
             },
             ExchangeEvents::PositionUnwound(e) => {
-                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId) {
+                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId)? {
                     let pos = acc
                         .positions_mut()
                         .remove(&perp.id())
                         .ok_or(DexError::PositionNotFound(acc.id(), perp.id()))?;
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
+                    acc.update_balance(
+                        instant,
+                        cc.try_from_unsigned(e.balanceCNS)?,
+                        BalanceChangeReason::Liquidation,
+                    );
                     chain!(
                         Some(StateEvents::position(
                             &pos,
                             ctx,
                             PositionEventType::Unwound {
                                 r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                exit_price: perp.price_converter().from_unsigned(e.pricePNS),
-                                size: pos.size(),
-                                fair_market_value: cc.from_signed(e.positionFmvCNS),
-                                payment: cc.from_unsigned(e.paymentCNS),
+                                entry_price: pos.entry_price().get(),
+                                exit_price: perp.price_converter().try_from_unsigned(e.pricePNS)?.get(),
+                                size: pos.size().get(),
+                                fair_market_value: cc.try_from_signed(e.positionFmvCNS)?,
+                                payment: cc.try_from_unsigned(e.paymentCNS)?.get(),
                             }
                         )),
                         Some(StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance())
+                            AccountEventType::BalanceUpdated(acc.balance().get())
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, pos.size(), UD64::ZERO);
+                            perp.update_open_interest(instant, pos.size().get(), UD64::ZERO);
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1697,32 +2921,36 @@ impl Exchange {
             },
             ExchangeEvents::PositionUnwoundV2(e) => {
                 // Position is being removed; residue field is informational only.
-                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId) {
+                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId)? {
                     let pos = acc
                         .positions_mut()
                         .remove(&perp.id())
                         .ok_or(DexError::PositionNotFound(acc.id(), perp.id()))?;
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
+                    acc.update_balance(
+                        instant,
+                        cc.try_from_unsigned(e.balanceCNS)?,
+                        BalanceChangeReason::Liquidation,
+                    );
                     chain!(
                         Some(StateEvents::position(
                             &pos,
                             ctx,
                             PositionEventType::Unwound {
                                 r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                exit_price: perp.price_converter().from_unsigned(e.pricePNS),
-                                size: pos.size(),
-                                fair_market_value: cc.from_signed(e.positionFmvCNS),
-                                payment: cc.from_unsigned(e.paymentCNS),
+                                entry_price: pos.entry_price().get(),
+                                exit_price: perp.price_converter().try_from_unsigned(e.pricePNS)?.get(),
+                                size: pos.size().get(),
+                                fair_market_value: cc.try_from_signed(e.positionFmvCNS)?,
+                                payment: cc.try_from_unsigned(e.paymentCNS)?.get(),
                             }
                         )),
                         Some(StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance())
+                            AccountEventType::BalanceUpdated(acc.balance().get())
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, pos.size(), UD64::ZERO);
+                            perp.update_open_interest(instant, pos.size().get(), UD64::ZERO);
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1737,7 +2965,7 @@ impl Exchange {
                 }
             },
             ExchangeEvents::PositionUnwoundWithoutPayment(e) => {
-                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId) {
+                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId)? {
                     let pos = acc
                         .positions_mut()
                         .remove(&perp.id())
@@ -1748,15 +2976,15 @@ impl Exchange {
                             ctx,
                             PositionEventType::Unwound {
                                 r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                exit_price: perp.price_converter().from_unsigned(e.pricePNS),
-                                size: pos.size(),
-                                fair_market_value: cc.from_signed(e.positionFmvCNS),
+                                entry_price: pos.entry_price().get(),
+                                exit_price: perp.price_converter().try_from_unsigned(e.pricePNS)?.get(),
+                                size: pos.size().get(),
+                                fair_market_value: cc.try_from_signed(e.positionFmvCNS)?,
                                 payment: UD128::ZERO,
                             }
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, pos.size(), UD64::ZERO);
+                            perp.update_open_interest(instant, pos.size().get(), UD64::ZERO);
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1772,7 +3000,7 @@ impl Exchange {
             },
             ExchangeEvents::PositionUnwoundWithoutPaymentV2(e) => {
                 // Position is being removed; residue field is informational only.
-                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId) {
+                if let Some((acc, perp)) = self.account_perpetual(e.accountId, e.perpId)? {
                     let pos = acc
                         .positions_mut()
                         .remove(&perp.id())
@@ -1783,15 +3011,15 @@ impl Exchange {
                             ctx,
                             PositionEventType::Unwound {
                                 r#type: pos.r#type(),
-                                entry_price: pos.entry_price(),
-                                exit_price: perp.price_converter().from_unsigned(e.pricePNS),
-                                size: pos.size(),
-                                fair_market_value: cc.from_signed(e.positionFmvCNS),
+                                entry_price: pos.entry_price().get(),
+                                exit_price: perp.price_converter().try_from_unsigned(e.pricePNS)?.get(),
+                                size: pos.size().get(),
+                                fair_market_value: cc.try_from_signed(e.positionFmvCNS)?,
                                 payment: UD128::ZERO,
                             }
                         )),
                         if pos.r#type() == PositionType::Long {
-                            perp.update_open_interest(instant, pos.size(), UD64::ZERO);
+                            perp.update_open_interest(instant, pos.size().get(), UD64::ZERO);
                             Some(StateEvents::perpetual(
                                 perp,
                                 PerpetualEventType::OpenInterestUpdated(perp.open_interest()),
@@ -1812,10 +3040,20 @@ impl Exchange {
                 .collect(),
             ExchangeEvents::PriceAdministratorUpdated(_) => vec![],
             ExchangeEvents::PriceMaxAgeUpdated(e) => {
-                if let Some(perp) = self.perpetual(e.perpId) {
-                    perp.update_price_max_age_sec(instant, e.maxAgeSec.to());
-                }
-                vec![]
+                let max_age_sec = num::narrow(e.maxAgeSec, "price max age")?;
+                self.perpetual(e.perpId)?
+                    .and_then(|perp| {
+                        let old = perp.price_max_age_sec();
+                        perp.update_price_max_age_sec(instant, max_age_sec);
+                        param_changed(
+                            perp,
+                            PerpetualParam::PriceMaxAge,
+                            UD64::from(old),
+                            UD64::from(perp.price_max_age_sec()),
+                        )
+                    })
+                    .into_iter()
+                    .collect()
             },
             ExchangeEvents::PriceOutOfRange(_) => self
                 .err_ctx(ctx, event)
@@ -1832,8 +3070,10 @@ impl Exchange {
             ExchangeEvents::RecycleFeeToAccount(_) => vec![],
             ExchangeEvents::RecycleFeeToProtocol(_) => vec![],
             ExchangeEvents::RecycleFeeUpdated(e) => {
-                self.recycle_fee = cc.from_unsigned(e.recycleFeeCNS);
-                vec![StateEvents::Exchange(ExchangeEvent::RecycleFeeUpdated(self.recycle_fee()))]
+                self.recycle_fee = cc.try_from_unsigned(e.recycleFeeCNS)?;
+                vec![StateEvents::Exchange(ExchangeEvent::RecycleFeeUpdated(
+                    self.recycle_fee.get(),
+                ))]
             },
             ExchangeEvents::ReportAgeExceedsLastUpdate(_) => vec![],
             ExchangeEvents::ReportExpiresTooSoon(_) => vec![],
@@ -1841,70 +3081,144 @@ impl Exchange {
             ExchangeEvents::ReportPriceIsNegative(_) => vec![],
             ExchangeEvents::ResidueBalanceInsufficient(_) => vec![],
             ExchangeEvents::ResidueTransferred(_) => vec![],
-            ExchangeEvents::TakerFeeUpdated(e) => self
-                .perpetual(e.perpId)
-                .map(|perp| {
-                    perp.update_taker_fee(
-                        instant,
-                        perp.fee_converter().from_unsigned(e.takerFeePer100K),
-                    );
-                    StateEvents::perpetual(
-                        perp,
-                        PerpetualEventType::TakerFeeUpdated(perp.taker_fee()),
+            ExchangeEvents::TakerFeeUpdated(e) => {
+                if let Some(perp) = self.perpetual(e.perpId)? {
+                    let fee = perp.fee_converter().try_from_unsigned(e.takerFeePer100K)?;
+                    let old = perp.taker_fee();
+                    perp.update_taker_fee(instant, fee);
+                    chain!(
+                        [StateEvents::perpetual(
+                            perp,
+                            PerpetualEventType::TakerFeeUpdated(perp.taker_fee()),
+                        )],
+                        param_changed(perp, PerpetualParam::TakerFee, old, perp.taker_fee()),
                     )
-                })
-                .into_iter()
-                .collect(),
+                    .collect()
+                } else {
+                    vec![]
+                }
+            },
             ExchangeEvents::TakerOrderFilled(e) => {
                 let c = must_ctx()?;
-                let taker_fee = cc.from_unsigned(e.feeCNS);
-                chain!(
-                    self.perpetuals
-                        .get(&c.perpetual_id)
-                        .map(|perp| StateEvents::Order(OrderEvent {
+                let taker_fee = cc.try_from_unsigned(e.feeCNS)?.get().resize();
+                let mut taker_side = c.r#type.try_side();
+                let mut events = vec![];
+                if let Some(perp) = self.perpetuals.get_mut(&c.perpetual_id) {
+                    let fill_price = perp.price_converter().try_from_unsigned(e.collatPricePNS)?;
+                    let fill_size = perp.size_converter().try_from_unsigned(e.lotLNS)?;
+                    let request_size = perp.size_converter().try_from_unsigned(c.lot)?;
+                    // `Change` request repricing a resting order across the spread gets
+                    // matched as a taker on the side of the changed order
+                    let changed_order = match (c.r#type, c.order_id) {
+                        (RequestType::Change, Some(order_id)) => perp.get_order(order_id).copied(),
+                        _ => None,
+                    };
+                    let remaining_size = changed_order.map(|_| {
+                        if request_size > fill_size {
+                            (request_size - fill_size).get()
+                        } else {
+                            UD64::ZERO
+                        }
+                    });
+                    if tracking.books {
+                        events.push(StateEvents::Order(OrderEvent {
                             perpetual_id: perp.id(),
                             account_id: c.account_id,
                             request_id: Some(c.request_id),
-                            client_order_id: Some(c.request_id),
-                            order_id: None,
+                            client_order_id: changed_order
+                                .map_or(Some(c.request_id), |o| o.client_order_id()),
+                            order_id: changed_order.map(|o| o.order_id()),
                             r#type: OrderEventType::Filled {
-                                fill_price: perp.price_converter().from_unsigned(e.collatPricePNS),
-                                fill_size: perp.size_converter().from_unsigned(e.lotLNS),
+                                fill_price: fill_price.get(),
+                                fill_size: fill_size.get(),
+                                remaining_size,
                                 fee: taker_fee,
                                 is_maker: false,
                             },
-                        })),
-                    self.accounts.get_mut(&c.account_id).map(|acc| {
-                        acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
+                        }));
+                    }
+                    if let Some((order, remaining_size)) = changed_order.zip(remaining_size) {
+                        taker_side = Some(order.r#type().side());
+                        if remaining_size.is_zero() {
+                            // Completely filled, nothing left to rest in the book
+                            perp.remove_order(order.order_id())?;
+                            events.push(StateEvents::order(
+                                perp,
+                                &order,
+                                ctx,
+                                OrderEventType::Removed,
+                            ));
+                        } else {
+                            // Remaining size rests at the new price with `OrderChanged`
+                            perp.update_order(order.updated(
+                                instant,
+                                ctx,
+                                None,
+                                Some(remaining_size),
+                                None,
+                                None,
+                            ))?;
+                            events.push(StateEvents::order(
+                                perp,
+                                &order,
+                                ctx,
+                                OrderEventType::Updated {
+                                    price: None,
+                                    size: Some(remaining_size),
+                                    expiry_block: None,
+                                },
+                            ));
+                        }
+                    }
+                }
+                if let Some(acc) = self.accounts.get_mut(&c.account_id) {
+                    acc.update_balance(
+                        instant,
+                        cc.try_from_unsigned(e.balanceCNS)?,
+                        BalanceChangeReason::Settlement,
+                    );
+                    events.push(StateEvents::account(
+                        acc,
+                        ctx,
+                        AccountEventType::BalanceUpdated(acc.balance().get()),
+                    ));
+                }
+                if tracking.trades {
+                    events.extend(taker_side.map(|side| StateEvents::trade(c, side, taker_fee)));
+                }
+                events
+            },
+            ExchangeEvents::ToleranceAdministratorUpdated(_) => vec![],
+            ExchangeEvents::TransferAccountToProtocol(e) => {
+                let balance = cc.try_from_unsigned(e.balanceCNS)?;
+                self.account(e.accountId)?
+                    .map(|acc| {
+                        acc.update_balance(instant, balance, BalanceChangeReason::Transfer);
                         StateEvents::account(
                             acc,
                             ctx,
-                            AccountEventType::BalanceUpdated(acc.balance()),
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
                         )
-                    }),
-                    iter::once(StateEvents::trade(c, taker_fee)),
-                )
-                .collect()
+                    })
+                    .into_iter()
+                    .collect()
             },
-            ExchangeEvents::ToleranceAdministratorUpdated(_) => vec![],
-            ExchangeEvents::TransferAccountToProtocol(e) => self
-                .account(e.accountId)
-                .map(|acc| {
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                })
-                .into_iter()
-                .collect(),
             ExchangeEvents::TransferPerpInsToProtocol(_) => vec![],
             ExchangeEvents::TransferPerpPosToProtocol(_) => vec![],
-            ExchangeEvents::TransferProtocolToAccount(e) => self
-                .account(e.accountId)
-                .map(|acc| {
-                    acc.update_balance(instant, cc.from_unsigned(e.balanceCNS));
-                    StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance()))
-                })
-                .into_iter()
-                .collect(),
+            ExchangeEvents::TransferProtocolToAccount(e) => {
+                let balance = cc.try_from_unsigned(e.balanceCNS)?;
+                self.account(e.accountId)?
+                    .map(|acc| {
+                        acc.update_balance(instant, balance, BalanceChangeReason::Transfer);
+                        StateEvents::account(
+                            acc,
+                            ctx,
+                            AccountEventType::BalanceUpdated(acc.balance().get()),
+                        )
+                    })
+                    .into_iter()
+                    .collect()
+            },
             ExchangeEvents::TransferProtocolToPerp(_) => vec![],
             ExchangeEvents::TransferProtocolToRecycleBal(_) => vec![],
             ExchangeEvents::TriggerDescIdTooLow(_) => vec![],
@@ -1955,7 +3269,7 @@ impl Exchange {
                     PerpetualEventType::MaintenanceMarginFractionUpdated(maintenance_margin) => {
                         // Applying new maintenance margin to all tracked positions
                         self.accounts
-                            .values_mut()
+                            .values_mut_where(|acc| acc.positions().contains_key(&pe.perpetual_id))
                             .filter_map(|acc| {
                                 acc.positions_mut().get_mut(&pe.perpetual_id).map(|pos| {
                                     pos.apply_maintenance_margin(instant, maintenance_margin);
@@ -1963,7 +3277,7 @@ impl Exchange {
                                         pos,
                                         &None,
                                         PositionEventType::MaintenanceMarginUpdated(
-                                            pos.maintenance_margin_requirement(),
+                                            pos.maintenance_margin_requirement().get(),
                                         ),
                                     )
                                 })
@@ -1988,17 +3302,20 @@ impl Exchange {
         Ok(self.accounts.contains_key(&c.account_id).then_some(c))
     }
 
-    fn ensure_account(&mut self, id: U256) {
-        let id = id.to::<types::AccountId>();
+    fn ensure_account(&mut self, id: U256) -> Result<types::AccountId, DexError> {
+        let id = num::narrow(id, "account id")?;
         if self.track_all_accounts && !self.accounts.contains_key(&id) {
-            self.accounts
-                .insert(id, Account::from_event(types::StateInstant::default(), id, Address::ZERO));
+            let address = self.pruned_addresses.remove(&id).unwrap_or(Address::ZERO);
+            let account = Account::from_event(types::StateInstant::default(), id, address)
+                .with_balance_history(self.balance_history_capacity);
+            self.accounts.insert(id, account);
         }
+        Ok(id)
     }
 
-    fn account(&mut self, id: U256) -> Option<&mut Account> {
-        self.ensure_account(id);
-        self.accounts.get_mut(&id.to::<types::AccountId>())
+    fn account(&mut self, id: U256) -> Result<Option<&mut Account>, DexError> {
+        let id = self.ensure_account(id)?;
+        Ok(self.accounts.get_mut(&id))
     }
 
     fn order(
@@ -2006,9 +3323,8 @@ impl Exchange {
         perp_id: U256,
         ord_id: U256,
     ) -> Result<Option<(&mut Perpetual, Order)>, DexError> {
-        let ord_id = std::num::NonZeroU16::new(ord_id.to::<u16>())
-            .expect("ord_id in order lookup cannot be 0");
-        Ok(if let Some(perp) = self.perpetuals.get_mut(&perp_id.to::<types::PerpetualId>()) {
+        let ord_id = event_order_id(ord_id)?;
+        Ok(if let Some(perp) = self.book(num::narrow(perp_id, "perpetual id")?) {
             let ord = perp
                 .get_order(ord_id)
                 .copied()
@@ -2019,19 +3335,59 @@ impl Exchange {
         })
     }
 
-    fn perpetual(&mut self, id: U256) -> Option<&mut Perpetual> {
-        self.perpetuals.get_mut(&id.to::<types::PerpetualId>())
+    fn update_mark_price(
+        &mut self,
+        instant: types::StateInstant,
+        perp_id: types::PerpetualId,
+        mark_price: num::Price,
+    ) -> Vec<StateEvents> {
+        let Some(perp) = self.perpetuals.get_mut(&perp_id) else {
+            return vec![];
+        };
+        perp.update_mark_price(instant, mark_price);
+        chain!(
+            Some(StateEvents::Perpetual(PerpetualEvent {
+                perpetual_id: perp_id,
+                r#type: PerpetualEventType::MarkPriceUpdated(mark_price.get()),
+            })),
+            // Applying updated mark to all tracked positions
+            self.accounts
+                .values_mut_where(|acc| acc.positions().contains_key(&perp_id))
+                .filter_map(|acc| {
+                    acc.positions_mut().get_mut(&perp_id).map(|pos| {
+                        pos.apply_mark_price(instant, mark_price);
+                        StateEvents::position(
+                            pos,
+                            &None,
+                            PositionEventType::UnrealizedPnLUpdated {
+                                pnl: pos.pnl(),
+                                delta_pnl: pos.delta_pnl(),
+                                premium_pnl: pos.premium_pnl(),
+                            },
+                        )
+                    })
+                }),
+        )
+        .collect()
+    }
+
+    fn perpetual(&mut self, id: U256) -> Result<Option<&mut Perpetual>, DexError> {
+        Ok(self.perpetuals.get_mut(&num::narrow(id, "perpetual id")?))
+    }
+
+    /// Perpetual contract to maintain the order book of, if books are tracked.
+    fn book(&mut self, id: types::PerpetualId) -> Option<&mut Perpetual> {
+        if self.tracking.books { self.perpetuals.get_mut(&id) } else { None }
     }
 
     fn account_perpetual(
         &mut self,
         acc_id: U256,
         perp_id: U256,
-    ) -> Option<(&mut Account, &mut Perpetual)> {
-        self.ensure_account(acc_id);
-        self.accounts
-            .get_mut(&acc_id.to::<types::AccountId>())
-            .zip(self.perpetuals.get_mut(&perp_id.to::<types::PerpetualId>()))
+    ) -> Result<Option<(&mut Account, &mut Perpetual)>, DexError> {
+        let acc_id = self.ensure_account(acc_id)?;
+        let perp_id = num::narrow(perp_id, "perpetual id")?;
+        Ok(self.accounts.get_mut(&acc_id).zip(self.perpetuals.get_mut(&perp_id)))
     }
 
     fn position(
@@ -2039,9 +3395,8 @@ impl Exchange {
         acc_id: U256,
         perp_id: U256,
     ) -> Result<Option<(&mut Position, &mut Perpetual)>, DexError> {
-        self.ensure_account(acc_id);
-        let acc_id = acc_id.to::<types::AccountId>();
-        let perp_id = perp_id.to::<types::PerpetualId>();
+        let acc_id = self.ensure_account(acc_id)?;
+        let perp_id = num::narrow(perp_id, "perpetual id")?;
         Ok(
             if let Some(acc) = self.accounts.get_mut(&acc_id)
                 && let Some(perp) = self.perpetuals.get_mut(&perp_id)
@@ -2058,6 +3413,218 @@ impl Exchange {
     }
 }
 
+/// Test utility builders for `Exchange`, gated behind the `test-utils`
+/// feature same as the ones of [`Perpetual`].
+#[cfg(any(test, feature = "test-utils"))]
+impl Exchange {
+    /// Empty exchange with the `perpetuals` listed, tracking all accounts.
+    pub fn for_test(collateral_decimals: u8, perpetuals: Vec<Perpetual>) -> Self {
+        Self::new(
+            Chain::testnet(),
+            types::StateInstant::new(0, 0),
+            num::CollateralConverter::new(collateral_decimals),
+            100,
+            num::Collateral::ZERO,
+            num::Collateral::ZERO,
+            num::Collateral::ZERO,
+            perpetuals.into_iter().map(|perp| (perp.id(), perp)).collect(),
+            HashMap::new(),
+            false,
+            true,
+        )
+    }
+
+    /// Same as [`Self::for_test`], maintaining only the `tracking` parts of
+    /// the state.
+    pub fn for_test_with_tracking(
+        collateral_decimals: u8,
+        perpetuals: Vec<Perpetual>,
+        tracking: Tracking,
+    ) -> Self {
+        Self::for_test(collateral_decimals, perpetuals).with_tracking(tracking)
+    }
+}
+
+/// Order ID carried by an event, which is never 0 (NULL_ORDER_ID) for a
+/// well-formed one.
+fn event_order_id(order_id: U256) -> Result<types::OrderId, DexError> {
+    u16::try_from(order_id)
+        .ok()
+        .and_then(types::OrderId::new)
+        .ok_or_else(|| DexError::MalformedData(format!("invalid order id in event: {order_id}")))
+}
+
+/// [`PerpetualEventType::ParamChanged`] event, if the parameter value
+/// actually changed.
+fn param_changed(
+    perp: &Perpetual,
+    param: PerpetualParam,
+    old: UD64,
+    new: UD64,
+) -> Option<StateEvents> {
+    (old != new)
+        .then(|| StateEvents::perpetual(perp, PerpetualEventType::ParamChanged { param, old, new }))
+}
+
+/// Updates locked balance and balance of the account charged for an order
+/// placement/cancellation, with the corresponding events.
+fn order_balances_updated(
+    acc: &mut Account,
+    instant: types::StateInstant,
+    locked_balance: num::Collateral,
+    balance: num::Collateral,
+    ctx: &Option<OrderContext>,
+) -> [StateEvents; 2] {
+    acc.update_locked_balance(instant, locked_balance);
+    acc.update_balance(instant, balance, BalanceChangeReason::Fee);
+    [
+        StateEvents::account(
+            acc,
+            ctx,
+            AccountEventType::LockedBalanceUpdated(acc.locked_balance().get()),
+        ),
+        StateEvents::account(acc, ctx, AccountEventType::BalanceUpdated(acc.balance().get())),
+    ]
+}
+
+/// Map shared between clones, with its entries shared as well, so only the
+/// map itself and the entries mutated are copied on mutation while shared.
+///
+/// While journaling, the prior value of each entry is recorded on its first
+/// mutation, so the mutations can be reverted, see [`Exchange::rollback_to`].
+#[derive(Clone)]
+struct CowMap<K, V> {
+    map: Arc<HashMap<K, Arc<V>>>,
+    journal: Option<Arc<Journal<K, V>>>,
+}
+
+/// Prior values of the entries mutated since the journal started, `None` for
+/// the entries inserted.
+type Journal<K, V> = HashMap<K, Option<Arc<V>>>;
+
+impl<K: Eq + Hash, V> CowMap<K, V> {
+    fn new(map: HashMap<K, V>) -> Self {
+        let map = map
+            .into_iter()
+            .map(|(key, value)| (key, Arc::new(value)))
+            .collect();
+        Self { map: Arc::new(map), journal: None }
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> CowMap<K, V> {
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let value = Arc::make_mut(&mut self.map).get_mut(key)?;
+        if let Some(journal) = self.journal.as_mut() {
+            Arc::make_mut(journal)
+                .entry(key.clone())
+                .or_insert_with(|| Some(value.clone()));
+        }
+        Some(Arc::make_mut(value))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<Arc<V>> {
+        let prev = Arc::make_mut(&mut self.map).insert(key.clone(), Arc::new(value));
+        if let Some(journal) = self.journal.as_mut() {
+            Arc::make_mut(journal)
+                .entry(key)
+                .or_insert_with(|| prev.clone());
+        }
+        prev
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut V> { self.values_mut_where(|_| true) }
+
+    /// Mutable values matching `predicate`, only these get journaled and
+    /// copied.
+    fn values_mut_where(&mut self, predicate: impl Fn(&V) -> bool) -> impl Iterator<Item = &mut V> {
+        let journal = &mut self.journal;
+        Arc::make_mut(&mut self.map)
+            .iter_mut()
+            .filter(move |(_, value)| predicate(value))
+            .map(move |(key, value)| {
+                if let Some(journal) = journal.as_mut() {
+                    Arc::make_mut(journal)
+                        .entry(key.clone())
+                        .or_insert_with(|| Some(value.clone()));
+                }
+                Arc::make_mut(value)
+            })
+    }
+
+    /// Removes the entries not matching `f`, only these get journaled, the
+    /// map is copied only if there are any.
+    fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) {
+        let removed: Vec<K> = self
+            .map
+            .iter()
+            .filter(|(key, value)| !f(key, value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        if removed.is_empty() {
+            return;
+        }
+        let map = Arc::make_mut(&mut self.map);
+        for key in removed {
+            let prev = map.remove(&key);
+            if let Some(journal) = self.journal.as_mut() {
+                Arc::make_mut(journal).entry(key).or_insert(prev);
+            }
+        }
+    }
+
+    /// Same as [`Self::get_mut`], without journaling, so reverting the
+    /// mutation is up to the caller.
+    fn get_mut_unjournaled(&mut self, key: &K) -> Option<&mut V> {
+        Arc::make_mut(&mut self.map).get_mut(key).map(Arc::make_mut)
+    }
+
+    /// Mutable entries along with the indicator of each one being journaled
+    /// already, mutated without journaling, so reverting the mutations of the
+    /// rest is up to the caller.
+    fn iter_mut_unjournaled(&mut self) -> impl Iterator<Item = (&K, &mut V, bool)> {
+        let journal = self.journal.as_deref();
+        Arc::make_mut(&mut self.map)
+            .iter_mut()
+            .map(move |(key, value)| {
+                let journaled = journal.is_some_and(|journal| journal.contains_key(key));
+                (key, Arc::make_mut(value), journaled)
+            })
+    }
+
+    fn shrink_to_fit(&mut self) { Arc::make_mut(&mut self.map).shrink_to_fit(); }
+
+    /// Starts a new journal, returning the current one if any.
+    fn restart_journal(&mut self) -> Option<Arc<Journal<K, V>>> {
+        self.journal.replace(Arc::default())
+    }
+
+    /// Restores the prior values recorded by `journal`, without journaling.
+    fn revert(&mut self, journal: Arc<Journal<K, V>>) {
+        let map = Arc::make_mut(&mut self.map);
+        for (key, prior) in Arc::unwrap_or_clone(journal) {
+            match prior {
+                Some(value) => map.insert(key, value),
+                None => map.remove(&key),
+            };
+        }
+    }
+}
+
+impl<K, V> Default for CowMap<K, V> {
+    fn default() -> Self { Self { map: Arc::default(), journal: None } }
+}
+
+impl<K, V> std::ops::Deref for CowMap<K, V> {
+    type Target = HashMap<K, Arc<V>>;
+
+    fn deref(&self) -> &Self::Target { &self.map }
+}
+
+impl<K: std::fmt::Debug, V: std::fmt::Debug> std::fmt::Debug for CowMap<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { self.map.fmt(f) }
+}
+
 #[cfg(feature = "display")]
 impl std::fmt::Display for Exchange {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
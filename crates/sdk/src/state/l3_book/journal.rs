@@ -0,0 +1,114 @@
+//! Change journal of the order book to reconstruct its past states.
+
+use std::collections::VecDeque;
+
+use super::{OrderBook, OrderBookResult};
+use crate::{state::Order, types};
+
+/// Change of the order book recorded by the journal, see
+/// [`OrderBook::as_of`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
+pub enum BookEvent {
+    /// Order added to the back of its price level queue.
+    Added(Order),
+    /// Order updated keeping its queue position.
+    Updated(Order),
+    /// Order updated and moved to the back of its price level queue.
+    MovedToBack(Order),
+    /// Order removed from the book.
+    Removed(types::OrderId),
+    /// Orders expired as of the event instant.
+    Expired,
+}
+
+/// Bounded journal of the book changes on top of the book state preceding
+/// them.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
+pub(super) struct Journal {
+    capacity: usize,
+    /// Book state as of `base_instant`, without the journaled changes.
+    base: Box<OrderBook>,
+    base_instant: types::StateInstant,
+    /// Changes of the block being applied, recorded once it is completed.
+    pending: Vec<BookEvent>,
+    events: VecDeque<(types::StateInstant, BookEvent)>,
+}
+
+impl Journal {
+    pub(super) fn new(capacity: usize, base: OrderBook, instant: types::StateInstant) -> Self {
+        Self {
+            capacity,
+            base: Box::new(base),
+            base_instant: instant,
+            pending: vec![],
+            events: VecDeque::new(),
+        }
+    }
+
+    pub(super) fn capacity(&self) -> usize { self.capacity }
+
+    pub(super) fn events(&self) -> &VecDeque<(types::StateInstant, BookEvent)> { &self.events }
+
+    pub(super) fn record(&mut self, event: BookEvent) { self.pending.push(event); }
+
+    pub(super) fn has_pending(&self) -> bool { !self.pending.is_empty() }
+
+    /// Records the pending changes with the `instant` of the completed block,
+    /// folding the oldest changes exceeding the capacity into the base state.
+    pub(super) fn commit(&mut self, instant: types::StateInstant) -> OrderBookResult<()> {
+        self.events
+            .extend(self.pending.drain(..).map(|event| (instant, event)));
+        self.trim()
+    }
+
+    /// Drops the record of the orders expired as of the completed block at
+    /// `instant`, if it is the last change recorded.
+    pub(super) fn revert_expired(&mut self, instant: types::StateInstant) {
+        if matches!(self.events.back(), Some((last, BookEvent::Expired)) if *last == instant) {
+            self.events.pop_back();
+        }
+    }
+
+    pub(super) fn set_capacity(&mut self, capacity: usize) -> OrderBookResult<()> {
+        self.capacity = capacity;
+        self.trim()
+    }
+
+    /// Book state as of the `instant`, `None` if it precedes the retained
+    /// changes.
+    pub(super) fn book_as_of(
+        &self,
+        instant: types::StateInstant,
+    ) -> OrderBookResult<Option<OrderBook>> {
+        if instant.block_number() < self.base_instant.block_number() {
+            return Ok(None);
+        }
+        let mut book = (*self.base).clone();
+        for (event_instant, event) in self
+            .events
+            .iter()
+            .take_while(|(i, _)| i.block_number() <= instant.block_number())
+        {
+            book.replay(*event_instant, event)?;
+        }
+        Ok(Some(book))
+    }
+
+    pub(super) fn approximate_heap_bytes(&self) -> usize {
+        std::mem::size_of::<OrderBook>()
+            + self.base.approximate_heap_bytes()
+            + self.pending.capacity() * std::mem::size_of::<BookEvent>()
+            + self.events.capacity() * std::mem::size_of::<(types::StateInstant, BookEvent)>()
+    }
+
+    fn trim(&mut self) -> OrderBookResult<()> {
+        while self.events.len() > self.capacity {
+            let Some((instant, event)) = self.events.pop_front() else { break };
+            self.base.replay(instant, &event)?;
+            self.base_instant = instant;
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,13 @@
 //! L3 price level with linked list of orders.
 
-use fastnum::UD64;
-
-use crate::types;
+use crate::{num, types};
 
 /// Price level containing orders in a doubly-linked list (FIFO order).
 ///
 /// The level stores head/tail pointers to the linked list and maintains
 /// cached aggregates for O(1) access to total size and order count.
 #[derive(Clone, derive_more::Debug, Default)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
 pub struct BookLevel {
     /// First order in the FIFO queue (oldest).
     head: Option<types::OrderId>,
@@ -16,7 +15,7 @@ pub struct BookLevel {
     tail: Option<types::OrderId>,
     /// Cached aggregate: total size at this level.
     #[debug("{cached_size}")]
-    cached_size: UD64,
+    cached_size: num::Size,
     /// Cached aggregate: number of orders at this level.
     cached_count: u32,
 }
@@ -26,7 +25,7 @@ impl BookLevel {
     pub fn new() -> Self { Self::default() }
 
     /// Total size at this price level, excluding expired orders (cached, O(1)).
-    pub fn size(&self) -> UD64 { self.cached_size }
+    pub fn size(&self) -> num::Size { self.cached_size }
 
     /// Number of orders at this price level, excluding expired orders (cached,
     /// O(1)).
@@ -35,6 +34,20 @@ impl BookLevel {
     /// Check if this level has no orders.
     pub fn is_empty(&self) -> bool { self.head.is_none() }
 
+    /// Recomputes the total size of non-expired orders at this level of the
+    /// `book` and checks it matches the cached [`Self::size`].
+    ///
+    /// The cached size is maintained incrementally, so this is the way to
+    /// catch its drift, e.g. in tests.
+    pub fn verify_size(&self, book: &super::OrderBook) -> bool {
+        let size: num::Size = book
+            .level_orders(self)
+            .filter(|order| !order.is_expired())
+            .map(|order| order.size())
+            .sum();
+        size == self.cached_size
+    }
+
     /// First (oldest) order ID at this level.
     pub(crate) fn head(&self) -> Option<types::OrderId> { self.head }
 
@@ -48,19 +61,19 @@ impl BookLevel {
     pub(crate) fn set_tail(&mut self, tail: Option<types::OrderId>) { self.tail = tail; }
 
     /// Add to cached size.
-    pub(crate) fn add_size(&mut self, size: UD64) {
+    pub(crate) fn add_size(&mut self, size: num::Size) {
         self.cached_size += size;
         self.cached_count += 1;
     }
 
     /// Subtract from cached size.
-    pub(crate) fn sub_size(&mut self, size: UD64) {
+    pub(crate) fn sub_size(&mut self, size: num::Size) {
         self.cached_size -= size;
         self.cached_count -= 1;
     }
 
     /// Update cached size (for size changes without count change).
-    pub(crate) fn update_size(&mut self, old_size: UD64, new_size: UD64) {
+    pub(crate) fn update_size(&mut self, old_size: num::Size, new_size: num::Size) {
         self.cached_size -= old_size;
         self.cached_size += new_size;
     }
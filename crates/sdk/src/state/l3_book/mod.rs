@@ -5,8 +5,10 @@
 //! lists.
 
 mod error;
+mod journal;
 mod level;
 mod order;
+mod top;
 #[cfg(feature = "display")]
 mod view;
 
@@ -15,21 +17,24 @@ mod tests;
 
 use std::{
     cmp::Reverse,
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, VecDeque},
 };
 
 pub use error::{OrderBookError, OrderBookResult};
 use fastnum::{
-    UD64, UD128,
+    UD64,
     decimal::{Context, RoundingMode},
 };
 use itertools::{FoldWhile, Itertools};
+pub use journal::BookEvent;
+use journal::Journal;
 pub use level::BookLevel;
 pub use order::BookOrder;
+pub use top::{BookTop, TopLevel};
 #[cfg(feature = "display")]
-pub use view::OrderBookView;
+pub use view::{DepthSeries, OrderBookView};
 
-use crate::{state::Order, types};
+use crate::{num, state::Order, types};
 
 /// L3 order book with intrusive linked lists.
 ///
@@ -37,53 +42,108 @@ use crate::{state::Order, types};
 /// maintaining a doubly-linked list of orders in FIFO (time-priority) order.
 /// Provides both L2 (aggregated price levels) and L3 (individual orders) views.
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
 pub struct OrderBook {
     /// Storage for all orders, keyed by OrderId.
     orders: HashMap<types::OrderId, BookOrder>,
     /// Orders keyed by client order ID, per account ID.
     client_orders: HashMap<(types::AccountId, types::RequestId), types::OrderId>,
     /// Ask levels sorted by price (ascending, best ask first).
-    asks: BTreeMap<UD64, BookLevel>,
+    asks: BTreeMap<num::Price, BookLevel>,
     /// Bid levels sorted by price (descending, best bid first).
-    bids: BTreeMap<Reverse<UD64>, BookLevel>,
+    bids: BTreeMap<Reverse<num::Price>, BookLevel>,
+    /// Optional journal of the recent changes, see [`Self::as_of`].
+    journal: Option<Journal>,
 }
 
 impl OrderBook {
+    /// Max number of orders in the book, limited by 16-bit order IDs, see
+    /// [`super::Order`].
+    pub const CAPACITY: usize = u16::MAX as usize;
+
     pub(crate) fn new() -> Self { Self::default() }
 
     // === L2 API ===
 
     /// Asks sorted away from the spread.
-    pub fn asks(&self) -> &BTreeMap<UD64, BookLevel> { &self.asks }
+    pub fn asks(&self) -> &BTreeMap<num::Price, BookLevel> { &self.asks }
 
     /// Bids sorted away from the spread.
-    pub fn bids(&self) -> &BTreeMap<Reverse<UD64>, BookLevel> { &self.bids }
+    pub fn bids(&self) -> &BTreeMap<Reverse<num::Price>, BookLevel> { &self.bids }
 
     /// Best ask price/size.
-    pub fn best_ask(&self) -> Option<(UD64, UD64)> {
+    pub fn best_ask(&self) -> Option<(num::Price, num::Size)> {
         self.asks
             .iter()
-            .find(|(_, lvl)| lvl.size() > UD64::ZERO)
+            .find(|(_, lvl)| !lvl.size().is_zero())
             .map(|(k, v)| (*k, v.size()))
     }
 
     /// Best bid price/size.
-    pub fn best_bid(&self) -> Option<(UD64, UD64)> {
+    pub fn best_bid(&self) -> Option<(num::Price, num::Size)> {
         self.bids
             .iter()
-            .find(|(_, lvl)| lvl.size() > UD64::ZERO)
+            .find(|(_, lvl)| !lvl.size().is_zero())
             .map(|(k, v)| (k.0, v.size()))
     }
 
+    /// Order at the front of the best ask level queue, skipping expired
+    /// orders.
+    pub fn best_ask_order(&self) -> Option<&BookOrder> {
+        let level = self.asks.values().find(|lvl| !lvl.size().is_zero())?;
+        self.level_orders(level).find(|o| !o.is_expired())
+    }
+
+    /// Order at the front of the best bid level queue, skipping expired
+    /// orders.
+    pub fn best_bid_order(&self) -> Option<&BookOrder> {
+        let level = self.bids.values().find(|lvl| !lvl.size().is_zero())?;
+        self.level_orders(level).find(|o| !o.is_expired())
+    }
+
+    /// Indicator of the best bid price being at or above the best ask price.
+    ///
+    /// Matching on-chain never leaves the book crossed, so a crossed book
+    /// reconstructed from a snapshot or events indicates a bug.
+    pub fn is_crossed(&self) -> bool {
+        self.best_bid()
+            .is_some_and(|(bid, _)| self.crosses(types::OrderSide::Bid, bid))
+    }
+
+    /// Best price of the `side`, see [`Self::best_ask`] and [`Self::best_bid`].
+    pub fn best_price(&self, side: types::OrderSide) -> Option<num::Price> {
+        match side {
+            types::OrderSide::Ask => self.best_ask(),
+            types::OrderSide::Bid => self.best_bid(),
+        }
+        .map(|(price, _)| price)
+    }
+
+    /// Indicator of an order on the `side` at `price` crossing the best price
+    /// of the opposite side, i.e. getting matched immediately rather than
+    /// resting in the book, see [`types::OrderSide::crosses`].
+    pub fn crosses(&self, side: types::OrderSide, price: num::Price) -> bool {
+        self.best_price(side.opposite())
+            .is_some_and(|best| side.crosses(price, best))
+    }
+
+    /// Indicator of an order on the `side` at `price` improving the best price
+    /// of the side, i.e. quoting at the new top of the book, see
+    /// [`types::OrderSide::is_better_price`]. Any price improves an empty side.
+    pub fn improves(&self, side: types::OrderSide, price: num::Price) -> bool {
+        self.best_price(side)
+            .is_none_or(|best| side.is_better_price(price, best))
+    }
+
     /// Ask impact price for the requested size, along with the fillable size
     /// and size-averaged price.
-    pub fn ask_impact(&self, want_size: UD64) -> Option<(UD64, UD64, UD64)> {
+    pub fn ask_impact(&self, want_size: num::Size) -> Option<(num::Price, num::Size, num::Price)> {
         Self::impact(self.asks.iter(), want_size)
     }
 
     /// Bid impact price for the requested size, along with the fillable size
     /// and size-averaged price.
-    pub fn bid_impact(&self, want_size: UD64) -> Option<(UD64, UD64, UD64)> {
+    pub fn bid_impact(&self, want_size: num::Size) -> Option<(num::Price, num::Size, num::Price)> {
         Self::impact(self.bids.iter().map(|(k, v)| (&k.0, v)), want_size)
     }
 
@@ -91,7 +151,10 @@ impl OrderBook {
     /// fillable size, size-averaged price, and filled notional.
     /// CAREFUL: See [`impact_notional`](Self::impact_notional) for partial-fill
     /// semantics.
-    pub fn ask_impact_notional(&self, want_notional: UD128) -> Option<(UD64, UD64, UD64, UD128)> {
+    pub fn ask_impact_notional(
+        &self,
+        want_notional: num::Collateral,
+    ) -> Option<(num::Price, num::Size, num::Price, num::Collateral)> {
         Self::impact_notional(self.asks.iter(), want_notional)
     }
 
@@ -99,17 +162,54 @@ impl OrderBook {
     /// fillable size, size-averaged price, and filled notional.
     /// CAREFUL: See [`impact_notional`](Self::impact_notional) for partial-fill
     /// semantics.
-    pub fn bid_impact_notional(&self, want_notional: UD128) -> Option<(UD64, UD64, UD64, UD128)> {
+    pub fn bid_impact_notional(
+        &self,
+        want_notional: num::Collateral,
+    ) -> Option<(num::Price, num::Size, num::Price, num::Collateral)> {
         Self::impact_notional(self.bids.iter().map(|(k, v)| (&k.0, v)), want_notional)
     }
 
+    /// Up to `levels` best non-empty price levels of the `side`, along with
+    /// the cumulative notional (price * size) of the levels up to and
+    /// including each one, e.g. for depth charts in collateral terms.
+    pub fn depth_notional(
+        &self,
+        side: types::OrderSide,
+        levels: usize,
+    ) -> Vec<(num::Price, num::Collateral)> {
+        match side {
+            types::OrderSide::Ask => {
+                Self::cumulative_notional(self.asks.iter().map(|(k, v)| (*k, v)), levels)
+            },
+            types::OrderSide::Bid => {
+                Self::cumulative_notional(self.bids.iter().map(|(k, v)| (k.0, v)), levels)
+            },
+        }
+    }
+
+    fn cumulative_notional<'a>(
+        levels: impl Iterator<Item = (num::Price, &'a BookLevel)>,
+        n: usize,
+    ) -> Vec<(num::Price, num::Collateral)> {
+        levels
+            .filter(|(_, lvl)| !lvl.size().is_zero())
+            .scan(num::Collateral::ZERO, |notional, (price, lvl)| {
+                *notional += price * lvl.size();
+                Some((price, *notional))
+            })
+            .take(n)
+            .collect()
+    }
+
     // === L3 API ===
 
     /// Get L3 level at a specific ask price.
-    pub fn ask_level(&self, price: UD64) -> Option<&BookLevel> { self.asks.get(&price) }
+    pub fn ask_level(&self, price: num::Price) -> Option<&BookLevel> { self.asks.get(&price) }
 
     /// Get L3 level at a specific bid price.
-    pub fn bid_level(&self, price: UD64) -> Option<&BookLevel> { self.bids.get(&Reverse(price)) }
+    pub fn bid_level(&self, price: num::Price) -> Option<&BookLevel> {
+        self.bids.get(&Reverse(price))
+    }
 
     /// Get a specific order by ID (O(1) via HashMap lookup).
     pub fn get_order(&self, order_id: types::OrderId) -> Option<&BookOrder> {
@@ -141,14 +241,88 @@ impl OrderBook {
             .flat_map(|level| self.level_orders(level))
     }
 
+    /// Up to `n` best non-empty price levels on each side, along with the
+    /// head order ID of each level to reason about queue position.
+    pub fn top(&self, n: usize) -> BookTop {
+        BookTop::new(
+            Self::top_levels(self.asks.iter().map(|(k, v)| (*k, v)), n),
+            Self::top_levels(self.bids.iter().map(|(k, v)| (k.0, v)), n),
+        )
+    }
+
+    fn top_levels<'a>(
+        levels: impl Iterator<Item = (num::Price, &'a BookLevel)>,
+        n: usize,
+    ) -> Vec<TopLevel> {
+        levels
+            .filter(|(_, lvl)| !lvl.size().is_zero())
+            .filter_map(|(price, lvl)| {
+                lvl.head()
+                    .map(|head| (price, lvl.size(), lvl.num_orders(), head))
+            })
+            .take(n)
+            .collect()
+    }
+
     /// Total number of orders in the book.
     pub fn total_orders(&self) -> usize { self.orders.len() }
 
+    /// Indicates if the book is filled to at least `threshold` fraction of
+    /// [`Self::CAPACITY`], e.g. `0.9`, after which placing of new orders may
+    /// soon start failing.
+    pub fn is_near_capacity(&self, threshold: f64) -> bool {
+        self.total_orders() as f64 >= Self::CAPACITY as f64 * threshold
+    }
+
     /// Access to all orders in the book keyed by order ID.
     pub fn all_orders(&self) -> &HashMap<types::OrderId, BookOrder> { &self.orders }
 
-    /// Iterator over orders at a specific level (follows the linked list).
-    pub(crate) fn level_orders<'a>(&'a self, level: &'a BookLevel) -> LevelOrdersIter<'a> {
+    /// Reconstructs the book as of the completed block of the `instant`.
+    ///
+    /// Returns `None` if the change journal is disabled, see
+    /// [`crate::state::Exchange::set_book_journal_capacity`], or the block
+    /// precedes the retained changes. Fails if the retained changes cannot be
+    /// replayed.
+    pub fn as_of(&self, instant: types::StateInstant) -> OrderBookResult<Option<OrderBook>> {
+        self.journal
+            .as_ref()
+            .map_or(Ok(None), |journal| journal.book_as_of(instant))
+    }
+
+    /// Retained changes of the book with instants of the blocks they were
+    /// made at, oldest first, `None` if the change journal is disabled.
+    pub fn journal(&self) -> Option<&VecDeque<(types::StateInstant, BookEvent)>> {
+        self.journal.as_ref().map(Journal::events)
+    }
+
+    /// Approximate heap memory used by the book, in bytes.
+    pub(crate) fn approximate_heap_bytes(&self) -> usize {
+        use std::mem::size_of;
+        self.orders.capacity() * size_of::<(types::OrderId, BookOrder)>()
+            + self.client_orders.capacity()
+                * size_of::<((types::AccountId, types::RequestId), types::OrderId)>()
+            + self.asks.len() * size_of::<(num::Price, BookLevel)>()
+            + self.bids.len() * size_of::<(Reverse<num::Price>, BookLevel)>()
+            + self
+                .journal
+                .as_ref()
+                .map_or(0, Journal::approximate_heap_bytes)
+    }
+
+    /// Checks cached sizes of all the levels, see [`BookLevel::verify_size`].
+    pub fn verify_sizes(&self) -> bool {
+        self.asks
+            .values()
+            .chain(self.bids.values())
+            .all(|level| level.verify_size(self))
+    }
+
+    /// Iterator over orders at a specific level in FIFO (time-priority) order.
+    ///
+    /// The level is expected to be obtained from this book, see
+    /// [`Self::ask_level`], [`Self::bid_level`], [`Self::asks`] and
+    /// [`Self::bids`].
+    pub fn level_orders<'a>(&'a self, level: &'a BookLevel) -> LevelOrdersIter<'a> {
         LevelOrdersIter { orders: &self.orders, current: level.head() }
     }
 
@@ -176,18 +350,18 @@ impl OrderBook {
         let order_id = order.order_id();
 
         // Validate order
-        if order.size() == UD64::ZERO {
-            return Err(OrderBookError::InvalidOrderSize { order_id, size: order.size() });
+        if order.size().is_zero() {
+            return Err(OrderBookError::InvalidOrderSize { order_id, size: order.size().get() });
         }
-        if order.price() == UD64::ZERO {
-            return Err(OrderBookError::InvalidOrderPrice { order_id, price: order.price() });
+        if order.price().is_zero() {
+            return Err(OrderBookError::InvalidOrderPrice { order_id, price: order.price().get() });
         }
 
         // Check if order already exists
         if let Some(existing) = self.orders.get(&order_id) {
             return Err(OrderBookError::OrderAlreadyExists {
                 order_id,
-                existing_price: existing.price(),
+                existing_price: existing.price().get(),
             });
         }
 
@@ -209,6 +383,7 @@ impl OrderBook {
         // Link at tail
         self.link_at_tail(side, order.price(), old_tail, order_id, order.size());
 
+        self.record(BookEvent::Added(*order));
         Ok(())
     }
 
@@ -228,8 +403,8 @@ impl OrderBook {
         let order_id = order.order_id();
 
         // Validate new size
-        if order.size() == UD64::ZERO {
-            return Err(OrderBookError::InvalidOrderSize { order_id, size: order.size() });
+        if order.size().is_zero() {
+            return Err(OrderBookError::InvalidOrderSize { order_id, size: order.size().get() });
         }
 
         let old_size = prev_order.size();
@@ -245,9 +420,10 @@ impl OrderBook {
         // Update level cached size
         let level = self
             .get_level_mut(side, price)
-            .ok_or(OrderBookError::LevelNotFound { price, side })?;
+            .ok_or(OrderBookError::LevelNotFound { price: price.get(), side })?;
         level.update_size(old_size, order.size());
 
+        self.record(BookEvent::Updated(*order));
         Ok(())
     }
 
@@ -273,7 +449,7 @@ impl OrderBook {
         // Update level head/tail and check if empty
         let level = self
             .get_level_mut(side, price)
-            .ok_or(OrderBookError::LevelNotFound { price, side })?;
+            .ok_or(OrderBookError::LevelNotFound { price: price.get(), side })?;
         if level.head() == Some(order_id) {
             level.set_head(next_id);
         }
@@ -301,6 +477,7 @@ impl OrderBook {
                 .remove(&(removed.account_id(), client_order_id));
         }
 
+        self.record(BookEvent::Removed(order_id));
         Ok(*removed)
     }
 
@@ -326,7 +503,7 @@ impl OrderBook {
         // If already at tail, just update the order data
         let is_at_tail = self
             .get_level(side, price)
-            .ok_or(OrderBookError::LevelNotFound { price, side })?
+            .ok_or(OrderBookError::LevelNotFound { price: price.get(), side })?
             .tail()
             == Some(order_id);
 
@@ -337,13 +514,14 @@ impl OrderBook {
             }
             let level = self
                 .get_level_mut(side, price)
-                .ok_or(OrderBookError::LevelNotFound { price, side })?;
+                .ok_or(OrderBookError::LevelNotFound { price: price.get(), side })?;
             if prev_order.is_expired() {
                 // Expired order was removed from the cached level size/count
                 level.add_size(order.size());
             } else {
                 level.update_size(old_size, order.size());
             }
+            self.record(BookEvent::MovedToBack(*order));
             return Ok(());
         }
 
@@ -353,7 +531,7 @@ impl OrderBook {
         // Update level head if we were the head
         let level = self
             .get_level_mut(side, price)
-            .ok_or(OrderBookError::LevelNotFound { price, side })?;
+            .ok_or(OrderBookError::LevelNotFound { price: price.get(), side })?;
         if level.head() == Some(order_id) {
             level.set_head(next_id);
         }
@@ -380,7 +558,7 @@ impl OrderBook {
         // Update level tail and size - need to re-borrow
         let level = self
             .get_level_mut(side, price)
-            .ok_or(OrderBookError::LevelNotFound { price, side })?;
+            .ok_or(OrderBookError::LevelNotFound { price: price.get(), side })?;
         level.set_tail(Some(order_id));
         if prev_order.is_expired() {
             // Expired order was removed from the cached level size/count
@@ -389,6 +567,7 @@ impl OrderBook {
             level.update_size(old_size, order.size());
         }
 
+        self.record(BookEvent::MovedToBack(*order));
         Ok(())
     }
 
@@ -410,11 +589,11 @@ impl OrderBook {
         for order in orders {
             let order_id = order.order_id();
 
-            if order.size() == UD64::ZERO {
-                return Err(OrderBookError::InvalidOrderSize { order_id, size: order.size() });
+            if order.size().is_zero() {
+                return Err(OrderBookError::InvalidOrderSize { order_id, size: order.size().get() });
             }
-            if order.price() == UD64::ZERO {
-                return Err(OrderBookError::InvalidOrderPrice { order_id, price: order.price() });
+            if order.price().is_zero() {
+                return Err(OrderBookError::InvalidOrderPrice { order_id, price: order.price().get() });
             }
 
             // Validate that referenced orders exist in this snapshot
@@ -447,7 +626,7 @@ impl OrderBook {
 
         // Second pass: build levels with head/tail and cached aggregates
         // Group orders by (price, side)
-        let mut level_orders: HashMap<(UD64, types::OrderSide), Vec<types::OrderId>> =
+        let mut level_orders: HashMap<(num::Price, types::OrderSide), Vec<types::OrderId>> =
             HashMap::new();
         for order in orders {
             let key = (order.price(), order.r#type().side());
@@ -494,25 +673,156 @@ impl OrderBook {
         Ok(())
     }
 
+    /// Remove empty price levels and release excess capacity of the book maps.
+    ///
+    /// Returns the number of levels removed.
+    pub(crate) fn compact(&mut self) -> usize {
+        let num_levels = self.asks.len() + self.bids.len();
+        self.asks.retain(|_, level| !level.is_empty());
+        self.bids.retain(|_, level| !level.is_empty());
+        self.orders.shrink_to_fit();
+        self.client_orders.shrink_to_fit();
+        num_levels - self.asks.len() - self.bids.len()
+    }
+
+    /// Indicates if [`Self::check_expired`] as of `instant` changes the book,
+    /// i.e. some order expires or the journal has pending changes.
+    pub(crate) fn needs_completion(&self, instant: types::StateInstant) -> bool {
+        self.journal.as_ref().is_some_and(Journal::has_pending)
+            || self.orders.values().any(|order| order.expires_at(instant))
+    }
+
     /// Check if any orders are expired and update cached L2 book state.
-    pub(crate) fn check_expired(&mut self, instant: types::StateInstant) {
-        let mut update_levels = vec![];
+    ///
+    /// Completes the block of the `instant`, committing its changes to the
+    /// journal, if enabled.
+    ///
+    /// Returns prior state of the orders expired, see
+    /// [`Self::revert_expired`].
+    pub(crate) fn check_expired(&mut self, instant: types::StateInstant) -> Vec<Order> {
+        let mut expired = vec![];
         for order in self.orders.values_mut() {
+            let prior = **order;
             if order.update_if_expired(instant) {
-                update_levels.push((order.r#type().side(), order.price(), order.size()));
+                expired.push(prior);
+            }
+        }
+        if !expired.is_empty() {
+            self.record(BookEvent::Expired);
+        }
+        for order in &expired {
+            if let Some(level) = self.get_level_mut(order.r#type().side(), order.price()) {
+                level.sub_size(order.size());
             }
         }
-        for (side, price, size) in update_levels {
-            if let Some(level) = self.get_level_mut(side, price) {
-                level.sub_size(size);
+        if let Some(journal) = self.journal.as_mut()
+            && let Err(err) = journal.commit(instant)
+        {
+            self.restart_journal(instant, err);
+        }
+        expired
+    }
+
+    /// Reverts [`Self::check_expired`] of the block at `instant`, given prior
+    /// state of the orders it expired, along with its journal record.
+    pub(crate) fn revert_expired(&mut self, instant: types::StateInstant, expired: &[Order]) {
+        for prior in expired {
+            let Some(order) = self.orders.get_mut(&prior.order_id()) else {
+                continue;
+            };
+            **order = *prior;
+            if let Some(level) = self.get_level_mut(prior.r#type().side(), prior.price()) {
+                level.add_size(prior.size());
             }
         }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.revert_expired(instant);
+        }
+    }
+
+    /// Retains up to `capacity` most recent changes of the book on top of its
+    /// state preceding them, see [`Self::as_of`]. Zero capacity disables the
+    /// journal.
+    ///
+    /// Journal enabled at `instant` starts with the current book state.
+    pub(crate) fn set_journal_capacity(&mut self, capacity: usize, instant: types::StateInstant) {
+        if capacity == 0 {
+            self.journal = None;
+        } else if let Some(journal) = self.journal.as_mut() {
+            if let Err(err) = journal.set_capacity(capacity) {
+                self.restart_journal(instant, err);
+            }
+        } else {
+            self.journal = Some(Journal::new(capacity, self.clone(), instant));
+        }
+    }
+
+    /// Removes all orders expired as of the `current_block` and returns them,
+    /// sorted by order ID.
+    ///
+    /// Expired orders stay on-chain until cleared or changed with a new
+    /// expiry, so the snapshot keeps them, only excluding them from the
+    /// cached level state. The sweep is meant for a clone of the book, e.g.
+    /// to react to expiries, as the events referencing swept orders would fail
+    /// to be applied to it.
+    pub fn sweep_expired(&mut self, current_block: u64) -> Vec<Order> {
+        let mut expired = self
+            .orders
+            .values()
+            .filter(|order| order.expiry_block() != 0 && order.expiry_block() <= current_block)
+            .cloned()
+            .collect::<Vec<_>>();
+        expired.sort_unstable_by_key(|order| order.order_id());
+        expired
+            .iter()
+            .map(|order| self.remove_order(order).expect("order exists"))
+            .collect()
+    }
+
+    /// Records the change to the journal, if enabled.
+    fn record(&mut self, event: BookEvent) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record(event);
+        }
+    }
+
+    /// Restarts the journal failing to fold its changes into the base state
+    /// from the current book state at `instant`, dropping retained changes.
+    fn restart_journal(&mut self, instant: types::StateInstant, err: OrderBookError) {
+        tracing::warn!(%err, "order book journal inconsistent with the book, restarting");
+        if let Some(journal) = self.journal.take() {
+            self.journal = Some(Journal::new(journal.capacity(), self.clone(), instant));
+        }
+    }
+
+    /// Applies the change recorded by the journal at the `instant`.
+    fn replay(&mut self, instant: types::StateInstant, event: &BookEvent) -> OrderBookResult<()> {
+        let cloned_order = |book: &Self, order_id| {
+            book.orders
+                .get(&order_id)
+                .cloned()
+                .ok_or(OrderBookError::OrderNotFound { order_id })
+        };
+        match event {
+            BookEvent::Added(order) => self.add_order(order),
+            BookEvent::Updated(order) => cloned_order(self, order.order_id())
+                .and_then(|prev| self.update_order(order, &prev)),
+            BookEvent::MovedToBack(order) => cloned_order(self, order.order_id())
+                .and_then(|prev| self.move_to_back(order, &prev)),
+            BookEvent::Removed(order_id) => {
+                cloned_order(self, *order_id).and_then(|prev| self.remove_order(&prev).map(|_| ()))
+            },
+            BookEvent::Expired => {
+                self.check_expired(instant);
+                Ok(())
+            },
+        }
     }
 
     // === Linked list helpers ===
 
     /// Get a level by side and price (immutable).
-    fn get_level(&self, side: types::OrderSide, price: UD64) -> Option<&BookLevel> {
+    fn get_level(&self, side: types::OrderSide, price: num::Price) -> Option<&BookLevel> {
         match side {
             types::OrderSide::Ask => self.asks.get(&price),
             types::OrderSide::Bid => self.bids.get(&Reverse(price)),
@@ -520,7 +830,11 @@ impl OrderBook {
     }
 
     /// Get a level by side and price (mutable).
-    fn get_level_mut(&mut self, side: types::OrderSide, price: UD64) -> Option<&mut BookLevel> {
+    fn get_level_mut(
+        &mut self,
+        side: types::OrderSide,
+        price: num::Price,
+    ) -> Option<&mut BookLevel> {
         match side {
             types::OrderSide::Ask => self.asks.get_mut(&price),
             types::OrderSide::Bid => self.bids.get_mut(&Reverse(price)),
@@ -528,7 +842,11 @@ impl OrderBook {
     }
 
     /// Get or create a level by side and price.
-    fn get_or_create_level_mut(&mut self, side: types::OrderSide, price: UD64) -> &mut BookLevel {
+    fn get_or_create_level_mut(
+        &mut self,
+        side: types::OrderSide,
+        price: num::Price,
+    ) -> &mut BookLevel {
         match side {
             types::OrderSide::Ask => self.asks.entry(price).or_default(),
             types::OrderSide::Bid => self.bids.entry(Reverse(price)).or_default(),
@@ -536,7 +854,7 @@ impl OrderBook {
     }
 
     /// Remove a level by side and price.
-    fn remove_level(&mut self, side: types::OrderSide, price: UD64) {
+    fn remove_level(&mut self, side: types::OrderSide, price: num::Price) {
         match side {
             types::OrderSide::Ask => {
                 self.asks.remove(&price);
@@ -549,7 +867,7 @@ impl OrderBook {
 
     /// Force remove a level (for testing state inconsistency handling).
     #[cfg(test)]
-    pub(crate) fn force_remove_level(&mut self, side: types::OrderSide, price: UD64) {
+    pub(crate) fn force_remove_level(&mut self, side: types::OrderSide, price: num::Price) {
         self.remove_level(side, price);
     }
 
@@ -575,10 +893,10 @@ impl OrderBook {
     fn link_at_tail(
         &mut self,
         side: types::OrderSide,
-        price: UD64,
+        price: num::Price,
         old_tail: Option<types::OrderId>,
         order_id: types::OrderId,
-        size: UD64,
+        size: num::Size,
     ) {
         // Update old tail's next pointer
         if let Some(old_tail_id) = old_tail
@@ -599,35 +917,32 @@ impl OrderBook {
     /// Gets the impact price for a market order of the requested size, along
     /// with the fillable size and size-averaged price.
     fn impact<'a>(
-        mut side: impl Iterator<Item = (&'a UD64, &'a BookLevel)>,
-        want_size: UD64,
-    ) -> Option<(UD64, UD64, UD64)> {
-        let (price, unfilled, price_size) = side
+        mut side: impl Iterator<Item = (&'a num::Price, &'a BookLevel)>,
+        want_size: num::Size,
+    ) -> Option<(num::Price, num::Size, num::Price)> {
+        let (price, unfilled, notional) = side
             .fold_while(
-                (UD64::ZERO, want_size, UD128::ZERO),
-                |(_, unfilled, price_size), (price, level)| {
+                (num::Price::ZERO, want_size, num::Collateral::ZERO),
+                |(_, unfilled, notional), (price, level)| {
                     let level_size = level.size();
                     if unfilled > level_size {
                         FoldWhile::Continue((
                             *price,
                             unfilled - level_size,
-                            price_size + (price.resize() * level_size.resize()),
+                            notional + *price * level_size,
                         ))
                     } else {
-                        FoldWhile::Done((
-                            *price,
-                            UD64::ZERO,
-                            price_size + (price.resize() * unfilled.resize()),
-                        ))
+                        FoldWhile::Done((*price, num::Size::ZERO, notional + *price * unfilled))
                     }
                 },
             )
             .into_inner();
         let filled = want_size - unfilled;
-        if filled > UD64::ZERO {
-            Some((price, filled, (price_size / filled.resize()).resize()))
-        } else {
+        if filled.is_zero() {
             None
+        } else {
+            let average: UD64 = (notional.get() / filled.get().resize()).resize();
+            Some((price, filled, num::Price::new(average)))
         }
     }
 
@@ -641,15 +956,15 @@ impl OrderBook {
     /// `filled_notional` meets or exceeds the value specified for
     /// `want_notional`.
     fn impact_notional<'a>(
-        mut side: impl Iterator<Item = (&'a UD64, &'a BookLevel)>,
-        want_notional: UD128,
-    ) -> Option<(UD64, UD64, UD64, UD128)> {
+        mut side: impl Iterator<Item = (&'a num::Price, &'a BookLevel)>,
+        want_notional: num::Collateral,
+    ) -> Option<(num::Price, num::Size, num::Price, num::Collateral)> {
         let (price, _, filled_size, filled_notional) = side
             .fold_while(
-                (UD64::ZERO, want_notional, UD64::ZERO, UD128::ZERO),
+                (num::Price::ZERO, want_notional, num::Size::ZERO, num::Collateral::ZERO),
                 |(_, remaining, filled_size, filled_notional), (price, level)| {
                     let level_size = level.size();
-                    let level_notional = price.resize() * level_size.resize();
+                    let level_notional = *price * level_size;
                     if remaining > level_notional {
                         FoldWhile::Continue((
                             *price,
@@ -658,11 +973,11 @@ impl OrderBook {
                             filled_notional + level_notional,
                         ))
                     } else {
-                        let partial_size: UD64 = (remaining / price.resize()).resize();
+                        let partial_size: UD64 = (remaining.get() / price.get().resize()).resize();
                         FoldWhile::Done((
                             *price,
-                            UD128::ZERO,
-                            filled_size + partial_size,
+                            num::Collateral::ZERO,
+                            filled_size + num::Size::new(partial_size),
                             filled_notional + remaining,
                         ))
                     }
@@ -673,13 +988,57 @@ impl OrderBook {
             None
         } else {
             let ctx = Context::default().with_rounding_mode(RoundingMode::HalfUp);
-            let vwap: UD64 =
-                (filled_notional.with_ctx(ctx) / filled_size.resize().with_ctx(ctx)).resize();
-            Some((price, filled_size, vwap, filled_notional))
+            let vwap: UD64 = (filled_notional.get().with_ctx(ctx)
+                / filled_size.get().resize().with_ctx(ctx))
+            .resize();
+            Some((price, filled_size, num::Price::new(vwap), filled_notional))
         }
     }
 }
 
+/// Test utility builders for `OrderBook`, gated behind the `test-utils`
+/// feature same as the ones of [`crate::state::Perpetual`], to drive the book
+/// directly, e.g. from the benchmarks.
+#[cfg(any(test, feature = "test-utils"))]
+impl OrderBook {
+    /// Empty order book.
+    pub fn for_test() -> Self { Self::new() }
+
+    /// Adds an order to the back of the queue of its price level.
+    pub fn add_test_order(
+        &mut self,
+        r#type: types::OrderType,
+        order_id: types::OrderId,
+        price: num::Price,
+        size: num::Size,
+    ) -> OrderBookResult<()> {
+        self.add_order(&Order::for_l3_testing(r#type, price.get(), size.get(), 0, order_id, 0))
+    }
+
+    /// Changes the size of an order, keeping its queue position.
+    pub fn update_test_order(
+        &mut self,
+        order_id: types::OrderId,
+        size: num::Size,
+    ) -> OrderBookResult<()> {
+        let prev = self.test_order(order_id)?;
+        self.update_order(&prev.with_size(size.get()), &prev)
+    }
+
+    /// Removes an order, returning it.
+    pub fn remove_test_order(&mut self, order_id: types::OrderId) -> OrderBookResult<Order> {
+        let prev = self.test_order(order_id)?;
+        self.remove_order(&prev)
+    }
+
+    fn test_order(&self, order_id: types::OrderId) -> OrderBookResult<BookOrder> {
+        self.orders
+            .get(&order_id)
+            .cloned()
+            .ok_or(OrderBookError::OrderNotFound { order_id })
+    }
+}
+
 #[cfg(feature = "display")]
 impl std::fmt::Display for OrderBook {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -688,7 +1047,7 @@ impl std::fmt::Display for OrderBook {
 }
 
 /// Iterator over orders at a price level (follows linked list).
-pub(crate) struct LevelOrdersIter<'a> {
+pub struct LevelOrdersIter<'a> {
     orders: &'a HashMap<types::OrderId, BookOrder>,
     current: Option<types::OrderId>,
 }
@@ -9,6 +9,7 @@ use crate::{state::Order, types};
 /// Each order belongs to a doubly-linked list at its price level,
 /// enabling O(1) insertion/removal and natural FIFO ordering.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
 pub struct BookOrder {
     order: Order,
     /// Previous order in queue (toward head). None if this is the head.
@@ -50,13 +50,17 @@ macro_rules! bid {
 /// Assert L2 level state: (price, total_size, num_orders).
 macro_rules! assert_level {
     ($book:expr, ask @ $price:expr => ($size:expr, $count:expr)) => {
-        let level = $book.ask_level(udec64!($price)).expect("ask level exists");
-        assert_eq!(level.size(), udec64!($size), "ask@{} size", $price);
+        let level = $book
+            .ask_level(num::Price::new(udec64!($price)))
+            .expect("ask level exists");
+        assert_eq!(level.size(), num::Size::new(udec64!($size)), "ask@{} size", $price);
         assert_eq!(level.num_orders(), $count, "ask@{} count", $price);
     };
     ($book:expr, bid @ $price:expr => ($size:expr, $count:expr)) => {
-        let level = $book.bid_level(udec64!($price)).expect("bid level exists");
-        assert_eq!(level.size(), udec64!($size), "bid@{} size", $price);
+        let level = $book
+            .bid_level(num::Price::new(udec64!($price)))
+            .expect("bid level exists");
+        assert_eq!(level.size(), num::Size::new(udec64!($size)), "bid@{} size", $price);
         assert_eq!(level.num_orders(), $count, "bid@{} count", $price);
     };
 }
@@ -67,7 +71,11 @@ macro_rules! assert_best_ask {
         assert_eq!($book.best_ask(), None, "expected no best ask");
     };
     ($book:expr, $price:expr, $size:expr) => {
-        assert_eq!($book.best_ask(), Some((udec64!($price), udec64!($size))), "best ask");
+        assert_eq!(
+            $book.best_ask(),
+            Some((num::Price::new(udec64!($price)), num::Size::new(udec64!($size)))),
+            "best ask"
+        );
     };
 }
 
@@ -76,7 +84,11 @@ macro_rules! assert_best_bid {
         assert_eq!($book.best_bid(), None, "expected no best bid");
     };
     ($book:expr, $price:expr, $size:expr) => {
-        assert_eq!($book.best_bid(), Some((udec64!($price), udec64!($size))), "best bid");
+        assert_eq!(
+            $book.best_bid(),
+            Some((num::Price::new(udec64!($price)), num::Size::new(udec64!($size)))),
+            "best bid"
+        );
     };
 }
 
@@ -86,8 +98,8 @@ macro_rules! assert_order {
         let order = $book
             .get_order(oid($oid))
             .expect(&format!("order {} exists", $oid));
-        assert_eq!(order.price(), udec64!($price), "order {} price", $oid);
-        assert_eq!(order.size(), udec64!($size), "order {} size", $oid);
+        assert_eq!(order.price(), udec64!($price).into(), "order {} price", $oid);
+        assert_eq!(order.size(), udec64!($size).into(), "order {} size", $oid);
         assert_eq!(order.account_id(), $aid, "order {} account_id", $oid);
     };
 }
@@ -98,8 +110,8 @@ macro_rules! assert_order_by_client_id {
         let order = $book
             .get_order_by_client_id($aid, $client_order_id)
             .expect(&format!("order with client ID {} exists", $client_order_id));
-        assert_eq!(order.price(), udec64!($price), "order {} price", $client_order_id);
-        assert_eq!(order.size(), udec64!($size), "order {} size", $client_order_id);
+        assert_eq!(order.price(), udec64!($price).into(), "order {} price", $client_order_id);
+        assert_eq!(order.size(), udec64!($size).into(), "order {} size", $client_order_id);
         assert_eq!(order.account_id(), $aid, "order {} account_id", $client_order_id);
     };
 }
@@ -107,12 +119,12 @@ macro_rules! assert_order_by_client_id {
 /// Assert FIFO order at a price level.
 macro_rules! assert_fifo {
     ($book:expr, ask @ $price:expr => [$($oid:expr),*]) => {
-        let level = $book.ask_level(udec64!($price)).expect("ask level exists");
+        let level = $book.ask_level(num::Price::new(udec64!($price))).expect("ask level exists");
         let order_ids: Vec<_> = $book.level_orders(level).map(|o| o.order_id()).collect();
         assert_eq!(order_ids, vec![$(oid($oid)),*], "ask@{} FIFO order", $price);
     };
     ($book:expr, bid @ $price:expr => [$($oid:expr),*]) => {
-        let level = $book.bid_level(udec64!($price)).expect("bid level exists");
+        let level = $book.bid_level(num::Price::new(udec64!($price))).expect("bid level exists");
         let order_ids: Vec<_> = $book.level_orders(level).map(|o| o.order_id()).collect();
         assert_eq!(order_ids, vec![$(oid($oid)),*], "bid@{} FIFO order", $price);
     };
@@ -126,7 +138,7 @@ macro_rules! assert_fifo {
 fn l3_level_new_is_empty() {
     let level = BookLevel::new();
     assert!(level.is_empty());
-    assert_eq!(level.size(), udec64!(0));
+    assert_eq!(level.size(), num::Size::ZERO);
     assert_eq!(level.num_orders(), 0);
     assert!(level.head().is_none());
     assert!(level.tail().is_none());
@@ -173,6 +185,66 @@ fn l3_book_best_prices() {
     assert_best_bid!(book, 90, 1.0);
 }
 
+#[test]
+fn l3_book_best_orders() {
+    // Head of the best level queue is the first order placed at it.
+    let mut book = OrderBook::new();
+    assert!(book.best_ask_order().is_none());
+    book.add_order(&ask!(110, 1.0, 1, 1, 1)).unwrap();
+    book.add_order(&ask!(100, 2.0, 2, 2, 2)).unwrap();
+    book.add_order(&ask!(100, 1.0, 3, 3, 3)).unwrap();
+    book.add_order(&bid!(90, 1.0, 4, 4, 4)).unwrap();
+    book.add_order(&bid!(90, 1.0, 5, 5, 5)).unwrap();
+
+    let head = book.best_ask_order().unwrap();
+    assert_eq!((head.order_id(), head.account_id()), (oid(2), 2));
+    assert_eq!(head.size(), udec64!(2.0).into());
+    assert_eq!(book.best_bid_order().unwrap().order_id(), oid(4));
+
+    book.remove_order(&book.get_order(oid(2)).cloned().unwrap())
+        .unwrap();
+    assert_eq!(book.best_ask_order().unwrap().order_id(), oid(3));
+}
+
+#[test]
+fn l3_book_is_crossed() {
+    let mut book = OrderBook::new();
+    assert!(!book.is_crossed());
+    book.add_order(&ask!(100, 1.0, 1, 1, 1)).unwrap();
+    book.add_order(&bid!(90, 1.0, 2, 2, 2)).unwrap();
+    assert!(!book.is_crossed());
+
+    // Bid at the best ask price crosses the book
+    book.add_order(&bid!(100, 1.0, 3, 3, 3)).unwrap();
+    assert!(book.is_crossed());
+}
+
+#[test]
+fn l3_book_crosses_and_improves() {
+    use types::OrderSide::{Ask, Bid};
+
+    let mut book = OrderBook::new();
+    assert!(!book.crosses(Bid, udec64!(100).into()));
+    assert!(book.improves(Ask, udec64!(100).into()));
+
+    book.add_order(&ask!(100, 1.0, 1, 1, 1)).unwrap();
+    book.add_order(&bid!(90, 1.0, 2, 2, 2)).unwrap();
+    assert_eq!(book.best_price(Ask), Some(num::Price::new(udec64!(100))));
+    assert_eq!(book.best_price(Bid), Some(num::Price::new(udec64!(90))));
+
+    // Crossing the opposite best price, inclusive
+    assert!(book.crosses(Bid, udec64!(100).into()));
+    assert!(!book.crosses(Bid, udec64!(99).into()));
+    assert!(book.crosses(Ask, udec64!(90).into()));
+    assert!(!book.crosses(Ask, udec64!(91).into()));
+
+    // Improving the best price of the same side, exclusive
+    assert!(book.improves(Bid, udec64!(91).into()));
+    assert!(!book.improves(Bid, udec64!(90).into()));
+    assert!(book.improves(Ask, udec64!(99).into()));
+    assert!(!book.improves(Ask, udec64!(100).into()));
+}
+
 #[test]
 fn l3_book_multiple_orders_same_price() {
     // Multiple orders at same price: sizes aggregate, FIFO by insertion order.
@@ -185,15 +257,44 @@ fn l3_book_multiple_orders_same_price() {
     assert_fifo!(book, ask @ 100 => [1, 2, 3]); // insertion order
 }
 
+#[test]
+fn l3_book_level_orders_fifo() {
+    let mut book = OrderBook::new();
+    book.add_order(&ask!(100, 1, 1, 3, 1)).unwrap();
+    book.add_order(&ask!(100, 2, 2, 1, 2)).unwrap();
+    book.add_order(&ask!(101, 1, 2, 4, 1)).unwrap();
+    book.add_order(&ask!(100, 3, 3, 2, 3)).unwrap();
+
+    let level = book.ask_level(num::Price::new(udec64!(100))).unwrap();
+    let orders: Vec<_> = book
+        .level_orders(level)
+        .map(|o| (o.order_id(), o.account_id(), o.size().get()))
+        .collect();
+    assert_eq!(
+        orders,
+        vec![(oid(3), 1, udec64!(1)), (oid(1), 2, udec64!(2)), (oid(2), 3, udec64!(3))]
+    );
+
+    let level = book.ask_level(num::Price::new(udec64!(101))).unwrap();
+    assert_eq!(book.level_orders(level).map(|o| o.order_id()).collect::<Vec<_>>(), vec![oid(4)]);
+}
+
 #[test]
 fn l3_book_ask_impact_single_level() {
     // Impact within one price level.
     let mut book = OrderBook::new();
     book.add_order(&ask!(100, 5.0, 1, 1, 1)).unwrap();
 
-    let impact = book.ask_impact(udec64!(2.0));
+    let impact = book.ask_impact(udec64!(2.0).into());
     // (impact_price, fillable_size, vwap)
-    assert_eq!(impact, Some((udec64!(100), udec64!(2.0), udec64!(100))));
+    assert_eq!(
+        impact,
+        Some((
+            num::Price::new(udec64!(100)),
+            num::Size::new(udec64!(2.0)),
+            num::Price::new(udec64!(100))
+        ))
+    );
 }
 
 #[test]
@@ -205,8 +306,15 @@ fn l3_book_ask_impact_multiple_levels() {
     book.add_order(&ask!(120, 3.0, 3, 3, 3)).unwrap();
 
     // Want 2.5: fills 1.0@100 + 1.5@110 = 100 + 165 = 265 / 2.5 = 106
-    let impact = book.ask_impact(udec64!(2.5));
-    assert_eq!(impact, Some((udec64!(110), udec64!(2.5), udec64!(265) / udec64!(2.5))));
+    let impact = book.ask_impact(udec64!(2.5).into());
+    assert_eq!(
+        impact,
+        Some((
+            num::Price::new(udec64!(110)),
+            num::Size::new(udec64!(2.5)),
+            num::Price::new(udec64!(265) / udec64!(2.5))
+        ))
+    );
 }
 
 #[test]
@@ -217,8 +325,15 @@ fn l3_book_bid_impact() {
     book.add_order(&bid!(90, 3.0, 2, 2, 2)).unwrap();
 
     // Want 3.0: fills 2.0@100 + 1.0@90 = 200 + 90 = 290 / 3.0
-    let impact = book.bid_impact(udec64!(3.0));
-    assert_eq!(impact, Some((udec64!(90), udec64!(3.0), udec64!(290) / udec64!(3.0))));
+    let impact = book.bid_impact(udec64!(3.0).into());
+    assert_eq!(
+        impact,
+        Some((
+            num::Price::new(udec64!(90)),
+            num::Size::new(udec64!(3.0)),
+            num::Price::new(udec64!(290) / udec64!(3.0))
+        ))
+    );
 }
 
 // ============================================================================
@@ -281,10 +396,10 @@ fn l3_book_level_accessors() {
     book.add_order(&ask!(100, 2.0, 2, 2, 2)).unwrap();
     book.add_order(&bid!(90, 3.0, 3, 3, 3)).unwrap();
 
-    assert!(book.ask_level(udec64!(100)).is_some());
-    assert!(book.ask_level(udec64!(99)).is_none());
-    assert!(book.bid_level(udec64!(90)).is_some());
-    assert!(book.bid_level(udec64!(91)).is_none());
+    assert!(book.ask_level(num::Price::new(udec64!(100))).is_some());
+    assert!(book.ask_level(num::Price::new(udec64!(99))).is_none());
+    assert!(book.bid_level(num::Price::new(udec64!(90))).is_some());
+    assert!(book.bid_level(num::Price::new(udec64!(91))).is_none());
 }
 
 // ============================================================================
@@ -337,7 +452,7 @@ fn l3_book_remove_last_order_at_level() {
     book.remove_order(&book.get_order(order.order_id()).cloned().unwrap())
         .unwrap();
 
-    assert!(book.ask_level(udec64!(100)).is_none());
+    assert!(book.ask_level(num::Price::new(udec64!(100))).is_none());
     assert_best_ask!(book, 110, 1.0);
 }
 
@@ -401,8 +516,8 @@ fn l3_book_empty_book_operations() {
 
     assert_best_ask!(book, none);
     assert_best_bid!(book, none);
-    assert!(book.ask_impact(udec64!(1.0)).is_none());
-    assert!(book.bid_impact(udec64!(1.0)).is_none());
+    assert!(book.ask_impact(udec64!(1.0).into()).is_none());
+    assert!(book.bid_impact(udec64!(1.0).into()).is_none());
     assert!(book.get_order(oid(1)).is_none());
     assert_eq!(book.total_orders(), 0);
 }
@@ -605,14 +720,14 @@ fn scenario_multi_level_book() {
     assert_eq!(book.total_orders(), 6);
 
     // All 3 ask levels exist
-    assert!(book.ask_level(udec64!(100)).is_some());
-    assert!(book.ask_level(udec64!(110)).is_some());
-    assert!(book.ask_level(udec64!(120)).is_some());
+    assert!(book.ask_level(num::Price::new(udec64!(100))).is_some());
+    assert!(book.ask_level(num::Price::new(udec64!(110))).is_some());
+    assert!(book.ask_level(num::Price::new(udec64!(120))).is_some());
 
     // All 3 bid levels exist
-    assert!(book.bid_level(udec64!(90)).is_some());
-    assert!(book.bid_level(udec64!(80)).is_some());
-    assert!(book.bid_level(udec64!(70)).is_some());
+    assert!(book.bid_level(num::Price::new(udec64!(90))).is_some());
+    assert!(book.bid_level(num::Price::new(udec64!(80))).is_some());
+    assert!(book.bid_level(num::Price::new(udec64!(70))).is_some());
 
     // L3 iteration order
     let ask_ids: Vec<_> = book.ask_orders().map(|o| o.order_id()).collect();
@@ -678,6 +793,81 @@ fn scenario_size_decrease_keeps_priority() {
     assert_level!(book, ask @ 100 => (5.5, 3)); // 1.0 + 1.5 + 3.0
 }
 
+#[test]
+fn scenario_partial_fills_keep_level_size() {
+    let mut book = OrderBook::new();
+    let a = ask!(100, 1.0, 1, 1, 1);
+    let b = ask!(100, 2.0, 2, 2, 2);
+    let c = bid!(99, 3.0, 3, 3, 3);
+    for order in [&a, &b, &c] {
+        book.add_order(order).unwrap();
+    }
+    assert!(book.verify_sizes());
+
+    // Partial fills of each order, then the rest of A filled
+    for (order, size) in
+        [(&a, udec64!(0.4)), (&b, udec64!(1.5)), (&c, udec64!(0.1)), (&b, udec64!(0.5))]
+    {
+        let prev = book.get_order(order.order_id()).cloned().unwrap();
+        book.update_order(&order.with_size(size), &prev).unwrap();
+        assert!(book.verify_sizes());
+    }
+    book.remove_order(&book.get_order(a.order_id()).cloned().unwrap())
+        .unwrap();
+    assert!(book.verify_sizes());
+    assert_level!(book, ask @ 100 => (0.5, 1));
+    assert_level!(book, bid @ 99 => (0.1, 1));
+
+    // Drift of the cached size is detected
+    book.get_level_mut(types::OrderSide::Ask, udec64!(100).into())
+        .unwrap()
+        .sub_size(udec64!(0.1).into());
+    assert!(
+        !book
+            .ask_level(num::Price::new(udec64!(100)))
+            .unwrap()
+            .verify_size(&book)
+    );
+    assert!(
+        book.bid_level(num::Price::new(udec64!(99)))
+            .unwrap()
+            .verify_size(&book)
+    );
+    assert!(!book.verify_sizes());
+}
+
+#[test]
+fn scenario_sweep_expired() {
+    let mut book = OrderBook::new();
+    let a = ask!(100, 1.0, 1, 1, 1).with_expiry_block(10);
+    let b = ask!(100, 2.0, 1, 2, 2).with_expiry_block(20);
+    let c = ask!(101, 3.0, 1, 3, 3);
+    let d = bid!(99, 4.0, 1, 4, 4).with_expiry_block(5);
+    for order in [&a, &b, &c, &d] {
+        book.add_order(order).unwrap();
+    }
+
+    // Nothing expired yet
+    assert!(book.sweep_expired(4).is_empty());
+    assert_eq!(book.total_orders(), 4);
+
+    let swept = book.sweep_expired(10);
+    assert_eq!(swept.iter().map(|o| o.order_id()).collect::<Vec<_>>(), vec![oid(1), oid(4)]);
+    assert_eq!(book.total_orders(), 2);
+    assert_level!(book, ask @ 100 => (2.0, 1));
+    assert_level!(book, ask @ 101 => (3.0, 1));
+    assert!(book.bid_level(num::Price::new(udec64!(99))).is_none());
+    assert!(book.verify_sizes());
+
+    // Orders marked expired are swept as well
+    book.check_expired(types::StateInstant::new(20, 0));
+    let swept = book.sweep_expired(20);
+    assert_eq!(swept.len(), 1);
+    assert_eq!(swept[0].order_id(), oid(2));
+    assert!(book.ask_level(num::Price::new(udec64!(100))).is_none());
+    assert_eq!(book.total_orders(), 1);
+}
+
 // ============================================================================
 // SNAPSHOT RECONSTRUCTION TESTS
 // ============================================================================
@@ -771,7 +961,7 @@ fn linked_list_head_tail_single_order() {
     let mut book = OrderBook::new();
     book.add_order(&ask!(100, 1.0, 1, 1, 1)).unwrap();
 
-    let level = book.ask_level(udec64!(100)).unwrap();
+    let level = book.ask_level(num::Price::new(udec64!(100))).unwrap();
     assert!(level.head().is_some());
     assert!(level.tail().is_some());
     assert_eq!(level.head(), level.tail());
@@ -785,7 +975,7 @@ fn linked_list_head_tail_multiple_orders() {
     book.add_order(&ask!(100, 2.0, 2, 2, 2)).unwrap();
     book.add_order(&ask!(100, 3.0, 3, 3, 3)).unwrap();
 
-    let level = book.ask_level(udec64!(100)).unwrap();
+    let level = book.ask_level(num::Price::new(udec64!(100))).unwrap();
     let head = level.head().unwrap();
     let tail = level.tail().unwrap();
 
@@ -812,7 +1002,7 @@ fn linked_list_remove_head() {
     book.remove_order(&book.get_order(order1.order_id()).cloned().unwrap())
         .unwrap();
 
-    let level = book.ask_level(udec64!(100)).unwrap();
+    let level = book.ask_level(num::Price::new(udec64!(100))).unwrap();
     let head = level.head().unwrap();
     assert_eq!(head, oid(2));
     let head_order = book.get_order(head).unwrap();
@@ -831,7 +1021,7 @@ fn linked_list_remove_tail() {
     book.remove_order(&book.get_order(order2.order_id()).cloned().unwrap())
         .unwrap();
 
-    let level = book.ask_level(udec64!(100)).unwrap();
+    let level = book.ask_level(num::Price::new(udec64!(100))).unwrap();
     let tail = level.tail().unwrap();
     assert_eq!(tail, oid(1));
     let tail_order = book.get_order(tail).unwrap();
@@ -856,7 +1046,7 @@ fn linked_list_remove_middle() {
     assert_fifo!(book, ask @ 100 => [1, 3]);
 
     // Check links
-    let level = book.ask_level(udec64!(100)).unwrap();
+    let level = book.ask_level(num::Price::new(udec64!(100))).unwrap();
     let head = level.head().unwrap();
     let tail = level.tail().unwrap();
     assert_eq!(head, oid(1));
@@ -945,7 +1135,7 @@ fn snapshot_orphaned_orders_no_links() {
 
     // When links are missing, we still have valid head/tail (at least one order is
     // head, one is tail)
-    let level = book.ask_level(udec64!(100)).unwrap();
+    let level = book.ask_level(num::Price::new(udec64!(100))).unwrap();
     assert!(level.head().is_some());
     assert!(level.tail().is_some());
 }
@@ -1012,7 +1202,7 @@ fn level_not_found_on_update_order() {
     book.add_order(&order).unwrap();
 
     // Force remove the level (simulating corruption)
-    book.force_remove_level(types::OrderSide::Ask, udec64!(100));
+    book.force_remove_level(types::OrderSide::Ask, udec64!(100).into());
 
     // Try to update the order - should fail with LevelNotFound
     let updated = order.with_size(udec64!(0.5));
@@ -1031,7 +1221,7 @@ fn level_not_found_on_remove_order() {
     book.add_order(&order).unwrap();
 
     // Force remove the level
-    book.force_remove_level(types::OrderSide::Ask, udec64!(100));
+    book.force_remove_level(types::OrderSide::Ask, udec64!(100).into());
 
     // Try to remove the order - should fail with LevelNotFound
     let result = book.remove_order(&book.get_order(order.order_id()).cloned().unwrap());
@@ -1049,7 +1239,7 @@ fn level_not_found_on_move_to_back() {
     book.add_order(&order).unwrap();
 
     // Force remove the level
-    book.force_remove_level(types::OrderSide::Ask, udec64!(100));
+    book.force_remove_level(types::OrderSide::Ask, udec64!(100).into());
 
     // Try to move to back - should fail with LevelNotFound
     let updated = order.with_size(udec64!(1.5));
@@ -1122,13 +1312,13 @@ fn book_with_inventory(ask_levels: &[(u64, &[u64])], bid_levels: &[(u64, &[u64])
 #[test]
 fn impact_notional_empty_book_ask() {
     let book = OrderBook::new();
-    assert_eq!(book.ask_impact_notional(udec128!(1000)), None);
+    assert_eq!(book.ask_impact_notional(udec128!(1000).into()), None);
 }
 
 #[test]
 fn impact_notional_empty_book_bid() {
     let book = OrderBook::new();
-    assert_eq!(book.bid_impact_notional(udec128!(1000)), None);
+    assert_eq!(book.bid_impact_notional(udec128!(1000).into()), None);
 }
 
 // 2. Cross-talk: only opposing inventory → None
@@ -1136,13 +1326,13 @@ fn impact_notional_empty_book_bid() {
 #[test]
 fn impact_notional_crosstalk_ask_with_only_bid_inventory() {
     let book = book_with_inventory(&[], &[(100, &[10])]);
-    assert_eq!(book.ask_impact_notional(udec128!(500)), None);
+    assert_eq!(book.ask_impact_notional(udec128!(500).into()), None);
 }
 
 #[test]
 fn impact_notional_crosstalk_bid_with_only_ask_inventory() {
     let book = book_with_inventory(&[(100, &[10])], &[]);
-    assert_eq!(book.bid_impact_notional(udec128!(500)), None);
+    assert_eq!(book.bid_impact_notional(udec128!(500).into()), None);
 }
 
 // 3. Inventory exists, want_notional = 0 → None
@@ -1150,13 +1340,13 @@ fn impact_notional_crosstalk_bid_with_only_ask_inventory() {
 #[test]
 fn impact_notional_zero_notional_ask() {
     let book = book_with_inventory(&[(100, &[10])], &[]);
-    assert_eq!(book.ask_impact_notional(UD128::ZERO), None);
+    assert_eq!(book.ask_impact_notional(UD128::ZERO.into()), None);
 }
 
 #[test]
 fn impact_notional_zero_notional_bid() {
     let book = book_with_inventory(&[], &[(100, &[10])]);
-    assert_eq!(book.bid_impact_notional(UD128::ZERO), None);
+    assert_eq!(book.bid_impact_notional(UD128::ZERO.into()), None);
 }
 
 // 4. Single level, half inventory
@@ -1168,15 +1358,31 @@ fn impact_notional_zero_notional_bid() {
 #[test]
 fn impact_notional_single_level_half_ask() {
     let book = book_with_inventory(&[(100, &[10, 20, 30])], &[]);
-    let result = book.ask_impact_notional(udec128!(3000));
-    assert_eq!(result, Some((udec64!(100), udec64!(30), udec64!(100), udec128!(3000))));
+    let result = book.ask_impact_notional(udec128!(3000).into());
+    assert_eq!(
+        result,
+        Some((
+            num::Price::new(udec64!(100)),
+            num::Size::new(udec64!(30)),
+            num::Price::new(udec64!(100)),
+            num::Collateral::new(udec128!(3000))
+        ))
+    );
 }
 
 #[test]
 fn impact_notional_single_level_half_bid() {
     let book = book_with_inventory(&[], &[(100, &[10, 20, 30])]);
-    let result = book.bid_impact_notional(udec128!(3000));
-    assert_eq!(result, Some((udec64!(100), udec64!(30), udec64!(100), udec128!(3000))));
+    let result = book.bid_impact_notional(udec128!(3000).into());
+    assert_eq!(
+        result,
+        Some((
+            num::Price::new(udec64!(100)),
+            num::Size::new(udec64!(30)),
+            num::Price::new(udec64!(100)),
+            num::Collateral::new(udec128!(3000))
+        ))
+    );
 }
 
 // 5. Single level, full inventory
@@ -1186,15 +1392,31 @@ fn impact_notional_single_level_half_bid() {
 #[test]
 fn impact_notional_single_level_full_ask() {
     let book = book_with_inventory(&[(100, &[10, 20, 30])], &[]);
-    let result = book.ask_impact_notional(udec128!(6000));
-    assert_eq!(result, Some((udec64!(100), udec64!(60), udec64!(100), udec128!(6000))));
+    let result = book.ask_impact_notional(udec128!(6000).into());
+    assert_eq!(
+        result,
+        Some((
+            num::Price::new(udec64!(100)),
+            num::Size::new(udec64!(60)),
+            num::Price::new(udec64!(100)),
+            num::Collateral::new(udec128!(6000))
+        ))
+    );
 }
 
 #[test]
 fn impact_notional_single_level_full_bid() {
     let book = book_with_inventory(&[], &[(100, &[10, 20, 30])]);
-    let result = book.bid_impact_notional(udec128!(6000));
-    assert_eq!(result, Some((udec64!(100), udec64!(60), udec64!(100), udec128!(6000))));
+    let result = book.bid_impact_notional(udec128!(6000).into());
+    assert_eq!(
+        result,
+        Some((
+            num::Price::new(udec64!(100)),
+            num::Size::new(udec64!(60)),
+            num::Price::new(udec64!(100)),
+            num::Collateral::new(udec128!(6000))
+        ))
+    );
 }
 
 // 6. Single level, exceeding inventory → partial fill
@@ -1205,15 +1427,31 @@ fn impact_notional_single_level_full_bid() {
 #[test]
 fn impact_notional_single_level_exceeding_ask() {
     let book = book_with_inventory(&[(100, &[10, 20, 30])], &[]);
-    let result = book.ask_impact_notional(udec128!(7000));
-    assert_eq!(result, Some((udec64!(100), udec64!(60), udec64!(100), udec128!(6000))));
+    let result = book.ask_impact_notional(udec128!(7000).into());
+    assert_eq!(
+        result,
+        Some((
+            num::Price::new(udec64!(100)),
+            num::Size::new(udec64!(60)),
+            num::Price::new(udec64!(100)),
+            num::Collateral::new(udec128!(6000))
+        ))
+    );
 }
 
 #[test]
 fn impact_notional_single_level_exceeding_bid() {
     let book = book_with_inventory(&[], &[(100, &[10, 20, 30])]);
-    let result = book.bid_impact_notional(udec128!(7000));
-    assert_eq!(result, Some((udec64!(100), udec64!(60), udec64!(100), udec128!(6000))));
+    let result = book.bid_impact_notional(udec128!(7000).into());
+    assert_eq!(
+        result,
+        Some((
+            num::Price::new(udec64!(100)),
+            num::Size::new(udec64!(60)),
+            num::Price::new(udec64!(100)),
+            num::Collateral::new(udec128!(6000))
+        ))
+    );
 }
 
 // 7. Multiple levels, reaching into third level
@@ -1233,15 +1471,31 @@ fn impact_notional_single_level_exceeding_bid() {
 #[test]
 fn impact_notional_multi_level_partial_ask() {
     let book = book_with_inventory(&[(100, &[10]), (200, &[20]), (300, &[30])], &[]);
-    let result = book.ask_impact_notional(udec128!(8000));
-    assert_eq!(result, Some((udec64!(300), udec64!(40), udec64!(200), udec128!(8000))));
+    let result = book.ask_impact_notional(udec128!(8000).into());
+    assert_eq!(
+        result,
+        Some((
+            num::Price::new(udec64!(300)),
+            num::Size::new(udec64!(40)),
+            num::Price::new(udec64!(200)),
+            num::Collateral::new(udec128!(8000))
+        ))
+    );
 }
 
 #[test]
 fn impact_notional_multi_level_partial_bid() {
     let book = book_with_inventory(&[], &[(300, &[10]), (200, &[20]), (100, &[30])]);
-    let result = book.bid_impact_notional(udec128!(8000));
-    assert_eq!(result, Some((udec64!(100), udec64!(40), udec64!(200), udec128!(8000))));
+    let result = book.bid_impact_notional(udec128!(8000).into());
+    assert_eq!(
+        result,
+        Some((
+            num::Price::new(udec64!(100)),
+            num::Size::new(udec64!(40)),
+            num::Price::new(udec64!(200)),
+            num::Collateral::new(udec128!(8000))
+        ))
+    );
 }
 
 // 8. Multiple levels, exceeding all inventory → partial fill
@@ -1254,21 +1508,21 @@ fn impact_notional_multi_level_partial_bid() {
 #[test]
 fn impact_notional_multi_level_exceeding_ask() {
     let book = book_with_inventory(&[(100, &[10]), (200, &[20]), (300, &[30])], &[]);
-    let result = book.ask_impact_notional(udec128!(15000));
+    let result = book.ask_impact_notional(udec128!(15000).into());
     let (price, filled_size, _vwap, filled_notional) = result.unwrap();
-    assert_eq!(price, udec64!(300));
-    assert_eq!(filled_size, udec64!(60));
-    assert_eq!(filled_notional, udec128!(14000));
+    assert_eq!(price, udec64!(300).into());
+    assert_eq!(filled_size, udec64!(60).into());
+    assert_eq!(filled_notional, udec128!(14000).into());
 }
 
 #[test]
 fn impact_notional_multi_level_exceeding_bid() {
     let book = book_with_inventory(&[], &[(300, &[10]), (200, &[20]), (100, &[30])]);
-    let result = book.bid_impact_notional(udec128!(11000));
+    let result = book.bid_impact_notional(udec128!(11000).into());
     let (price, filled_size, _vwap, filled_notional) = result.unwrap();
-    assert_eq!(price, udec64!(100));
-    assert_eq!(filled_size, udec64!(60));
-    assert_eq!(filled_notional, udec128!(10000));
+    assert_eq!(price, udec64!(100).into());
+    assert_eq!(filled_size, udec64!(60).into());
+    assert_eq!(filled_notional, udec128!(10000).into());
 }
 
 // 9. want_notional exceeding UD64 range → partial fill
@@ -1281,9 +1535,201 @@ fn impact_notional_ud128_range_want() {
     let book = book_with_inventory(&[(100, &[10, 20, 30])], &[]);
     // 2^65 ≈ 36_893_488_147_419_103_232, well beyond UD64::MAX
     let large_want: UD128 = udec128!(36893488147419103232);
-    let result = book.ask_impact_notional(large_want);
+    let result = book.ask_impact_notional(large_want.into());
     let (price, filled_size, _vwap, filled_notional) = result.unwrap();
-    assert_eq!(price, udec64!(100));
-    assert_eq!(filled_size, udec64!(60));
-    assert_eq!(filled_notional, udec128!(6000));
+    assert_eq!(price, udec64!(100).into());
+    assert_eq!(filled_size, udec64!(60).into());
+    assert_eq!(filled_notional, udec128!(6000).into());
+}
+
+// ============================================================================
+// TOP OF THE BOOK
+// ============================================================================
+
+#[test]
+fn top_levels_with_head_order_ids() {
+    let mut book = book_with_inventory(
+        &[(100, &[10, 20]), (110, &[5]), (120, &[1])],
+        &[(90, &[7, 8]), (80, &[3])],
+    );
+
+    let top = book.top(2);
+    assert_eq!(
+        top.asks(),
+        &[
+            (num::Price::new(udec64!(100)), num::Size::new(udec64!(30)), 2, oid(1)),
+            (num::Price::new(udec64!(110)), num::Size::new(udec64!(5)), 1, oid(3))
+        ]
+    );
+    assert_eq!(
+        top.bids(),
+        &[
+            (num::Price::new(udec64!(90)), num::Size::new(udec64!(15)), 2, oid(5)),
+            (num::Price::new(udec64!(80)), num::Size::new(udec64!(3)), 1, oid(7))
+        ]
+    );
+
+    // Head moves to the next order in the FIFO queue
+    let head = book.get_order(oid(1)).unwrap().clone();
+    book.remove_order(&head).unwrap();
+    let top = book.top(5);
+    assert_eq!(top.asks().len(), 3);
+    assert_eq!(
+        top.asks()[0],
+        (num::Price::new(udec64!(100)), num::Size::new(udec64!(20)), 1, oid(2))
+    );
+    assert_eq!(top.bids().len(), 2);
+
+    assert!(book.top(0).asks().is_empty());
+    assert!(OrderBook::new().top(3).bids().is_empty());
+}
+
+// ============================================================================
+// DEPTH SERIES
+// ============================================================================
+
+#[cfg(feature = "display")]
+#[test]
+fn depth_series_cumulative_sizes() {
+    let book = book_with_inventory(
+        &[(100, &[10, 20]), (110, &[5]), (120, &[1])],
+        &[(90, &[7, 8]), (80, &[3])],
+    );
+
+    let (asks, bids) = book.view(None, None, false).depth_series();
+    assert_eq!(
+        asks,
+        vec![
+            (num::Price::new(udec64!(100)), num::Size::new(udec64!(30))),
+            (num::Price::new(udec64!(110)), num::Size::new(udec64!(35))),
+            (num::Price::new(udec64!(120)), num::Size::new(udec64!(36)))
+        ]
+    );
+    assert_eq!(
+        bids,
+        vec![
+            (num::Price::new(udec64!(90)), num::Size::new(udec64!(15))),
+            (num::Price::new(udec64!(80)), num::Size::new(udec64!(18)))
+        ]
+    );
+    for series in [&asks, &bids] {
+        assert!(series.windows(2).all(|w| w[0].1 <= w[1].1));
+    }
+
+    // Limited by the view depth
+    let (asks, bids) = book.view(Some(1), None, false).depth_series();
+    assert_eq!(asks, vec![(num::Price::new(udec64!(100)), num::Size::new(udec64!(30)))]);
+    assert_eq!(bids, vec![(num::Price::new(udec64!(90)), num::Size::new(udec64!(15)))]);
+}
+
+#[test]
+fn depth_notional_cumulative() {
+    let book = book_with_inventory(
+        &[(100, &[10, 20]), (110, &[5]), (120, &[1])],
+        &[(90, &[7, 8]), (80, &[3])],
+    );
+
+    let asks = book.depth_notional(types::OrderSide::Ask, 10);
+    let expected = [(100u64, 30u64), (110, 5), (120, 1)]
+        .into_iter()
+        .scan(num::Collateral::ZERO, |notional, (price, size)| {
+            let price = num::Price::new(UD64::from(price));
+            *notional += price * num::Size::new(UD64::from(size));
+            Some((price, *notional))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(asks, expected);
+    assert_eq!(
+        asks.last(),
+        Some(&(num::Price::new(udec64!(120)), num::Collateral::new(udec128!(3670))))
+    );
+
+    let bids = book.depth_notional(types::OrderSide::Bid, 10);
+    assert_eq!(
+        bids,
+        vec![
+            (num::Price::new(udec64!(90)), num::Collateral::new(udec128!(1350))),
+            (num::Price::new(udec64!(80)), num::Collateral::new(udec128!(1590)))
+        ]
+    );
+
+    // Limited by the number of levels
+    assert_eq!(
+        book.depth_notional(types::OrderSide::Ask, 1),
+        vec![(num::Price::new(udec64!(100)), num::Collateral::new(udec128!(3000)))]
+    );
+    assert_eq!(OrderBook::new().depth_notional(types::OrderSide::Bid, 3), vec![]);
+}
+
+#[test]
+fn l3_book_journal_as_of() {
+    let instant = |block| types::StateInstant::new(block, block * 10);
+    let mut book = OrderBook::new();
+    book.add_order(&ask!(100, 1.0, 1, 1, 1)).unwrap();
+    book.set_journal_capacity(10, instant(1));
+
+    // Block 2: ask and bid placed
+    book.add_order(&ask!(100, 2.0, 2, 2, 2)).unwrap();
+    book.add_order(&bid!(90, 3.0, 2, 3, 3)).unwrap();
+    book.check_expired(instant(2));
+
+    // Block 3: order 1 cancelled, order 2 partially filled
+    book.remove_order(&book.get_order(oid(1)).cloned().unwrap())
+        .unwrap();
+    let prev = book.get_order(oid(2)).cloned().unwrap();
+    book.update_order(&prev.with_size(udec64!(0.5)), &prev)
+        .unwrap();
+    book.check_expired(instant(3));
+    assert_eq!(book.journal().unwrap().len(), 4);
+
+    let at_block_2 = book.as_of(instant(2)).unwrap().unwrap();
+    assert_level!(at_block_2, ask @ 100 => (3.0, 2));
+    assert_fifo!(at_block_2, ask @ 100 => [1, 2]);
+    assert_level!(at_block_2, bid @ 90 => (3.0, 1));
+
+    let at_block_1 = book.as_of(instant(1)).unwrap().unwrap();
+    assert_level!(at_block_1, ask @ 100 => (1.0, 1));
+    assert_best_bid!(at_block_1, none);
+
+    let at_block_3 = book.as_of(instant(3)).unwrap().unwrap();
+    assert_level!(at_block_3, ask @ 100 => (0.5, 1));
+    assert_fifo!(at_block_3, ask @ 100 => [2]);
+    assert!(book.as_of(instant(0)).unwrap().is_none());
+
+    // Changes of block 2 folded into the base state
+    book.set_journal_capacity(2, instant(3));
+    assert!(book.as_of(instant(1)).unwrap().is_none());
+    let at_block_2 = book.as_of(instant(2)).unwrap().unwrap();
+    assert_level!(at_block_2, ask @ 100 => (3.0, 2));
+
+    book.set_journal_capacity(0, instant(3));
+    assert!(book.journal().is_none());
+    assert!(book.as_of(instant(3)).unwrap().is_none());
+}
+
+#[test]
+fn l3_book_journal_inconsistent() {
+    let instant = |block| types::StateInstant::new(block, block * 10);
+    let mut book = OrderBook::new();
+    book.add_order(&ask!(100, 1.0, 1, 1, 1)).unwrap();
+    book.set_journal_capacity(1, instant(1));
+
+    // Removal of an order missing from the book fails to be replayed
+    book.journal
+        .as_mut()
+        .unwrap()
+        .record(BookEvent::Removed(oid(2)));
+    book.check_expired(instant(2));
+    assert!(matches!(
+        book.as_of(instant(2)),
+        Err(OrderBookError::OrderNotFound { order_id }) if order_id == oid(2)
+    ));
+
+    // Folding it into the base state restarts the journal from the book
+    book.add_order(&ask!(100, 2.0, 3, 3, 3)).unwrap();
+    book.check_expired(instant(3));
+    assert!(book.journal().unwrap().is_empty());
+    assert!(book.as_of(instant(2)).unwrap().is_none());
+    let at_block_3 = book.as_of(instant(3)).unwrap().unwrap();
+    assert_fifo!(at_block_3, ask @ 100 => [1, 3]);
 }
@@ -0,0 +1,25 @@
+//! Top of the book with queue heads.
+
+use crate::{num, types};
+
+/// Price level summary: price, size, number of orders and ID of the order at
+/// the head of the FIFO queue.
+pub type TopLevel = (num::Price, num::Size, u32, types::OrderId);
+
+/// Best non-empty price levels on each side of the book, see
+/// [`super::OrderBook::top`].
+#[derive(Clone, Debug, Default)]
+pub struct BookTop {
+    asks: Vec<TopLevel>,
+    bids: Vec<TopLevel>,
+}
+
+impl BookTop {
+    pub(crate) fn new(asks: Vec<TopLevel>, bids: Vec<TopLevel>) -> Self { Self { asks, bids } }
+
+    /// Best ask levels sorted away from the spread.
+    pub fn asks(&self) -> &[TopLevel] { &self.asks }
+
+    /// Best bid levels sorted away from the spread.
+    pub fn bids(&self) -> &[TopLevel] { &self.bids }
+}
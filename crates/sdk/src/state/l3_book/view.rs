@@ -11,6 +11,11 @@ use tabled::{
 };
 
 use super::{BookLevel, OrderBook};
+use crate::num::{self, DisplayConfig};
+
+/// Cumulative depth of one side of the book as (price, cumulative size)
+/// points, see [`OrderBookView::depth_series`].
+pub type DepthSeries = Vec<(num::Price, num::Size)>;
 
 /// View of an order book.
 /// Can be rendered as plain table or compact L3 representation limited by depth
@@ -31,10 +36,44 @@ impl<'a> OrderBookView<'a> {
     ) -> Self {
         Self { book, depth, orders_per_level, show_expired }
     }
+
+    /// Cumulative depth of the book as (price, cumulative size) points of
+    /// asks and bids respectively, sorted away from the spread and limited
+    /// by the view depth.
+    pub fn depth_series(&self) -> (DepthSeries, DepthSeries) {
+        let depth = self.depth.unwrap_or(usize::MAX);
+        let asks = self
+            .cumulative_levels(self.book.asks.iter().map(|(price, level)| (*price, level)))
+            .map(|(price, _, cumulative_size)| (price, cumulative_size))
+            .take(depth)
+            .collect();
+        let bids = self
+            .cumulative_levels(self.book.bids.iter().map(|(price, level)| (price.0, level)))
+            .map(|(price, _, cumulative_size)| (price, cumulative_size))
+            .take(depth)
+            .collect();
+        (asks, bids)
+    }
+
+    /// Levels shown by the view with the cumulative size of the levels up to
+    /// and including each one.
+    fn cumulative_levels(
+        &self,
+        levels: impl Iterator<Item = (num::Price, &'a BookLevel)>,
+    ) -> impl Iterator<Item = (num::Price, &'a BookLevel, num::Size)> {
+        let show_expired = self.show_expired;
+        levels
+            .filter(move |(_, level)| level.num_orders() > 0 || show_expired)
+            .scan(num::Size::ZERO, |cumulative_size, (price, level)| {
+                *cumulative_size += level.size();
+                Some((price, level, *cumulative_size))
+            })
+    }
 }
 
 impl<'a> std::fmt::Display for OrderBookView<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let config = DisplayConfig::current();
         let spread_panel = |table: &mut Table, row_idx: usize| {
             if let Some(((best_ask, _), (best_bid, _))) =
                 self.book.best_ask().zip(self.book.best_bid())
@@ -43,10 +82,10 @@ impl<'a> std::fmt::Display for OrderBookView<'a> {
                     row_idx,
                     format!(
                         "Best ASK: {} :: Best BID: {} :: Spread: {} ({:.2} %)",
-                        best_ask,
-                        best_bid,
-                        best_ask - best_bid,
-                        (best_ask - best_bid) / ((best_ask + best_bid) / 2) * 100
+                        config.price(best_ask),
+                        config.price(best_bid),
+                        config.price(best_ask - best_bid),
+                        (best_ask - best_bid).get() / ((best_ask + best_bid).get() / 2) * 100
                     ),
                 ));
                 table.modify(Row::from(row_idx), Alignment::right());
@@ -87,20 +126,18 @@ impl<'a> std::fmt::Display for OrderBookView<'a> {
             let mut asks = Vec::with_capacity(self.book.asks.len());
             let mut num_ask_levels = 0;
             let mut num_ask_orders = 0;
-            let mut cumulative_ask_size = UD64::ZERO;
-            for (price, level) in self
-                .book
-                .asks
-                .iter()
-                .filter(|(_, lvl)| lvl.num_orders() > 0 || self.show_expired)
+            let mut cumulative_ask_size = num::Size::ZERO;
+            for (price, level, cumulative_size) in
+                self.cumulative_levels(self.book.asks.iter().map(|(price, level)| (*price, level)))
             {
                 num_ask_levels += 1;
                 num_ask_orders += level.num_orders();
-                cumulative_ask_size += level.size();
+                cumulative_ask_size = cumulative_size;
+                let cumulative_size = config.size(cumulative_ask_size).to_string();
                 asks.push(vec![
-                    price.to_string().red().to_string(),
-                    level.size().to_string().red().to_string(),
-                    cumulative_ask_size.to_string().red().to_string(),
+                    config.price(price).to_string().red().to_string(),
+                    config.size(level.size()).to_string().red().to_string(),
+                    cumulative_size.red().to_string(),
                     level.num_orders().to_string().red().to_string(),
                     level_orders(level),
                 ]);
@@ -110,20 +147,18 @@ impl<'a> std::fmt::Display for OrderBookView<'a> {
             let mut bids = Vec::with_capacity(self.book.bids.len());
             let mut num_bid_levels = 0;
             let mut num_bid_orders = 0;
-            let mut cumulative_bid_size = UD64::ZERO;
-            for (price, level) in self
-                .book
-                .bids
-                .iter()
-                .filter(|(_, lvl)| lvl.num_orders() > 0 || self.show_expired)
+            let mut cumulative_bid_size = num::Size::ZERO;
+            for (price, level, cumulative_size) in
+                self.cumulative_levels(self.book.bids.iter().map(|(price, level)| (price.0, level)))
             {
                 num_bid_levels += 1;
                 num_bid_orders += level.num_orders();
-                cumulative_bid_size += level.size();
+                cumulative_bid_size = cumulative_size;
+                let cumulative_size = config.size(cumulative_bid_size).to_string();
                 bids.push(vec![
-                    price.0.to_string().green().to_string(),
-                    level.size().to_string().green().to_string(),
-                    cumulative_bid_size.to_string().green().to_string(),
+                    config.price(price).to_string().green().to_string(),
+                    config.size(level.size()).to_string().green().to_string(),
+                    cumulative_size.green().to_string(),
                     level.num_orders().to_string().green().to_string(),
                     level_orders(level),
                 ]);
@@ -148,10 +183,10 @@ impl<'a> std::fmt::Display for OrderBookView<'a> {
 
             // Header with totals
             let (ask_pct, bid_pct) =
-                if cumulative_ask_size > UD64::ZERO || cumulative_bid_size > UD64::ZERO {
-                    let total = cumulative_ask_size + cumulative_bid_size;
-                    let ask_pct = (cumulative_ask_size / total) * UD64::from(100u32);
-                    let bid_pct = (cumulative_bid_size / total) * UD64::from(100u32);
+                if !cumulative_ask_size.is_zero() || !cumulative_bid_size.is_zero() {
+                    let total = (cumulative_ask_size + cumulative_bid_size).get();
+                    let ask_pct = (cumulative_ask_size.get() / total) * UD64::from(100u32);
+                    let bid_pct = (cumulative_bid_size.get() / total) * UD64::from(100u32);
                     (ask_pct, bid_pct)
                 } else {
                     (UD64::ZERO, UD64::ZERO)
@@ -162,11 +197,11 @@ impl<'a> std::fmt::Display for OrderBookView<'a> {
                 num_ask_orders + num_bid_orders,
                 num_ask_orders,
                 num_ask_levels,
-                cumulative_ask_size,
+                config.size(cumulative_ask_size),
                 ask_pct,
                 num_bid_orders,
                 num_bid_levels,
-                cumulative_bid_size,
+                config.size(cumulative_bid_size),
                 bid_pct,
             )));
             table.modify(Rows::first(), Alignment::right());
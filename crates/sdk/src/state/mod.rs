@@ -6,42 +6,52 @@
 //!
 //! [`Exchange`] is at the root of indexed state and provides access to all
 //! nested state entities, as well as basic market data derived from observed
-//! trading activity.
+//! trading activity. [`SharedExchange`] shares it between reader tasks and a
+//! single writer task applying events, with [`FrozenExchange`] providing
+//! consistent point-in-time views of it.
 //!
 //! Some of the state and market data can be retrieved/computed only from the
 //! event stream and is not available from the plain snapshot, the documentation
 //! for corresponding access methods explicitly covers such cases.
 
 mod account;
+mod audit;
 mod event;
 mod exchange;
 mod l3_book;
 mod order;
 mod perpetual;
 mod position;
+mod shared;
 
-use std::collections::{HashMap, hash_map};
+use std::collections::{HashMap, HashSet, hash_map};
 
 pub use account::*;
 use alloy::{
-    eips::BlockId,
+    eips::{BlockId, BlockNumberOrTag},
     primitives::{Address, U256},
     providers::Provider,
+    rpc::types::Filter,
+    sol_types::{SolEvent, SolEventInterface},
 };
+pub use audit::*;
 pub use event::*;
 pub use exchange::*;
+use fastnum::UD64;
 use itertools::Itertools;
 pub use l3_book::*;
 pub use order::*;
 pub use perpetual::*;
 pub use position::*;
+pub use shared::*;
 
 use crate::{
     Chain,
     abi::dex::{
         self,
         Exchange::{
-            PerpetualInfo, PerpetualInfoV2, PositionInfo, PositionInfoV2, getExchangeInfoReturn,
+            ExchangeEvents, PerpetualInfo, PerpetualInfoV2, PositionInfo, PositionInfoV2,
+            getExchangeInfoReturn, getMarginFractionsReturn,
         },
     },
     error::{DexError, ProviderError},
@@ -58,6 +68,41 @@ const DEFAULT_ORDERS_PER_BATCH: usize = 1000;
 /// `eth_call`, plus some buffer.
 const DEFAULT_POSITIONS_PER_BATCH: usize = 1000;
 
+/// Parameters of a perpetual contract: info, maker fee, taker fee and margin
+/// fractions.
+type PerpetualParams = (PerpetualInfoV2, U256, U256, getMarginFractionsReturn);
+
+/// Sizes orders were placed with, in lots, along with the accounts issued
+/// them, by perpetual contract and order ID.
+type PlacedSizes = HashMap<(types::PerpetualId, u16), (types::AccountId, U256)>;
+
+/// Entity omitted from the snapshot built with
+/// [`SnapshotBuilder::build_lenient`] as it failed to be fetched.
+#[derive(Debug)]
+pub enum SnapshotFailure {
+    /// Perpetual contract along with its order book.
+    Perpetual(types::PerpetualId, DexError),
+
+    /// Account along with its positions.
+    Account(types::AccountAddressOrID, DexError),
+
+    /// Positions of all accounts in the perpetual contract, see
+    /// [`SnapshotBuilder::with_all_positions`].
+    Positions(types::PerpetualId, DexError),
+}
+
+/// Handling of fetched orders that fail to be parsed, e.g. with
+/// [`OrderParseError::ZeroOrderId`], see [`SnapshotBuilder::with_invalid_orders`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidOrderPolicy {
+    /// Perpetual contract fails to be fetched with [`DexError::OrderParse`].
+    #[default]
+    Fail,
+
+    /// Order is omitted from the order book, logged as a warning.
+    Skip,
+}
+
 /// Builds a consistent snapshot of the exchange state
 /// that can be then kept up-to-date by the data from [`crate::stream::raw`].
 pub struct SnapshotBuilder<P> {
@@ -66,10 +111,20 @@ pub struct SnapshotBuilder<P> {
     provider: P,
     block_id: BlockId,
     perpetuals: Vec<types::PerpetualId>,
+    onchain_perpetuals: bool,
     accounts: Vec<types::AccountAddressOrID>,
     all_positions: bool,
+    tracking: Tracking,
     orders_per_batch: usize,
     positions_per_batch: usize,
+    check_pnl: bool,
+    check_books: bool,
+    invalid_orders: InvalidOrderPolicy,
+    mark_ema_alpha: Option<UD64>,
+    mark_history_capacity: usize,
+    multicall: Option<Address>,
+    multicall_fallback: bool,
+    placed_size_window: u64,
 }
 
 impl<P: Provider + Clone> SnapshotBuilder<P> {
@@ -82,10 +137,20 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
             provider,
             block_id: BlockId::Number(alloy::eips::BlockNumberOrTag::Safe),
             perpetuals: chain.perpetuals.clone(),
+            onchain_perpetuals: false,
             accounts: vec![],
             all_positions: false,
+            tracking: Tracking::ALL,
             orders_per_batch: DEFAULT_ORDERS_PER_BATCH,
             positions_per_batch: DEFAULT_POSITIONS_PER_BATCH,
+            check_pnl: false,
+            check_books: false,
+            invalid_orders: InvalidOrderPolicy::Fail,
+            mark_ema_alpha: None,
+            mark_history_capacity: 0,
+            multicall: None,
+            multicall_fallback: true,
+            placed_size_window: 0,
         }
     }
 
@@ -98,17 +163,35 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
         self
     }
 
-    /// Sets the list of perpetual contract IDs to fetch the state for.
+    /// Sets the list of perpetual contract IDs to fetch the state for,
+    /// overriding [`Self::with_onchain_perpetuals`].
     pub fn with_perpetuals(mut self, perpetuals: Vec<types::PerpetualId>) -> Self {
         self.perpetuals = perpetuals;
+        self.onchain_perpetuals = false;
+        self
+    }
+
+    /// Fetches the state of all perpetual contracts listed by the exchange
+    /// at the snapshot block rather than the ones known to the [`Chain`], see
+    /// [`Chain::fetch_perpetuals`].
+    ///
+    /// The snapshot [`Exchange::chain`] lists the fetched perpetual
+    /// contracts as well.
+    pub fn with_onchain_perpetuals(mut self) -> Self {
+        self.onchain_perpetuals = true;
         self
     }
 
     /// Sets the list of addresses to fetch the state of exchange accounts for.
     /// Assumes accounts already exist, snapshot creation will fail otherwise.
+    ///
+    /// Duplicates are ignored, as well as an account referenced by both its
+    /// address and ID, which is fetched once. Zero address or ID fails the
+    /// snapshot creation with [`DexError::InvalidArgument`].
     pub fn with_accounts(mut self, accounts: Vec<types::AccountAddressOrID>) -> Self {
-        self.accounts = accounts;
+        self.accounts = accounts.into_iter().unique().collect();
         self.all_positions = false;
+        self.tracking.positions = true;
         self
     }
 
@@ -118,6 +201,34 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
     pub fn with_all_positions(mut self) -> Self {
         self.accounts = vec![];
         self.all_positions = true;
+        self.tracking.positions = true;
+        self
+    }
+
+    /// Fetches only perpetual contracts and their order books, skipping any
+    /// account and position calls, so the snapshot has no accounts.
+    /// Mutually exclusive with [`Self::with_accounts`] and
+    /// [`Self::with_all_positions`].
+    pub fn books_only(mut self) -> Self {
+        self.accounts = vec![];
+        self.all_positions = false;
+        self.tracking.positions = false;
+        self
+    }
+
+    /// Sets the parts of the state to fetch and then maintain by
+    /// [`Exchange::apply_events`] (default: [`Tracking::ALL`]).
+    ///
+    /// Skipping order books substantially reduces the cost of both the
+    /// snapshot and applying each block for consumers only interested in
+    /// positions and balances. Skipping positions is equivalent to
+    /// [`Self::books_only`].
+    pub fn track(mut self, tracking: Tracking) -> Self {
+        if !tracking.positions {
+            self.accounts = vec![];
+            self.all_positions = false;
+        }
+        self.tracking = tracking;
         self
     }
 
@@ -136,33 +247,150 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
         self
     }
 
+    /// Verifies PnL breakdown of each fetched position adds up to the total
+    /// PnL reported by the exchange, see [`Position::pnl_is_consistent`],
+    /// failing with [`DexError::InconsistentPnl`] otherwise.
+    pub fn with_pnl_check(mut self) -> Self {
+        self.check_pnl = true;
+        self
+    }
+
+    /// Fails with [`DexError::CrossedBook`] if any reconstructed order book is
+    /// crossed, see [`OrderBook::is_crossed`], which is only logged as a
+    /// warning otherwise.
+    pub fn with_book_check(mut self) -> Self {
+        self.check_books = true;
+        self
+    }
+
+    /// Sets the handling of fetched orders that fail to be parsed (default:
+    /// [`InvalidOrderPolicy::Fail`]).
+    ///
+    /// Skipping keeps the rest of the order book, which may then be
+    /// inconsistent with the exchange one until the skipped order is removed.
+    pub fn with_invalid_orders(mut self, policy: InvalidOrderPolicy) -> Self {
+        self.invalid_orders = policy;
+        self
+    }
+
+    /// Enables tracking of the mark price exponential moving average for each
+    /// fetched perpetual contract, see [`Perpetual::with_mark_ema`].
+    ///
+    /// An `alpha` out of `(0, 1]` fails the snapshot creation with
+    /// [`DexError::InvalidArgument`].
+    pub fn with_mark_ema(mut self, alpha: UD64) -> Self {
+        self.mark_ema_alpha = Some(alpha);
+        self
+    }
+
+    /// Enables retaining of up to `capacity` most recent mark prices for each
+    /// fetched perpetual contract, see [`Perpetual::with_mark_history`].
+    pub fn with_mark_history(mut self, capacity: usize) -> Self {
+        self.mark_history_capacity = capacity;
+        self
+    }
+
+    /// Fetches parameters of all perpetual contracts via the Multicall3
+    /// contract deployed at `address` with a few calls in total rather than a
+    /// few calls per contract, falling back to the latter if it fails.
+    ///
+    /// The address is also used for batched order fetching instead of the
+    /// canonical [`alloy::providers::MULTICALL3_ADDRESS`].
+    pub fn with_multicall(mut self, address: Address) -> Self {
+        self.multicall = Some(address);
+        self
+    }
+
+    /// Sets whether parameters of perpetual contracts get fetched with
+    /// separate calls if the multicall fails (default: `true`), see
+    /// [`Self::with_multicall`]. Without the fallback the snapshot fails
+    /// instead.
+    pub fn with_multicall_fallback(mut self, fallback: bool) -> Self {
+        self.multicall_fallback = fallback;
+        self
+    }
+
+    /// Backfills [`Order::placed_size`] of the fetched orders from the order
+    /// placement and change events emitted within `blocks` most recent blocks
+    /// up to the snapshot block, so [`Order::filled_size`] of orders placed
+    /// before the snapshot is known (default: disabled).
+    ///
+    /// Costs a single log query over the whole window, which may exceed
+    /// node/provider range limits if the window is too large. Orders placed
+    /// before the window keep unknown placed size, and failure to fetch the
+    /// events is only logged as a warning.
+    pub fn with_placed_size_backfill(mut self, blocks: u64) -> Self {
+        self.placed_size_window = blocks;
+        self
+    }
+
     /// Build the snapshot
-    pub async fn build(mut self) -> Result<Exchange, DexError> {
+    ///
+    /// Orders expired as of the snapshot block stay in the order book, as they
+    /// do on-chain until cleared, but are already marked expired, see
+    /// [`Order::is_expired`], and excluded from the cached level sizes.
+    pub async fn build(self) -> Result<Exchange, DexError> {
+        self.build_with(None).await
+    }
+
+    /// Build the snapshot omitting perpetual contracts, accounts and
+    /// positions that failed to be fetched, e.g. due to transient RPC errors,
+    /// rather than failing entirely.
+    ///
+    /// Fails only if the block or global exchange parameters cannot be
+    /// fetched.
+    ///
+    /// # Returns
+    ///
+    /// Partial snapshot along with the list of omitted entities.
+    pub async fn build_lenient(self) -> Result<(Exchange, Vec<SnapshotFailure>), DexError> {
+        let mut failures = vec![];
+        let exchange = self.build_with(Some(&mut failures)).await?;
+        Ok((exchange, failures))
+    }
+
+    /// Builds the snapshot, collecting entities failed to be fetched into
+    /// `failures` if provided, or failing on the first such entity otherwise.
+    async fn build_with(
+        mut self,
+        mut failures: Option<&mut Vec<SnapshotFailure>>,
+    ) -> Result<Exchange, DexError> {
+        validate_accounts(&self.accounts)?;
+        validate_mark_ema(self.mark_ema_alpha)?;
+
         // Normalize block ID to fetch consistent state
         let instant = self.normalize_block().await?;
 
+        if self.onchain_perpetuals {
+            let block = BlockNumberOrTag::Number(instant.block_number());
+            self.perpetuals = self
+                .chain
+                .fetch_perpetuals_at(&self.provider, block)
+                .await?;
+            self.chain.perpetuals = self.perpetuals.clone();
+        }
+
         // Probe once to learn whether the deployed contract exposes the V2
         // getters added in v1.1.7.3b. Older deployments revert on the selector.
         let supports_v2 = self.supports_v2().await;
 
         // Global exchange parameters and state
-        let (
-            exchange_info,
-            funding_interval,
-            min_post,
-            min_settle,
-            recycle_fee,
-            is_halted,
-            num_of_accounts,
-        ) = self.exchange_info().await?;
-        let collateral_converter = num::Converter::new(exchange_info.collateralDecimals.to());
+        let (exchange_info, funding_interval, min_post, min_settle, recycle_fee, is_halted) =
+            self.exchange_info().await?;
+        let collateral_converter =
+            num::CollateralConverter::new(exchange_info.collateralDecimals.to());
 
         // Perpetual contracts parameters, state and active orders
-        let perpetuals = self.perpetuals(instant, supports_v2).await?;
+        let perpetuals = self
+            .perpetuals(instant, supports_v2, failures.as_deref_mut())
+            .await?;
 
-        let accounts = if !self.accounts.is_empty() {
+        let accounts = if !self.tracking.positions {
+            // Positions not tracked, no account state at all
+            HashMap::new()
+        } else if !self.accounts.is_empty() {
             // Accounts parameters, state and open positions if specific accounts requested
-            self.accounts(instant, &perpetuals, collateral_converter, supports_v2)
+            self.accounts(instant, &perpetuals, collateral_converter, supports_v2, failures)
                 .await?
         } else if self.all_positions {
             // All positions with corresponding accounts without parameters and balance
@@ -170,9 +398,9 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
             self.position_accounts(
                 instant,
                 &perpetuals,
-                num_of_accounts.to(),
                 collateral_converter,
                 supports_v2,
+                failures,
             )
             .await?
         } else {
@@ -191,7 +419,8 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
             accounts,
             is_halted,
             self.all_positions,
-        ))
+        )
+        .with_tracking(self.tracking))
     }
 
     /// Returns true if the deployed exchange exposes the V2 getter functions
@@ -284,7 +513,7 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
 
     async fn exchange_info(
         &self,
-    ) -> Result<(getExchangeInfoReturn, U256, U256, U256, U256, bool, U256), DexError> {
+    ) -> Result<(getExchangeInfoReturn, U256, U256, U256, U256, bool), DexError> {
         let (
             exchange_info_call,
             funding_interval_call,
@@ -292,7 +521,6 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
             min_settle_call,
             recycle_fee_call,
             is_halted_call,
-            num_of_accounts_call,
         ) = (
             self.instance.getExchangeInfo().block(self.block_id),
             self.instance.getFundingInterval().block(self.block_id),
@@ -300,7 +528,6 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
             self.instance.getMinimumSettleCNS().block(self.block_id),
             self.instance.getRecycleFeeCNS().block(self.block_id),
             self.instance.isHalted().block(self.block_id),
-            self.instance.numberOfAccounts(),
         );
         futures::try_join!(
             exchange_info_call.call().into_future(),
@@ -309,7 +536,6 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
             min_settle_call.call().into_future(),
             recycle_fee_call.call().into_future(),
             is_halted_call.call().into_future(),
-            num_of_accounts_call.call().into_future(),
         )
         .map_err(|err| DexError::Provider(err.into()))
     }
@@ -318,7 +544,83 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
         &self,
         instant: types::StateInstant,
         supports_v2: bool,
+        mut failures: Option<&mut Vec<SnapshotFailure>>,
     ) -> Result<HashMap<types::PerpetualId, perpetual::Perpetual>, DexError> {
+        let params = match self.multicall {
+            Some(address) => match self.perpetual_params_multicall(address, supports_v2).await {
+                Ok(params) => self
+                    .perpetuals
+                    .iter()
+                    .copied()
+                    .zip(params.into_iter().map(Ok))
+                    .collect(),
+                Err(err) if self.multicall_fallback => {
+                    tracing::warn!(%err, "multicall failed, fetching perpetuals separately");
+                    self.perpetual_params(supports_v2).await
+                },
+                Err(err) => return Err(err),
+            },
+            None => self.perpetual_params(supports_v2).await,
+        };
+
+        let mut perpetuals = HashMap::new();
+        for (perp_id, result) in params {
+            let failure = |err| SnapshotFailure::Perpetual(perp_id, err);
+            let Some((perp_info, maker_fee, taker_fee, margins)) =
+                tolerate(result, failures.as_deref_mut(), failure)?
+            else {
+                continue;
+            };
+            let mut perp = Perpetual::new(
+                instant,
+                perp_id,
+                &perp_info,
+                maker_fee,
+                taker_fee,
+                margins.perpInitMarginFracHdths,
+                margins.perpMaintMarginFracHdths,
+            );
+            if let Some(alpha) = self.mark_ema_alpha {
+                perp = perp.with_mark_ema(alpha);
+            }
+            perp = perp.with_mark_history(self.mark_history_capacity);
+            perpetuals.insert(perp_id, perp);
+        }
+
+        if !self.tracking.books {
+            return Ok(perpetuals);
+        }
+
+        let placed_sizes = if self.placed_size_window > 0 {
+            self.placed_sizes(instant).await.unwrap_or_else(|err| {
+                tracing::warn!(%err, "failed to fetch order events, placed sizes not backfilled");
+                PlacedSizes::new()
+            })
+        } else {
+            PlacedSizes::new()
+        };
+
+        // Fetching orders one perp at a time to bound parallel requests
+        let mut failed_perps = vec![];
+        for (perp_id, perp) in perpetuals.iter_mut() {
+            let result = self.perpetual_orders(perp, &placed_sizes).await;
+            let failure = |err| SnapshotFailure::Perpetual(*perp_id, err);
+            if tolerate(result, failures.as_deref_mut(), failure)?.is_none() {
+                failed_perps.push(*perp_id);
+            }
+        }
+        for perp_id in failed_perps {
+            perpetuals.remove(&perp_id);
+        }
+
+        Ok(perpetuals)
+    }
+
+    /// Fetches parameters of each perpetual contract with separate calls.
+    async fn perpetual_params(
+        &self,
+        supports_v2: bool,
+    ) -> Vec<(types::PerpetualId, Result<PerpetualParams, DexError>)> {
         let perpetual_futs = self.perpetuals.iter().map(|perp_id| async move {
             let pid = U256::from(*perp_id);
             let (maker_fee_call, taker_fee_call, margins_call) = (
@@ -329,44 +631,135 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
                     .block(self.block_id),
             );
 
-            futures::try_join!(
+            let result = futures::try_join!(
                 self.fetch_perpetual_info(pid, supports_v2),
                 maker_fee_call.call().into_future(),
                 taker_fee_call.call().into_future(),
                 margins_call.call().into_future(),
-            )
-            .map(|(perp_info, maker_fee, taker_fee, margins)| {
-                (*perp_id, perp_info, maker_fee, taker_fee, margins)
-            })
+            );
+            (*perp_id, result.map_err(|err| DexError::Provider(err.into())))
         });
+        futures::future::join_all(perpetual_futs).await
+    }
+
+    /// Fetches parameters of all perpetual contracts with one multicall per
+    /// parameter kind, in the order of [`Self::with_perpetuals`].
+    async fn perpetual_params_multicall(
+        &self,
+        address: Address,
+        supports_v2: bool,
+    ) -> Result<Vec<PerpetualParams>, DexError> {
+        let pids = self
+            .perpetuals
+            .iter()
+            .map(|id| U256::from(*id))
+            .collect::<Vec<_>>();
+        let multicall = || {
+            self.provider
+                .multicall()
+                .address(address)
+                .block(self.block_id)
+        };
+
+        let infos = async {
+            if supports_v2 {
+                multicall()
+                    .dynamic()
+                    .extend(
+                        pids.iter()
+                            .map(|pid| self.instance.getPerpetualInfoV2(*pid)),
+                    )
+                    .aggregate()
+                    .await
+            } else {
+                multicall()
+                    .dynamic()
+                    .extend(pids.iter().map(|pid| self.instance.getPerpetualInfo(*pid)))
+                    .aggregate()
+                    .await
+                    .map(|infos| infos.into_iter().map(perpetual_info_v0_to_v2).collect())
+            }
+        };
+        let (maker_fees, taker_fees, margins) = (
+            multicall()
+                .dynamic()
+                .extend(pids.iter().map(|pid| self.instance.getMakerFee(*pid))),
+            multicall()
+                .dynamic()
+                .extend(pids.iter().map(|pid| self.instance.getTakerFee(*pid))),
+            multicall().dynamic().extend(
+                pids.iter()
+                    .map(|pid| self.instance.getMarginFractions(*pid, U256::ZERO)),
+            ),
+        );
+
+        let (infos, maker_fees, taker_fees, margins) = futures::try_join!(
+            infos,
+            maker_fees.aggregate(),
+            taker_fees.aggregate(),
+            margins.aggregate(),
+        )
+        .map_err(|err| DexError::Provider(err.into()))?;
+        Ok(itertools::izip!(infos, maker_fees, taker_fees, margins).collect())
+    }
 
-        let mut perpetuals = futures::future::try_join_all(perpetual_futs)
+    /// Fetches sizes orders were placed with or changed to within the
+    /// [`Self::with_placed_size_backfill`] window, the most recent ones taking
+    /// precedence as order IDs get reused.
+    async fn placed_sizes(&self, instant: types::StateInstant) -> Result<PlacedSizes, DexError> {
+        let to_block = instant.block_number();
+        let from_block = (to_block + 1)
+            .saturating_sub(self.placed_size_window)
+            .max(self.chain.deployed_at_block());
+        let filter = Filter::new()
+            .address(self.chain.exchange())
+            .event_signature(vec![
+                dex::Exchange::OrderRequest::SIGNATURE_HASH,
+                dex::Exchange::OrderPlaced::SIGNATURE_HASH,
+                dex::Exchange::OrderChanged::SIGNATURE_HASH,
+            ])
+            .from_block(from_block)
+            .to_block(to_block);
+        let mut logs = self
+            .provider
+            .get_logs(&filter)
             .await
-            .map_err(|err| DexError::Provider(err.into()))?
-            .into_iter()
-            .map(|(perp_id, perp_info, maker_fee, taker_fee, margins)| {
-                let perp = Perpetual::new(
-                    instant,
-                    perp_id,
-                    &perp_info,
-                    maker_fee,
-                    taker_fee,
-                    margins.perpInitMarginFracHdths,
-                    margins.perpMaintMarginFracHdths,
-                );
-                (perp_id, perp)
-            })
-            .collect::<HashMap<_, _>>();
+            .map_err(|err| DexError::Provider(err.into()))?;
+        // Logs are not guaranteed to be returned in block-internal order
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
 
-        // Fetching orders one perp at a time to bound parallel requests
-        for perp in perpetuals.values_mut() {
-            self.perpetual_orders(perp).await?;
+        let mut placed_sizes = PlacedSizes::new();
+        let mut request = None;
+        for log in &logs {
+            let event = ExchangeEvents::decode_log(&log.inner)
+                .map_err(|err| DexError::Provider(err.into()))?
+                .data;
+            let (order_id, lot) = match event {
+                ExchangeEvents::OrderRequest(e) => {
+                    request = Some(e);
+                    continue;
+                },
+                ExchangeEvents::OrderPlaced(e) => (Some(e.orderId), e.lotLNS),
+                // Fully filled changed orders are removed
+                ExchangeEvents::OrderChanged(e) if !e.lotLNS.is_zero() => (None, e.lotLNS),
+                _ => continue,
+            };
+            let Some(r) = &request else { continue };
+            if let Ok(order_id) = order_id.unwrap_or(r.orderId).try_into() {
+                placed_sizes.insert(
+                    (num::narrow(r.perpId, "perpetual id")?, order_id),
+                    (num::narrow(r.accountId, "account id")?, lot),
+                );
+            }
         }
-
-        Ok(perpetuals)
+        Ok(placed_sizes)
     }
 
-    async fn perpetual_orders(&self, perp: &mut perpetual::Perpetual) -> Result<(), DexError> {
+    async fn perpetual_orders(
+        &self,
+        perp: &mut perpetual::Perpetual,
+        placed_sizes: &PlacedSizes,
+    ) -> Result<(), DexError> {
         let pid = U256::from(perp.id());
         let order_id_index = self
             .instance
@@ -397,6 +790,10 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
             let multicall = self
                 .provider
                 .multicall()
+                .address(
+                    self.multicall
+                        .unwrap_or(alloy::providers::MULTICALL3_ADDRESS),
+                )
                 .block(self.block_id)
                 .dynamic()
                 .extend(
@@ -407,61 +804,81 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
             async move { multicall.aggregate().await }
         });
 
-        let (instant, base_price, price_converter, size_converter, leverage_converter) = (
-            perp.instant(),
-            perp.base_price(),
-            perp.price_converter(),
-            perp.size_converter(),
-            perp.leverage_converter(),
-        );
-
         // Collect all orders first, then add via snapshot method to preserve FIFO
         // ordering
-        let orders: Vec<Order> = futures::future::try_join_all(order_batch_futs)
+        let fetched = futures::future::try_join_all(order_batch_futs)
             .await
-            .map_err(|err| DexError::Provider(err.into()))?
-            .into_iter()
-            .flatten()
-            .map(|ord| {
-                Order::from_snapshot(
-                    instant,
-                    ord,
-                    base_price,
-                    price_converter,
-                    size_converter,
-                    leverage_converter,
-                )
-            })
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|err| DexError::OrderParse(perp.id(), err))?;
+            .map_err(|err| DexError::Provider(err.into()))?;
+        let mut orders = parse_orders(perp, fetched.into_iter().flatten(), self.invalid_orders)?;
+        let size_converter = perp.size_converter();
 
-        perp.add_orders_from_snapshot(orders)
+        for order in orders.iter_mut() {
+            let key = (perp.id(), order.order_id().get());
+            if let Some((account_id, lot)) = placed_sizes.get(&key)
+                && *account_id == order.account_id()
+            {
+                // Remaining size cannot exceed the placed one, guarding against
+                // events of an unrelated order with the same ID
+                let placed_size = size_converter.try_from_unsigned(*lot)?;
+                if placed_size >= order.size() {
+                    *order = order.with_placed_size(placed_size.get());
+                }
+            }
+        }
+
+        perp.add_orders_from_snapshot(orders)?;
+        if perp.l3_book().is_crossed() {
+            tracing::warn!(perp_id = perp.id(), "reconstructed order book is crossed");
+            if self.check_books {
+                return Err(DexError::CrossedBook(perp.id()));
+            }
+        }
+        Ok(())
     }
 
     async fn accounts(
         &self,
         instant: types::StateInstant,
         perpetuals: &HashMap<types::PerpetualId, perpetual::Perpetual>,
-        collateral_converter: num::Converter,
+        collateral_converter: num::CollateralConverter,
         supports_v2: bool,
+        mut failures: Option<&mut Vec<SnapshotFailure>>,
     ) -> Result<HashMap<types::AccountId, Account>, DexError> {
-        let account_futs = self.accounts.iter().map(|acc| async move {
-            let acc_info = match acc {
+        let info_futs = self.accounts.iter().map(|acc| async move {
+            match acc {
                 types::AccountAddressOrID::Address(addr) => self
                     .instance
                     .getAccountByAddr(*addr)
                     .block(self.block_id)
                     .call()
                     .await
-                    .map_err(|err| DexError::Provider(err.into()))?,
+                    .map_err(|err| DexError::Provider(err.into())),
                 types::AccountAddressOrID::ID(id) => self
                     .instance
                     .getAccountById(U256::from(*id))
                     .block(self.block_id)
                     .call()
                     .await
-                    .map_err(|err| DexError::Provider(err.into()))?,
+                    .map_err(|err| DexError::Provider(err.into())),
+            }
+        });
+        let infos = futures::future::join_all(info_futs).await;
+
+        // Same account could be referenced by both address and ID, fetching its
+        // positions once
+        let mut account_ids = HashSet::new();
+        let mut unique_infos = vec![];
+        for (acc, result) in self.accounts.iter().zip(infos) {
+            let failure = |err| SnapshotFailure::Account(*acc, err);
+            let Some(acc_info) = tolerate(result, failures.as_deref_mut(), failure)? else {
+                continue;
             };
+            if account_ids.insert(acc_info.accountId) {
+                unique_infos.push((acc, acc_info));
+            }
+        }
+
+        let position_futs = unique_infos.iter().map(|(_, acc_info)| async move {
             let perps_with_positions = perpetuals_with_position(&acc_info.positions);
             let position_futs = perps_with_positions.iter().map(|perp_id| async {
                 self.fetch_position_info(U256::from(*perp_id), acc_info.accountId, supports_v2)
@@ -469,73 +886,73 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
                     .map(|pos_info| (*perp_id, pos_info))
                     .map_err(|err| DexError::Provider(err.into()))
             });
-            let positions = futures::future::try_join_all(position_futs).await?;
-            Ok::<_, DexError>((acc_info.accountId, acc_info, positions))
+            futures::future::try_join_all(position_futs).await
         });
+        let results = futures::future::join_all(position_futs).await;
 
-        Ok(futures::future::try_join_all(account_futs)
-            .await?
-            .into_iter()
-            .map(|(acc_id, acc_info, positions)| {
-                (
-                    acc_id.to(),
-                    Account::new(
-                        instant,
-                        acc_id.to(),
-                        &acc_info,
-                        positions
-                            .into_iter()
-                            .filter_map(|(perp_id, pos_info)| {
-                                perpetuals.get(&perp_id).map(|perp| {
-                                    (
-                                        perp_id,
-                                        Position::new(
-                                            instant,
-                                            perp_id,
-                                            &pos_info,
-                                            collateral_converter,
-                                            perp.price_converter(),
-                                            perp.size_converter(),
-                                            perp.maintenance_margin(),
-                                        ),
-                                    )
-                                })
-                            })
-                            .collect(),
-                        collateral_converter,
-                    ),
-                )
-            })
-            .collect())
+        let mut accounts = HashMap::new();
+        for ((acc, acc_info), result) in unique_infos.iter().zip(results) {
+            let positions = result.and_then(|positions| {
+                positions
+                    .into_iter()
+                    .filter_map(|(perp_id, pos_info)| {
+                        perpetuals.get(&perp_id).map(|perp| {
+                            self.position(instant, perp, &pos_info, collateral_converter)
+                                .map(|pos| (perp_id, pos))
+                        })
+                    })
+                    .collect::<Result<_, _>>()
+            });
+            let failure = |err| SnapshotFailure::Account(**acc, err);
+            let Some(positions) = tolerate(positions, failures.as_deref_mut(), failure)? else {
+                continue;
+            };
+            let acc_id = acc_info.accountId.to();
+            accounts.insert(
+                acc_id,
+                Account::new(instant, acc_id, acc_info, positions, collateral_converter),
+            );
+        }
+
+        Ok(accounts)
     }
 
     async fn position_accounts(
         &self,
         instant: types::StateInstant,
         perpetuals: &HashMap<types::PerpetualId, perpetual::Perpetual>,
-        num_accounts: usize,
-        collateral_converter: num::Converter,
+        collateral_converter: num::CollateralConverter,
         supports_v2: bool,
+        mut failures: Option<&mut Vec<SnapshotFailure>>,
     ) -> Result<HashMap<types::AccountId, Account>, DexError> {
+        let num_accounts: usize = self
+            .instance
+            .numberOfAccounts()
+            .block(self.block_id)
+            .call()
+            .await
+            .map_err(|err| DexError::Provider(err.into()))?
+            .to();
         let mut accounts: HashMap<types::AccountId, Account> = HashMap::new();
         for (perp_id, perp) in perpetuals {
             let pid = U256::from(*perp_id);
-            let infos = self
+            let result = self
                 .fetch_position_infos_for_perp(pid, num_accounts, supports_v2)
-                .await?;
+                .await;
+            let failure = |err| SnapshotFailure::Positions(*perp_id, err);
+            let Some(infos) = tolerate(result, failures.as_deref_mut(), failure)? else {
+                continue;
+            };
             for info in infos {
                 if info.lotLNS.is_zero() {
                     continue;
                 }
-                let position = Position::new(
-                    instant,
-                    *perp_id,
-                    &info,
-                    collateral_converter,
-                    perp.price_converter(),
-                    perp.size_converter(),
-                    perp.maintenance_margin(),
-                );
+                let position = self.position(instant, perp, &info, collateral_converter);
+                let account = types::AccountAddressOrID::ID(info.accountId.to());
+                let failure = |err| SnapshotFailure::Account(account, err);
+                let Some(position) = tolerate(position, failures.as_deref_mut(), failure)? else {
+                    continue;
+                };
                 match accounts.entry(info.accountId.to()) {
                     hash_map::Entry::Occupied(mut e) => {
                         e.get_mut().positions_mut().insert(*perp_id, position);
@@ -550,6 +967,34 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
         Ok(accounts)
     }
 
+    /// Creates the position from the fetched info, checking its PnL breakdown
+    /// if requested, see [`Self::with_pnl_check`].
+    fn position(
+        &self,
+        instant: types::StateInstant,
+        perp: &perpetual::Perpetual,
+        info: &PositionInfoV2,
+        collateral_converter: num::CollateralConverter,
+    ) -> Result<Position, DexError> {
+        let position = Position::new(
+            instant,
+            perp.id(),
+            info,
+            collateral_converter,
+            perp.price_converter(),
+            perp.size_converter(),
+            perp.maintenance_margin(),
+        )?;
+        // Components and total are rounded to collateral precision separately
+        let tolerance = collateral_converter.from_i64(1);
+        if self.check_pnl
+            && !position.pnl_is_consistent(collateral_converter.from_signed(info.pnlCNS), tolerance)
+        {
+            return Err(DexError::InconsistentPnl(position.account_id(), position.perpetual_id()));
+        }
+        Ok(position)
+    }
+
     /// Batches `getPosition`/`getPositionV2` calls for every account id of a
     /// single perpetual. Normalizes both ABI versions to `PositionInfoV2`.
     async fn fetch_position_infos_for_perp(
@@ -597,6 +1042,101 @@ impl<P: Provider + Clone> SnapshotBuilder<P> {
     }
 }
 
+/// Parses orders fetched for the `perp` order book, handling the ones failing
+/// to be parsed according to the `policy`.
+///
+/// Neighbours of skipped orders get linked to each other around them, so the
+/// price levels stay consistent.
+fn parse_orders(
+    perp: &perpetual::Perpetual,
+    orders: impl IntoIterator<Item = dex::Exchange::Order>,
+    policy: InvalidOrderPolicy,
+) -> Result<Vec<Order>, DexError> {
+    let mut parsed = vec![];
+    let mut skipped = HashMap::new();
+    for order in orders {
+        let (order_id, links) = (order.orderId, (order.prevOrderId, order.nextOrderId));
+        let result = Order::from_snapshot(
+            perp.instant(),
+            order,
+            perp.base_price(),
+            perp.price_converter(),
+            perp.size_converter(),
+            perp.leverage_converter(),
+        );
+        match result {
+            Ok(order) => parsed.push(order),
+            Err(err) if policy == InvalidOrderPolicy::Skip => {
+                tracing::warn!(perp_id = perp.id(), order_id, %err, "skipping invalid order");
+                skipped.insert(order_id, links);
+            },
+            Err(err) => return Err(DexError::OrderParse(perp.id(), err)),
+        }
+    }
+    if skipped.is_empty() {
+        return Ok(parsed);
+    }
+
+    let skip_over = |mut id: Option<types::OrderId>, link: fn(&(u16, u16)) -> u16| {
+        // Bounded by the number of skipped orders in case of cyclic links
+        for _ in 0..skipped.len() {
+            match id.and_then(|id| skipped.get(&id.get())) {
+                Some(links) => id = types::OrderId::new(link(links)),
+                None => break,
+            }
+        }
+        id
+    };
+    Ok(parsed
+        .into_iter()
+        .map(|order| {
+            let prev_order_id = skip_over(order.prev_order_id(), |(prev, _)| *prev);
+            let next_order_id = skip_over(order.next_order_id(), |(_, next)| *next);
+            order.with_links(prev_order_id, next_order_id)
+        })
+        .collect())
+}
+
+/// Checks the requested accounts do not reference zero address or ID, which
+/// no exchange account can have.
+fn validate_accounts(accounts: &[types::AccountAddressOrID]) -> Result<(), DexError> {
+    match accounts.iter().find(|acc| match acc {
+        types::AccountAddressOrID::Address(addr) => addr.is_zero(),
+        types::AccountAddressOrID::ID(id) => *id == 0,
+    }) {
+        Some(acc) => Err(DexError::InvalidArgument(format!("invalid account: {acc:?}"))),
+        None => Ok(()),
+    }
+}
+
+/// Checks the mark price EMA smoothing factor, if any, is within `(0, 1]`, as
+/// required by [`Perpetual::with_mark_ema`].
+fn validate_mark_ema(alpha: Option<UD64>) -> Result<(), DexError> {
+    match alpha {
+        Some(alpha) if alpha.is_zero() || alpha > UD64::ONE => Err(DexError::InvalidArgument(
+            format!("mark EMA alpha out of range: {alpha}"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Passes the successful `result` through, otherwise records the failure into
+/// `failures` if provided, or returns the error.
+fn tolerate<T>(
+    result: Result<T, DexError>,
+    failures: Option<&mut Vec<SnapshotFailure>>,
+    failure: impl FnOnce(DexError) -> SnapshotFailure,
+) -> Result<Option<T>, DexError> {
+    match (result, failures) {
+        (Ok(value), _) => Ok(Some(value)),
+        (Err(err), Some(failures)) => {
+            failures.push(failure(err));
+            Ok(None)
+        },
+        (Err(err), None) => Err(err),
+    }
+}
+
 fn position_info_v0_to_v2(v0: PositionInfo) -> PositionInfoV2 {
     PositionInfoV2 {
         accountId: v0.accountId,
@@ -649,3 +1189,93 @@ fn perpetual_info_v0_to_v2(v0: PerpetualInfo) -> PerpetualInfoV2 {
         fundingSumScalingExp: U256::ZERO,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_orders_policy() {
+        let perp = perpetual::Perpetual::for_testing(1);
+        let order = |order_id| dex::Exchange::Order {
+            accountId: 1,
+            orderType: 0,
+            priceONS: Default::default(),
+            lotLNS: Default::default(),
+            recycleFeeRaw: 0,
+            expiryBlock: 0,
+            leverageHdths: 100,
+            orderId: order_id,
+            prevOrderId: 0,
+            nextOrderId: 0,
+            maxNegPnlCollatBPS: 0,
+        };
+        let orders = || [order(1), order(0), order(2)];
+
+        assert!(matches!(
+            parse_orders(&perp, orders(), InvalidOrderPolicy::Fail),
+            Err(DexError::OrderParse(1, OrderParseError::ZeroOrderId))
+        ));
+
+        let parsed = parse_orders(&perp, orders(), InvalidOrderPolicy::Skip).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].order_id().get(), 2);
+    }
+
+    #[test]
+    fn test_parse_orders_skipped_within_level() {
+        let mut perp = perpetual::Perpetual::for_testing(1);
+        let order = |order_id, order_type, prev_order_id, next_order_id| dex::Exchange::Order {
+            accountId: 1,
+            orderType: order_type,
+            priceONS: alloy::primitives::Uint::from(100),
+            lotLNS: alloy::primitives::Uint::from(1),
+            recycleFeeRaw: 0,
+            expiryBlock: 0,
+            leverageHdths: 100,
+            orderId: order_id,
+            prevOrderId: prev_order_id,
+            nextOrderId: next_order_id,
+            maxNegPnlCollatBPS: 0,
+        };
+        // Level of 1 <-> 2 <-> 3 <-> 4 with 2 and 3 of invalid type
+        let orders = [order(1, 0, 0, 2), order(2, 9, 1, 3), order(3, 9, 2, 4), order(4, 0, 3, 0)];
+
+        let parsed = parse_orders(&perp, orders, InvalidOrderPolicy::Skip).unwrap();
+        let links: Vec<_> = parsed
+            .iter()
+            .map(|o| (o.order_id().get(), o.prev_order_id(), o.next_order_id()))
+            .collect();
+        assert_eq!(
+            links,
+            vec![(1, None, types::OrderId::new(4)), (4, types::OrderId::new(1), None)]
+        );
+
+        perp.add_orders_from_snapshot(parsed).unwrap();
+        assert_eq!(perp.total_orders(), 2);
+    }
+
+    #[test]
+    fn test_validate_accounts() {
+        use types::AccountAddressOrID::{Address as Addr, ID};
+
+        assert!(validate_accounts(&[ID(1), Addr(Address::repeat_byte(1))]).is_ok());
+        assert!(matches!(validate_accounts(&[ID(1), ID(0)]), Err(DexError::InvalidArgument(_))));
+        assert!(matches!(
+            validate_accounts(&[Addr(Address::ZERO)]),
+            Err(DexError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_mark_ema() {
+        assert!(validate_mark_ema(None).is_ok());
+        assert!(validate_mark_ema(Some(UD64::ONE)).is_ok());
+        assert!(validate_mark_ema(Some(fastnum::udec64!(0.1))).is_ok());
+        assert!(matches!(validate_mark_ema(Some(UD64::ZERO)), Err(DexError::InvalidArgument(_))));
+        assert!(matches!(
+            validate_mark_ema(Some(fastnum::udec64!(1.5))),
+            Err(DexError::InvalidArgument(_))
+        ));
+    }
+}
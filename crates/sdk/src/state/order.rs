@@ -1,10 +1,10 @@
-use std::num::NonZeroU16;
+use std::{num::NonZeroU16, time::Duration};
 
 use fastnum::UD64;
 use thiserror::Error;
 
 use super::{event, types};
-use crate::{abi::dex, num};
+use crate::{Chain, abi::dex, error::DexError, num};
 
 /// Error creating an Order from exchange data.
 #[derive(Debug, Clone, Error)]
@@ -13,6 +13,10 @@ pub enum OrderParseError {
     /// exchange).
     #[error("order has invalid id 0")]
     ZeroOrderId,
+
+    /// Order has unknown order type.
+    #[error("order has invalid type {0}")]
+    InvalidOrderType(u8),
 }
 
 /// Active order in the perpetual contract order book.
@@ -40,6 +44,7 @@ pub enum OrderParseError {
 /// This wrapper provides automatic conversion from exchnage fixed numeric types
 /// to decimal numbers.
 #[derive(Clone, Copy, derive_more::Debug)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
 pub struct Order {
     instant: types::StateInstant,
     request_id: Option<types::RequestId>,
@@ -68,9 +73,9 @@ impl Order {
     pub(crate) fn from_snapshot(
         instant: types::StateInstant,
         order: dex::Exchange::Order,
-        base_price: UD64,
-        price_converter: num::Converter,
-        size_converter: num::Converter,
+        base_price: num::Price,
+        price_converter: num::PriceConverter,
+        size_converter: num::SizeConverter,
         leverage_converter: num::Converter,
     ) -> Result<Self, OrderParseError> {
         // Exchange uses 0 as NULL_ORDER_ID - a valid order must have non-zero ID
@@ -86,10 +91,13 @@ impl Order {
             request_id: None,
             client_order_id: None, // Not available from snapshot
             order_id,
-            r#type: order.orderType.into(),
+            r#type: order
+                .orderType
+                .try_into()
+                .map_err(|_| OrderParseError::InvalidOrderType(order.orderType))?,
             account_id: order.accountId,
-            price: base_price + price_converter.from_unsigned(order.priceONS.to()),
-            size: size_converter.from_unsigned(order.lotLNS.to()),
+            price: (base_price + price_converter.from_unsigned(order.priceONS.to())).get(),
+            size: size_converter.from_unsigned(order.lotLNS.to()).get(),
             placed_size: None,
             expiry_block: order.expiryBlock as u64,
             leverage: leverage_converter.from_u64(order.leverageHdths as u64),
@@ -106,29 +114,33 @@ impl Order {
         ctx: &event::OrderContext,
         order_id: types::OrderId,
         size: UD64,
-        price_converter: num::Converter,
+        price_converter: num::PriceConverter,
         leverage_converter: num::Converter,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, DexError> {
+        Ok(Self {
             instant,
             request_id: Some(ctx.request_id),
             // Original [`request_id`] becomes [`client_order_id`]
             client_order_id: Some(ctx.request_id),
             order_id,
-            r#type: ctx.r#type.into(),
+            r#type: ctx.r#type.try_into()?,
             account_id: ctx.account_id,
-            price: price_converter.from_unsigned(ctx.price),
+            price: price_converter.try_from_unsigned(ctx.price)?.get(),
             size,
             placed_size: Some(size),
             expiry_block: ctx.expiry_block,
-            leverage: leverage_converter.from_unsigned(ctx.leverage),
+            leverage: leverage_converter.try_from_unsigned(ctx.leverage)?,
             post_only: Some(ctx.post_only),
             fill_or_kill: Some(ctx.fill_or_kill),
             immediate_or_cancel: Some(ctx.immediate_or_cancel),
             // New orders don't have linked list info from events
             prev_order_id: None,
             next_order_id: None,
-        }
+        })
+    }
+
+    pub(crate) fn with_placed_size(self, placed_size: UD64) -> Self {
+        Self { placed_size: Some(placed_size), ..self }
     }
 
     pub(crate) fn updated(
@@ -163,11 +175,13 @@ impl Order {
         }
     }
 
+    /// Indicates if the order not expired yet expires as of `instant`.
+    pub(crate) fn expires_at(&self, instant: types::StateInstant) -> bool {
+        self.expiry_block != 0 && self.expiry_block <= instant.block_number() && !self.is_expired()
+    }
+
     pub(crate) fn update_if_expired(&mut self, instant: types::StateInstant) -> bool {
-        if self.expiry_block != 0
-            && self.expiry_block <= instant.block_number()
-            && !self.is_expired()
-        {
+        if self.expires_at(instant) {
             // Just updating instant so `is_expired` returns true
             self.instant = instant;
             true
@@ -331,9 +345,7 @@ impl Order {
         }
     }
 
-    /// Create a copy with linked list pointers (for testing snapshot
-    /// reconstruction).
-    #[allow(unused)]
+    /// Create a copy with linked list pointers.
     pub(crate) fn with_links(
         &self,
         prev_order_id: Option<types::OrderId>,
@@ -403,24 +415,35 @@ impl Order {
     pub fn account_id(&self) -> types::AccountId { self.account_id }
 
     /// Limit price of the order.
-    pub fn price(&self) -> UD64 { self.price }
+    pub fn price(&self) -> num::Price { num::Price::new(self.price) }
 
     /// Current size of the order.
-    pub fn size(&self) -> UD64 { self.size }
+    pub fn size(&self) -> num::Size { num::Size::new(self.size) }
 
     /// Size of the order that was placed.
-    /// Available only from real-time events, not from the initial snapshot.
-    pub fn placed_size(&self) -> Option<UD64> { self.placed_size }
+    /// Available only from real-time events, not from the initial snapshot,
+    /// unless backfilled, see [`super::SnapshotBuilder::with_placed_size_backfill`].
+    pub fn placed_size(&self) -> Option<num::Size> { self.placed_size.map(num::Size::new) }
 
     /// Filled size of the order.
-    /// Available only from real-time events, not from the initial snapshot.
-    pub fn filled_size(&self) -> Option<UD64> {
-        self.placed_size.map(|placed_size| placed_size - self.size)
+    /// Available only from real-time events, not from the initial snapshot,
+    /// unless backfilled, see [`super::SnapshotBuilder::with_placed_size_backfill`].
+    pub fn filled_size(&self) -> Option<num::Size> {
+        self.placed_size
+            .map(|placed_size| num::Size::new(placed_size - self.size))
     }
 
     /// Expiry block of the order, zero if was not specified.
     pub fn expiry_block(&self) -> u64 { self.expiry_block }
 
+    /// Estimated wall-clock time from `current_block` until the order
+    /// expires, zero if already expired, see [`Chain::block_time`].
+    /// Returns `None` if the order has no expiry block.
+    pub fn time_to_expiry(&self, current_block: u64, chain: &Chain) -> Option<Duration> {
+        (self.expiry_block != 0)
+            .then(|| chain.blocks_duration(self.expiry_block.saturating_sub(current_block)))
+    }
+
     /// Check if the order is expired.
     /// NOTE: Valid only after the end of expiry block processing.
     pub fn is_expired(&self) -> bool {
@@ -455,11 +478,12 @@ impl Order {
 
 impl std::fmt::Display for Order {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let config = num::DisplayConfig::current();
         if f.alternate() {
             // Short order representation
             let mut short = format!(
                 "{} {:#} #{} 👤{}",
-                self.size(),
+                config.size(self.size()),
                 self.r#type(),
                 self.order_id(),
                 self.account_id(),
@@ -472,8 +496,8 @@ impl std::fmt::Display for Order {
             write!(
                 f,
                 "[{}@{} {:#} #{} acc:{} rq:{} exp:{}{} lev:{}]",
-                self.size(),
-                self.price(),
+                config.size(self.size()),
+                config.price(self.price()),
                 self.r#type(),
                 self.order_id(),
                 self.account_id(),
@@ -495,14 +519,17 @@ impl tabled::Tabled for Order {
 
         use crate::types::OrderSide;
 
+        let config = num::DisplayConfig::current();
+        let price = config.price(self.price()).to_string();
+        let size = config.size(self.size()).to_string();
         vec![
             match self.r#type.side() {
-                OrderSide::Ask => self.price().to_string().red().to_string().into(),
-                OrderSide::Bid => self.price().to_string().green().to_string().into(),
+                OrderSide::Ask => price.red().to_string().into(),
+                OrderSide::Bid => price.green().to_string().into(),
             },
             match self.r#type.side() {
-                OrderSide::Ask => self.size().to_string().red().to_string().into(),
-                OrderSide::Bid => self.size().to_string().green().to_string().into(),
+                OrderSide::Ask => size.red().to_string().into(),
+                OrderSide::Bid => size.green().to_string().into(),
             },
             match self.r#type.side() {
                 OrderSide::Ask => self.r#type().to_string().red().to_string().into(),
@@ -562,3 +589,36 @@ impl tabled::Tabled for Order {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fastnum::udec64;
+
+    use super::*;
+
+    #[test]
+    fn test_display_precision() {
+        let order =
+            Order::for_testing(types::OrderType::OpenLong, udec64!(101.25), udec64!(0.12345));
+        assert_eq!(format!("{:#}", order), "[0.12345 OL #1 👤0]");
+
+        let config = num::DisplayConfig::new()
+            .with_price_decimals(2)
+            .with_size_decimals(3);
+        assert_eq!(config.scoped(|| format!("{:#}", order)), "[0.123 OL #1 👤0]");
+        assert_eq!(
+            config.scoped(|| order.to_string()),
+            "[0.123@101.25 OL #1 acc:0 rq:0 exp:0 lev:0]"
+        );
+    }
+
+    #[test]
+    fn test_display_without_colors() {
+        num::set_colors(false);
+        let order =
+            Order::for_testing(types::OrderType::OpenShort, udec64!(101.25), udec64!(0.12345));
+        let fields = tabled::Tabled::fields(&order);
+        assert!(fields.iter().all(|f| !f.contains('\x1b')));
+        assert!(!order.to_string().contains('\x1b'));
+    }
+}
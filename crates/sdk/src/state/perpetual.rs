@@ -1,18 +1,23 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
 use alloy::primitives::{B256, I256, U256};
 use fastnum::{D64, D256, UD64, UD128};
 
 use super::*;
-use crate::{abi::dex::Exchange::PerpetualInfoV2, types};
+use crate::{Chain, abi::dex::Exchange::PerpetualInfoV2, types};
 
 const FEE_SCALE: u8 = 5;
 const FUNDING_RATE_SCALE: u8 = 5;
 const LEVERAGE_SCALE: u8 = 2;
+/// Order prices are stored as offsets from the base price, in ticks.
+const ORDER_PRICE_OFFSET_BITS: u32 = 24;
 
 /// Perpetual contract tradeable at the exchange.
 ///
 /// Provides the current state of contract parameters, market data and
 /// order book.
 #[derive(Clone, derive_more::Debug)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
 pub struct Perpetual {
     instant: types::StateInstant,
     state_instant: types::StateInstant,
@@ -21,14 +26,14 @@ pub struct Perpetual {
     symbol: String,
     is_paused: bool,
 
-    price_converter: num::Converter,
-    size_converter: num::Converter,
+    price_converter: num::PriceConverter,
+    size_converter: num::SizeConverter,
     leverage_converter: num::Converter,
     fee_converter: num::Converter,
     funding_rate_converter: num::Converter,
     funding_sum_converter: num::Converter,
     #[debug("{base_price}")]
-    base_price: UD64, // SC allocates 32 bits
+    base_price: num::Price, // SC allocates 32 bits
 
     #[debug("{maker_fee}")]
     maker_fee: UD64, // SC allocates 16 bits
@@ -40,17 +45,24 @@ pub struct Perpetual {
     maintenance_margin: UD64, // SC allocates 16 bits
 
     #[debug("{last_price}")]
-    last_price: UD64, // SC allocates 32 bits
+    last_price: num::Price, // SC allocates 32 bits
     last_price_block: Option<u64>,
     last_price_timestamp: u64,
 
     #[debug("{mark_price}")]
-    mark_price: UD64, // SC allocates 32 bits
+    mark_price: num::Price, // SC allocates 32 bits
     mark_price_block: Option<u64>,
     mark_price_timestamp: u64,
+    #[debug("{:?}", mark_ema_alpha.map(|v| format!("{v}")))]
+    mark_ema_alpha: Option<UD64>,
+    #[debug("{:?}", mark_ema.map(|v| format!("{v}")))]
+    mark_ema: Option<num::Price>,
+    mark_history_capacity: usize,
+    #[debug(skip)]
+    mark_history: VecDeque<(types::StateInstant, num::Price)>,
 
     #[debug("{oracle_price}")]
-    oracle_price: UD64, // SC allocates 32 bits
+    oracle_price: num::Price, // SC allocates 32 bits
     oracle_price_block: Option<u64>,
     oracle_price_timestamp: u64,
 
@@ -67,7 +79,7 @@ pub struct Perpetual {
     is_oracle_used: bool,
     price_max_age_sec: u64,
 
-    l3_book: OrderBook,
+    l3_book: Arc<OrderBook>,
 
     #[debug("{open_interest}")]
     open_interest: UD128,
@@ -84,8 +96,8 @@ impl Perpetual {
         initial_margin: U256,
         maintenance_margin: U256,
     ) -> Self {
-        let price_converter = num::Converter::new(info.priceDecimals.to());
-        let size_converter = num::Converter::new(info.lotDecimals.to());
+        let price_converter = num::PriceConverter::new(info.priceDecimals.to());
+        let size_converter = num::SizeConverter::new(info.lotDecimals.to());
         let leverage_converter = num::Converter::new(LEVERAGE_SCALE);
         let fee_converter = num::Converter::new(FEE_SCALE);
         let funding_rate_converter = num::Converter::new(FUNDING_RATE_SCALE);
@@ -124,6 +136,10 @@ impl Perpetual {
             mark_price: price_converter.from_unsigned(info.markPNS),
             mark_price_block: None,
             mark_price_timestamp: info.markTimestamp.to(),
+            mark_ema_alpha: None,
+            mark_ema: None,
+            mark_history_capacity: 0,
+            mark_history: VecDeque::new(),
 
             oracle_price: price_converter.from_unsigned(info.oraclePNS),
             oracle_price_block: None,
@@ -140,9 +156,10 @@ impl Perpetual {
             is_oracle_used: !info.ignOracle,
             price_max_age_sec: info.refPriceMaxAgeSec.to(),
 
-            l3_book: OrderBook::new(),
+            l3_book: Arc::new(OrderBook::new()),
 
-            open_interest: size_converter.from_unsigned(info.longOpenInterestLNS),
+            open_interest: num::Converter::new(size_converter.decimals())
+                .from_unsigned(info.longOpenInterestLNS),
         }
     }
 
@@ -161,8 +178,8 @@ impl Perpetual {
         initial_margin: U256,
         maintenance_margin: U256,
     ) -> Self {
-        let price_converter = num::Converter::new(price_decimals);
-        let size_converter = num::Converter::new(size_decimals);
+        let price_converter = num::PriceConverter::new(price_decimals);
+        let size_converter = num::SizeConverter::new(size_decimals);
         let leverage_converter = num::Converter::new(LEVERAGE_SCALE);
         let fee_converter = num::Converter::new(FEE_SCALE);
         let funding_rate_converter = num::Converter::new(FUNDING_RATE_SCALE);
@@ -192,15 +209,19 @@ impl Perpetual {
             // Margins are in hundredths
             maintenance_margin: leverage_converter.from_unsigned(maintenance_margin),
 
-            last_price: UD64::ZERO,
+            last_price: num::Price::ZERO,
             last_price_block: None,
             last_price_timestamp: 0,
 
-            mark_price: UD64::ZERO,
+            mark_price: num::Price::ZERO,
             mark_price_block: None,
             mark_price_timestamp: 0,
+            mark_ema_alpha: None,
+            mark_ema: None,
+            mark_history_capacity: 0,
+            mark_history: VecDeque::new(),
 
-            oracle_price: UD64::ZERO,
+            oracle_price: num::Price::ZERO,
             oracle_price_block: None,
             oracle_price_timestamp: 0,
 
@@ -214,7 +235,7 @@ impl Perpetual {
             is_oracle_used: true,
             price_max_age_sec: 60,
 
-            l3_book: OrderBook::new(),
+            l3_book: Arc::new(OrderBook::new()),
 
             open_interest: UD128::ZERO,
         }
@@ -228,21 +249,21 @@ impl Perpetual {
     pub fn id(&self) -> types::PerpetualId { self.id }
 
     /// Name of the perpetual contract.
-    pub fn name(&self) -> String { self.name.clone() }
+    pub fn name(&self) -> &str { &self.name }
 
     /// Symbol of the perpetual contract.
-    pub fn symbol(&self) -> String { self.symbol.clone() }
+    pub fn symbol(&self) -> &str { &self.symbol }
 
     /// Indicates if the perpetual contract is paused.
     pub fn is_paused(&self) -> bool { self.is_paused }
 
     /// Converter of prices between internal fixed-point and decimal
     /// representations.
-    pub fn price_converter(&self) -> num::Converter { self.price_converter }
+    pub fn price_converter(&self) -> num::PriceConverter { self.price_converter }
 
     /// Converter of sizes between internal fixed-point and decimal
     /// representations.
-    pub fn size_converter(&self) -> num::Converter { self.size_converter }
+    pub fn size_converter(&self) -> num::SizeConverter { self.size_converter }
 
     /// Converter of leverage/margin between internal fixed-point and decimal
     /// representations.
@@ -274,7 +295,7 @@ impl Perpetual {
     pub fn maintenance_margin(&self) -> UD64 { self.maintenance_margin }
 
     /// The price last trade was executed at.
-    pub fn last_price(&self) -> UD64 { self.last_price }
+    pub fn last_price(&self) -> num::Price { self.last_price }
 
     /// Instant the last trade was executed at.
     /// Block number available only from real-time events, not from the initial
@@ -290,7 +311,7 @@ impl Perpetual {
     pub fn last_price_timestamp(&self) -> u64 { self.last_price_timestamp }
 
     /// Mark price of the contract.
-    pub fn mark_price(&self) -> UD64 { self.mark_price }
+    pub fn mark_price(&self) -> num::Price { self.mark_price }
 
     /// Instant the mark price was updated at.
     /// Block number available only from real-time events, not from the initial
@@ -305,14 +326,87 @@ impl Perpetual {
     /// Unix timestamp (in seconds) of the most recent mark price update.
     pub fn mark_price_timestamp(&self) -> u64 { self.mark_price_timestamp }
 
+    /// Enables tracking of the exponential moving average of the mark price
+    /// with the smoothing factor `alpha` in `(0, 1]`, updated with each mark
+    /// price update, see [`Self::mark_ema`].
+    ///
+    /// The average starts from the current mark price, if known.
+    ///
+    /// # Panics
+    ///
+    /// If `alpha` is out of range.
+    pub fn with_mark_ema(mut self, alpha: UD64) -> Self {
+        assert!(!alpha.is_zero() && alpha <= UD64::ONE, "mark EMA alpha out of range: {alpha}");
+        self.mark_ema_alpha = Some(alpha);
+        self.mark_ema = (!self.mark_price.is_zero()).then_some(self.mark_price);
+        self
+    }
+
+    /// Exponential moving average of the mark price, if enabled with
+    /// [`Self::with_mark_ema`] and any mark price is known.
+    pub fn mark_ema(&self) -> Option<num::Price> { self.mark_ema }
+
+    /// Enables retaining of up to `capacity` most recent mark price updates,
+    /// see [`Self::mark_history`]. Zero capacity disables the history.
+    ///
+    /// The history starts from the current mark price, if known.
+    pub fn with_mark_history(mut self, capacity: usize) -> Self {
+        self.mark_history_capacity = capacity;
+        self.mark_history = VecDeque::with_capacity(capacity);
+        if capacity > 0 && !self.mark_price.is_zero() {
+            self.mark_history.push_back((self.instant, self.mark_price));
+        }
+        self
+    }
+
+    /// Recent mark prices with the instants they were set at, if enabled
+    /// with [`Self::with_mark_history`], oldest first.
+    ///
+    /// Multiple updates within a block are collapsed into the last one.
+    pub fn mark_history(&self) -> &VecDeque<(types::StateInstant, num::Price)> {
+        &self.mark_history
+    }
+
+    /// Time-weighted average of the mark price over the last `blocks` blocks
+    /// up to [`Self::instant`], each price weighted by the number of blocks
+    /// it was in effect.
+    ///
+    /// Returns `None` if `blocks` is zero or [`Self::mark_history`] does not
+    /// cover the whole window.
+    pub fn twap(&self, blocks: u64) -> Option<num::Price> {
+        let end = self.instant.block_number();
+        let start = end.checked_sub(blocks).filter(|_| blocks > 0)?;
+        // The last price set at or before the window start is in effect at its start
+        let first = self
+            .mark_history
+            .iter()
+            .rposition(|(instant, _)| instant.block_number() <= start)?;
+        let mut sum = UD128::ZERO;
+        let mut points = self.mark_history.range(first..).peekable();
+        while let Some((instant, price)) = points.next() {
+            let from = instant.block_number().max(start);
+            let to = points.peek().map_or(end, |(next, _)| next.block_number());
+            let price: UD128 = price.get().resize();
+            sum += price * UD128::from(to - from);
+        }
+        Some(num::Price::new((sum / UD128::from(blocks)).resize()))
+    }
+
     /// Indicates that the mark price is obsolete and will not be accepted
     /// during the order/position settlement
     pub fn is_mark_price_obsolete(&self) -> bool {
         self.mark_price_timestamp + self.price_max_age_sec <= self.instant.block_timestamp()
     }
 
+    /// Wall-clock time left as of the `clock` until the mark price gets
+    /// obsolete, zero if it already is, see [`Self::is_mark_price_obsolete`].
+    pub fn time_until_mark_price_obsolete(&self, clock: &impl types::Clock) -> Duration {
+        let obsolete_at = self.mark_price_timestamp + self.price_max_age_sec;
+        Duration::from_secs(obsolete_at.saturating_sub(clock.now()))
+    }
+
     /// Oracle price of the contract.
-    pub fn oracle_price(&self) -> UD64 { self.oracle_price }
+    pub fn oracle_price(&self) -> num::Price { self.oracle_price }
 
     /// Instant the oracle price was updated at.
     /// Block number available only from real-time events, not from the initial
@@ -333,6 +427,25 @@ impl Perpetual {
         self.oracle_price_timestamp + self.price_max_age_sec <= self.instant.block_timestamp()
     }
 
+    /// Wall-clock time left as of the `clock` until the oracle price gets
+    /// obsolete, zero if it already is, see [`Self::is_oracle_price_obsolete`].
+    pub fn time_until_oracle_price_obsolete(&self, clock: &impl types::Clock) -> Duration {
+        let obsolete_at = self.oracle_price_timestamp + self.price_max_age_sec;
+        Duration::from_secs(obsolete_at.saturating_sub(clock.now()))
+    }
+
+    /// Mark price minus oracle price, positive when the mark price is above
+    /// the oracle price.
+    /// `None` if either of the prices is not known yet.
+    pub fn basis(&self) -> Option<D256> { price_difference(self.mark_price, self.oracle_price) }
+
+    /// Last trade price minus mark price, positive when the last trade was
+    /// executed above the mark price.
+    /// `None` if either of the prices is not known yet.
+    pub fn last_vs_mark(&self) -> Option<D256> {
+        price_difference(self.last_price, self.mark_price)
+    }
+
     /// The funding rate applied at the previous funding event.
     pub fn funding_rate(&self) -> D64 {
         if let Some((next, bl)) = self.next_funding_rate.zip(self.next_funding_event_block)
@@ -365,6 +478,64 @@ impl Perpetual {
     /// The block number of the next funding event, if scheduled.
     pub fn next_funding_event_block(&self) -> Option<u64> { self.next_funding_event_block }
 
+    /// The currently effective funding rate, or `None` if funding has not
+    /// started yet (perpetual was never unpaused).
+    pub fn current_funding_rate(&self) -> Option<D64> {
+        (self.funding_start_block != 0).then(|| self.funding_rate())
+    }
+
+    /// Estimated payment of the `position` at the next funding event, in
+    /// collateral token: positive when the position pays and negative when
+    /// it receives, as longs pay shorts when the funding rate is positive.
+    ///
+    /// Applies the next funding rate if scheduled, or [`Self::funding_rate`]
+    /// otherwise, to the position notional at the mark price. Funding rates
+    /// are percentages, as reported by the exchange.
+    pub fn estimated_funding_payment(&self, position: &Position) -> D256 {
+        let rate = self
+            .next_funding_rate()
+            .unwrap_or_else(|| self.funding_rate());
+        let notional: D256 =
+            self.mark_price.get().resize().to_signed() * position.size().get().resize().to_signed();
+        let payment = rate.resize() * notional / D256::from(100u32);
+        if position.r#type().is_long() { payment } else { -payment }
+    }
+
+    /// Number of blocks from `current_block` until the next funding interval
+    /// boundary strictly after it.
+    ///
+    /// Boundaries are at `funding_start_block + k * funding_interval_blocks`,
+    /// see [`Exchange::funding_interval_blocks`].
+    /// Returns `None` if funding has not started yet or the interval is zero.
+    pub fn blocks_until_funding(
+        &self,
+        current_block: u64,
+        funding_interval_blocks: u32,
+    ) -> Option<u64> {
+        let interval = funding_interval_blocks as u64;
+        if self.funding_start_block == 0 || interval == 0 {
+            return None;
+        }
+        Some(if current_block < self.funding_start_block {
+            self.funding_start_block - current_block
+        } else {
+            interval - (current_block - self.funding_start_block) % interval
+        })
+    }
+
+    /// Estimated wall-clock time from `current_block` until the next funding
+    /// interval boundary, see [`Self::blocks_until_funding`] and
+    /// [`Chain::block_time`].
+    pub fn time_until_funding(
+        &self,
+        current_block: u64,
+        funding_interval_blocks: u32,
+        chain: &Chain,
+    ) -> Option<Duration> {
+        self.blocks_until_funding(current_block, funding_interval_blocks)
+            .map(|blocks| chain.blocks_duration(blocks))
+    }
+
     /// Feed ID of ChainLink DataStreams price oracle.
     pub fn oracle_feed_id(&self) -> B256 { self.oracle_feed_id }
 
@@ -382,23 +553,81 @@ impl Perpetual {
     /// Total number of orders in the book.
     pub fn total_orders(&self) -> usize { self.l3_book.total_orders() }
 
+    /// Number of orders, total size and total notional of the account's
+    /// orders resting in the book, on both sides.
+    pub fn account_resting(
+        &self,
+        account_id: types::AccountId,
+    ) -> (usize, num::Size, num::Collateral) {
+        self.l3_book
+            .all_orders()
+            .values()
+            .filter(|order| order.account_id() == account_id)
+            .fold((0, num::Size::ZERO, num::Collateral::ZERO), |(count, size, notional), order| {
+                (count + 1, size + order.size(), notional + order.price() * order.size())
+            })
+    }
+
     /// Up to date L3 order book.
     pub fn l3_book(&self) -> &OrderBook { &self.l3_book }
 
+    /// Order book for mutation, copied first if shared with a clone of the
+    /// perpetual contract.
+    fn book_mut(&mut self) -> &mut OrderBook { Arc::make_mut(&mut self.l3_book) }
+
     /// Open interest size.
     pub fn open_interest(&self) -> UD128 { self.open_interest }
 
     /// Open interest amount.
-    pub fn open_interest_amount(&self) -> UD128 { self.open_interest * self.last_price.resize() }
+    pub fn open_interest_amount(&self) -> num::Collateral {
+        num::Collateral::new(self.open_interest * self.last_price.get().resize())
+    }
 
-    pub(crate) fn base_price(&self) -> UD64 { self.base_price }
+    /// Indicates if the order `price` is representable by the exchange,
+    /// i.e. within 2^24 ticks above the base price, orders with prices out of
+    /// the range are reverted.
+    pub fn price_in_range(&self, price: num::Price) -> bool {
+        let max_ticks: u64 = (1 << ORDER_PRICE_OFFSET_BITS) - 1;
+        let max_offset = self.price_converter.from_u64(max_ticks);
+        price >= self.base_price && price - self.base_price <= max_offset
+    }
+
+    pub(crate) fn base_price(&self) -> num::Price { self.base_price }
+
+    /// Approximate heap memory used by the perpetual contract state, in bytes.
+    pub(crate) fn approximate_heap_bytes(&self) -> usize {
+        self.name.capacity()
+            + self.symbol.capacity()
+            + std::mem::size_of::<OrderBook>()
+            + self.l3_book.approximate_heap_bytes()
+            + self.mark_history.capacity()
+                * std::mem::size_of::<(types::StateInstant, num::Price)>()
+    }
 
-    pub(crate) fn update_state_instant(&mut self, instant: types::StateInstant) {
+    pub(crate) fn state_instant(&self) -> types::StateInstant { self.state_instant }
+
+    /// Advances the state to the completed block at `instant`, returning
+    /// prior state of the orders expired as of the block.
+    pub(crate) fn update_state_instant(&mut self, instant: types::StateInstant) -> Vec<Order> {
         // Update state instant first
         self.state_instant = instant;
 
-        // Check for expired orders
-        self.l3_book.check_expired(instant);
+        // Check for expired orders, leaving the book shared if nothing changes
+        if self.l3_book.needs_completion(instant) {
+            self.book_mut().check_expired(instant)
+        } else {
+            vec![]
+        }
+    }
+
+    /// Reverts [`Self::update_state_instant`] back to the `prior` state
+    /// instant, given prior state of the orders it expired.
+    pub(crate) fn revert_state_instant(&mut self, prior: types::StateInstant, expired: &[Order]) {
+        if !expired.is_empty() {
+            let instant = self.state_instant;
+            self.book_mut().revert_expired(instant, expired);
+        }
+        self.state_instant = prior;
     }
 
     /// If a scheduled funding payment takes effect at `instant` (i.e. this is
@@ -416,10 +645,7 @@ impl Perpetual {
         &mut self,
         instant: types::StateInstant,
     ) -> Option<(D64, D256)> {
-        if self
-            .next_funding_event_block
-            .is_some_and(|fe| fe == instant.block_number())
-        {
+        if self.funding_event_at(instant) {
             let rate = self.next_funding_rate.unwrap_or(self.prev_funding_rate);
             self.next_funding_payment.take().map(|payment| (rate, payment))
         } else {
@@ -427,8 +653,19 @@ impl Perpetual {
         }
     }
 
+    /// Indicates if the scheduled funding payment takes effect at `instant`,
+    /// see [`Self::take_funding_payment`].
+    pub(crate) fn has_funding_payment(&self, instant: types::StateInstant) -> bool {
+        self.funding_event_at(instant) && self.next_funding_payment.is_some()
+    }
+
+    fn funding_event_at(&self, instant: types::StateInstant) -> bool {
+        self.next_funding_event_block
+            .is_some_and(|fe| fe == instant.block_number())
+    }
+
     pub(crate) fn add_order(&mut self, order: Order) -> Result<(), DexError> {
-        self.l3_book
+        self.book_mut()
             .add_order(&order)
             .map_err(|err| DexError::OrderBook(self.id, err))?;
         Ok(())
@@ -440,7 +677,7 @@ impl Perpetual {
     /// Uses the `prev_order_id`/`next_order_id` fields from the snapshot to
     /// determine the correct queue position within each price level.
     pub(crate) fn add_orders_from_snapshot(&mut self, orders: Vec<Order>) -> Result<(), DexError> {
-        self.l3_book
+        self.book_mut()
             .add_orders_from_snapshot(&orders)
             .map_err(|err| DexError::OrderBook(self.id, err))?;
         Ok(())
@@ -455,15 +692,15 @@ impl Perpetual {
 
         if prev.price() != order.price() {
             // Price changed: remove from old level, add to new level (back of queue)
-            self.l3_book
+            self.book_mut()
                 .remove_order(&prev)
                 .map_err(|err| DexError::OrderBook(self.id, err))?;
-            self.l3_book
+            self.book_mut()
                 .add_order(&order)
                 .map_err(|err| DexError::OrderBook(self.id, err))?;
         } else if order.size() > prev.size() {
             // Size INCREASED at same price: move to back of queue (loses priority)
-            self.l3_book
+            self.book_mut()
                 .move_to_back(&order, &prev)
                 .map_err(|err| DexError::OrderBook(self.id, err))?;
         } else if prev.expiry_block() > 0
@@ -471,12 +708,12 @@ impl Perpetual {
             && prev.expiry_block() != order.expiry_block()
         {
             // Expired order got new expiry: move to back of queue (loses priority)
-            self.l3_book
+            self.book_mut()
                 .move_to_back(&order, &prev)
                 .map_err(|err| DexError::OrderBook(self.id, err))?;
         } else {
             // Size decreased or unchanged: keep queue position
-            self.l3_book
+            self.book_mut()
                 .update_order(&order, &prev)
                 .map_err(|err| DexError::OrderBook(self.id, err))?;
         }
@@ -489,11 +726,21 @@ impl Perpetual {
             .get_order(order_id)
             .cloned()
             .ok_or(DexError::OrderNotFound(self.id, order_id))?;
-        self.l3_book
+        self.book_mut()
             .remove_order(&order)
             .map_err(|err| DexError::OrderBook(self.id, err))
     }
 
+    /// Compacts the order book, see [`OrderBook::compact`].
+    pub(crate) fn compact_book(&mut self) -> usize { self.book_mut().compact() }
+
+    /// Sets capacity of the order book change journal, see
+    /// [`OrderBook::as_of`].
+    pub(crate) fn set_book_journal_capacity(&mut self, capacity: usize) {
+        let instant = self.state_instant;
+        self.book_mut().set_journal_capacity(capacity, instant);
+    }
+
     pub(crate) fn update_paused(&mut self, instant: types::StateInstant, paused: bool) {
         self.is_paused = paused;
         self.instant = instant;
@@ -531,21 +778,50 @@ impl Perpetual {
         self.instant = instant;
     }
 
-    pub(crate) fn update_last_price(&mut self, instant: types::StateInstant, last_price: UD64) {
+    pub(crate) fn update_last_price(
+        &mut self,
+        instant: types::StateInstant,
+        last_price: num::Price,
+    ) {
         self.last_price = last_price;
         self.last_price_block = Some(instant.block_number());
         self.last_price_timestamp = instant.block_timestamp();
         self.instant = instant;
     }
 
-    pub(crate) fn update_mark_price(&mut self, instant: types::StateInstant, mark_price: UD64) {
+    pub(crate) fn update_mark_price(
+        &mut self,
+        instant: types::StateInstant,
+        mark_price: num::Price,
+    ) {
+        if let Some(alpha) = self.mark_ema_alpha {
+            self.mark_ema = Some(match self.mark_ema {
+                Some(ema) => {
+                    num::Price::new(alpha * mark_price.get() + (UD64::ONE - alpha) * ema.get())
+                },
+                None => mark_price,
+            });
+        }
+        if self.mark_history_capacity > 0 {
+            let last = self.mark_history.back();
+            if last.is_some_and(|(last, _)| last.block_number() == instant.block_number()) {
+                self.mark_history.pop_back();
+            } else if self.mark_history.len() == self.mark_history_capacity {
+                self.mark_history.pop_front();
+            }
+            self.mark_history.push_back((instant, mark_price));
+        }
         self.mark_price = mark_price;
         self.mark_price_block = Some(instant.block_number());
         self.mark_price_timestamp = instant.block_timestamp();
         self.instant = instant;
     }
 
-    pub(crate) fn update_oracle_price(&mut self, instant: types::StateInstant, oracle_price: UD64) {
+    pub(crate) fn update_oracle_price(
+        &mut self,
+        instant: types::StateInstant,
+        oracle_price: num::Price,
+    ) {
         self.oracle_price = oracle_price;
         self.oracle_price_block = Some(instant.block_number());
         self.oracle_price_timestamp = instant.block_timestamp();
@@ -633,24 +909,28 @@ impl Perpetual {
             name: "TEST".to_string(),
             symbol: "TEST".to_string(),
             is_paused: false,
-            price_converter: num::Converter::new(0),
-            size_converter: num::Converter::new(0),
+            price_converter: num::PriceConverter::new(0),
+            size_converter: num::SizeConverter::new(0),
             leverage_converter: num::Converter::new(2),
             fee_converter: num::Converter::new(5),
             funding_rate_converter: num::Converter::new(5),
             funding_sum_converter: num::Converter::new(0),
-            base_price: UD64::ZERO,
+            base_price: num::Price::ZERO,
             maker_fee: UD64::ZERO,
             taker_fee: UD64::ZERO,
             initial_margin: UD64::ZERO,
             maintenance_margin: UD64::ZERO,
-            last_price: UD64::ZERO,
+            last_price: num::Price::ZERO,
             last_price_block: None,
             last_price_timestamp: 0,
-            mark_price: UD64::ZERO,
+            mark_price: num::Price::ZERO,
             mark_price_block: None,
             mark_price_timestamp: 0,
-            oracle_price: UD64::ZERO,
+            mark_ema_alpha: None,
+            mark_ema: None,
+            mark_history_capacity: 0,
+            mark_history: VecDeque::new(),
+            oracle_price: num::Price::ZERO,
             oracle_price_block: None,
             oracle_price_timestamp: 0,
             prev_funding_rate: D64::ZERO,
@@ -661,12 +941,18 @@ impl Perpetual {
             oracle_feed_id: B256::ZERO,
             is_oracle_used: false,
             price_max_age_sec: 0,
-            l3_book: OrderBook::new(),
+            l3_book: Arc::new(OrderBook::new()),
             open_interest: UD128::ZERO,
         }
     }
 }
 
+/// Signed difference of two prices, if both are known.
+fn price_difference(price: num::Price, reference: num::Price) -> Option<D256> {
+    (!price.is_zero() && !reference.is_zero())
+        .then(|| price.get().resize().to_signed() - reference.get().resize().to_signed())
+}
+
 /// Test utility builders for `Perpetual`.
 ///
 /// Gated behind the `test-utils` feature to keep internal mutation methods
@@ -678,7 +964,7 @@ impl Perpetual {
 impl Perpetual {
     pub fn for_test(id: types::PerpetualId) -> Self { Self::testing(id) }
 
-    pub fn with_last_price(mut self, price: UD64) -> Self {
+    pub fn with_last_price(mut self, price: num::Price) -> Self {
         self.last_price = price;
         self
     }
@@ -688,23 +974,37 @@ impl Perpetual {
         self
     }
 
-    pub fn with_bid(mut self, price: UD64, size: UD64) -> Self {
+    pub fn with_bid(mut self, price: num::Price, size: num::Size) -> Self {
         use std::num::NonZeroU16;
         let order_id =
             NonZeroU16::new((self.l3_book.total_orders() + 1) as u16).expect("order id overflow");
-        let order = Order::for_l3_testing(types::OrderType::OpenLong, price, size, 0, order_id, 0);
-        self.l3_book
+        let order = Order::for_l3_testing(
+            types::OrderType::OpenLong,
+            price.get(),
+            size.get(),
+            0,
+            order_id,
+            0,
+        );
+        self.book_mut()
             .add_order(&order)
             .expect("failed to add bid order");
         self
     }
 
-    pub fn with_ask(mut self, price: UD64, size: UD64) -> Self {
+    pub fn with_ask(mut self, price: num::Price, size: num::Size) -> Self {
         use std::num::NonZeroU16;
         let order_id =
             NonZeroU16::new((self.l3_book.total_orders() + 1) as u16).expect("order id overflow");
-        let order = Order::for_l3_testing(types::OrderType::OpenShort, price, size, 0, order_id, 0);
-        self.l3_book
+        let order = Order::for_l3_testing(
+            types::OrderType::OpenShort,
+            price.get(),
+            size.get(),
+            0,
+            order_id,
+            0,
+        );
+        self.book_mut()
             .add_order(&order)
             .expect("failed to add ask order");
         self
@@ -777,6 +1077,7 @@ impl std::fmt::Display for Perpetual {
                     "Open Interest: {}\namount: ${}",
                     self.open_interest.to_string().cyan(),
                     self.open_interest_amount()
+                        .get()
                         .trunc_with_scale(2)
                         .to_string()
                         .cyan(),
@@ -815,12 +1116,52 @@ impl std::fmt::Display for Perpetual {
 mod tests {
     use std::num::NonZeroU16;
 
-    use fastnum::{dec64, dec256, udec64};
+    use alloy::primitives::Address;
+    use fastnum::{dec64, dec256, udec64, udec128};
 
     use super::*;
 
     fn oid(n: u16) -> types::OrderId { NonZeroU16::new(n).expect("test order id must be non-zero") }
 
+    #[test]
+    fn perpetual_name_symbol_borrowed() {
+        let perp = Perpetual::for_testing(9);
+        let symbols = (0..3).map(|_| perp.symbol()).collect::<Vec<_>>();
+        // Stored strings are borrowed rather than cloned on each call
+        for symbol in symbols {
+            assert_eq!(symbol.as_ptr(), perp.symbol.as_ptr());
+            assert_eq!(perp.name().as_ptr(), perp.name.as_ptr());
+        }
+        assert_eq!(perp.name(), "TEST");
+    }
+
+    #[test]
+    fn perpetual_price_in_range() {
+        let perp = Perpetual::added(
+            types::StateInstant::new(0, 0),
+            1,
+            "TEST".to_string(),
+            "TEST".to_string(),
+            false,
+            2,
+            0,
+            U256::from(10_000),
+            U256::ZERO,
+            U256::ZERO,
+            U256::ZERO,
+            U256::ZERO,
+        );
+        assert!(perp.price_in_range(udec64!(100).into()));
+        assert!(perp.price_in_range(udec64!(12345.67).into()));
+        assert!(!perp.price_in_range(udec64!(99.99).into()));
+        assert!(!perp.price_in_range(num::Price::ZERO));
+
+        // Base price + (2^24 - 1) ticks
+        assert!(perp.price_in_range(udec64!(167872.15).into()));
+        assert!(!perp.price_in_range(udec64!(167872.16).into()));
+        assert!(!perp.price_in_range(udec64!(1000000).into()));
+    }
+
     #[test]
     fn update_order_expired_order_renewal_moves_to_back() {
         let mut perp = Perpetual::for_testing(1);
@@ -979,4 +1320,208 @@ mod tests {
         perp.update_state_instant(types::StateInstant::new(5, 5));
         assert_eq!(perp.funding_rate(), dec64!(0.03)); // 5 <= 5? yes -> next
     }
+
+    #[test]
+    fn perpetual_current_funding_rate() {
+        let mut perp = Perpetual::for_testing(4);
+        assert_eq!(perp.current_funding_rate(), None);
+
+        perp.update_paused(types::StateInstant::new(10, 10), false);
+        assert_eq!(perp.current_funding_rate(), Some(D64::ZERO));
+
+        perp.update_funding(types::StateInstant::new(11, 11), dec64!(0.01), dec256!(1.0), 20);
+        perp.update_state_instant(types::StateInstant::new(20, 20));
+        assert_eq!(perp.current_funding_rate(), Some(dec64!(0.01)));
+    }
+
+    #[test]
+    fn perpetual_blocks_until_funding() {
+        let mut perp = Perpetual::for_testing(5);
+        assert_eq!(perp.blocks_until_funding(100, 10), None);
+
+        perp.update_paused(types::StateInstant::new(100, 100), false);
+        assert_eq!(perp.funding_start_block(), 100);
+        assert_eq!(perp.blocks_until_funding(100, 0), None);
+
+        // Before the start block
+        assert_eq!(perp.blocks_until_funding(95, 10), Some(5));
+        // Within the first interval
+        assert_eq!(perp.blocks_until_funding(100, 10), Some(10));
+        assert_eq!(perp.blocks_until_funding(101, 10), Some(9));
+        assert_eq!(perp.blocks_until_funding(109, 10), Some(1));
+        // At and across interval boundaries
+        assert_eq!(perp.blocks_until_funding(110, 10), Some(10));
+        assert_eq!(perp.blocks_until_funding(111, 10), Some(9));
+        assert_eq!(perp.blocks_until_funding(1_099, 10), Some(1));
+        assert_eq!(perp.blocks_until_funding(1_100, 10), Some(10));
+    }
+
+    #[test]
+    fn perpetual_time_until_funding() {
+        let mut perp = Perpetual::for_testing(5);
+        let chain = Chain::testnet();
+        assert_eq!(chain.block_time(), Chain::DEFAULT_BLOCK_TIME);
+        assert_eq!(perp.time_until_funding(100, 10, &chain), None);
+
+        perp.update_paused(types::StateInstant::new(100, 100), false);
+        assert_eq!(perp.time_until_funding(101, 10, &chain), Some(Duration::from_millis(3600)));
+
+        // Uses configured block time
+        let chain =
+            Chain::custom(1, Address::ZERO, 0, Address::ZERO, vec![], Duration::from_secs(2));
+        assert_eq!(chain.blocks_duration(0), Duration::ZERO);
+        assert_eq!(chain.blocks_duration(3), Duration::from_secs(6));
+        assert_eq!(chain.blocks_duration(u64::MAX), Duration::MAX);
+        assert_eq!(perp.time_until_funding(101, 10, &chain), Some(Duration::from_secs(18)));
+    }
+
+    #[test]
+    fn perpetual_price_divergence() {
+        let mut perp = Perpetual::for_testing(6);
+        assert_eq!(perp.basis(), None);
+        assert_eq!(perp.last_vs_mark(), None);
+
+        perp.update_mark_price(types::StateInstant::new(1, 1), udec64!(100.5).into());
+        assert_eq!(perp.basis(), None);
+        assert_eq!(perp.last_vs_mark(), None);
+
+        perp.update_oracle_price(types::StateInstant::new(2, 2), udec64!(100).into());
+        perp.update_last_price(types::StateInstant::new(3, 3), udec64!(99.75).into());
+        assert_eq!(perp.basis(), Some(dec256!(0.5)));
+        assert_eq!(perp.last_vs_mark(), Some(dec256!(-0.75)));
+
+        perp.update_oracle_price(types::StateInstant::new(4, 4), udec64!(101).into());
+        perp.update_last_price(types::StateInstant::new(5, 5), udec64!(101.5).into());
+        assert_eq!(perp.basis(), Some(dec256!(-0.5)));
+        assert_eq!(perp.last_vs_mark(), Some(dec256!(1)));
+    }
+
+    #[test]
+    fn perpetual_mark_ema() {
+        let mut perp = Perpetual::for_testing(7);
+        perp.update_mark_price(types::StateInstant::new(1, 1), udec64!(100).into());
+        assert_eq!(perp.mark_ema(), None);
+
+        let mut perp = perp.with_mark_ema(udec64!(0.5));
+        assert_eq!(perp.mark_ema(), Some(num::Price::new(udec64!(100))));
+        perp.update_mark_price(types::StateInstant::new(2, 2), udec64!(110).into());
+        assert_eq!(perp.mark_ema(), Some(num::Price::new(udec64!(105))));
+        perp.update_mark_price(types::StateInstant::new(3, 3), udec64!(110).into());
+        assert_eq!(perp.mark_ema(), Some(num::Price::new(udec64!(107.5))));
+
+        // Converges to the steady mark price, halving the distance each update
+        for block in 4..24 {
+            perp.update_mark_price(types::StateInstant::new(block, block), udec64!(120).into());
+        }
+        let ema = perp.mark_ema().unwrap();
+        assert!(ema < udec64!(120).into() && ema > udec64!(119.9999).into());
+        assert_eq!(perp.mark_price(), udec64!(120).into());
+    }
+
+    #[test]
+    fn perpetual_mark_twap() {
+        let mut perp = Perpetual::for_testing(8).with_mark_history(3);
+        assert!(perp.mark_history().is_empty());
+        assert_eq!(perp.twap(1), None);
+
+        perp.update_mark_price(types::StateInstant::new(10, 10), udec64!(100).into());
+        perp.update_mark_price(types::StateInstant::new(12, 12), udec64!(90).into());
+        perp.update_mark_price(types::StateInstant::new(13, 13), udec64!(120).into());
+        perp.update_mark_price(types::StateInstant::new(13, 13), udec64!(110).into());
+        assert_eq!(perp.mark_history().len(), 3);
+
+        // 100 for blocks 10-11, 90 for block 12, then 110 up to block 14
+        perp.update_last_price(types::StateInstant::new(14, 14), udec64!(105).into());
+        assert_eq!(perp.twap(4), Some(num::Price::new(udec64!(100))));
+        assert_eq!(perp.twap(2), Some(num::Price::new(udec64!(100))));
+        assert_eq!(perp.twap(1), Some(num::Price::new(udec64!(110))));
+        assert_eq!(perp.twap(5), None);
+        assert_eq!(perp.twap(0), None);
+
+        // The oldest price is evicted, history starts at block 12
+        perp.update_mark_price(types::StateInstant::new(16, 16), udec64!(100).into());
+        assert_eq!(perp.mark_history().len(), 3);
+        assert_eq!(perp.mark_history()[0].0.block_number(), 12);
+        assert_eq!(perp.twap(4), Some(num::Price::new(udec64!(105))));
+        assert_eq!(perp.twap(5), None);
+    }
+
+    #[test]
+    fn perpetual_estimated_funding_payment() {
+        let mut perp = Perpetual::for_testing(1);
+        perp.update_mark_price(types::StateInstant::new(1, 1), udec64!(100).into());
+        let position = |r#type| {
+            Position::opened(
+                types::StateInstant::new(1, 1),
+                1,
+                1,
+                r#type,
+                90,
+                0,
+                perp.price_converter(),
+                udec64!(2),
+                udec128!(20),
+                udec64!(20),
+            )
+        };
+        let (long, short) = (position(PositionType::Long), position(PositionType::Short));
+
+        // Notional of 200 at the mark price, rate of 0.01%
+        perp.prev_funding_rate = dec64!(0.01);
+        assert_eq!(perp.estimated_funding_payment(&long), dec256!(0.02));
+        assert_eq!(perp.estimated_funding_payment(&short), dec256!(-0.02));
+
+        perp.prev_funding_rate = dec64!(-0.01);
+        assert_eq!(perp.estimated_funding_payment(&long), dec256!(-0.02));
+        assert_eq!(perp.estimated_funding_payment(&short), dec256!(0.02));
+
+        // Scheduled rate takes precedence
+        perp.update_funding(types::StateInstant::new(1, 1), dec64!(0.05), D256::ZERO, 5);
+        assert_eq!(perp.estimated_funding_payment(&long), dec256!(0.1));
+        assert_eq!(perp.estimated_funding_payment(&short), dec256!(-0.1));
+    }
+
+    #[test]
+    fn perpetual_time_until_price_obsolete() {
+        let mut perp = Perpetual::for_testing(1);
+        perp.update_price_max_age_sec(types::StateInstant::new(1, 1_000), 60);
+        perp.update_mark_price(types::StateInstant::new(1, 1_000), udec64!(100).into());
+        perp.update_oracle_price(types::StateInstant::new(1, 990), udec64!(100).into());
+
+        let clock = types::FixedClock(1_015);
+        assert_eq!(perp.time_until_mark_price_obsolete(&clock), Duration::from_secs(45));
+        assert_eq!(perp.time_until_oracle_price_obsolete(&clock), Duration::from_secs(35));
+
+        let clock = types::FixedClock(1_060);
+        assert_eq!(perp.time_until_mark_price_obsolete(&clock), Duration::ZERO);
+        assert_eq!(perp.time_until_oracle_price_obsolete(&clock), Duration::ZERO);
+    }
+
+    #[test]
+    fn perpetual_account_resting() {
+        let mut perp = Perpetual::for_testing(1);
+        assert_eq!(perp.account_resting(101), (0, num::Size::ZERO, num::Collateral::ZERO));
+
+        let orders = [
+            (types::OrderType::OpenShort, udec64!(101), udec64!(1), 101),
+            (types::OrderType::OpenShort, udec64!(102), udec64!(2), 101),
+            (types::OrderType::OpenLong, udec64!(99), udec64!(0.5), 101),
+            (types::OrderType::OpenShort, udec64!(101), udec64!(3), 102),
+        ];
+        for (n, (r#type, price, size, account_id)) in orders.into_iter().enumerate() {
+            let order =
+                Order::for_l3_testing(r#type, price, size, 1, oid(n as u16 + 1), account_id);
+            perp.add_order(order).unwrap();
+        }
+
+        assert_eq!(
+            perp.account_resting(101),
+            (3, num::Size::new(udec64!(3.5)), num::Collateral::new(udec128!(354.5)))
+        );
+        assert_eq!(
+            perp.account_resting(102),
+            (1, num::Size::new(udec64!(3)), num::Collateral::new(udec128!(303)))
+        );
+        assert_eq!(perp.account_resting(103), (0, num::Size::ZERO, num::Collateral::ZERO));
+    }
 }
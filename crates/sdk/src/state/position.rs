@@ -1,8 +1,8 @@
-use alloy::primitives::U256;
-use fastnum::{D64, D256, UD64, UD128, udec64};
+use alloy::primitives::{I256, U256};
+use fastnum::{D64, D256, UD64, UD128, dec256, udec64};
 
-use super::num;
-use crate::{abi::dex::Exchange::PositionInfoV2, types};
+use super::{Exchange, num};
+use crate::{abi::dex::Exchange::PositionInfoV2, error::DexError, types};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum PositionType {
@@ -10,8 +10,26 @@ pub enum PositionType {
     Short = 1,
 }
 
+/// Raw fixed-point values of the position state as used by the exchange
+/// contract, see [`Position::raw`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawPosition {
+    /// Size in lots, `lotLNS`.
+    pub lot_lns: U256,
+
+    /// Deposit in collateral token units, `depositCNS`.
+    pub deposit_cns: U256,
+
+    /// Delta PnL in collateral token units, `deltaPnlCNS`.
+    pub delta_pnl_cns: I256,
+
+    /// Premium PnL in collateral token units, `premiumPnlCNS`.
+    pub premium_pnl_cns: I256,
+}
+
 /// Open perpetual contract position.
 #[derive(Clone, derive_more::Debug)]
+#[cfg_attr(feature = "testing", derive(PartialEq, Eq))]
 pub struct Position {
     instant: types::StateInstant,
     funding_instant: types::StateInstant,
@@ -37,20 +55,20 @@ impl Position {
         instant: types::StateInstant,
         perpetual_id: types::PerpetualId,
         info: &PositionInfoV2,
-        collateral_converter: num::Converter,
-        price_converter: num::Converter,
-        size_converter: num::Converter,
+        collateral_converter: num::CollateralConverter,
+        price_converter: num::PriceConverter,
+        size_converter: num::SizeConverter,
         maintenance_margin: UD64,
-    ) -> Self {
-        let r#type = info.positionType.into();
+    ) -> Result<Self, DexError> {
+        let r#type = info.positionType.try_into()?;
         let entry_price = Self::effective_entry_price(
             r#type,
-            info.pricePNS,
+            num::narrow(info.pricePNS, "entry price")?,
             info.priceResiduePNSQ16.to(),
             price_converter,
         );
-        let size = size_converter.from_unsigned(info.lotLNS);
-        Self {
+        let size = size_converter.from_unsigned(info.lotLNS).get();
+        Ok(Self {
             instant,
             funding_instant: instant,
             perpetual_id,
@@ -58,12 +76,12 @@ impl Position {
             r#type,
             entry_price,
             size,
-            deposit: collateral_converter.from_unsigned(info.depositCNS),
+            deposit: collateral_converter.from_unsigned(info.depositCNS).get(),
             delta_pnl: collateral_converter.from_signed(info.deltaPnlCNS),
             premium_pnl: collateral_converter.from_signed(info.premiumPnlCNS),
             maintenance_margin_requirement: entry_price.resize() * size.resize()
                 / maintenance_margin.resize(),
-        }
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -72,9 +90,9 @@ impl Position {
         perpetual_id: types::PerpetualId,
         account_id: types::AccountId,
         r#type: PositionType,
-        price_pns: U256,
+        price_pns: u64,
         price_residue_pnsq16: u32,
-        price_converter: num::Converter,
+        price_converter: num::PriceConverter,
         size: UD64,
         deposit: UD128,
         maintenance_margin: UD64,
@@ -110,13 +128,13 @@ impl Position {
     pub fn r#type(&self) -> PositionType { self.r#type }
 
     /// Position entry price, full precision - including 16 bit rounding residue
-    pub fn entry_price(&self) -> UD64 { self.entry_price }
+    pub fn entry_price(&self) -> num::Price { num::Price::new(self.entry_price) }
 
     /// Size of the position.
-    pub fn size(&self) -> UD64 { self.size }
+    pub fn size(&self) -> num::Size { num::Size::new(self.size) }
 
     /// Collateral deposit / margin locked in the position.
-    pub fn deposit(&self) -> UD128 { self.deposit }
+    pub fn deposit(&self) -> num::Collateral { num::Collateral::new(self.deposit) }
 
     /// Unrealized Delta PnL of the position.
     pub fn delta_pnl(&self) -> D256 { self.delta_pnl }
@@ -127,11 +145,49 @@ impl Position {
     /// Unrealized PnL of the position.
     pub fn pnl(&self) -> D256 { self.delta_pnl + self.premium_pnl }
 
+    /// Return on equity, i.e. unrealized PnL in percent of the deposit.
+    ///
+    /// Returns `None` if the position has no deposit.
+    pub fn roe(&self) -> Option<D256> { self.roe_of(self.pnl()) }
+
+    /// Return on equity as of the `mark_price`, with delta PnL recomputed
+    /// from it, see [`Self::roe`].
+    pub fn roe_at(&self, mark_price: num::Price) -> Option<D256> {
+        self.roe_of(self.delta_pnl_at(mark_price) + self.premium_pnl)
+    }
+
+    /// Raw fixed-point values of the position state as reported by the
+    /// exchange contract, for precise accounting without fetching them from
+    /// the chain.
+    ///
+    /// PnL updated by the SDK from mark price or funding is truncated to the
+    /// collateral token precision. Returns `None` if the perpetual contract
+    /// is not tracked by the `exchange`.
+    pub fn raw(&self, exchange: &Exchange) -> Option<RawPosition> {
+        let perp = exchange.perpetuals().get(&self.perpetual_id)?;
+        let cc = exchange.collateral_converter();
+        Some(RawPosition {
+            lot_lns: perp.size_converter().to_unsigned(self.size()),
+            deposit_cns: cc.to_unsigned(self.deposit()),
+            delta_pnl_cns: cc.to_signed(self.delta_pnl),
+            premium_pnl_cns: cc.to_signed(self.premium_pnl),
+        })
+    }
+
+    /// Checks that [`Self::delta_pnl`] and [`Self::premium_pnl`] add up to
+    /// the total `pnl` reported by the exchange, within `tolerance` for
+    /// rounding.
+    pub fn pnl_is_consistent(&self, pnl: D256, tolerance: D256) -> bool {
+        (self.pnl() - pnl).abs() <= tolerance
+    }
+
     /// Maintenance margin requirement of the position.
-    pub fn maintenance_margin_requirement(&self) -> UD128 { self.maintenance_margin_requirement }
+    pub fn maintenance_margin_requirement(&self) -> num::Collateral {
+        num::Collateral::new(self.maintenance_margin_requirement)
+    }
 
     /// Liquidation price of the position.
-    pub fn liquidation_price(&self) -> UD64 {
+    pub fn liquidation_price(&self) -> num::Price {
         let side = if self.r#type.is_long() { D256::ONE } else { D256::ONE.neg() };
         let liquidation_price = self.entry_price.to_signed()
             + (side
@@ -140,17 +196,17 @@ impl Position {
                     - self.premium_pnl)
                 / self.size.to_signed().resize())
             .resize();
-        liquidation_price.max(D64::ZERO).unsigned_abs()
+        num::Price::new(liquidation_price.max(D64::ZERO).unsigned_abs())
     }
 
     /// Bankruptcy price of the position.
-    pub fn bankruptcy_price(&self) -> UD64 {
+    pub fn bankruptcy_price(&self) -> num::Price {
         let side = if self.r#type.is_long() { D256::ONE } else { D256::ONE.neg() };
         let bankruptcy_price = self.entry_price.to_signed()
             - (side * (self.deposit.to_signed().resize() + self.premium_pnl)
                 / self.size.to_signed().resize())
             .resize();
-        bankruptcy_price.max(D64::ZERO).unsigned_abs()
+        num::Price::new(bankruptcy_price.max(D64::ZERO).unsigned_abs())
     }
 
     pub(crate) fn update_type(&mut self, instant: types::StateInstant, r#type: PositionType) {
@@ -161,9 +217,9 @@ impl Position {
     pub(crate) fn update_entry_price(
         &mut self,
         instant: types::StateInstant,
-        price_pns: U256,
+        price_pns: u64,
         price_residue_pnsq16: u32,
-        price_converter: num::Converter,
+        price_converter: num::PriceConverter,
     ) {
         self.entry_price = Self::effective_entry_price(
             self.r#type,
@@ -189,14 +245,28 @@ impl Position {
         self.funding_instant = instant;
     }
 
-    pub(crate) fn apply_mark_price(&mut self, instant: types::StateInstant, mark_price: UD64) {
-        let sign = if self.r#type.is_long() { D256::ONE } else { D256::ONE.neg() };
-        self.delta_pnl = sign
-            * (mark_price.resize().to_signed() - self.entry_price.resize().to_signed())
-            * self.size.resize().to_signed();
+    pub(crate) fn apply_mark_price(
+        &mut self,
+        instant: types::StateInstant,
+        mark_price: num::Price,
+    ) {
+        self.delta_pnl = self.delta_pnl_at(mark_price);
         self.instant = instant;
     }
 
+    fn delta_pnl_at(&self, mark_price: num::Price) -> D256 {
+        let sign = if self.r#type.is_long() { D256::ONE } else { D256::ONE.neg() };
+        sign * (mark_price.get().resize().to_signed() - self.entry_price.resize().to_signed())
+            * self.size.resize().to_signed()
+    }
+
+    fn roe_of(&self, pnl: D256) -> Option<D256> {
+        if self.deposit.is_zero() {
+            return None;
+        }
+        Some(pnl * dec256!(100) / self.deposit.resize().to_signed())
+    }
+
     pub(crate) fn apply_funding_payment(
         &mut self,
         instant: types::StateInstant,
@@ -241,15 +311,15 @@ impl Position {
     ///   where Q = 2^16 = 65536
     pub(crate) fn effective_entry_price(
         position_type: PositionType,
-        price_pns: U256,
+        price_pns: u64,
         price_residue_pnsq16: u32,
-        price_converter: num::Converter,
+        price_converter: num::PriceConverter,
     ) -> UD64 {
         const Q: UD64 = udec64!(65536).with_rounding_mode(fastnum::decimal::RoundingMode::Floor);
         if price_residue_pnsq16 == 0 {
-            price_converter.from_unsigned(price_pns)
+            price_converter.from_u64(price_pns).get()
         } else {
-            let mut entry_price = UD64::from_u64(price_pns.to())
+            let mut entry_price = UD64::from_u64(price_pns)
                 .with_rounding_mode(fastnum::decimal::RoundingMode::Floor); // SC allocates 32 bits
             if position_type.is_long() && entry_price >= UD64::ONE {
                 entry_price -= UD64::ONE;
@@ -269,12 +339,14 @@ impl PositionType {
     pub fn is_short(&self) -> bool { matches!(self, PositionType::Short) }
 }
 
-impl From<u8> for PositionType {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for PositionType {
+    type Error = DexError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => PositionType::Long,
-            1 => PositionType::Short,
-            _ => unreachable!(),
+            0 => Ok(PositionType::Long),
+            1 => Ok(PositionType::Short),
+            _ => Err(DexError::MalformedData(format!("invalid position type: {value}"))),
         }
     }
 }
@@ -294,6 +366,7 @@ impl tabled::Tabled for Position {
 
     fn fields(&self) -> Vec<std::borrow::Cow<'_, str>> {
         use colored::Colorize;
+        let config = num::DisplayConfig::current();
         vec![
             self.perpetual_id().to_string().into(),
             if self.r#type.is_long() {
@@ -301,24 +374,12 @@ impl tabled::Tabled for Position {
             } else {
                 self.r#type().to_string().red().to_string().into()
             },
-            self.entry_price().to_string().into(),
-            self.size().to_string().into(),
-            self.deposit().to_string().into(),
-            if self.delta_pnl.is_negative() {
-                self.delta_pnl.to_string().red().to_string().into()
-            } else {
-                self.delta_pnl.to_string().green().to_string().into()
-            },
-            if self.premium_pnl.is_negative() {
-                self.premium_pnl.to_string().red().to_string().into()
-            } else {
-                self.premium_pnl.to_string().green().to_string().into()
-            },
-            if self.pnl().is_negative() {
-                self.pnl().to_string().red().to_string().into()
-            } else {
-                self.pnl().to_string().green().to_string().into()
-            },
+            config.price(self.entry_price()).to_string().into(),
+            config.size(self.size()).to_string().into(),
+            config.amount(self.deposit()).to_string().into(),
+            num::fmt_signed(self.delta_pnl).into(),
+            num::fmt_signed(self.premium_pnl).into(),
+            num::fmt_signed(self.pnl()).into(),
             format!("{:.6}", self.liquidation_price()).into(),
             format!("{:.6}", self.bankruptcy_price()).into(),
         ]
@@ -342,6 +403,7 @@ impl tabled::Tabled for Position {
 
 #[cfg(test)]
 mod tests {
+    use alloy::primitives::I256;
     use fastnum::{dec256, udec64, udec128};
 
     use super::*;
@@ -349,51 +411,87 @@ mod tests {
 
     #[test]
     fn test_effective_entry_price() {
-        let pc = num::Converter::new(4);
+        let pc = num::PriceConverter::new(4);
         assert_eq!(
-            Position::effective_entry_price(PositionType::Long, U256::from(1), 0, pc),
+            Position::effective_entry_price(PositionType::Long, 1, 0, pc),
             udec64!(0.0001)
         );
         assert_eq!(
-            Position::effective_entry_price(PositionType::Long, U256::from(1), 1, pc),
+            Position::effective_entry_price(PositionType::Long, 1, 1, pc),
             udec64!(0.00000000152587890625) // 1 / 65536 * 10 ^ -4
         );
         assert_eq!(
-            Position::effective_entry_price(PositionType::Long, U256::from(10001), 8, pc),
+            Position::effective_entry_price(PositionType::Long, 10001, 8, pc),
             udec64!(1.00000001220703125) // 1 + (8 / 65536 * 10 ^ -4)
         );
         assert_eq!(
-            Position::effective_entry_price(PositionType::Long, U256::from(10000), 65535, pc),
+            Position::effective_entry_price(PositionType::Long, 10000, 65535, pc),
             udec64!(0.9999999984741210937) // 1 - (1 / 65536 * 10 ^ -4)
         );
 
         assert_eq!(
-            Position::effective_entry_price(PositionType::Short, U256::from(1), 0, pc),
+            Position::effective_entry_price(PositionType::Short, 1, 0, pc),
             udec64!(0.0001)
         );
         assert_eq!(
-            Position::effective_entry_price(PositionType::Short, U256::from(0), 1, pc),
+            Position::effective_entry_price(PositionType::Short, 0, 1, pc),
             udec64!(0.00000000152587890625) // 1 / 65536 * 10 ^ -4
         );
         assert_eq!(
-            Position::effective_entry_price(PositionType::Short, U256::from(10000), 8, pc),
+            Position::effective_entry_price(PositionType::Short, 10000, 8, pc),
             udec64!(1.00000001220703125) // 1 + (8 / 65536 * 10 ^ -4)
         );
         assert_eq!(
-            Position::effective_entry_price(PositionType::Short, U256::from(9999), 65535, pc),
+            Position::effective_entry_price(PositionType::Short, 9999, 65535, pc),
             udec64!(0.9999999984741210937) // 1 - (1 / 65536 * 10 ^ -4)
         );
     }
 
+    #[test]
+    fn test_pnl_is_consistent() {
+        let cc = num::CollateralConverter::new(6);
+        let (pc, sc) = (num::PriceConverter::new(6), num::SizeConverter::new(6));
+        let info = PositionInfoV2 {
+            accountId: U256::from(1),
+            nextNodeId: Default::default(),
+            prevNodeId: Default::default(),
+            positionType: 0,
+            depositCNS: U256::ZERO,
+            pricePNS: U256::ZERO,
+            lotLNS: U256::ZERO,
+            entryBlock: Default::default(),
+            pnlCNS: I256::try_from(2_000_000).unwrap(),
+            deltaPnlCNS: I256::try_from(3_000_000).unwrap(),
+            premiumPnlCNS: I256::try_from(-1_000_000).unwrap(),
+            priceResiduePNSQ16: U256::ZERO,
+        };
+        let pos = Position::new(StateInstant::default(), 1, &info, cc, pc, sc, UD64::ONE).unwrap();
+        let tolerance = dec256!(0.000001);
+
+        // Components sum to the total
+        assert!(pos.pnl_is_consistent(dec256!(2), tolerance));
+        assert!(pos.pnl_is_consistent(dec256!(2.000001), tolerance));
+
+        // Components do not sum to the total
+        assert!(!pos.pnl_is_consistent(dec256!(3), tolerance));
+        assert!(!pos.pnl_is_consistent(dec256!(-2), tolerance));
+        assert!(!pos.pnl_is_consistent(dec256!(2.000002), tolerance));
+
+        // Unknown position type
+        let info = PositionInfoV2 { positionType: 2, ..info };
+        let result = Position::new(StateInstant::default(), 1, &info, cc, pc, sc, UD64::ONE);
+        assert!(matches!(result, Err(DexError::MalformedData(_))));
+    }
+
     #[test]
     fn test_apply_mark_price() {
-        let pc = num::Converter::new(4);
+        let pc = num::PriceConverter::new(4);
         let mut pos = Position::opened(
             StateInstant::default(),
             1,
             1,
             PositionType::Long,
-            U256::from(100),
+            100,
             0,
             pc,
             udec64!(10),
@@ -401,10 +499,10 @@ mod tests {
             UD64::ONE,
         );
 
-        pos.apply_mark_price(StateInstant::default(), udec64!(0.015));
+        pos.apply_mark_price(StateInstant::default(), udec64!(0.015).into());
         assert_eq!(pos.delta_pnl(), dec256!(0.05));
 
-        pos.apply_mark_price(StateInstant::default(), udec64!(0.005));
+        pos.apply_mark_price(StateInstant::default(), udec64!(0.005).into());
         assert_eq!(pos.delta_pnl(), dec256!(-0.05));
 
         let mut pos = Position::opened(
@@ -412,23 +510,23 @@ mod tests {
             1,
             1,
             PositionType::Short,
-            U256::from(100),
+            100,
             0,
             pc,
             udec64!(10),
             UD128::ZERO,
             UD64::ONE,
         );
-        pos.apply_mark_price(StateInstant::default(), udec64!(0.015));
+        pos.apply_mark_price(StateInstant::default(), udec64!(0.015).into());
         assert_eq!(pos.delta_pnl(), dec256!(-0.05));
 
-        pos.apply_mark_price(StateInstant::default(), udec64!(0.005));
+        pos.apply_mark_price(StateInstant::default(), udec64!(0.005).into());
         assert_eq!(pos.delta_pnl(), dec256!(0.05));
     }
 
     #[test]
     fn test_apply_funding_payment() {
-        let pc = num::Converter::new(4);
+        let pc = num::PriceConverter::new(4);
         let (i0, i1, i2) =
             (StateInstant::default(), StateInstant::new(1, 1), StateInstant::new(2, 2));
         let mut pos = Position::opened(
@@ -436,7 +534,7 @@ mod tests {
             1,
             1,
             PositionType::Long,
-            U256::from(100),
+            100,
             0,
             pc,
             udec64!(10),
@@ -457,7 +555,7 @@ mod tests {
             1,
             1,
             PositionType::Short,
-            U256::from(100),
+            100,
             0,
             pc,
             udec64!(10),
@@ -484,9 +582,9 @@ mod tests {
             1,
             1,
             PositionType::Short,
-            U256::from(100),
+            100,
             0,
-            num::Converter::new(4),
+            num::PriceConverter::new(4),
             udec64!(10),
             UD128::ZERO,
             UD64::ONE,
@@ -533,6 +631,45 @@ mod tests {
         assert_eq!(pos.premium_pnl(), dec256!(100));
     }
 
+    #[test]
+    fn test_roe() {
+        let pc = num::PriceConverter::new(0);
+        let i0 = StateInstant::default();
+        let opened = |r#type, deposit| {
+            Position::opened(
+                i0,
+                1,
+                1,
+                r#type,
+                100,
+                0,
+                pc,
+                udec64!(2),
+                deposit,
+                UD64::ONE,
+            )
+        };
+
+        // Profitable long
+        let mut pos = opened(PositionType::Long, udec128!(50));
+        assert_eq!(pos.roe(), Some(D256::ZERO));
+        assert_eq!(pos.roe_at(udec64!(110).into()), Some(dec256!(40)));
+        pos.apply_mark_price(i0, udec64!(110).into());
+        assert_eq!(pos.roe(), Some(dec256!(40)));
+
+        // Losing short, including premium PnL
+        let mut pos = opened(PositionType::Short, udec128!(50));
+        assert_eq!(pos.roe_at(udec64!(110).into()), Some(dec256!(-40)));
+        pos.update_premium_pnl(i0, dec256!(-5));
+        assert_eq!(pos.roe_at(udec64!(110).into()), Some(dec256!(-50)));
+        assert_eq!(pos.roe(), Some(dec256!(-10)));
+
+        // Zero deposit
+        let pos = opened(PositionType::Long, UD128::ZERO);
+        assert_eq!(pos.roe(), None);
+        assert_eq!(pos.roe_at(udec64!(110).into()), None);
+    }
+
     #[test]
     fn test_funding_block_single_decrease_matches_sc() {
         let (i1, i2) = (StateInstant::new(1, 1), StateInstant::new(2, 2));
@@ -574,7 +711,7 @@ mod tests {
 
     #[test]
     fn test_maintenance_margin_requirement() {
-        let pc = num::Converter::new(4);
+        let pc = num::PriceConverter::new(4);
         let i0 = StateInstant::default();
         let (mm1, mm2) = (udec64!(20), udec64!(10));
 
@@ -583,55 +720,55 @@ mod tests {
             1,
             1,
             PositionType::Long,
-            U256::from(1000000),
+            1000000,
             0,
             pc,
             udec64!(10),
             udec128!(100),
             mm1,
         );
-        assert_eq!(pos.maintenance_margin_requirement(), udec128!(50));
+        assert_eq!(pos.maintenance_margin_requirement(), udec128!(50).into());
 
-        pos.update_entry_price(i0, U256::from(800000), 0, pc);
+        pos.update_entry_price(i0, 800000, 0, pc);
         pos.apply_maintenance_margin(i0, mm1);
-        assert_eq!(pos.maintenance_margin_requirement(), udec128!(40));
+        assert_eq!(pos.maintenance_margin_requirement(), udec128!(40).into());
 
         pos.update_size(i0, udec64!(20));
         pos.apply_maintenance_margin(i0, mm1);
-        assert_eq!(pos.maintenance_margin_requirement(), udec128!(80));
+        assert_eq!(pos.maintenance_margin_requirement(), udec128!(80).into());
 
         pos.apply_maintenance_margin(i0, mm2);
-        assert_eq!(pos.maintenance_margin_requirement(), udec128!(160));
+        assert_eq!(pos.maintenance_margin_requirement(), udec128!(160).into());
 
         let mut pos = Position::opened(
             i0,
             1,
             1,
             PositionType::Short,
-            U256::from(1000000),
+            1000000,
             0,
             pc,
             udec64!(10),
             udec128!(100),
             mm1,
         );
-        assert_eq!(pos.maintenance_margin_requirement(), udec128!(50));
+        assert_eq!(pos.maintenance_margin_requirement(), udec128!(50).into());
 
-        pos.update_entry_price(i0, U256::from(800000), 0, pc);
+        pos.update_entry_price(i0, 800000, 0, pc);
         pos.apply_maintenance_margin(i0, mm1);
-        assert_eq!(pos.maintenance_margin_requirement(), udec128!(40));
+        assert_eq!(pos.maintenance_margin_requirement(), udec128!(40).into());
 
         pos.update_size(i0, udec64!(20));
         pos.apply_maintenance_margin(i0, mm1);
-        assert_eq!(pos.maintenance_margin_requirement(), udec128!(80));
+        assert_eq!(pos.maintenance_margin_requirement(), udec128!(80).into());
 
         pos.apply_maintenance_margin(i0, mm2);
-        assert_eq!(pos.maintenance_margin_requirement(), udec128!(160));
+        assert_eq!(pos.maintenance_margin_requirement(), udec128!(160).into());
     }
 
     #[test]
     fn test_liquidation_price() {
-        let pc = num::Converter::new(4);
+        let pc = num::PriceConverter::new(4);
         let (i0, i1) = (StateInstant::default(), StateInstant::new(1, 1));
         let mm1 = udec64!(20);
 
@@ -640,39 +777,39 @@ mod tests {
             1,
             1,
             PositionType::Long,
-            U256::from(1000000),
+            1000000,
             0,
             pc,
             udec64!(10),
             udec128!(100),
             mm1,
         );
-        assert_eq!(pos.liquidation_price(), udec64!(95));
+        assert_eq!(pos.liquidation_price(), udec64!(95).into());
 
         assert!(pos.apply_funding_payment(i1, dec256!(5)));
-        assert_eq!(pos.liquidation_price(), udec64!(100));
+        assert_eq!(pos.liquidation_price(), udec64!(100).into());
 
         let mut pos = Position::opened(
             i0,
             1,
             1,
             PositionType::Short,
-            U256::from(1000000),
+            1000000,
             0,
             pc,
             udec64!(10),
             udec128!(100),
             mm1,
         );
-        assert_eq!(pos.liquidation_price(), udec64!(105));
+        assert_eq!(pos.liquidation_price(), udec64!(105).into());
 
         assert!(pos.apply_funding_payment(i1, dec256!(-5)));
-        assert_eq!(pos.liquidation_price(), udec64!(100));
+        assert_eq!(pos.liquidation_price(), udec64!(100).into());
     }
 
     #[test]
     fn test_bankruptcy_price() {
-        let pc = num::Converter::new(4);
+        let pc = num::PriceConverter::new(4);
         let (i0, i1) = (StateInstant::default(), StateInstant::new(1, 1));
         let mm1 = udec64!(20);
 
@@ -681,34 +818,34 @@ mod tests {
             1,
             1,
             PositionType::Long,
-            U256::from(1000000),
+            1000000,
             0,
             pc,
             udec64!(10),
             udec128!(100),
             mm1,
         );
-        assert_eq!(pos.bankruptcy_price(), udec64!(90));
+        assert_eq!(pos.bankruptcy_price(), udec64!(90).into());
 
         assert!(pos.apply_funding_payment(i1, dec256!(5)));
-        assert_eq!(pos.bankruptcy_price(), udec64!(95));
+        assert_eq!(pos.bankruptcy_price(), udec64!(95).into());
 
         let mut pos = Position::opened(
             i0,
             1,
             1,
             PositionType::Short,
-            U256::from(1000000),
+            1000000,
             0,
             pc,
             udec64!(10),
             udec128!(100),
             mm1,
         );
-        assert_eq!(pos.bankruptcy_price(), udec64!(110));
+        assert_eq!(pos.bankruptcy_price(), udec64!(110).into());
 
         assert!(pos.apply_funding_payment(i1, dec256!(-5)));
-        assert_eq!(pos.bankruptcy_price(), udec64!(105));
+        assert_eq!(pos.bankruptcy_price(), udec64!(105).into());
     }
 
     #[test]
@@ -717,71 +854,71 @@ mod tests {
         // Here the position RECEIVES funding, driving premium to +50 and moving the liquidation
         // price AWAY from entry — long 95 -> 90, short 105 -> 110. Setup mirrors
         // test_liquidation_price (entry 100, size 10, deposit 100, mm 20 -> MMR 50).
-        let pc = num::Converter::new(4);
+        let pc = num::PriceConverter::new(4);
         let (i0, i1) = (StateInstant::default(), StateInstant::new(1, 1));
         let mm1 = udec64!(20);
 
         // Long receives funding when longs are paid (negative payment).
         let mut pos = Position::opened(
-            i0, 1, 1, PositionType::Long, U256::from(1000000), 0, pc, udec64!(10), udec128!(100), mm1,
+            i0, 1, 1, PositionType::Long, 1000000, 0, pc, udec64!(10), udec128!(100), mm1,
         );
-        assert_eq!(pos.liquidation_price(), udec64!(95)); // 100 + (50-100-0)/10
+        assert_eq!(pos.liquidation_price(), udec64!(95).into()); // 100 + (50-100-0)/10
         assert!(pos.apply_funding_payment(i1, dec256!(-5)), "long receives funding");
         assert_eq!(pos.premium_pnl(), dec256!(50)); // long sign -1: += -1*(-5)*10 = +50
-        assert_eq!(pos.liquidation_price(), udec64!(90)); // 100 + (50-100-50)/10
+        assert_eq!(pos.liquidation_price(), udec64!(90).into()); // 100 + (50-100-50)/10
 
         // Short receives funding when shorts are paid (positive payment).
         let mut pos = Position::opened(
-            i0, 1, 1, PositionType::Short, U256::from(1000000), 0, pc, udec64!(10), udec128!(100), mm1,
+            i0, 1, 1, PositionType::Short, 1000000, 0, pc, udec64!(10), udec128!(100), mm1,
         );
-        assert_eq!(pos.liquidation_price(), udec64!(105)); // 100 - (50-100-0)/10
+        assert_eq!(pos.liquidation_price(), udec64!(105).into()); // 100 - (50-100-0)/10
         assert!(pos.apply_funding_payment(i1, dec256!(5)), "short receives funding");
         assert_eq!(pos.premium_pnl(), dec256!(50)); // short sign +1: += 1*5*10 = +50
-        assert_eq!(pos.liquidation_price(), udec64!(110)); // 100 - (50-100-50)/10
+        assert_eq!(pos.liquidation_price(), udec64!(110).into()); // 100 - (50-100-50)/10
     }
 
     #[test]
     fn test_bankruptcy_price_after_funding_received() {
         // Complements test_bankruptcy_price (premium negative only). The position RECEIVES funding
         // (+50): long bank 90 -> 85, short bank 110 -> 115. Same setup as test_bankruptcy_price.
-        let pc = num::Converter::new(4);
+        let pc = num::PriceConverter::new(4);
         let (i0, i1) = (StateInstant::default(), StateInstant::new(1, 1));
         let mm1 = udec64!(20);
 
         let mut pos = Position::opened(
-            i0, 1, 1, PositionType::Long, U256::from(1000000), 0, pc, udec64!(10), udec128!(100), mm1,
+            i0, 1, 1, PositionType::Long, 1000000, 0, pc, udec64!(10), udec128!(100), mm1,
         );
-        assert_eq!(pos.bankruptcy_price(), udec64!(90)); // 100 - (100+0)/10
+        assert_eq!(pos.bankruptcy_price(), udec64!(90).into()); // 100 - (100+0)/10
         assert!(pos.apply_funding_payment(i1, dec256!(-5)), "long receives funding"); // premium +50
-        assert_eq!(pos.bankruptcy_price(), udec64!(85)); // 100 - (100+50)/10
+        assert_eq!(pos.bankruptcy_price(), udec64!(85).into()); // 100 - (100+50)/10
 
         let mut pos = Position::opened(
-            i0, 1, 1, PositionType::Short, U256::from(1000000), 0, pc, udec64!(10), udec128!(100), mm1,
+            i0, 1, 1, PositionType::Short, 1000000, 0, pc, udec64!(10), udec128!(100), mm1,
         );
-        assert_eq!(pos.bankruptcy_price(), udec64!(110)); // 100 + (100+0)/10
+        assert_eq!(pos.bankruptcy_price(), udec64!(110).into()); // 100 + (100+0)/10
         assert!(pos.apply_funding_payment(i1, dec256!(5)), "short receives funding"); // premium +50
-        assert_eq!(pos.bankruptcy_price(), udec64!(115)); // 100 + (100+50)/10
+        assert_eq!(pos.bankruptcy_price(), udec64!(115).into()); // 100 + (100+50)/10
     }
 
     #[test]
     fn test_apply_mark_price_with_residue() {
         let i0 = StateInstant::default();
         let half_lsb = dec256!(0.0000005);
-        let pc = num::Converter::new(6);
+        let pc = num::PriceConverter::new(6);
 
         let mut pos = Position::opened(
             i0,
             1,
             1,
             PositionType::Long,
-            U256::from(100000000),
+            100000000,
             32768, // residue = Q/2
             pc,
             udec64!(10),
             UD128::ZERO,
             UD64::ONE,
         );
-        pos.apply_mark_price(i0, udec64!(100));
+        pos.apply_mark_price(i0, udec64!(100).into());
         // mark - effective = 100 - (100 - 0.5e-6) = +0.5e-6, * size 10 = 0.5e-5
         assert_eq!(pos.delta_pnl(), half_lsb * dec256!(10));
 
@@ -790,14 +927,14 @@ mod tests {
             1,
             1,
             PositionType::Short,
-            U256::from(100000000),
+            100000000,
             32768,
             pc,
             udec64!(10),
             UD128::ZERO,
             UD64::ONE,
         );
-        pos.apply_mark_price(i0, udec64!(100));
+        pos.apply_mark_price(i0, udec64!(100).into());
         // short: sign flip, effective = entry + 0.5e-6, (mark - effective) = -0.5e-6
         // delta_pnl = -1 * -0.5e-6 * 10 = 0.5e-5
         assert_eq!(pos.delta_pnl(), half_lsb * dec256!(10));
@@ -808,14 +945,42 @@ mod tests {
             1,
             1,
             PositionType::Long,
-            U256::from(100000000),
+            100000000,
             0,
             pc,
             udec64!(10),
             UD128::ZERO,
             UD64::ONE,
         );
-        pos.apply_mark_price(i0, udec64!(150));
+        pos.apply_mark_price(i0, udec64!(150).into());
         assert_eq!(pos.delta_pnl(), dec256!(500));
     }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_position_eq() {
+        let pc = num::PriceConverter::new(4);
+        let open = || {
+            Position::opened(
+                StateInstant::new(1, 1),
+                1,
+                1,
+                PositionType::Long,
+                100,
+                0,
+                pc,
+                udec64!(10),
+                UD128::ZERO,
+                UD64::ONE,
+            )
+        };
+        let (mut pos1, mut pos2) = (open(), open());
+        assert_eq!(pos1, pos2);
+
+        pos1.apply_mark_price(StateInstant::new(2, 2), udec64!(0.015).into());
+        assert_ne!(pos1, pos2);
+
+        pos2.apply_mark_price(StateInstant::new(2, 2), udec64!(0.015).into());
+        assert_eq!(pos1, pos2);
+    }
 }
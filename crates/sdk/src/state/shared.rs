@@ -0,0 +1,88 @@
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock, RwLockReadGuard},
+};
+
+use super::*;
+use crate::stream;
+
+// All public state types can be shared with and sent across tasks.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Exchange>();
+    assert_send_sync::<SharedExchange>();
+    assert_send_sync::<FrozenExchange>();
+    assert_send_sync::<Account>();
+    assert_send_sync::<Perpetual>();
+    assert_send_sync::<Position>();
+    assert_send_sync::<Order>();
+    assert_send_sync::<OrderBook>();
+    assert_send_sync::<BookOrder>();
+    assert_send_sync::<StateEvents>();
+    assert_send_sync::<StateBlockEvents>();
+};
+
+/// [`Exchange`] snapshot shared between tasks.
+///
+/// Cheaply clonable handle coordinating any number of readers with a single
+/// writer keeping the snapshot up to date via [`Self::apply_events`].
+/// Readers observe the snapshot either before or after a block of events
+/// is applied, never in between.
+#[derive(Clone, Debug)]
+pub struct SharedExchange(Arc<RwLock<Arc<Exchange>>>);
+
+impl SharedExchange {
+    /// Wraps the snapshot to share it between tasks.
+    pub fn new(exchange: Exchange) -> Self { Self(Arc::new(RwLock::new(Arc::new(exchange)))) }
+
+    /// Read access to the current snapshot.
+    ///
+    /// The guard blocks [`Self::apply_events`] while held, so it should not be
+    /// kept across `.await` points or for prolonged periods, see
+    /// [`Self::freeze`] instead.
+    pub fn read(&self) -> RwLockReadGuard<'_, Arc<Exchange>> {
+        self.0.read().expect("exchange lock poisoned")
+    }
+
+    /// Immutable point-in-time view of the current snapshot.
+    ///
+    /// Taking the view is cheap and does not block the writer, which copies
+    /// the snapshot on the next [`Self::apply_events`] only while any view
+    /// taken before is still alive.
+    pub fn freeze(&self) -> FrozenExchange { FrozenExchange(self.read().clone()) }
+
+    /// Runs `f` against the current snapshot.
+    pub fn with<R>(&self, f: impl FnOnce(&Exchange) -> R) -> R { f(&self.read()) }
+
+    /// Instant the snapshot is consistent with or was last updated at.
+    pub fn instant(&self) -> types::StateInstant { self.read().instant() }
+
+    /// Copy of the current snapshot.
+    pub fn snapshot(&self) -> Exchange { Exchange::clone(&self.read()) }
+
+    /// Applies events to the shared snapshot, see [`Exchange::apply_events`].
+    pub fn apply_events(
+        &self,
+        events: &stream::RawBlockEvents,
+    ) -> Result<Option<StateBlockEvents>, DexError> {
+        let mut exchange = self.0.write().expect("exchange lock poisoned");
+        Arc::make_mut(&mut exchange).apply_events(events)
+    }
+}
+
+impl From<Exchange> for SharedExchange {
+    fn from(exchange: Exchange) -> Self { Self::new(exchange) }
+}
+
+/// Immutable point-in-time view of [`Exchange`] snapshot, see
+/// [`SharedExchange::freeze`] and [`Exchange::freeze`].
+///
+/// Cheaply clonable and safe to hold across `.await` points.
+#[derive(Clone, Debug)]
+pub struct FrozenExchange(pub(super) Arc<Exchange>);
+
+impl Deref for FrozenExchange {
+    type Target = Exchange;
+
+    fn deref(&self) -> &Self::Target { &self.0 }
+}
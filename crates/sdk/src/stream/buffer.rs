@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use super::{RawBlockEvents, RawEvent};
+use crate::{error::DexError, types};
+
+/// Default maximum number of blocks [`BlockBuffer`] keeps in flight.
+pub const DEFAULT_MAX_BUFFERED_BLOCKS: usize = 16;
+
+/// Bounded buffer assembling raw events arriving out of order, e.g. from
+/// WebSocket log subscriptions, into complete blocks to be applied with
+/// [`crate::state::Exchange::apply_events`].
+///
+/// Events are grouped by [`types::EventContext::block_number`], so they
+/// should carry it. The number of incomplete blocks is capped to protect
+/// long-running subscribers from unbounded memory growth if a block is never
+/// completed, e.g. due to a missed block boundary.
+#[derive(Clone, Debug)]
+pub struct BlockBuffer {
+    max_blocks: usize,
+    blocks: BTreeMap<u64, Vec<RawEvent>>,
+}
+
+impl BlockBuffer {
+    /// Creates a buffer keeping up to `max_blocks` incomplete blocks.
+    pub fn new(max_blocks: usize) -> Self { Self { max_blocks, blocks: BTreeMap::new() } }
+
+    /// Maximum number of incomplete blocks kept in the buffer.
+    pub fn max_blocks(&self) -> usize { self.max_blocks }
+
+    /// Number of incomplete blocks currently in the buffer.
+    pub fn num_blocks(&self) -> usize { self.blocks.len() }
+
+    /// Earliest incomplete block in the buffer, if any.
+    ///
+    /// Subscribers can fall back to polling from this block with
+    /// [`super::raw`] once the buffer overflows.
+    pub fn first_block(&self) -> Option<u64> { self.blocks.keys().next().copied() }
+
+    /// Buffers the event until its block is completed with
+    /// [`Self::complete`].
+    ///
+    /// Fails with [`DexError::BufferOverflow`] if the event starts a new
+    /// block while [`Self::max_blocks`] blocks are already in flight, leaving
+    /// the buffer unchanged.
+    pub fn push(&mut self, event: RawEvent) -> Result<(), DexError> {
+        if !self.blocks.contains_key(&event.block_number()) && self.blocks.len() >= self.max_blocks
+        {
+            return Err(DexError::BufferOverflow(self.max_blocks));
+        }
+        self.blocks.entry(event.block_number()).or_default().push(event);
+        Ok(())
+    }
+
+    /// Takes events of the completed block at `instant` out of the buffer,
+    /// ordered by log index.
+    pub fn complete(&mut self, instant: types::StateInstant) -> RawBlockEvents {
+        let mut events = self.blocks.remove(&instant.block_number()).unwrap_or_default();
+        events.sort_by_key(|e| e.log_index());
+        RawBlockEvents::new(instant, events)
+    }
+}
+
+impl Default for BlockBuffer {
+    fn default() -> Self { Self::new(DEFAULT_MAX_BUFFERED_BLOCKS) }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{Address, TxHash, U256};
+
+    use super::*;
+    use crate::abi::dex::Exchange::{AccountCreated, ExchangeEvents};
+
+    fn event(block_number: u64, log_index: u64) -> RawEvent {
+        let event = ExchangeEvents::AccountCreated(AccountCreated {
+            account: Address::ZERO,
+            id: U256::from(log_index),
+        });
+        RawEvent::new(TxHash::ZERO, 0, log_index, event.into()).with_block(block_number)
+    }
+
+    #[test]
+    fn test_block_buffer_overflow() {
+        let mut buffer = BlockBuffer::new(2);
+        buffer.push(event(10, 1)).unwrap();
+        buffer.push(event(11, 0)).unwrap();
+        buffer.push(event(10, 0)).unwrap();
+        assert_eq!(buffer.num_blocks(), 2);
+
+        // Third block in flight exceeds the buffer
+        assert!(matches!(buffer.push(event(12, 0)), Err(DexError::BufferOverflow(2))));
+        assert_eq!(buffer.num_blocks(), 2);
+        assert_eq!(buffer.first_block(), Some(10));
+
+        // Completed block frees up the buffer
+        let block = buffer.complete(types::StateInstant::new(10, 100));
+        assert_eq!(block.events().iter().map(|e| e.log_index()).collect::<Vec<_>>(), [0, 1]);
+        buffer.push(event(12, 0)).unwrap();
+        assert_eq!(buffer.first_block(), Some(11));
+    }
+}
@@ -0,0 +1,283 @@
+use std::{collections::HashMap, time::Duration};
+
+use fastnum::{UD64, UD128};
+
+use super::BlockTrades;
+use crate::types;
+
+/// Interval of the [`Candle`]s aggregated by [`CandleBuilder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CandleInterval {
+    /// Number of blocks, aligned to block numbers divisible by it.
+    Blocks(u64),
+
+    /// Period of time according to block timestamps, aligned to timestamps
+    /// divisible by it, rounded to whole seconds.
+    Time(Duration),
+}
+
+impl CandleInterval {
+    /// Length of the interval in blocks or seconds.
+    fn len(&self) -> u64 {
+        match self {
+            CandleInterval::Blocks(blocks) => (*blocks).max(1),
+            CandleInterval::Time(period) => period.as_secs().max(1),
+        }
+    }
+
+    /// Start of the interval the `instant` falls into.
+    fn start(&self, instant: types::StateInstant) -> u64 {
+        let point = match self {
+            CandleInterval::Blocks(_) => instant.block_number(),
+            CandleInterval::Time(_) => instant.block_timestamp(),
+        };
+        point - point % self.len()
+    }
+}
+
+/// OHLCV candle of a single perpetual contract.
+#[derive(Clone, Copy, derive_more::Debug, PartialEq, Eq)]
+pub struct Candle {
+    /// Perpetual contract ID.
+    pub perpetual_id: types::PerpetualId,
+
+    /// Start of the interval, block number or timestamp in seconds depending
+    /// on the [`CandleInterval`].
+    pub start: u64,
+
+    /// Price of the first fill, or the previous close if there were no
+    /// trades.
+    #[debug("{open}")]
+    pub open: UD64,
+
+    /// Highest fill price.
+    #[debug("{high}")]
+    pub high: UD64,
+
+    /// Lowest fill price.
+    #[debug("{low}")]
+    pub low: UD64,
+
+    /// Price of the last fill.
+    #[debug("{close}")]
+    pub close: UD64,
+
+    /// Total traded size.
+    #[debug("{volume}")]
+    pub volume: UD64,
+
+    /// Total traded amount in collateral token.
+    #[debug("{notional_volume}")]
+    pub notional_volume: UD128,
+
+    /// Number of trades, each taker order execution counted once.
+    pub trade_count: u64,
+}
+
+impl Candle {
+    /// Candle of an interval without trades, carrying the previous close
+    /// forward.
+    fn flat(perpetual_id: types::PerpetualId, start: u64, close: UD64) -> Self {
+        Self {
+            perpetual_id,
+            start,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: UD64::ZERO,
+            notional_volume: UD128::ZERO,
+            trade_count: 0,
+        }
+    }
+}
+
+/// Aggregator of [`BlockTrades`] from [`super::trade`] into per perpetual
+/// contract OHLCV [`Candle`]s of the fixed interval.
+///
+/// Pure in-memory aggregation, blocks are expected to be fed in order.
+/// Candles are emitted once the interval is completed, i.e. a block of a
+/// later interval is fed, for every perpetual contract traded so far.
+/// Intervals without trades produce flat candles at the previous close, at
+/// most [`Self::MAX_FLAT_INTERVALS`] of them per gap between the fed blocks:
+/// only the intervals right before the block are emitted for longer gaps,
+/// e.g. after a pause of the stream, leaving the earlier ones out.
+#[derive(Clone, Debug)]
+pub struct CandleBuilder {
+    interval: CandleInterval,
+    /// Start of the current interval, `None` until the first block.
+    start: Option<u64>,
+    /// Candles of the current interval with trades.
+    current: HashMap<types::PerpetualId, Candle>,
+    /// Most recent fill prices.
+    last_close: HashMap<types::PerpetualId, UD64>,
+}
+
+impl CandleBuilder {
+    /// Max number of intervals without trades emitted as flat candles per gap
+    /// between the fed blocks.
+    pub const MAX_FLAT_INTERVALS: u64 = 1_000;
+
+    /// Creates an empty builder aggregating candles of the `interval`.
+    pub fn new(interval: CandleInterval) -> Self {
+        Self { interval, start: None, current: HashMap::new(), last_close: HashMap::new() }
+    }
+
+    /// Interval the candles are aggregated over.
+    pub fn interval(&self) -> CandleInterval { self.interval }
+
+    /// Candle of the current, not yet completed, interval, `None` if there
+    /// were no trades of the perpetual contract within it.
+    pub fn current(&self, perpetual_id: types::PerpetualId) -> Option<&Candle> {
+        self.current.get(&perpetual_id)
+    }
+
+    /// Adds trades of the block, returning candles of the intervals completed
+    /// by it, ordered by interval start and perpetual contract ID.
+    pub fn update(&mut self, block_trades: &BlockTrades) -> Vec<Candle> {
+        let start = self.interval.start(block_trades.instant());
+        let mut completed = vec![];
+        match self.start {
+            Some(current) if current < start => {
+                completed.extend(self.complete(current));
+                let len = self.interval.len();
+                let mut interval_start = (current + len)
+                    .max(start.saturating_sub(Self::MAX_FLAT_INTERVALS.saturating_mul(len)));
+                while interval_start < start {
+                    completed.extend(self.complete(interval_start));
+                    interval_start += self.interval.len();
+                }
+                self.start = Some(start);
+            },
+            Some(_) => {},
+            None => self.start = Some(start),
+        }
+        let start = self.start.unwrap_or(start);
+
+        for event in block_trades.events() {
+            let trade = event.event();
+            for fill in &trade.maker_fills {
+                let notional: UD128 = fill.price.resize() * fill.size.resize();
+                let candle = self
+                    .current
+                    .entry(trade.perpetual_id)
+                    .or_insert_with(|| Candle::flat(trade.perpetual_id, start, fill.price));
+                candle.high = candle.high.max(fill.price);
+                candle.low = candle.low.min(fill.price);
+                candle.close = fill.price;
+                candle.volume += fill.size;
+                candle.notional_volume += notional;
+                self.last_close.insert(trade.perpetual_id, fill.price);
+            }
+            if let Some(candle) = self.current.get_mut(&trade.perpetual_id)
+                && !trade.maker_fills.is_empty()
+            {
+                candle.trade_count += 1;
+            }
+        }
+        completed
+    }
+
+    /// Completes the interval starting at `start`, returning candles of all
+    /// the perpetual contracts traded so far.
+    fn complete(&mut self, start: u64) -> Vec<Candle> {
+        let mut candles: Vec<_> = self
+            .last_close
+            .iter()
+            .map(|(perpetual_id, close)| {
+                self.current
+                    .remove(perpetual_id)
+                    .unwrap_or_else(|| Candle::flat(*perpetual_id, start, *close))
+            })
+            .collect();
+        candles.sort_by_key(|candle| candle.perpetual_id);
+        candles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fastnum::{udec64, udec128};
+
+    use super::*;
+    use crate::stream::tests::{block, trade};
+
+    const PERP_ID: types::PerpetualId = 1;
+
+    #[test]
+    fn test_block_candles() {
+        let mut builder = CandleBuilder::new(CandleInterval::Blocks(10));
+        use types::OrderSide::{Ask, Bid};
+
+        let bid = trade(PERP_ID, Bid, &[(udec64!(100), udec64!(1)), (udec64!(101), udec64!(2))]);
+        assert!(builder.update(&block(1, 100, vec![bid])).is_empty());
+        let ask = trade(PERP_ID, Ask, &[(udec64!(98), udec64!(0.5))]);
+        assert!(builder.update(&block(9, 108, vec![ask])).is_empty());
+        assert_eq!(builder.current(PERP_ID).unwrap().trade_count, 2);
+
+        let fills = [(udec64!(99), udec64!(1))];
+        let completed = builder.update(&block(12, 111, vec![trade(PERP_ID, Bid, &fills)]));
+        let expected = Candle {
+            perpetual_id: PERP_ID,
+            start: 0,
+            open: udec64!(100),
+            high: udec64!(101),
+            low: udec64!(98),
+            close: udec64!(98),
+            volume: udec64!(3.5),
+            notional_volume: udec128!(351),
+            trade_count: 2,
+        };
+        assert_eq!(completed, vec![expected]);
+
+        // Interval 20 without trades carries the close of interval 10 forward
+        let completed = builder.update(&block(35, 134, vec![]));
+        assert_eq!(completed.len(), 2);
+        assert_eq!(completed[0].start, 10);
+        assert_eq!((completed[0].open, completed[0].close), (udec64!(99), udec64!(99)));
+        assert_eq!(completed[0].volume, udec64!(1));
+        assert_eq!(completed[1], Candle::flat(PERP_ID, 20, udec64!(99)));
+        assert_eq!(builder.current(PERP_ID), None);
+    }
+
+    #[test]
+    fn test_time_candles() {
+        let mut builder = CandleBuilder::new(CandleInterval::Time(Duration::from_secs(60)));
+        let bid = |perpetual_id, price| {
+            trade(perpetual_id, types::OrderSide::Bid, &[(price, udec64!(1))])
+        };
+
+        builder.update(&block(1, 1_000, vec![bid(PERP_ID, udec64!(100))]));
+        builder.update(&block(2, 1_010, vec![bid(PERP_ID + 1, udec64!(10))]));
+        builder.update(&block(3, 1_015, vec![bid(PERP_ID, udec64!(105))]));
+
+        let completed = builder.update(&block(4, 1_030, vec![bid(PERP_ID + 1, udec64!(11))]));
+        assert_eq!(completed.len(), 2);
+        assert_eq!(completed[0].start, 960);
+        assert_eq!(completed[0].perpetual_id, PERP_ID);
+        assert_eq!((completed[0].open, completed[0].close), (udec64!(100), udec64!(105)));
+        assert_eq!(completed[0].trade_count, 2);
+        assert_eq!(completed[1].perpetual_id, PERP_ID + 1);
+        assert_eq!(completed[1].close, udec64!(10));
+
+        // Perpetual contract without trades in the interval gets a flat candle
+        let completed = builder.update(&block(5, 1_080, vec![]));
+        assert_eq!(completed[0], Candle::flat(PERP_ID, 1_020, udec64!(105)));
+        assert_eq!(completed[1].open, udec64!(11));
+        assert_eq!(completed[1].trade_count, 1);
+    }
+
+    #[test]
+    fn test_candles_gap() {
+        let mut builder = CandleBuilder::new(CandleInterval::Blocks(1));
+        let fills = [(udec64!(100), udec64!(1))];
+        builder.update(&block(1, 100, vec![trade(PERP_ID, types::OrderSide::Bid, &fills)]));
+
+        // Only the intervals right before the block are filled in
+        let completed = builder.update(&block(1_000_000, 2_000_000, vec![]));
+        assert_eq!(completed.len() as u64, 1 + CandleBuilder::MAX_FLAT_INTERVALS);
+        assert_eq!((completed[0].start, completed[0].trade_count), (1, 1));
+        assert_eq!(completed[1], Candle::flat(PERP_ID, 999_000, udec64!(100)));
+        assert_eq!(completed.last().unwrap().start, 999_999);
+    }
+}
@@ -0,0 +1,121 @@
+use colored::Colorize;
+
+use super::RawEvent;
+use crate::abi::dex::Exchange::ExchangeEvents;
+
+/// Renders the raw exchange event along with its log index, for traces of
+/// blocks and transactions.
+///
+/// Order requests and order batch completions are rendered at the top
+/// level, other events are nested under the preceding order request if
+/// `order_request_scope` is set, see [`order_request_scope`].
+pub fn format_event(event: &RawEvent, order_request_scope: bool) -> String {
+    match event.event().known() {
+        Some(ExchangeEvents::OrderRequest(_) | ExchangeEvents::OrderBatchCompleted(_)) => {
+            format!("  {}: {:?}", event.log_index(), event.event())
+                .cyan()
+                .to_string()
+        },
+        _ => {
+            let nesting = if order_request_scope { "   ↳ " } else { "" };
+            format!("  {nesting}{}: {:?}", event.log_index(), event.event())
+                .bright_cyan()
+                .to_string()
+        },
+    }
+}
+
+/// Order request scope for the events following the `event`, given the
+/// `scope` of the event itself.
+///
+/// The scope is opened by an order request and closed by completion of the
+/// order batch, and should be reset at transaction boundaries.
+pub fn order_request_scope(event: &RawEvent, scope: bool) -> bool {
+    match event.event().known() {
+        Some(ExchangeEvents::OrderRequest(_)) => true,
+        Some(ExchangeEvents::OrderBatchCompleted(_)) => false,
+        _ => scope,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{Address, B256, Bytes, TxHash, U256};
+
+    use super::*;
+    use crate::{
+        abi::dex::Exchange::{AccountCreated, OrderBatchCompleted, OrderRequest},
+        num,
+        stream::RawExchangeEvent,
+    };
+
+    fn raw_event(log_index: u64, event: RawExchangeEvent) -> RawEvent {
+        RawEvent::new(TxHash::ZERO, 0, log_index, event)
+    }
+
+    #[test]
+    fn test_format_event() {
+        num::set_colors(false);
+        let request = raw_event(
+            0,
+            ExchangeEvents::OrderRequest(OrderRequest {
+                perpId: U256::from(16),
+                accountId: U256::from(1),
+                orderDescId: U256::from(1),
+                orderId: U256::ZERO,
+                orderType: 0,
+                pricePNS: U256::from(1000),
+                lotLNS: U256::from(1),
+                expiryBlock: U256::ZERO,
+                postOnly: false,
+                fillOrKill: false,
+                immediateOrCancel: false,
+                maxMatches: U256::ZERO,
+                leverageHdths: U256::from(1000),
+                lastExecutionBlock: U256::ZERO,
+                amountCNS: U256::ZERO,
+                maxNegPnlCollatBPS: U256::ZERO,
+                gasLeft: U256::ZERO,
+            })
+            .into(),
+        );
+        let nested = raw_event(
+            1,
+            ExchangeEvents::AccountCreated(AccountCreated {
+                account: Address::ZERO,
+                id: U256::from(1),
+            })
+            .into(),
+        );
+        let completed = raw_event(
+            2,
+            ExchangeEvents::OrderBatchCompleted(OrderBatchCompleted { gasLeft: U256::from(7) })
+                .into(),
+        );
+        let unknown =
+            raw_event(3, RawExchangeEvent::Unknown { topic0: B256::ZERO, data: Bytes::new() });
+
+        // Order request opens the scope, not being nested itself
+        assert!(format_event(&request, true).starts_with("  0: OrderRequest(OrderRequest {"));
+        assert!(order_request_scope(&request, false));
+
+        let account_created = format!("1: {:?}", nested.event());
+        assert_eq!(format_event(&nested, true), format!("     ↳ {account_created}"));
+        assert_eq!(format_event(&nested, false), format!("  {account_created}"));
+        assert!(order_request_scope(&nested, true));
+        assert!(!order_request_scope(&nested, false));
+
+        // Batch completion closes the scope
+        assert_eq!(
+            format_event(&completed, true),
+            "  2: OrderBatchCompleted(OrderBatchCompleted { gasLeft: 7 })"
+        );
+        assert!(!order_request_scope(&completed, true));
+
+        assert_eq!(
+            format_event(&unknown, true),
+            format!("     ↳ 3: Unknown {{ topic0: {:?}, data: 0x }}", B256::ZERO)
+        );
+        assert!(order_request_scope(&unknown, true));
+    }
+}
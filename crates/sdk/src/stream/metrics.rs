@@ -0,0 +1,139 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use futures::{Stream, StreamExt};
+
+use super::RawBlockEvents;
+use crate::error::DexError;
+
+/// Per block event counts of the [`super::raw`] stream, e.g. to size
+/// downstream buffers.
+///
+/// Clones share the counters, so metrics can be read while the stream is
+/// consumed elsewhere, see [`metered`]. Counters are updated independently,
+/// so concurrent reads may observe a partially recorded block.
+#[derive(Clone, Debug, Default)]
+pub struct StreamMetrics(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    blocks: AtomicU64,
+    empty_blocks: AtomicU64,
+    events: AtomicU64,
+    max_block_events: AtomicU64,
+}
+
+impl StreamMetrics {
+    /// Creates metrics with no blocks recorded.
+    pub fn new() -> Self { Self::default() }
+
+    /// Records a block with `events` decoded events.
+    pub fn record_block(&self, events: usize) {
+        let events = events as u64;
+        self.0.blocks.fetch_add(1, Ordering::Relaxed);
+        if events == 0 {
+            self.0.empty_blocks.fetch_add(1, Ordering::Relaxed);
+        }
+        self.0.events.fetch_add(events, Ordering::Relaxed);
+        self.0.max_block_events.fetch_max(events, Ordering::Relaxed);
+    }
+
+    /// Number of recorded blocks.
+    pub fn blocks(&self) -> u64 { self.0.blocks.load(Ordering::Relaxed) }
+
+    /// Number of recorded blocks without events.
+    pub fn empty_blocks(&self) -> u64 { self.0.empty_blocks.load(Ordering::Relaxed) }
+
+    /// Total number of events in the recorded blocks.
+    pub fn events(&self) -> u64 { self.0.events.load(Ordering::Relaxed) }
+
+    /// Max number of events in a single recorded block.
+    pub fn max_block_events(&self) -> u64 { self.0.max_block_events.load(Ordering::Relaxed) }
+
+    /// Average number of events per block, zero if no blocks were recorded.
+    pub fn events_per_block(&self) -> f64 { ratio(self.events(), self.blocks()) }
+
+    /// Fraction of blocks without events, zero if no blocks were recorded.
+    pub fn empty_block_ratio(&self) -> f64 { ratio(self.empty_blocks(), self.blocks()) }
+}
+
+/// Records event count of each block produced by the [`super::raw`] or
+/// [`super::raw_range`] stream into `metrics`, passing the stream items
+/// through unchanged.
+///
+/// Errors are not recorded.
+pub fn metered<S>(
+    stream: S,
+    metrics: &StreamMetrics,
+) -> impl Stream<Item = Result<RawBlockEvents, DexError>>
+where
+    S: Stream<Item = Result<RawBlockEvents, DexError>>,
+{
+    let metrics = metrics.clone();
+    stream.inspect(move |result| {
+        if let Ok(block) = result {
+            metrics.record_block(block.events().len());
+        }
+    })
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 { 0.0 } else { numerator as f64 / denominator as f64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{Address, TxHash, U256};
+
+    use super::*;
+    use crate::{
+        abi::dex::Exchange::{AccountCreated, ExchangeEvents},
+        error::ProviderError,
+        stream::{RawEvent, RawExchangeEvent},
+        types,
+    };
+
+    fn block(block_number: u64, events: u64) -> Result<RawBlockEvents, DexError> {
+        Ok(RawBlockEvents::new(
+            types::StateInstant::new(block_number, block_number),
+            (0..events)
+                .map(|log_index| {
+                    let event = ExchangeEvents::AccountCreated(AccountCreated {
+                        account: Address::ZERO,
+                        id: U256::from(log_index),
+                    });
+                    RawEvent::new(TxHash::ZERO, 0, log_index, RawExchangeEvent::Known(event))
+                })
+                .collect(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_metered_stream() {
+        let metrics = StreamMetrics::new();
+        assert_eq!(metrics.events_per_block(), 0.0);
+        assert_eq!(metrics.empty_block_ratio(), 0.0);
+
+        let blocks = vec![
+            block(1, 3),
+            block(2, 0),
+            Err(DexError::Provider(ProviderError::InvalidRequest("UT".to_string()))),
+            block(3, 1),
+            block(4, 0),
+        ];
+        let results = metered(futures::stream::iter(blocks), &metrics)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(results.len(), 5);
+        assert!(results[2].is_err());
+
+        assert_eq!(metrics.blocks(), 4);
+        assert_eq!(metrics.empty_blocks(), 2);
+        assert_eq!(metrics.events(), 4);
+        assert_eq!(metrics.max_block_events(), 3);
+        assert_eq!(metrics.events_per_block(), 1.0);
+        assert_eq!(metrics.empty_block_ratio(), 0.5);
+    }
+}
@@ -1,5 +1,25 @@
+mod buffer;
+pub use buffer::*;
+
+mod candle;
+pub use candle::*;
+
+#[cfg(feature = "display")]
+mod format;
+#[cfg(feature = "display")]
+pub use format::*;
+
+mod metrics;
+pub use metrics::*;
+
 mod raw;
 pub use raw::*;
 
+mod stats;
+pub use stats::*;
+
 mod trade;
 pub use trade::*;
+
+#[cfg(test)]
+mod tests;
@@ -1,18 +1,107 @@
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
-use alloy::{eips::BlockId, providers::Provider, rpc::types::Filter, sol_types::SolEventInterface};
-use futures::{Stream, stream};
+use alloy::{
+    eips::BlockId,
+    primitives::{B256, Bytes, Log, keccak256},
+    providers::Provider,
+    rpc::types::Filter,
+    sol_types::SolEventInterface,
+};
+use futures::{Stream, StreamExt, stream};
 
 use crate::{
     Chain,
     abi::dex::Exchange::ExchangeEvents,
     error::{DexError, ProviderError},
+    state::Exchange,
     types,
 };
 
-pub type RawEvent = types::EventContext<ExchangeEvents>;
+pub type RawEvent = types::EventContext<RawExchangeEvent>;
 pub type RawBlockEvents = types::BlockEvents<RawEvent>;
 
+/// Optional checks of the [`raw_with_config`] stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawStreamConfig {
+    code_check_interval: Option<u64>,
+}
+
+impl RawStreamConfig {
+    /// Configuration without any checks, same as used by [`raw`].
+    pub const fn new() -> Self { Self { code_check_interval: None } }
+
+    /// Checks every `blocks` blocks, starting with the first one, that code
+    /// of the exchange contract implementation is the same as at the start of
+    /// the stream, failing with [`DexError::ContractChanged`] otherwise.
+    ///
+    /// Detects in-place upgrades, which may silently break decoding of events
+    /// by the SDK, at the cost of two extra RPC calls per check.
+    pub fn with_code_check(self, blocks: u64) -> Self {
+        Self { code_check_interval: Some(blocks.max(1)) }
+    }
+
+    /// Interval of the contract code checks in blocks, if enabled.
+    pub fn code_check_interval(&self) -> Option<u64> { self.code_check_interval }
+}
+
+/// Event emitted by the DEX smart contract.
+#[allow(clippy::large_enum_variant)] // Unknown events are rare, avoiding boxing of known ones
+#[derive(Clone)]
+pub enum RawExchangeEvent {
+    /// Event known to the ABI the SDK was built with.
+    Known(ExchangeEvents),
+    /// Event unknown to the SDK, most likely introduced by a newer revision
+    /// of the smart contract, see [`crate::state::Exchange::revision`].
+    Unknown { topic0: B256, data: Bytes },
+}
+
+impl RawExchangeEvent {
+    /// Decodes the exchange event from the log.
+    ///
+    /// Events with unrecognized signature do not fail the decoding and
+    /// produce [`RawExchangeEvent::Unknown`] instead, so the SDK keeps
+    /// processing the stream after smart contract upgrades.
+    pub fn decode_log(log: &Log) -> Result<Self, alloy::sol_types::Error> {
+        let topic0 = log.topics().first().copied().unwrap_or_default();
+        if ExchangeEvents::SELECTORS.contains(&topic0.0) {
+            ExchangeEvents::decode_log(log).map(|log| Self::Known(log.data))
+        } else {
+            tracing::warn!(%topic0, "unknown exchange event");
+            Ok(Self::Unknown { topic0, data: log.data.data.clone() })
+        }
+    }
+
+    /// Decoded event, if known to the SDK.
+    pub fn known(&self) -> Option<&ExchangeEvents> {
+        match self {
+            RawExchangeEvent::Known(event) => Some(event),
+            RawExchangeEvent::Unknown { .. } => None,
+        }
+    }
+}
+
+impl From<ExchangeEvents> for RawExchangeEvent {
+    fn from(event: ExchangeEvents) -> Self { RawExchangeEvent::Known(event) }
+}
+
+impl std::fmt::Debug for RawExchangeEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RawExchangeEvent::Known(event) => event.fmt(f),
+            RawExchangeEvent::Unknown { topic0, data } => {
+                f.debug_struct("Unknown").field("topic0", topic0).field("data", data).finish()
+            },
+        }
+    }
+}
+
 /// Returns stream of raw events emitted by the DEX smart contract,
 /// batched per block, starting from the specified block.
 ///
@@ -21,10 +110,25 @@ pub type RawBlockEvents = types::BlockEvents<RawEvent>;
 ///
 /// It is recommended to setup provider with
 /// [`alloy::transports::layers::FallbackLayer`]
-/// and/or [`alloy::transports::layers::RetryBackoffLayer`].
+/// and/or [`alloy::transports::layers::RetryBackoffLayer`], see
+/// [`crate::provider::recommended`].
 ///
 /// See [`crate::abi::dex::Exchange::ExchangeEvents`] for the list of possible
-/// events and corresponding details.
+/// events and corresponding details. Events unknown to the SDK are passed
+/// through as [`RawExchangeEvent::Unknown`].
+///
+/// Per block event counts can be tracked by wrapping the stream with
+/// [`super::metered`].
+///
+/// # Full replay
+///
+/// To index the whole exchange history, start the stream from
+/// [`types::StateInstant::genesis`]:
+///
+/// ```ignore
+/// let from = StateInstant::genesis(&chain, &provider).await?;
+/// let events = stream::raw(&chain, provider, from, tokio::time::sleep);
+/// ```
 ///
 /// # Safety note
 ///
@@ -41,7 +145,235 @@ where
     S: Fn(Duration) -> SFut + Copy,
     SFut: Future<Output = ()>,
 {
-    stream::unfold((provider, from.block_number()), move |(provider, mut block_num)| async move {
+    blocks(chain, provider, from, None, RawStreamConfig::new(), sleep)
+}
+
+/// Same as [`raw`], with optional checks enabled by the `config`.
+pub fn raw_with_config<P, S, SFut>(
+    chain: &Chain,
+    provider: P,
+    from: types::StateInstant,
+    config: RawStreamConfig,
+    sleep: S,
+) -> impl Stream<Item = Result<RawBlockEvents, DexError>>
+where
+    P: Provider,
+    S: Fn(Duration) -> SFut + Copy,
+    SFut: Future<Output = ()>,
+{
+    blocks(chain, provider, from, None, config, sleep)
+}
+
+/// Returns stream of raw events emitted by the DEX smart contract,
+/// batched per block, from the specified block up to and including
+/// `to_inclusive`.
+///
+/// The stream ends right after the `to_inclusive` block is produced, see
+/// [`raw`] for details.
+pub fn raw_range<P, S, SFut>(
+    chain: &Chain,
+    provider: P,
+    from: types::StateInstant,
+    to_inclusive: u64,
+    sleep: S,
+) -> impl Stream<Item = Result<RawBlockEvents, DexError>>
+where
+    P: Provider,
+    S: Fn(Duration) -> SFut + Copy,
+    SFut: Future<Output = ()>,
+{
+    blocks(chain, provider, from, Some(to_inclusive), RawStreamConfig::new(), sleep)
+}
+
+/// Max number of blocks covered by a single log query of the
+/// [`catch_up_then_follow`] stream.
+pub const CATCH_UP_BLOCKS_PER_QUERY: u64 = 100;
+
+/// Same as [`raw`], but first catches up with the safe chain tip fetching
+/// logs of up to [`CATCH_UP_BLOCKS_PER_QUERY`] blocks per query, then
+/// switches to polling block by block.
+///
+/// Closes a large gap between the snapshot and the chain tip in a fraction
+/// of RPC calls, e.g. after restoring a stale snapshot.
+///
+/// Only the last block of each query and blocks with events have their
+/// headers fetched, so the caught up blocks without events are timestamped
+/// with the closest preceding block of a known timestamp, starting with the
+/// `from` one.
+pub fn catch_up_then_follow<P, S, SFut>(
+    chain: &Chain,
+    provider: P,
+    from: types::StateInstant,
+    sleep: S,
+) -> impl Stream<Item = Result<RawBlockEvents, DexError>>
+where
+    P: Provider + Clone,
+    S: Fn(Duration) -> SFut + Copy,
+    SFut: Future<Output = ()>,
+{
+    let follow_from = Arc::new(AtomicU64::new(from.block_number()));
+    let caught_up = catch_up(chain, provider.clone(), from, follow_from.clone(), sleep);
+    let follow = stream::once(async move {
+        let from = types::StateInstant::new(follow_from.load(Ordering::Relaxed), 0);
+        blocks(chain, provider, from, None, RawStreamConfig::new(), sleep)
+    })
+    .flatten();
+    caught_up.chain(follow)
+}
+
+/// Stream of blocks up to the safe chain tip, storing the number of the
+/// block to follow from into `next_block`.
+fn catch_up<P, S, SFut>(
+    chain: &Chain,
+    provider: P,
+    from: types::StateInstant,
+    next_block: Arc<AtomicU64>,
+    sleep: S,
+) -> impl Stream<Item = Result<RawBlockEvents, DexError>>
+where
+    P: Provider,
+    S: Fn(Duration) -> SFut + Copy,
+    SFut: Future<Output = ()>,
+{
+    let init = (provider, from.block_number(), from.block_timestamp());
+    stream::unfold(init, move |(provider, block_num, timestamp)| {
+        let next_block = next_block.clone();
+        async move {
+            loop {
+                match fetch_blocks(chain, &provider, block_num, timestamp).await {
+                    Ok(blocks) => {
+                        let last = blocks.last()?.instant();
+                        next_block.store(last.block_number() + 1, Ordering::Relaxed);
+                        let state = (provider, last.block_number() + 1, last.block_timestamp());
+                        return Some((Ok(blocks), state));
+                    },
+                    Err(DexError::Provider(ProviderError::InvalidRequest(_))) => {
+                        // Block is not available yet
+                        sleep(provider.client().poll_interval()).await;
+                    },
+                    Err(err) => return Some((Err(err), (provider, block_num, timestamp))),
+                }
+            }
+        }
+    })
+    .flat_map(|result| {
+        stream::iter(match result {
+            Ok(blocks) => blocks.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        })
+    })
+}
+
+/// Fetches blocks starting from `from_block` with a single log query, up to
+/// [`CATCH_UP_BLOCKS_PER_QUERY`] of them and not past the safe chain tip.
+///
+/// Returns no blocks if `from_block` is past the safe chain tip.
+async fn fetch_blocks<P: Provider>(
+    chain: &Chain,
+    provider: &P,
+    from_block: u64,
+    mut timestamp: u64,
+) -> Result<Vec<RawBlockEvents>, DexError> {
+    let safe_block = provider
+        .get_block(BlockId::safe())
+        .await
+        .map_err(ProviderError::from)?;
+    let Some(safe_block) = safe_block.filter(|sb| sb.header.number >= from_block) else {
+        return Ok(vec![]);
+    };
+    let to_block = safe_block
+        .header
+        .number
+        .min(from_block + CATCH_UP_BLOCKS_PER_QUERY - 1);
+    let filter = Filter::new()
+        .address(chain.exchange())
+        .from_block(from_block)
+        .to_block(to_block);
+    let (last_block, logs) = futures::try_join!(
+        provider.get_block(BlockId::number(to_block)).into_future(),
+        provider.get_logs(&filter)
+    )
+    .map_err(ProviderError::from)?;
+    let last_header = last_block
+        .ok_or(ProviderError::InvalidRequest("block is not available yet".to_string()))?
+        .header;
+
+    let mut logs_by_block: HashMap<u64, (Option<u64>, Vec<RawEvent>)> = HashMap::new();
+    for log in &logs {
+        let block_num = log
+            .block_number
+            .ok_or(ProviderError::InvalidRequest("log without block number".to_string()))?;
+        let event = RawEvent::new(
+            log.transaction_hash.unwrap_or_default(),
+            log.transaction_index.unwrap_or_default(),
+            log.log_index.unwrap_or_default(),
+            RawExchangeEvent::decode_log(&log.inner).map_err(ProviderError::from)?,
+        )
+        .with_block(block_num);
+        let (block_timestamp, events) = logs_by_block.entry(block_num).or_default();
+        *block_timestamp = block_timestamp.or(log.block_timestamp);
+        events.push(event);
+    }
+
+    // Blocks with events are timestamped by their headers, unless the logs
+    // came with block timestamps
+    let untimestamped = logs_by_block
+        .iter()
+        .filter(|(block_num, (block_timestamp, _))| {
+            block_timestamp.is_none() && **block_num != to_block
+        })
+        .map(|(block_num, _)| *block_num)
+        .collect::<Vec<_>>();
+    let blocks = futures::future::try_join_all(untimestamped.iter().map(|block_num| {
+        provider
+            .get_block(BlockId::number(*block_num))
+            .into_future()
+    }))
+    .await
+    .map_err(ProviderError::from)?;
+    for (block_num, block) in untimestamped.into_iter().zip(blocks) {
+        let header = block
+            .ok_or(ProviderError::InvalidRequest("block is not available yet".to_string()))?
+            .header;
+        if let Some((block_timestamp, _)) = logs_by_block.get_mut(&block_num) {
+            *block_timestamp = Some(header.timestamp);
+        }
+    }
+
+    Ok((from_block..=to_block)
+        .map(|block_num| {
+            let (block_timestamp, mut events) =
+                logs_by_block.remove(&block_num).unwrap_or_default();
+            timestamp = if block_num == to_block {
+                last_header.timestamp
+            } else {
+                block_timestamp.unwrap_or(timestamp)
+            };
+            // Logs are not guaranteed to be returned in block-internal order, see `blocks`
+            events.sort_by_key(|e| e.log_index());
+            RawBlockEvents::new(types::StateInstant::new(block_num, timestamp), events)
+        })
+        .collect())
+}
+
+fn blocks<P, S, SFut>(
+    chain: &Chain,
+    provider: P,
+    from: types::StateInstant,
+    to_inclusive: Option<u64>,
+    config: RawStreamConfig,
+    sleep: S,
+) -> impl Stream<Item = Result<RawBlockEvents, DexError>>
+where
+    P: Provider,
+    S: Fn(Duration) -> SFut + Copy,
+    SFut: Future<Output = ()>,
+{
+    let init = (provider, from.block_number(), None);
+    stream::unfold(init, move |(provider, mut block_num, mut code_hash)| async move {
+        if to_inclusive.is_some_and(|to| block_num > to) {
+            return None;
+        }
         let filter = Filter::new()
             .address(chain.exchange())
             .from_block(block_num)
@@ -70,14 +402,16 @@ where
                     .header;
                 let mut events = Vec::with_capacity(logs.len());
                 for log in &logs {
-                    events.push(RawEvent::new(
-                        log.transaction_hash.unwrap_or_default(),
-                        log.transaction_index.unwrap_or_default(),
-                        log.log_index.unwrap_or_default(),
-                        ExchangeEvents::decode_log(&log.inner)
-                            .map_err(ProviderError::from)?
-                            .data,
-                    ));
+                    events.push(
+                        RawEvent::new(
+                            log.transaction_hash.unwrap_or_default(),
+                            log.transaction_index.unwrap_or_default(),
+                            log.log_index.unwrap_or_default(),
+                            RawExchangeEvent::decode_log(&log.inner)
+                                .map_err(ProviderError::from)?,
+                        )
+                        .with_block(block_num),
+                    );
                 }
                 // Monad RPC does not guarantee logs are returned in block-internal order
                 // (eg block 68747089 from https://rpc-mainnet.monadinfra.com)
@@ -88,28 +422,195 @@ where
                 ))
             });
             if result.is_ok() {
+                if config.code_check_interval.is_some_and(|interval| {
+                    (block_num - from.block_number()).is_multiple_of(interval)
+                }) {
+                    match check_code(chain, &provider, block_num, code_hash).await {
+                        Ok(hash) => code_hash = Some(hash),
+                        Err(err) => return Some((Err(err), (provider, block_num, code_hash))),
+                    }
+                }
                 block_num += 1;
-                return Some((result.map_err(DexError::Provider), (provider, block_num)));
+                return Some((
+                    result.map_err(DexError::Provider),
+                    (provider, block_num, code_hash),
+                ));
             }
             if matches!(result, Err(ProviderError::InvalidRequest(_))) {
                 // Block is not available yet
                 sleep(provider.client().poll_interval()).await;
                 continue;
             }
-            return Some((result.map_err(DexError::Provider), (provider, block_num)));
+            return Some((result.map_err(DexError::Provider), (provider, block_num, code_hash)));
         }
     })
 }
 
+/// Checks hash of the exchange contract implementation code as of the block
+/// against the `expected` one, if any, returning the actual hash.
+async fn check_code<P: Provider>(
+    chain: &Chain,
+    provider: &P,
+    block_num: u64,
+    expected: Option<B256>,
+) -> Result<B256, DexError> {
+    let (_, code) =
+        Exchange::implementation_code(chain, provider, BlockId::number(block_num)).await?;
+    let hash = keccak256(code);
+    if expected.is_some_and(|expected| expected != hash) {
+        return Err(DexError::ContractChanged(block_num));
+    }
+    Ok(hash)
+}
+
 #[cfg(test)]
 mod tests {
     use alloy::{
-        providers::ProviderBuilder, rpc::client::RpcClient, transports::layers::RetryBackoffLayer,
+        primitives::{Address, IntoLogData, U256},
+        providers::ProviderBuilder,
+        rpc::{
+            client::RpcClient,
+            types::{Block, Header},
+        },
+        transports::{layers::RetryBackoffLayer, mock::Asserter},
     };
     use futures::StreamExt;
 
     use super::*;
-    use crate::Chain;
+    use crate::{Chain, abi::dex::Exchange::AccountCreated};
+
+    #[test]
+    fn test_decode_unknown_event() {
+        let known = ExchangeEvents::AccountCreated(AccountCreated {
+            account: Address::ZERO,
+            id: U256::from(1),
+        });
+        let log = Log { address: Address::ZERO, data: known.to_log_data() };
+        assert!(matches!(
+            RawExchangeEvent::decode_log(&log).unwrap(),
+            RawExchangeEvent::Known(ExchangeEvents::AccountCreated(_))
+        ));
+
+        let topic0 = B256::repeat_byte(0xab);
+        let log = Log::new_unchecked(Address::ZERO, vec![topic0], Bytes::from_static(&[1, 2, 3]));
+        let event = RawExchangeEvent::decode_log(&log).unwrap();
+        assert!(event.known().is_none());
+        assert!(matches!(
+            event,
+            RawExchangeEvent::Unknown { topic0: t, data } if t == topic0 && data[..] == [1, 2, 3]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_code_check_detects_change() {
+        let asserter = Asserter::new();
+        let block: Block = Block::empty(Header::new(alloy::consensus::Header {
+            number: 10,
+            timestamp: 100,
+            ..Default::default()
+        }));
+        for code in [Bytes::from_static(&[1, 2, 3]), Bytes::from_static(&[1, 2, 4])] {
+            // Safe block, the block itself and its logs
+            asserter.push_success(&block);
+            asserter.push_success(&block);
+            asserter.push_success(&Vec::<alloy::rpc::types::Log>::new());
+            // Implementation slot of a contract without proxy and its code
+            asserter.push_success(&B256::ZERO);
+            asserter.push_success(&code);
+        }
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter);
+
+        let chain = Chain::testnet();
+        let config = RawStreamConfig::new().with_code_check(1);
+        let stream = raw_with_config(
+            &chain,
+            provider,
+            types::StateInstant::new(1, 0),
+            config,
+            tokio::time::sleep,
+        );
+        let results = stream.take(2).collect::<Vec<_>>().await;
+        assert_eq!(results[0].as_ref().unwrap().instant().block_number(), 1);
+        assert!(matches!(results[1], Err(DexError::ContractChanged(2))));
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_then_follow() {
+        let asserter = Asserter::new();
+        let block = |number, timestamp| -> Block {
+            Block::empty(Header::new(alloy::consensus::Header {
+                number,
+                timestamp,
+                ..Default::default()
+            }))
+        };
+        let chain = Chain::testnet();
+        let event = ExchangeEvents::AccountCreated(AccountCreated {
+            account: Address::ZERO,
+            id: U256::from(1),
+        });
+        let log = |block_number, block_timestamp| alloy::rpc::types::Log {
+            inner: Log { address: chain.exchange(), data: event.to_log_data() },
+            block_number: Some(block_number),
+            block_timestamp,
+            log_index: Some(3),
+            ..Default::default()
+        };
+
+        // Safe block, the last block of the range not available yet
+        asserter.push_success(&block(250, 2500));
+        asserter.push_success(&Option::<Block>::None);
+        asserter.push_success(&Vec::<alloy::rpc::types::Log>::new());
+        // Safe block, the last block of the range and logs of the range
+        asserter.push_success(&block(250, 2500));
+        asserter.push_success(&block(100, 1000));
+        asserter.push_success(&vec![log(50, Some(500))]);
+        // Block of the log without timestamp
+        asserter.push_success(&block(250, 2500));
+        asserter.push_success(&block(200, 2000));
+        asserter.push_success(&vec![log(150, None)]);
+        asserter.push_success(&block(150, 1500));
+        asserter.push_success(&block(250, 2500));
+        asserter.push_success(&block(250, 2500));
+        asserter.push_success(&Vec::<alloy::rpc::types::Log>::new());
+        // Caught up
+        asserter.push_success(&block(250, 2500));
+        // Following: safe block, the block itself and its logs
+        asserter.push_success(&block(251, 2510));
+        asserter.push_success(&block(251, 2510));
+        asserter.push_success(&Vec::<alloy::rpc::types::Log>::new());
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter);
+
+        let stream = catch_up_then_follow(
+            &chain,
+            provider,
+            types::StateInstant::new(1, 10),
+            tokio::time::sleep,
+        );
+        // Any RPC call beyond the expected 17 fails with the exhausted asserter
+        let blocks = stream
+            .take(251)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        for (block, block_num) in blocks.iter().zip(1..) {
+            assert_eq!(block.instant().block_number(), block_num);
+        }
+        let timestamp = |block_num: usize| blocks[block_num - 1].instant().block_timestamp();
+        assert_eq!(timestamp(49), 10);
+        assert_eq!(timestamp(50), 500);
+        assert_eq!(timestamp(99), 500);
+        assert_eq!(timestamp(100), 1000);
+        assert_eq!(timestamp(101), 1000);
+        assert_eq!(timestamp(150), 1500);
+        assert_eq!(timestamp(199), 1500);
+        assert_eq!(timestamp(250), 2500);
+        assert_eq!(timestamp(251), 2510);
+        assert_eq!(blocks[49].events().len(), 1);
+        assert_eq!(blocks[49].events()[0].log_index(), 3);
+        assert_eq!(blocks[149].events().len(), 1);
+        assert_eq!(blocks.iter().map(|b| b.events().len()).sum::<usize>(), 2);
+    }
 
     #[tokio::test]
     async fn test_stream_recent_blocks() {
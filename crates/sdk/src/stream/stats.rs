@@ -0,0 +1,198 @@
+use std::{collections::VecDeque, time::Duration};
+
+use fastnum::{D64, UD64, UD128};
+
+use super::BlockTrades;
+use crate::types;
+
+/// Rolling window [`PerpetualStats`] are aggregated over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsWindow {
+    /// Number of the most recent blocks.
+    Blocks(u64),
+
+    /// Most recent period of time, according to block timestamps.
+    Time(Duration),
+}
+
+/// Trade statistics of a single perpetual contract within the window.
+#[derive(Clone, Copy, derive_more::Debug, PartialEq, Eq)]
+pub struct TradeStats {
+    /// Number of trades, each taker order execution counted once.
+    pub trade_count: u64,
+
+    /// Total traded size.
+    #[debug("{volume}")]
+    pub volume: UD64,
+
+    /// Total traded amount in collateral token.
+    #[debug("{notional_volume}")]
+    pub notional_volume: UD128,
+
+    /// Highest fill price.
+    #[debug("{high}")]
+    pub high: UD64,
+
+    /// Lowest fill price.
+    #[debug("{low}")]
+    pub low: UD64,
+
+    /// Price of the most recent fill.
+    #[debug("{last}")]
+    pub last: UD64,
+
+    /// Size bought by takers less size sold by takers.
+    #[debug("{net_taker_flow}")]
+    pub net_taker_flow: D64,
+}
+
+/// Accumulator of per perpetual contract [`TradeStats`] over a rolling
+/// window, fed by [`BlockTrades`] from [`super::trade`].
+///
+/// Pure in-memory aggregation, blocks are expected to be fed in order.
+#[derive(Clone, Debug)]
+pub struct PerpetualStats {
+    window: StatsWindow,
+    trades: VecDeque<(types::StateInstant, types::Trade)>,
+}
+
+impl PerpetualStats {
+    /// Creates an empty accumulator aggregating over the `window`.
+    pub fn new(window: StatsWindow) -> Self { Self { window, trades: VecDeque::new() } }
+
+    /// Window the statistics are aggregated over.
+    pub fn window(&self) -> StatsWindow { self.window }
+
+    /// Adds trades of the block, evicting trades falling out of the window.
+    pub fn update(&mut self, block_trades: &BlockTrades) {
+        let instant = block_trades.instant();
+        self.trades.extend(
+            block_trades
+                .events()
+                .iter()
+                .map(|event| (instant, event.event().clone())),
+        );
+        while self
+            .trades
+            .front()
+            .is_some_and(|(trade_instant, _)| !self.within_window(*trade_instant, instant))
+        {
+            self.trades.pop_front();
+        }
+    }
+
+    /// Statistics of the perpetual contract within the window, `None` if
+    /// there were no trades.
+    pub fn get(&self, perpetual_id: types::PerpetualId) -> Option<TradeStats> {
+        let mut stats: Option<TradeStats> = None;
+        for (_, trade) in self
+            .trades
+            .iter()
+            .filter(|(_, t)| t.perpetual_id == perpetual_id)
+        {
+            let size = trade.total_size();
+            let flow = match trade.taker_side {
+                types::OrderSide::Bid => size.to_signed(),
+                types::OrderSide::Ask => -size.to_signed(),
+            };
+            for fill in &trade.maker_fills {
+                let notional: UD128 = fill.price.resize() * fill.size.resize();
+                let stats = stats.get_or_insert(TradeStats {
+                    trade_count: 0,
+                    volume: UD64::ZERO,
+                    notional_volume: UD128::ZERO,
+                    high: fill.price,
+                    low: fill.price,
+                    last: fill.price,
+                    net_taker_flow: D64::ZERO,
+                });
+                stats.volume += fill.size;
+                stats.notional_volume += notional;
+                stats.high = stats.high.max(fill.price);
+                stats.low = stats.low.min(fill.price);
+                stats.last = fill.price;
+            }
+            if let Some(stats) = stats.as_mut()
+                && !trade.maker_fills.is_empty()
+            {
+                stats.trade_count += 1;
+                stats.net_taker_flow += flow;
+            }
+        }
+        stats
+    }
+
+    fn within_window(&self, trade: types::StateInstant, latest: types::StateInstant) -> bool {
+        match self.window {
+            StatsWindow::Blocks(blocks) => trade.block_number() + blocks > latest.block_number(),
+            StatsWindow::Time(period) => {
+                trade.block_timestamp() + period.as_secs() > latest.block_timestamp()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fastnum::{dec64, udec64, udec128};
+
+    use super::*;
+    use crate::stream::tests::{block, trade};
+
+    const PERP_ID: types::PerpetualId = 1;
+
+    #[test]
+    fn test_perpetual_stats() {
+        let mut stats = PerpetualStats::new(StatsWindow::Blocks(3));
+        assert_eq!(stats.get(PERP_ID), None);
+
+        use types::OrderSide::{Ask, Bid};
+        stats.update(&block(
+            10,
+            100,
+            vec![trade(PERP_ID, Bid, &[(udec64!(100), udec64!(1)), (udec64!(101), udec64!(2))])],
+        ));
+        stats.update(&block(11, 101, vec![trade(PERP_ID, Ask, &[(udec64!(98), udec64!(0.5))])]));
+        // Trade without fills is not counted, same as by `CandleBuilder`
+        stats.update(&block(
+            12,
+            102,
+            vec![trade(PERP_ID, Bid, &[(udec64!(99), udec64!(1))]), trade(PERP_ID, Ask, &[])],
+        ));
+
+        let expected = TradeStats {
+            trade_count: 3,
+            volume: udec64!(4.5),
+            notional_volume: udec128!(450),
+            high: udec64!(101),
+            low: udec64!(98),
+            last: udec64!(99),
+            net_taker_flow: dec64!(3.5),
+        };
+        assert_eq!(stats.get(PERP_ID), Some(expected));
+        assert_eq!(stats.get(PERP_ID + 1), None);
+
+        // Block 10 falls out of the window
+        stats.update(&block(13, 103, vec![]));
+        let stats = stats.get(PERP_ID).unwrap();
+        assert_eq!(stats.trade_count, 2);
+        assert_eq!(stats.volume, udec64!(1.5));
+        assert_eq!(stats.high, udec64!(99));
+        assert_eq!(stats.low, udec64!(98));
+        assert_eq!(stats.net_taker_flow, dec64!(0.5));
+    }
+
+    #[test]
+    fn test_perpetual_stats_time_window() {
+        let mut stats = PerpetualStats::new(StatsWindow::Time(Duration::from_secs(60)));
+        let bid = |price| trade(PERP_ID, types::OrderSide::Bid, &[(price, udec64!(1))]);
+        stats.update(&block(1, 1_000, vec![bid(udec64!(100))]));
+        stats.update(&block(2, 1_059, vec![bid(udec64!(90))]));
+        assert_eq!(stats.get(PERP_ID).unwrap().trade_count, 2);
+
+        stats.update(&block(3, 1_060, vec![]));
+        let stats = stats.get(PERP_ID).unwrap();
+        assert_eq!(stats.trade_count, 1);
+        assert_eq!(stats.high, udec64!(90));
+    }
+}
@@ -0,0 +1,47 @@
+//! Fixtures shared by the unit tests of the stream components.
+
+use std::num::NonZeroU16;
+
+use alloy::primitives::TxHash;
+use fastnum::UD64;
+
+use super::{BlockTrades, TradeEvent};
+use crate::types;
+
+/// Trade of the `side` taker, filled by the same maker order at each of the
+/// `(price, size)` fills.
+pub(super) fn trade(
+    perpetual_id: types::PerpetualId,
+    side: types::OrderSide,
+    fills: &[(UD64, UD64)],
+) -> types::Trade {
+    types::Trade {
+        perpetual_id,
+        taker_account_id: 1,
+        taker_request_id: 1,
+        taker_side: side,
+        taker_fee: UD64::ZERO,
+        maker_fills: fills
+            .iter()
+            .map(|(price, size)| types::MakerFill {
+                log_index: 0,
+                maker_account_id: 2,
+                maker_order_id: NonZeroU16::new(1).unwrap(),
+                price: *price,
+                size: *size,
+                fee: UD64::ZERO,
+            })
+            .collect(),
+    }
+}
+
+/// Trades of the block at `block_number` and `timestamp`.
+pub(super) fn block(block_number: u64, timestamp: u64, trades: Vec<types::Trade>) -> BlockTrades {
+    BlockTrades::new(
+        types::StateInstant::new(block_number, timestamp),
+        trades
+            .into_iter()
+            .map(|t| TradeEvent::new(TxHash::ZERO, 0, 0, t))
+            .collect(),
+    )
+}
@@ -1,13 +1,14 @@
 use std::{collections::HashMap, num::NonZeroU16};
 
 use alloy::{primitives::U256, providers::Provider};
+use fastnum::UD64;
 use futures::{Stream, StreamExt};
 
 use crate::{
     Chain,
     abi::dex::Exchange::{ExchangeEvents, ExchangeInstance, MakerOrderFilled},
     error::DexError,
-    num, types,
+    num, state, types,
 };
 
 pub type TradeEvent = types::EventContext<types::Trade>;
@@ -23,7 +24,7 @@ pub type BlockTrades = types::BlockEvents<TradeEvent>;
 /// # Safety note
 ///
 /// The returned stream is not cancellation-safe and should not be used within
-/// `select!`.
+/// `select!`, see [`trade_with`] for the cancellation-safe alternative.
 ///
 /// # Architecture
 ///
@@ -97,31 +98,103 @@ where
     let mut processor = TradeProcessor::new(config);
 
     let stream = raw_events.map(move |block_result| {
-        block_result.map(|block_events| processor.process_block(&block_events))
+        block_result.and_then(|block_events| processor.process_block(&block_events))
     });
 
     Ok(stream)
 }
 
+/// Same as [`trade`], but only emits trades of the `perpetual_id`
+/// perpetual contract, see [`NormalizationConfig::for_perpetual`].
+///
+/// Fails with [`DexError::InvalidArgument`] if the perpetual contract is not
+/// listed by the `chain`.
+pub async fn trade_for<P>(
+    chain: &Chain,
+    provider: P,
+    raw_events: impl Stream<Item = Result<super::RawBlockEvents, DexError>>,
+    perpetual_id: types::PerpetualId,
+) -> Result<impl Stream<Item = Result<BlockTrades, DexError>>, DexError>
+where
+    P: Provider,
+{
+    let config = NormalizationConfig::fetch(chain, &provider)
+        .await?
+        .for_perpetual(perpetual_id);
+    if config.perpetuals.is_empty() {
+        return Err(DexError::InvalidArgument(format!("unknown perpetual: {perpetual_id}")));
+    }
+    let mut processor = TradeProcessor::new(config);
+
+    let stream = raw_events.map(move |block_result| {
+        block_result.and_then(|block_events| processor.process_block(&block_events))
+    });
+
+    Ok(stream)
+}
+
+/// Returns stream of normalized trade events aggregated from the
+/// [`super::raw`] event stream with the caller-owned `processor`.
+///
+/// Unlike [`trade`], the processor state survives the returned stream being
+/// dropped, so trades spanning the cancellation point are not lost: the
+/// caller keeps the raw event stream and the processor across `select!`
+/// iterations and resumes processing with a new stream, or calls
+/// [`TradeProcessor::process_block`] per raw block directly.
+///
+/// The stream is as cancellation-safe as `raw_events`, which should be
+/// polled via [`StreamExt::next`] on a stream kept outside of `select!`.
+///
+/// # Example
+///
+/// ```ignore
+/// let config = NormalizationConfig::fetch(&chain, &provider).await?;
+/// let mut processor = TradeProcessor::new(config);
+/// let mut raw_stream = pin!(stream::raw(&chain, provider, from, tokio::time::sleep));
+///
+/// loop {
+///     tokio::select! {
+///         Some(raw_block) = raw_stream.next() => {
+///             let block_trades = processor.process_block(&raw_block?)?;
+///             // ...
+///         },
+///         _ = other_events.recv() => { /* ... */ },
+///     }
+/// }
+/// ```
+pub fn trade_with<'a>(
+    processor: &'a mut TradeProcessor,
+    raw_events: impl Stream<Item = Result<super::RawBlockEvents, DexError>> + 'a,
+) -> impl Stream<Item = Result<BlockTrades, DexError>> + 'a {
+    raw_events.map(move |block_result| {
+        block_result.and_then(|block_events| processor.process_block(&block_events))
+    })
+}
+
 /// Configuration for normalization.
-#[derive(Clone)]
+///
+/// Either fetched from the chain with [`Self::fetch`], or built from an
+/// already fetched exchange snapshot with [`Self::from_exchange`].
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct NormalizationConfig {
-    collateral_converter: num::Converter,
+    collateral_converter: num::CollateralConverter,
     perpetuals: HashMap<types::PerpetualId, PerpetualConverters>,
 }
 
 /// Converters for a single perpetual.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct PerpetualConverters {
-    price_converter: num::Converter,
-    size_converter: num::Converter,
+    price_converter: num::PriceConverter,
+    size_converter: num::SizeConverter,
 }
 
 /// Context for tracking order requests (reuses pattern from exchange.rs).
 struct OrderContext {
     account_id: types::AccountId,
     request_id: types::RequestId,
-    side: types::OrderSide,
+    perpetual_id: types::PerpetualId,
+    order_id: Option<types::OrderId>,
+    side: Option<types::OrderSide>,
 }
 
 /// Pending maker fill waiting for taker match.
@@ -136,10 +209,31 @@ struct PendingMakerFill {
     maker_fee: fastnum::UD64,
 }
 
+/// Handling of self-trades by [`TradeProcessor`], see
+/// [`types::Trade::is_self_trade`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SelfTrades {
+    /// Emitted as is, flagged by [`types::Trade::is_self_trade`].
+    #[default]
+    Include,
+
+    /// Maker fills of the taker account are dropped, along with the trade if
+    /// no fills against other accounts remain.
+    Exclude,
+}
+
 /// Trade processor - pure logic, no async.
+///
+/// Sides of orders placed while processing are remembered, so fills of
+/// `Change` requests crossing the spread are attributed to the side of the
+/// changed order, or the side opposite to the filled makers. Trades with
+/// neither known, e.g. of orders placed before the processing started, are
+/// skipped.
 pub struct TradeProcessor {
     config: NormalizationConfig,
+    self_trades: SelfTrades,
     order_context: Option<OrderContext>,
+    order_sides: HashMap<(types::PerpetualId, types::OrderId), types::OrderSide>,
     pending_maker_fills: Vec<PendingMakerFill>,
     prev_tx_index: Option<u64>,
 }
@@ -147,45 +241,79 @@ pub struct TradeProcessor {
 impl TradeProcessor {
     /// Create a new trade processor with the given normalization config.
     pub fn new(config: NormalizationConfig) -> Self {
-        Self { config, order_context: None, pending_maker_fills: Vec::new(), prev_tx_index: None }
+        Self {
+            config,
+            self_trades: SelfTrades::default(),
+            order_context: None,
+            order_sides: HashMap::new(),
+            pending_maker_fills: Vec::new(),
+            prev_tx_index: None,
+        }
+    }
+
+    /// Sets the handling of self-trades (default: [`SelfTrades::Include`]).
+    pub fn with_self_trades(mut self, self_trades: SelfTrades) -> Self {
+        self.self_trades = self_trades;
+        self
     }
 
     /// Process a block of raw events and extract trades.
     ///
     /// This is pure logic - no async, no I/O.
-    pub fn process_block(&mut self, events: &super::RawBlockEvents) -> BlockTrades {
+    ///
+    /// Fails with [`DexError::MalformedData`] on malformed event data.
+    pub fn process_block(
+        &mut self,
+        events: &super::RawBlockEvents,
+    ) -> Result<BlockTrades, DexError> {
         let mut trades = Vec::new();
 
         for event in events.events() {
-            // Reset context at transaction boundary (pattern from exchange.rs)
-            if self.prev_tx_index.is_some_and(|idx| idx < event.tx_index()) {
-                self.order_context.take();
-                self.pending_maker_fills.clear();
-            }
-
-            if let Some(trade) = self.process_event(event) {
+            if let Some(trade) = self.process_event(event)? {
                 trades.push(trade);
             }
-
-            self.prev_tx_index = Some(event.tx_index());
         }
 
-        BlockTrades::new(events.instant(), trades)
+        Ok(BlockTrades::new(events.instant(), trades))
     }
 
     /// Process a single event, potentially emitting a trade.
-    fn process_event(&mut self, event: &super::RawEvent) -> Option<TradeEvent> {
-        match event.event() {
+    ///
+    /// Allows feeding events one by one as they arrive, state of incomplete
+    /// trades is kept in the processor until the taker fill.
+    pub fn process_event(
+        &mut self,
+        event: &super::RawEvent,
+    ) -> Result<Option<TradeEvent>, DexError> {
+        // Reset context at transaction boundary (pattern from exchange.rs)
+        if self.prev_tx_index.is_some_and(|idx| idx < event.tx_index()) {
+            self.order_context.take();
+            self.pending_maker_fills.clear();
+        }
+        self.prev_tx_index = Some(event.tx_index());
+
+        let Some(known) = event.event().known() else {
+            return Ok(None);
+        };
+        Ok(match known {
             ExchangeEvents::OrderRequest(e) => {
-                let request_type: types::RequestType = e.orderType.into();
-                // Only track context for order types that can have fills
-                if let Some(side) = request_type.try_side() {
-                    self.order_context = Some(OrderContext {
-                        account_id: e.accountId.to(),
-                        request_id: e.orderDescId.to(),
-                        side,
-                    });
-                }
+                let request_type: types::RequestType = e.orderType.try_into()?;
+                let perpetual_id = num::narrow(e.perpId, "perpetual id")?;
+                let order_id = parse_order_id(e.orderId);
+                let side = match request_type {
+                    // Fills on the side of the changed order
+                    types::RequestType::Change => order_id
+                        .and_then(|order_id| self.order_sides.get(&(perpetual_id, order_id)))
+                        .copied(),
+                    _ => request_type.try_side(),
+                };
+                self.order_context = Some(OrderContext {
+                    account_id: num::narrow(e.accountId, "account id")?,
+                    request_id: num::narrow(e.orderDescId, "request id")?,
+                    perpetual_id,
+                    order_id,
+                    side,
+                });
                 None
             },
             ExchangeEvents::OrderBatchCompleted(_) => {
@@ -193,42 +321,108 @@ impl TradeProcessor {
                 self.pending_maker_fills.clear();
                 None
             },
+            ExchangeEvents::OrderPlaced(e) => {
+                if let Some(ctx) = &self.order_context
+                    && let Some(side) = ctx.side
+                    && let Some(order_id) = parse_order_id(e.orderId)
+                    && self.config.perpetuals.contains_key(&ctx.perpetual_id)
+                {
+                    self.order_sides.insert((ctx.perpetual_id, order_id), side);
+                }
+                None
+            },
+            ExchangeEvents::OrderCancelled(_) => {
+                if let Some(ctx) = &self.order_context
+                    && let Some(order_id) = ctx.order_id
+                {
+                    self.order_sides.remove(&(ctx.perpetual_id, order_id));
+                }
+                None
+            },
+            ExchangeEvents::OrderCancelledByAdmin(e) => {
+                self.forget_order(e.perpId, e.orderId)?;
+                None
+            },
+            ExchangeEvents::OrderCancelledByLiquidator(e) => {
+                self.forget_order(e.perpId, e.orderId)?;
+                None
+            },
             ExchangeEvents::MakerOrderFilled(e) => {
-                self.handle_maker_fill(event, e);
+                self.handle_maker_fill(event, e)?;
                 None
             },
-            ExchangeEvents::TakerOrderFilled(e) => self.handle_taker_fill(event, e),
+            ExchangeEvents::TakerOrderFilled(e) => {
+                let taker_fee = self
+                    .config
+                    .collateral_converter
+                    .try_from_unsigned(e.feeCNS)?
+                    .get()
+                    .resize();
+                self.handle_taker_fill(event, taker_fee)
+            },
             _ => None,
+        })
+    }
+
+    fn forget_order(&mut self, perpetual_id: U256, order_id: U256) -> Result<(), DexError> {
+        if let Some(order_id) = parse_order_id(order_id) {
+            self.order_sides.remove(&(num::narrow(perpetual_id, "perpetual id")?, order_id));
         }
+        Ok(())
     }
 
-    fn handle_maker_fill(&mut self, event: &super::RawEvent, e: &MakerOrderFilled) {
-        let perp_id: types::PerpetualId = e.perpId.to();
+    fn handle_maker_fill(
+        &mut self,
+        event: &super::RawEvent,
+        e: &MakerOrderFilled,
+    ) -> Result<(), DexError> {
+        let perp_id: types::PerpetualId = num::narrow(e.perpId, "perpetual id")?;
         if let Some(converters) = self.config.perpetuals.get(&perp_id) {
+            let maker_order_id = parse_order_id(e.orderId).ok_or_else(|| {
+                DexError::MalformedData(format!("invalid maker order id: {}", e.orderId))
+            })?;
             self.pending_maker_fills.push(PendingMakerFill {
                 tx_hash: event.tx_hash(),
                 log_index: event.log_index(),
                 perpetual_id: perp_id,
-                maker_account_id: e.accountId.to(),
-                maker_order_id: NonZeroU16::new(e.orderId.to()).expect("non-zero maker order ID"),
-                price: converters.price_converter.from_unsigned(e.pricePNS),
-                size: converters.size_converter.from_unsigned(e.lotLNS),
-                maker_fee: self.config.collateral_converter.from_unsigned(e.feeCNS),
+                maker_account_id: num::narrow(e.accountId, "account id")?,
+                maker_order_id,
+                price: converters.price_converter.try_from_unsigned(e.pricePNS)?.get(),
+                size: converters.size_converter.try_from_unsigned(e.lotLNS)?.get(),
+                maker_fee: self
+                    .config
+                    .collateral_converter
+                    .try_from_unsigned(e.feeCNS)?
+                    .get()
+                    .resize(),
             });
         }
+        Ok(())
     }
 
     fn handle_taker_fill(
         &mut self,
         event: &super::RawEvent,
-        e: &crate::abi::dex::Exchange::TakerOrderFilled,
+        taker_fee: UD64,
     ) -> Option<TradeEvent> {
-        let makers = std::mem::take(&mut self.pending_maker_fills);
+        let mut makers = std::mem::take(&mut self.pending_maker_fills);
         if makers.is_empty() {
             return None;
         }
 
         let ctx = self.order_context.as_ref()?;
+        let taker_side = ctx.side.or_else(|| {
+            makers.iter().find_map(|m| {
+                let side = self.order_sides.get(&(m.perpetual_id, m.maker_order_id))?;
+                Some(side.opposite())
+            })
+        })?;
+        if self.self_trades == SelfTrades::Exclude {
+            makers.retain(|m| m.maker_account_id != ctx.account_id);
+            if makers.is_empty() {
+                return None;
+            }
+        }
         let taker_tx_hash = event.tx_hash();
 
         // Validate all maker fills have the same tx_hash as the taker fill
@@ -247,8 +441,8 @@ impl TradeProcessor {
                 perpetual_id,
                 taker_account_id: ctx.account_id,
                 taker_request_id: ctx.request_id,
-                taker_side: ctx.side,
-                taker_fee: self.config.collateral_converter.from_unsigned(e.feeCNS),
+                taker_side,
+                taker_fee,
                 maker_fills: makers
                     .into_iter()
                     .map(|m| types::MakerFill {
@@ -265,6 +459,10 @@ impl TradeProcessor {
     }
 }
 
+fn parse_order_id(value: U256) -> Option<types::OrderId> {
+    u16::try_from(value).ok().and_then(NonZeroU16::new)
+}
+
 impl NormalizationConfig {
     /// Fetch normalization config from the chain.
     pub async fn fetch<P: Provider>(chain: &Chain, provider: &P) -> Result<Self, DexError> {
@@ -276,7 +474,8 @@ impl NormalizationConfig {
             .call()
             .await
             .map_err(|err| DexError::Provider(err.into()))?;
-        let collateral_converter = num::Converter::new(exchange_info.collateralDecimals.to());
+        let collateral_converter =
+            num::CollateralConverter::new(exchange_info.collateralDecimals.to());
 
         // Fetch perpetual info for each perpetual
         let mut perpetuals = HashMap::new();
@@ -289,27 +488,431 @@ impl NormalizationConfig {
             perpetuals.insert(
                 *perp_id,
                 PerpetualConverters {
-                    price_converter: num::Converter::new(perp_info.priceDecimals.to()),
-                    size_converter: num::Converter::new(perp_info.lotDecimals.to()),
+                    price_converter: num::PriceConverter::new(perp_info.priceDecimals.to()),
+                    size_converter: num::SizeConverter::new(perp_info.lotDecimals.to()),
                 },
             );
         }
 
         Ok(Self { collateral_converter, perpetuals })
     }
+
+    /// Builds normalization config from the `exchange` snapshot, without
+    /// re-querying exchange and perpetual info.
+    ///
+    /// Covers perpetual contracts tracked by the snapshot.
+    pub fn from_exchange(exchange: &state::Exchange) -> Self {
+        Self {
+            collateral_converter: exchange.collateral_converter(),
+            perpetuals: exchange
+                .perpetuals()
+                .iter()
+                .map(|(id, perp)| {
+                    let converters = PerpetualConverters {
+                        price_converter: perp.price_converter(),
+                        size_converter: perp.size_converter(),
+                    };
+                    (*id, converters)
+                })
+                .collect(),
+        }
+    }
+
+    /// Restricts the config to the `perpetual_id` perpetual contract, so
+    /// [`TradeProcessor`] skips fills of other perpetual contracts and only
+    /// emits trades of this one.
+    pub fn for_perpetual(mut self, perpetual_id: types::PerpetualId) -> Self {
+        self.perpetuals.retain(|id, _| *id == perpetual_id);
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{pin::pin, time::Duration};
 
     use alloy::{
-        providers::ProviderBuilder, rpc::client::RpcClient, transports::layers::RetryBackoffLayer,
+        primitives::{I256, TxHash},
+        providers::ProviderBuilder,
+        rpc::client::RpcClient,
+        transports::layers::RetryBackoffLayer,
     };
-    use futures::StreamExt;
+    use fastnum::{udec64, udec128};
+    use futures::{FutureExt, StreamExt, channel::mpsc};
 
     use super::*;
-    use crate::Chain;
+    use crate::{
+        Chain,
+        abi::dex::Exchange::{OrderPlaced, OrderRequest, TakerOrderFilled},
+        stream::{RawBlockEvents, RawEvent},
+    };
+
+    fn raw_block(events: Vec<(u64, ExchangeEvents)>) -> RawBlockEvents {
+        let events = events
+            .into_iter()
+            .map(|(log_index, e)| RawEvent::new(TxHash::ZERO, 0, log_index, e.into()))
+            .collect();
+        RawBlockEvents::new(types::StateInstant::new(10, 100), events)
+    }
+
+    fn test_config() -> NormalizationConfig {
+        NormalizationConfig {
+            collateral_converter: num::CollateralConverter::new(0),
+            perpetuals: HashMap::from([(
+                1,
+                PerpetualConverters {
+                    price_converter: num::PriceConverter::new(0),
+                    size_converter: num::SizeConverter::new(0),
+                },
+            )]),
+        }
+    }
+
+    fn order_request(account_id: u64, request_id: u64, lot: u64) -> ExchangeEvents {
+        ExchangeEvents::OrderRequest(OrderRequest {
+            perpId: U256::from(1),
+            accountId: U256::from(account_id),
+            orderDescId: U256::from(request_id),
+            orderId: U256::ZERO,
+            orderType: types::RequestType::OpenLong as u8,
+            pricePNS: U256::from(100),
+            lotLNS: U256::from(lot),
+            expiryBlock: U256::ZERO,
+            postOnly: false,
+            fillOrKill: false,
+            immediateOrCancel: false,
+            maxMatches: U256::ZERO,
+            leverageHdths: U256::from(500),
+            lastExecutionBlock: U256::ZERO,
+            amountCNS: U256::ZERO,
+            maxNegPnlCollatBPS: U256::ZERO,
+            gasLeft: U256::ZERO,
+        })
+    }
+
+    fn maker_filled(account_id: u64, order_id: u64, price: u64, lot: u64) -> ExchangeEvents {
+        ExchangeEvents::MakerOrderFilled(MakerOrderFilled {
+            perpId: U256::from(1),
+            accountId: U256::from(account_id),
+            orderId: U256::from(order_id),
+            pricePNS: U256::from(price),
+            lotLNS: U256::from(lot),
+            feeCNS: U256::ZERO,
+            lockedBalanceCNS: U256::ZERO,
+            amountCNS: I256::ZERO,
+            balanceCNS: U256::ZERO,
+        })
+    }
+
+    fn taker_filled(lot: u64) -> ExchangeEvents {
+        ExchangeEvents::TakerOrderFilled(TakerOrderFilled {
+            entryPricePNS: U256::from(100),
+            collatPricePNS: U256::from(100),
+            pnlPricePNS: U256::from(100),
+            lotLNS: U256::from(lot),
+            feeCNS: U256::from(1),
+            amountCNS: I256::ZERO,
+            balanceCNS: U256::ZERO,
+        })
+    }
+
+    #[test]
+    fn test_trade_with_resumes_after_cancellation() {
+        let mut processor = TradeProcessor::new(test_config());
+        let (raw_tx, mut raw_rx) = mpsc::unbounded();
+
+        // First part of the block, up to the maker fill
+        let order_request = order_request(1, 7, 2);
+        let maker_filled = maker_filled(2, 3, 100, 2);
+        raw_tx
+            .unbounded_send(Ok(raw_block(vec![(0, order_request), (1, maker_filled)])))
+            .unwrap();
+        {
+            let mut trades = pin!(trade_with(&mut processor, &mut raw_rx));
+            let block_trades = trades.next().now_or_never().unwrap().unwrap().unwrap();
+            assert!(block_trades.events().is_empty());
+
+            // Cancelled while waiting for the rest of the block
+            assert!(trades.next().now_or_never().is_none());
+        }
+
+        // Resumed stream completes the trade
+        raw_tx
+            .unbounded_send(Ok(raw_block(vec![(2, taker_filled(2))])))
+            .unwrap();
+        let mut trades = pin!(trade_with(&mut processor, &mut raw_rx));
+        let block_trades = trades.next().now_or_never().unwrap().unwrap().unwrap();
+        assert_eq!(block_trades.events().len(), 1);
+        let trade = block_trades.events()[0].event();
+        assert_eq!(trade.taker_account_id, 1);
+        assert_eq!(trade.taker_request_id, 7);
+        assert_eq!(trade.taker_side, types::OrderSide::Bid);
+        assert_eq!(trade.total_size(), udec64!(2));
+        assert_eq!(trade.maker_fills[0].maker_account_id, 2);
+        assert!(!trade.is_self_trade());
+    }
+
+    #[test]
+    fn test_trade_last_price() {
+        let mut processor = TradeProcessor::new(test_config());
+        let block_trades = processor
+            .process_block(&raw_block(vec![
+                (0, order_request(1, 7, 3)),
+                (1, maker_filled(2, 3, 100, 1)),
+                (2, maker_filled(3, 4, 101, 2)),
+                (3, taker_filled(3)),
+            ]))
+            .unwrap();
+        let trade = block_trades.events()[0].event();
+        assert_eq!(trade.last_price(), Some(udec64!(101)));
+        assert_eq!(trade.last_price(), trade.maker_fills.last().map(|f| f.price));
+        assert!(trade.avg_price() < trade.last_price());
+    }
+
+    #[test]
+    fn test_trade_notional() {
+        let mut processor = TradeProcessor::new(test_config());
+        let block_trades = processor
+            .process_block(&raw_block(vec![
+                (0, order_request(1, 7, 4)),
+                (1, maker_filled(2, 3, 100, 1)),
+                (2, maker_filled(3, 4, 101, 2)),
+                (3, maker_filled(2, 5, 102, 1)),
+                (4, taker_filled(4)),
+            ]))
+            .unwrap();
+        let trade = block_trades.events()[0].event();
+        assert_eq!(trade.total_notional(), udec64!(404));
+        assert_eq!(trade.maker_notional(2), udec64!(202));
+        assert_eq!(trade.maker_notional(3), udec64!(202));
+        assert_eq!(trade.maker_notional(1), fastnum::UD64::ZERO);
+        assert_eq!(trade.avg_price(), Some(trade.total_notional() / trade.total_size()));
+    }
+
+    #[test]
+    fn test_trade_flatten() {
+        let mut processor = TradeProcessor::new(test_config());
+        let tx_hash = TxHash::repeat_byte(0xab);
+        let events = [
+            (4, order_request(1, 7, 3)),
+            (5, maker_filled(2, 3, 100, 1)),
+            (6, maker_filled(3, 4, 101, 2)),
+            (7, taker_filled(3)),
+        ];
+        let trade = events
+            .into_iter()
+            .filter_map(|(log_index, e)| {
+                let event = RawEvent::new(tx_hash, 2, log_index, e.into()).with_block(10);
+                processor.process_event(&event).unwrap()
+            })
+            .next()
+            .unwrap();
+
+        let flat = trade.flatten();
+        assert_eq!(flat.block_number, 10);
+        assert_eq!(flat.tx_hash, tx_hash);
+        assert_eq!(flat.tx_index, 2);
+        assert_eq!(flat.log_index, 7);
+        assert_eq!(flat.taker_account_id, 1);
+        assert_eq!(flat.taker_request_id, 7);
+        let fill_indices: Vec<_> = flat.maker_fills.iter().map(|f| f.log_index).collect();
+        assert_eq!(fill_indices, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_config_from_exchange() {
+        let exchange = state::Exchange::new(
+            Chain::testnet(),
+            types::StateInstant::new(0, 0),
+            num::CollateralConverter::new(0),
+            100,
+            udec128!(0.001).into(),
+            udec128!(0.001).into(),
+            udec128!(0.001).into(),
+            HashMap::from([(1, state::Perpetual::for_testing(1))]),
+            HashMap::new(),
+            false,
+            true,
+        );
+        assert_eq!(NormalizationConfig::from_exchange(&exchange), test_config());
+    }
+
+    #[test]
+    fn test_self_trade() {
+        // Account 1 crosses its own resting order 3 along with order 4 of account 2
+        let block = || {
+            raw_block(vec![
+                (0, order_request(1, 7, 3)),
+                (1, maker_filled(1, 3, 100, 2)),
+                (2, maker_filled(2, 4, 100, 1)),
+                (3, taker_filled(3)),
+            ])
+        };
+
+        let mut processor = TradeProcessor::new(test_config());
+        let block_trades = processor.process_block(&block()).unwrap();
+        assert_eq!(block_trades.events().len(), 1);
+        let trade = block_trades.events()[0].event();
+        assert!(trade.is_self_trade());
+        assert_eq!(trade.total_size(), udec64!(3));
+
+        let mut processor =
+            TradeProcessor::new(test_config()).with_self_trades(SelfTrades::Exclude);
+        let block_trades = processor.process_block(&block()).unwrap();
+        assert_eq!(block_trades.events().len(), 1);
+        let trade = block_trades.events()[0].event();
+        assert!(!trade.is_self_trade());
+        assert_eq!(trade.maker_fills.len(), 1);
+        assert_eq!(trade.maker_fills[0].maker_account_id, 2);
+        assert_eq!(trade.total_size(), udec64!(1));
+
+        // Trade entirely against own order is dropped
+        let block_trades = processor
+            .process_block(&raw_block(vec![
+                (0, order_request(1, 8, 2)),
+                (1, maker_filled(1, 3, 100, 2)),
+                (2, taker_filled(2)),
+            ]))
+            .unwrap();
+        assert!(block_trades.events().is_empty());
+    }
+
+    #[test]
+    fn test_trade_for_perpetual() {
+        let mut config = test_config();
+        config.perpetuals.insert(2, config.perpetuals[&1]);
+        let block = || {
+            let mut other_request = order_request(3, 8, 1);
+            if let ExchangeEvents::OrderRequest(e) = &mut other_request {
+                e.perpId = U256::from(2);
+            }
+            let mut other_fill = maker_filled(4, 5, 200, 1);
+            if let ExchangeEvents::MakerOrderFilled(e) = &mut other_fill {
+                e.perpId = U256::from(2);
+            }
+            raw_block(vec![
+                (0, order_request(1, 7, 1)),
+                (1, maker_filled(2, 3, 100, 1)),
+                (2, taker_filled(1)),
+                (3, other_request),
+                (4, other_fill),
+                (5, taker_filled(1)),
+            ])
+        };
+
+        let mut processor = TradeProcessor::new(config.clone());
+        assert_eq!(processor.process_block(&block()).unwrap().events().len(), 2);
+
+        let mut processor = TradeProcessor::new(config.for_perpetual(1));
+        let block_trades = processor.process_block(&block()).unwrap();
+        assert_eq!(block_trades.events().len(), 1);
+        assert_eq!(block_trades.events()[0].event().perpetual_id, 1);
+        assert_eq!(block_trades.events()[0].event().taker_request_id, 7);
+    }
+
+    #[test]
+    fn test_change_request_crossing_the_book() {
+        let request = |account_id, request_id, r#type: types::RequestType, order_id| {
+            let mut request = order_request(account_id, request_id, 2);
+            if let ExchangeEvents::OrderRequest(e) = &mut request {
+                e.orderType = r#type as u8;
+                e.orderId = U256::from(order_id);
+            }
+            request
+        };
+        let order_placed = |order_id| {
+            ExchangeEvents::OrderPlaced(OrderPlaced {
+                orderId: U256::from(order_id),
+                lotLNS: U256::from(2),
+                lockedBalanceCNS: U256::ZERO,
+                amountCNS: I256::ZERO,
+                balanceCNS: U256::ZERO,
+            })
+        };
+        let mut processor = TradeProcessor::new(test_config());
+
+        // Account 1 rests ask order 9, account 2 bid order 3
+        let block_trades = processor
+            .process_block(&raw_block(vec![
+                (0, request(1, 7, types::RequestType::OpenShort, 0)),
+                (1, order_placed(9)),
+                (2, request(2, 8, types::RequestType::OpenLong, 0)),
+                (3, order_placed(3)),
+            ]))
+            .unwrap();
+        assert!(block_trades.events().is_empty());
+
+        // Ask order 9 repriced across the spread fills as a taker on its side
+        let block_trades = processor
+            .process_block(&raw_block(vec![
+                (0, request(1, 10, types::RequestType::Change, 9)),
+                (1, maker_filled(2, 3, 100, 2)),
+                (2, taker_filled(2)),
+            ]))
+            .unwrap();
+        assert_eq!(block_trades.events().len(), 1);
+        let trade = block_trades.events()[0].event();
+        assert_eq!(trade.taker_account_id, 1);
+        assert_eq!(trade.taker_request_id, 10);
+        assert_eq!(trade.taker_side, types::OrderSide::Ask);
+        assert_eq!(trade.total_size(), udec64!(2));
+
+        // Unknown changed order takes the side opposite to the known maker,
+        // rather than the side of the preceding request
+        let block_trades = processor
+            .process_block(&raw_block(vec![
+                (0, request(1, 11, types::RequestType::OpenLong, 0)),
+                (1, request(1, 12, types::RequestType::Change, 5)),
+                (2, maker_filled(2, 3, 100, 1)),
+                (3, taker_filled(1)),
+            ]))
+            .unwrap();
+        let trade = block_trades.events()[0].event();
+        assert_eq!(trade.taker_request_id, 12);
+        assert_eq!(trade.taker_side, types::OrderSide::Ask);
+
+        // Neither side known, the trade is skipped
+        let block_trades = processor
+            .process_block(&raw_block(vec![
+                (0, request(1, 13, types::RequestType::Change, 5)),
+                (1, maker_filled(3, 4, 100, 1)),
+                (2, taker_filled(1)),
+            ]))
+            .unwrap();
+        assert!(block_trades.events().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_events() {
+        let mut processor = TradeProcessor::new(test_config());
+        let mut request = order_request(1, 7, 1);
+        if let ExchangeEvents::OrderRequest(e) = &mut request {
+            e.orderType = 9;
+        }
+        let result = processor.process_block(&raw_block(vec![(0, request)]));
+        assert!(matches!(result, Err(DexError::MalformedData(_))));
+
+        let result = processor.process_block(&raw_block(vec![
+            (0, order_request(1, 7, 1)),
+            (1, maker_filled(2, 0, 100, 1)),
+        ]));
+        assert!(matches!(result, Err(DexError::MalformedData(_))));
+
+        let mut request = order_request(1, 7, 1);
+        if let ExchangeEvents::OrderRequest(e) = &mut request {
+            e.perpId = U256::MAX;
+        }
+        let result = processor.process_block(&raw_block(vec![(0, request)]));
+        assert!(matches!(result, Err(DexError::MalformedData(_))));
+
+        let mut filled = maker_filled(2, 3, 100, 1);
+        if let ExchangeEvents::MakerOrderFilled(e) = &mut filled {
+            e.accountId = U256::MAX;
+        }
+        let result =
+            processor.process_block(&raw_block(vec![(0, order_request(1, 7, 1)), (1, filled)]));
+        assert!(matches!(result, Err(DexError::MalformedData(_))));
+    }
 
     #[tokio::test]
     async fn test_stream_recent_blocks() {
@@ -1,8 +1,11 @@
-use alloy::primitives::{Address, U256};
-use fastnum::UD128;
+use alloy::{
+    network::Ethereum,
+    primitives::{Address, U256},
+    providers::PendingTransactionBuilder,
+};
 
-use super::TestExchange;
-use crate::types;
+use super::{TestExchange, usd};
+use crate::{error::DexError, num, types};
 
 #[derive(Debug)]
 pub struct TestAccount<'e> {
@@ -12,7 +15,7 @@ pub struct TestAccount<'e> {
 }
 
 impl<'e> TestAccount<'e> {
-    pub async fn balance(&self) -> UD128 {
+    pub async fn balance(&self) -> num::Collateral {
         let acc = self
             .exchange
             .exchange
@@ -25,7 +28,7 @@ impl<'e> TestAccount<'e> {
             .from_unsigned(acc.balanceCNS)
     }
 
-    pub async fn locked_balance(&self) -> UD128 {
+    pub async fn locked_balance(&self) -> num::Collateral {
         let acc = self
             .exchange
             .exchange
@@ -37,4 +40,30 @@ impl<'e> TestAccount<'e> {
             .collateral_converter
             .from_unsigned(acc.lockedBalanceCNS)
     }
+
+    /// Mints and deposits `usd_amount` of collateral to the account.
+    pub async fn deposit(&self, usd_amount: u64) -> PendingTransactionBuilder<Ethereum> {
+        let amount = usd(usd_amount);
+        self.exchange.fund(self.address, amount).await;
+        self.exchange
+            .exchange
+            .depositCollateral(amount)
+            .from(self.address)
+            .send()
+            .await
+            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
+            .unwrap()
+    }
+
+    /// Withdraws `usd_amount` of collateral from the account.
+    pub async fn withdraw(&self, usd_amount: u64) -> PendingTransactionBuilder<Ethereum> {
+        self.exchange
+            .exchange
+            .withdrawCollateral(usd(usd_amount))
+            .from(self.address)
+            .send()
+            .await
+            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
+            .unwrap()
+    }
 }
@@ -1,9 +1,4 @@
-use std::{
-    collections::HashSet,
-    pin::pin,
-    sync::{Arc, RwLock, RwLockReadGuard},
-    time::Duration,
-};
+use std::{collections::HashSet, pin::pin, time::Duration};
 
 use alloy::providers::DynProvider;
 use futures::{
@@ -16,13 +11,13 @@ use crate::{Chain, state, stream, types};
 pub struct Indexer {
     chain: Chain,
     provider: DynProvider,
-    snapshot: Arc<RwLock<state::Exchange>>,
+    snapshot: state::SharedExchange,
     raw_events_tx: UnboundedSender<stream::RawBlockEvents>,
     state_events_tx: UnboundedSender<state::StateBlockEvents>,
 }
 
 pub struct IndexedState {
-    snapshot: Arc<RwLock<state::Exchange>>,
+    snapshot: state::SharedExchange,
     raw_events_rx: UnboundedReceiver<stream::RawBlockEvents>,
     state_events_rx: UnboundedReceiver<state::StateBlockEvents>,
     request_ids: HashSet<u64>,
@@ -30,7 +25,7 @@ pub struct IndexedState {
 
 impl Indexer {
     pub async fn new(exchange: &TestExchange) -> (Self, IndexedState) {
-        let snapshot = Arc::new(RwLock::new(
+        let snapshot = state::SharedExchange::new(
             state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
                 .with_accounts(
                     exchange
@@ -42,7 +37,7 @@ impl Indexer {
                 .build()
                 .await
                 .unwrap(),
-        ));
+        );
 
         let (raw_events_tx, raw_events_rx) = mpsc::unbounded();
         let (state_events_tx, state_events_rx) = mpsc::unbounded();
@@ -67,12 +62,12 @@ impl Indexer {
         let mut stream = pin!(stream::raw(
             &self.chain,
             self.provider,
-            self.snapshot.read().unwrap().instant(),
+            self.snapshot.instant(),
             sleep,
         ));
         while let Some(batch) = stream.next().await {
             let batch = batch.unwrap();
-            let res = self.snapshot.write().unwrap().apply_events(&batch);
+            let res = self.snapshot.apply_events(&batch);
             if self.raw_events_tx.send(batch).await.is_err() {
                 break;
             };
@@ -92,11 +87,9 @@ impl Indexer {
     }
 }
 
-impl<'a> IndexedState {
+impl IndexedState {
     /// Current state snapshot
-    pub fn snapshot(&'a self) -> RwLockReadGuard<'a, state::Exchange> {
-        self.snapshot.read().unwrap()
-    }
+    pub fn snapshot(&self) -> state::FrozenExchange { self.snapshot.freeze() }
 
     /// Next available batch of raw events
     pub async fn next_raw_events(&mut self) -> Option<stream::RawBlockEvents> {
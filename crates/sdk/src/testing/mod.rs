@@ -37,11 +37,65 @@ use crate::{
 };
 
 const CHAIN_ID: u64 = 1337;
-const BLOCK_TIME_SEC: f64 = 0.4;
+const BLOCK_TIME: Duration = Duration::from_millis(400);
 const POLL_INTERVAL_MS: u64 = 50;
 
 const USD_DECIMALS: u8 = 6;
 
+/// Anvil configuration of [`TestExchange`].
+///
+/// Accounts are derived from the mnemonic and contracts are deployed in the
+/// same order, so exchanges spawned with the same configuration have the same
+/// addresses. Fixed genesis timestamp and mining a block per transaction
+/// further make block numbers and timestamps independent of wall clock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestConfig {
+    mnemonic: Option<String>,
+    block_time: Option<Duration>,
+    accounts: usize,
+    balance: u64,
+    genesis_timestamp: Option<u64>,
+}
+
+impl TestConfig {
+    /// Anvil defaults with blocks mined every 0.4 seconds.
+    pub const fn new() -> Self {
+        Self {
+            mnemonic: None,
+            block_time: Some(BLOCK_TIME),
+            accounts: 10,
+            balance: 10_000,
+            genesis_timestamp: None,
+        }
+    }
+
+    /// Mnemonic to derive the accounts from instead of the Anvil default one.
+    pub fn with_mnemonic(self, mnemonic: impl Into<String>) -> Self {
+        Self { mnemonic: Some(mnemonic.into()), ..self }
+    }
+
+    /// Interval blocks are mined at, `None` to mine a block per transaction.
+    pub fn with_block_time(self, block_time: Option<Duration>) -> Self {
+        Self { block_time, ..self }
+    }
+
+    /// Number of accounts to derive, including owner, admin and price admin,
+    /// and their initial ETH balance.
+    pub fn with_accounts(self, accounts: usize, balance: u64) -> Self {
+        assert!(accounts > 3, "owner, admin and price admin accounts are reserved");
+        Self { accounts, balance, ..self }
+    }
+
+    /// Timestamp of the genesis block instead of the current time.
+    pub fn with_genesis_timestamp(self, timestamp: u64) -> Self {
+        Self { genesis_timestamp: Some(timestamp), ..self }
+    }
+}
+
+impl Default for TestConfig {
+    fn default() -> Self { Self::new() }
+}
+
 #[derive(Debug)]
 pub struct TestExchange {
     pub chain_id: u64,
@@ -55,17 +109,31 @@ pub struct TestExchange {
     pub admin_pk: String,
     pub price_admin: Address,
     pub price_admin_pk: String,
-    pub collateral_converter: num::Converter,
+    pub collateral_converter: num::CollateralConverter,
     perpetual_ids: Arc<DashSet<types::PerpetualId>>,
     account_address: Arc<DashMap<types::AccountId, Address>>,
     anvil: AnvilInstance,
 }
 
 impl TestExchange {
-    pub async fn new() -> Self {
-        let anvil = Anvil::new()
-            .block_time_f64(BLOCK_TIME_SEC)
+    pub async fn new() -> Self { Self::with_config(TestConfig::new()).await }
+
+    /// Spawns Anvil with the configuration, see [`TestConfig`].
+    pub async fn with_config(config: TestConfig) -> Self {
+        let mut anvil = Anvil::new()
             .chain_id(CHAIN_ID)
+            .args(["--accounts".to_string(), config.accounts.to_string()])
+            .args(["--balance".to_string(), config.balance.to_string()]);
+        if let Some(mnemonic) = config.mnemonic {
+            anvil = anvil.mnemonic(mnemonic);
+        }
+        if let Some(block_time) = config.block_time {
+            anvil = anvil.block_time_f64(block_time.as_secs_f64());
+        }
+        if let Some(timestamp) = config.genesis_timestamp {
+            anvil = anvil.args(["--timestamp".to_string(), timestamp.to_string()]);
+        }
+        let anvil = anvil
             .args(vec!["--code-size-limit", "131072"])
             .args(vec!["--gas-limit", "200000000"])
             .args(vec!["--base-fee", "100000000000"])
@@ -173,7 +241,7 @@ impl TestExchange {
             admin_pk: anvil.nth_key(1).unwrap().to_bytes().encode_hex(),
             price_admin,
             price_admin_pk: anvil.nth_key(2).unwrap().to_bytes().encode_hex(),
-            collateral_converter: num::Converter::new(USD_DECIMALS),
+            collateral_converter: num::CollateralConverter::new(USD_DECIMALS),
             perpetual_ids: Arc::new(DashSet::new()),
             account_address: Arc::new(DashMap::new()),
             anvil,
@@ -187,16 +255,46 @@ impl TestExchange {
             deployed_at_block: 0,
             exchange: *self.exchange.address(),
             perpetuals: self.perpetual_ids.iter().map(|p| *p).collect(),
+            block_time: Chain::DEFAULT_BLOCK_TIME,
         }
     }
 
     pub async fn account(&self, idx: usize, usd_balance: u64) -> TestAccount<'_> {
         let address = self.anvil.addresses()[idx + 3]; // skipping owner, admin and price admin
         let target_balance = usd(usd_balance);
+        self.fund(address, target_balance).await;
+        let receipt = self
+            .exchange
+            .createAccount(target_balance)
+            .from(address)
+            .send()
+            .await
+            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
+            .unwrap()
+            .get_receipt()
+            .await
+            .unwrap();
+        let log = receipt.decoded_log::<Exchange::AccountCreated>().unwrap();
+        self.account_address.insert(log.id.to(), log.account);
+        TestAccount { id: log.id.to(), address: log.account, exchange: self }
+    }
+
+    /// Creates funded accounts in order, see [`Self::account`].
+    pub async fn accounts(&self, specs: &[(usize, u64)]) -> Vec<TestAccount<'_>> {
+        let mut accounts = Vec::with_capacity(specs.len());
+        for (idx, usd_balance) in specs {
+            accounts.push(self.account(*idx, *usd_balance).await);
+        }
+        accounts
+    }
+
+    /// Mints missing collateral tokens to the address and approves the exchange
+    /// to spend `amount`.
+    async fn fund(&self, address: Address, amount: U256) {
         let cur_balance = self.token.balanceOf(address).call().await.unwrap();
-        if target_balance > cur_balance {
+        if amount > cur_balance {
             self.token
-                .mint(address, target_balance - cur_balance)
+                .mint(address, amount - cur_balance)
                 .send()
                 .await
                 .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
@@ -206,7 +304,7 @@ impl TestExchange {
                 .unwrap();
         }
         self.token
-            .approve(*self.exchange.address(), target_balance)
+            .approve(*self.exchange.address(), amount)
             .from(address)
             .send()
             .await
@@ -215,20 +313,6 @@ impl TestExchange {
             .get_receipt()
             .await
             .unwrap();
-        let receipt = self
-            .exchange
-            .createAccount(target_balance)
-            .from(address)
-            .send()
-            .await
-            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
-            .unwrap()
-            .get_receipt()
-            .await
-            .unwrap();
-        let log = receipt.decoded_log::<Exchange::AccountCreated>().unwrap();
-        self.account_address.insert(log.id.to(), log.account);
-        TestAccount { id: log.id.to(), address: log.account, exchange: self }
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -244,7 +328,7 @@ impl TestExchange {
         initial_margin: UD64,
         maintenance_margin: UD64,
     ) -> TestPerp<'_> {
-        let price_converter = num::Converter::new(price_decimals);
+        let price_converter = num::PriceConverter::new(price_decimals);
         let fee_converter = num::Converter::new(5); // Fees are in 1/100K
         let leverage_converter = num::Converter::new(2); // Margin and leverage are in 100th
         self.exchange
@@ -252,7 +336,7 @@ impl TestExchange {
                 name.to_string(),
                 name.to_string(),
                 U256::from(perp_id),
-                price_converter.to_unsigned(base_price),
+                price_converter.to_unsigned(num::Price::new(base_price)),
                 U256::from(price_decimals),
                 U256::from(size_decimals),
                 fee_converter.to_unsigned(taker_fee),
@@ -282,8 +366,10 @@ impl TestExchange {
             id: perp_id,
             name: name.to_string(),
             price_converter,
-            size_converter: num::Converter::new(size_decimals),
+            size_converter: num::SizeConverter::new(size_decimals),
             leverage_converter,
+            fee_converter,
+            funding_rate_converter: num::Converter::new(5), // Funding rates are in 1/100K
             exchange: self,
         }
     }
@@ -301,7 +387,7 @@ impl TestExchange {
             udec64!(20),
         )
         .await
-        .with_mark_price(udec64!(100000))
+        .with_mark_price(udec64!(100000).into())
         .await
         .unpause()
         .await
@@ -320,7 +406,7 @@ impl TestExchange {
             udec64!(20),
         )
         .await
-        .with_mark_price(udec64!(4000))
+        .with_mark_price(udec64!(4000).into())
         .await
         .unpause()
         .await
@@ -339,7 +425,7 @@ impl TestExchange {
             udec64!(20),
         )
         .await
-        .with_mark_price(udec64!(200))
+        .with_mark_price(udec64!(200).into())
         .await
         .unpause()
         .await
@@ -358,7 +444,7 @@ impl TestExchange {
             udec64!(20),
         )
         .await
-        .with_mark_price(udec64!(0.3))
+        .with_mark_price(udec64!(0.3).into())
         .await
         .unpause()
         .await
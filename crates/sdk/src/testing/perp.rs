@@ -12,14 +12,16 @@ use crate::{error::DexError, num, types};
 pub struct TestPerp<'e> {
     pub id: types::PerpetualId,
     pub name: String,
-    pub price_converter: num::Converter,
-    pub size_converter: num::Converter,
+    pub price_converter: num::PriceConverter,
+    pub size_converter: num::SizeConverter,
     pub leverage_converter: num::Converter,
+    pub fee_converter: num::Converter,
+    pub funding_rate_converter: num::Converter,
     pub exchange: &'e TestExchange,
 }
 
 impl<'e> TestPerp<'e> {
-    pub async fn with_mark_price(self, price: UD64) -> Self {
+    pub async fn with_mark_price(self, price: num::Price) -> Self {
         self.exchange
             .exchange
             .updateMarkPricePNS(U256::from(self.id), self.price_converter.to_unsigned(price).to())
@@ -95,7 +97,57 @@ impl<'e> TestPerp<'e> {
             .unwrap()
     }
 
-    pub async fn set_mark_price(&self, price: UD64) -> PendingTransactionBuilder<Ethereum> {
+    pub async fn set_initial_margin(
+        &self,
+        initial_margin: UD64,
+    ) -> PendingTransactionBuilder<Ethereum> {
+        self.exchange
+            .exchange
+            .setInitialMarginFraction(
+                U256::from(self.id),
+                self.leverage_converter.to_unsigned(initial_margin),
+            )
+            .gas(500000)
+            .send()
+            .await
+            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
+            .unwrap()
+    }
+
+    pub async fn set_maker_fee(&self, fee: UD64) -> PendingTransactionBuilder<Ethereum> {
+        self.exchange
+            .exchange
+            .setMakerFee(U256::from(self.id), self.fee_converter.to_unsigned(fee))
+            .gas(500000)
+            .send()
+            .await
+            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
+            .unwrap()
+    }
+
+    pub async fn set_taker_fee(&self, fee: UD64) -> PendingTransactionBuilder<Ethereum> {
+        self.exchange
+            .exchange
+            .setTakerFee(U256::from(self.id), self.fee_converter.to_unsigned(fee))
+            .gas(500000)
+            .send()
+            .await
+            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
+            .unwrap()
+    }
+
+    pub async fn set_price_max_age(&self, max_age_sec: u64) -> PendingTransactionBuilder<Ethereum> {
+        self.exchange
+            .exchange
+            .setPriceMaxAge(U256::from(self.id), U256::from(max_age_sec))
+            .gas(500000)
+            .send()
+            .await
+            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
+            .unwrap()
+    }
+
+    pub async fn set_mark_price(&self, price: num::Price) -> PendingTransactionBuilder<Ethereum> {
         self.exchange
             .exchange
             .updateMarkPricePNS(U256::from(self.id), self.price_converter.to_unsigned(price).to())
@@ -186,4 +238,23 @@ impl<'e> TestPerp<'e> {
             .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
             .unwrap()
     }
+
+    pub async fn exec_ops(&self, ops: Vec<types::Op>) -> PendingTransactionBuilder<Ethereum> {
+        self.exchange
+            .exchange
+            .execPerpOps(
+                ops.iter()
+                    .map(|op| {
+                        op.to_op_desc(self.price_converter, self.funding_rate_converter)
+                            .unwrap()
+                    })
+                    .collect(),
+            )
+            .from(self.exchange.price_admin)
+            .gas(5000000)
+            .send()
+            .await
+            .map_err::<DexError, _>(|err| DexError::Provider(err.into()))
+            .unwrap()
+    }
 }
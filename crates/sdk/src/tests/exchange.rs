@@ -1,20 +1,34 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use alloy::primitives::{I256, TxHash, U256};
-use fastnum::udec128;
+use alloy::{
+    primitives::{Address, B256, Bytes, I256, TxHash, U256},
+    providers::{Provider, ProviderBuilder},
+    transports::mock::Asserter,
+};
+use fastnum::{dec64, dec256, udec64, udec128};
 
 use crate::{
     Chain,
     abi::dex::Exchange::{
-        AccountCreated, ExchangeEvents, MaintenanceMarginFractionUpdated, MakerOrderFilled,
-        OrderPlaced, PositionClosed, PositionOpened, RecycleFeeToAccount,
+        AccountCreated, CollateralDeposit, CollateralWithdrawal, ExchangeCalls, ExchangeEvents,
+        InitialMarginFractionUpdated, MaintenanceMarginFractionUpdated, MakerOrderFilled,
+        OrderPlaced, OrderRequest, PositionClosed, PositionOpened, RecycleFeeToAccount,
+        TakerOrderFilled, TransferAccountToProtocol, TransferProtocolToAccount,
     },
-    num::Converter,
-    state::{Exchange, OrderContext, Perpetual},
-    stream::RawEvent,
+    error::DexError,
+    num,
+    state::{
+        BalanceChangeReason, Exchange, Inconsistency, OrderContext, OrderEvent,
+        OrderEventType, Perpetual, PerpetualEvent, PerpetualEventType, SharedExchange,
+        StateAuditRecord, StateAuditSink, StateEvents, Tracking,
+    },
+    stream::{RawBlockEvents, RawEvent, RawExchangeEvent},
     types::{
-        self, OrderId, RequestId,
-        RequestType::{self, CloseLong},
+        self, OrderId, OrderSide, RequestId,
+        RequestType::{self, Cancel, Change, CloseLong, CloseShort, OpenLong, OpenShort},
         StateInstant,
     },
 };
@@ -24,7 +38,7 @@ const TEST_PERP_ID: u32 = 123456789;
 fn create_test_exchange() -> Exchange {
     let chain = Chain::testnet();
     let instant = StateInstant::new(0, 0);
-    let collateral_converter = Converter::new(4);
+    let collateral_converter = num::CollateralConverter::new(4);
 
     let perpetuals = HashMap::from([(TEST_PERP_ID, Perpetual::for_testing(TEST_PERP_ID))]);
     let accounts = HashMap::new();
@@ -34,9 +48,9 @@ fn create_test_exchange() -> Exchange {
         instant,
         collateral_converter,
         100,
-        udec128!(0.001),
-        udec128!(0.001),
-        udec128!(0.001),
+        udec128!(0.001).into(),
+        udec128!(0.001).into(),
+        udec128!(0.001).into(),
         perpetuals,
         accounts,
         false,
@@ -58,6 +72,7 @@ fn create_test_order_context(
         order_id: order_id_opt,
         r#type: request_type,
         price,
+        lot: U256::ZERO,
         expiry_block: 100_000_000,
         leverage: U256::from(5),
         post_only: false,
@@ -76,6 +91,22 @@ fn event_account_created(id: u64) -> ExchangeEvents {
     })
 }
 
+fn event_collateral_deposit(account_id: u64, balance: u64) -> ExchangeEvents {
+    ExchangeEvents::CollateralDeposit(CollateralDeposit {
+        accountId: U256::from(account_id),
+        amountCNS: U256::from(balance),
+        balanceCNS: U256::from(balance),
+    })
+}
+
+fn event_collateral_withdrawal(account_id: u64, balance: u64) -> ExchangeEvents {
+    ExchangeEvents::CollateralWithdrawal(CollateralWithdrawal {
+        accountId: U256::from(account_id),
+        amountCNS: U256::ZERO,
+        balanceCNS: U256::from(balance),
+    })
+}
+
 fn event_maintenance_margin(margin_fraction_hdths: u64) -> ExchangeEvents {
     ExchangeEvents::MaintenanceMarginFractionUpdated(MaintenanceMarginFractionUpdated {
         perpId: U256::from(TEST_PERP_ID),
@@ -155,7 +186,7 @@ fn apply_event(
     log_index: u64,
 ) {
     let instant = StateInstant::new(0, 0);
-    let raw_event = RawEvent::new(TxHash::ZERO, 0, log_index, exchange_event);
+    let raw_event = RawEvent::new(TxHash::ZERO, 0, log_index, exchange_event.into());
     exchange
         .apply_raw_event(instant, &raw_event, order_context)
         .expect("UT");
@@ -215,3 +246,1673 @@ fn test_smart_contract_position_closed_recycling_fee_to_account() {
     let perp = perps.get(&TEST_PERP_ID).expect("UT");
     assert!(perp.get_order(OrderId::new(1).expect("UT")).is_none());
 }
+
+fn exchange_with_orders(num_orders: u64) -> Exchange {
+    let mut exchange = create_test_exchange();
+    apply_event(&mut exchange, event_account_created(1), &mut None, 0);
+    for i in 1..=num_orders {
+        let mut order_context =
+            Some(create_test_order_context(i, None, 1, OpenLong, U256::from(100 + i)));
+        apply_event(&mut exchange, event_order_placed(i), &mut order_context, i);
+    }
+    exchange
+}
+
+#[test]
+fn test_approximate_memory_bytes() {
+    let empty = exchange_with_orders(0).approximate_memory_bytes();
+    let size_n = exchange_with_orders(1000).approximate_memory_bytes();
+    let size_2n = exchange_with_orders(2000).approximate_memory_bytes();
+
+    assert!(empty > 0);
+    assert!(size_n > empty);
+    assert!(size_2n > size_n);
+
+    // Growth is roughly linear in the number of orders
+    let (growth_n, growth_2n) = (size_n - empty, size_2n - empty);
+    assert!(growth_2n >= growth_n * 3 / 2, "{growth_n} -> {growth_2n}");
+    assert!(growth_2n <= growth_n * 5 / 2, "{growth_n} -> {growth_2n}");
+}
+
+#[test]
+fn test_exchange_summary() {
+    let mut exchange = exchange_with_orders(5);
+    apply_event(&mut exchange, event_account_created(2), &mut None, 6);
+    let instant = exchange.instant();
+
+    let summary = exchange.summary(&types::FixedClock(instant.block_timestamp() + 3));
+    assert_eq!(summary.instant, instant);
+    assert_eq!(summary.num_perpetuals, exchange.perpetuals().len());
+    assert_eq!(summary.num_accounts, exchange.accounts().len());
+    assert_eq!(summary.num_orders, exchange.perpetuals()[&TEST_PERP_ID].total_orders());
+    assert_eq!((summary.num_perpetuals, summary.num_accounts, summary.num_orders), (1, 2, 5));
+    assert!(!summary.is_halted);
+    assert_eq!(summary.lag, std::time::Duration::from_secs(3));
+
+    #[cfg(feature = "serde")]
+    assert_eq!(
+        serde_json::to_value(summary).unwrap(),
+        serde_json::json!({
+            "block_number": instant.block_number(),
+            "block_timestamp": instant.block_timestamp(),
+            "num_perpetuals": 1,
+            "num_accounts": 2,
+            "num_orders": 5,
+            "is_halted": false,
+            "lag_secs": 3,
+        })
+    );
+}
+
+#[test]
+fn test_clone_copy_on_write() {
+    const NUM_ORDERS: u64 = 10_000;
+    let mut exchange = exchange_with_orders(NUM_ORDERS);
+
+    // Cloning shares the state instead of copying it, see `benches/apply_events.rs`
+    let view = exchange.clone();
+    assert!(std::ptr::eq(view.perpetuals(), exchange.perpetuals()));
+    assert!(std::ptr::eq(view.accounts(), exchange.accounts()));
+
+    // Mutation copies the shared state, leaving the view intact
+    let mut ctx =
+        Some(create_test_order_context(NUM_ORDERS + 1, None, 1, OpenLong, U256::from(100)));
+    apply_event(&mut exchange, event_order_placed(NUM_ORDERS + 1), &mut ctx, 0);
+    apply_event(&mut exchange, event_collateral_deposit(1, 1000), &mut None, 1);
+    assert!(!std::ptr::eq(view.perpetuals(), exchange.perpetuals()));
+    assert_eq!(exchange.perpetuals()[&TEST_PERP_ID].total_orders(), NUM_ORDERS as usize + 1);
+    assert_eq!(view.perpetuals()[&TEST_PERP_ID].total_orders(), NUM_ORDERS as usize);
+    assert_eq!(exchange.accounts()[&1].balance(), udec128!(0.1).into());
+    assert!(view.accounts()[&1].balance().is_zero());
+
+    // Not shared anymore, mutated in place
+    let accounts: *const _ = exchange.accounts();
+    apply_event(&mut exchange, event_collateral_deposit(1, 2000), &mut None, 2);
+    assert!(std::ptr::eq(exchange.accounts(), accounts));
+    assert!(view.accounts()[&1].balance().is_zero());
+
+    // Entries not mutated stay shared along with the order book
+    let view = exchange.clone();
+    apply_event(&mut exchange, event_collateral_deposit(1, 3000), &mut None, 3);
+    assert!(Arc::ptr_eq(&view.perpetuals()[&TEST_PERP_ID], &exchange.perpetuals()[&TEST_PERP_ID]));
+    assert!(!Arc::ptr_eq(&view.accounts()[&1], &exchange.accounts()[&1]));
+}
+
+#[test]
+fn test_book_near_capacity() {
+    let mut exchange = create_test_exchange();
+    assert_eq!(exchange.book_capacity_threshold(), 0.9);
+    let mut place_order = |order_id: u64| {
+        let mut ctx = Some(create_test_order_context(order_id, None, 1, OpenLong, U256::from(100)));
+        let raw_event = RawEvent::new(TxHash::ZERO, 0, 0, event_order_placed(order_id).into());
+        exchange
+            .apply_raw_event(StateInstant::new(0, 0), &raw_event, &mut ctx)
+            .expect("UT")
+            .into_iter()
+            .filter_map(|event| match event {
+                StateEvents::Perpetual(PerpetualEvent {
+                    r#type: PerpetualEventType::BookNearCapacity(orders),
+                    ..
+                }) => Some(orders),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // 90% of 2^16-1 orders is crossed once, by the 58982nd order
+    for order_id in 1..58_982 {
+        assert!(place_order(order_id).is_empty());
+    }
+    assert_eq!(place_order(58_982), vec![58_982]);
+    assert!(place_order(58_983).is_empty());
+
+    let book = exchange.perpetuals()[&TEST_PERP_ID].l3_book();
+    assert!(book.is_near_capacity(0.9));
+    assert!(!book.is_near_capacity(0.95));
+}
+
+#[test]
+fn test_prune_emptied_account() {
+    let mut exchange = create_test_exchange();
+    let address = Address::repeat_byte(1);
+    let account_created =
+        ExchangeEvents::AccountCreated(AccountCreated { account: address, id: U256::from(1) });
+    apply_event(&mut exchange, account_created, &mut None, 0);
+    apply_event(&mut exchange, event_account_created(2), &mut None, 1);
+    apply_event(&mut exchange, event_collateral_deposit(1, 1000), &mut None, 2);
+    apply_event(&mut exchange, event_collateral_deposit(2, 1000), &mut None, 3);
+
+    assert_eq!(exchange.prune(), (0, 0));
+    assert_eq!(exchange.accounts().len(), 2);
+
+    apply_event(&mut exchange, event_collateral_withdrawal(1, 0), &mut None, 4);
+
+    assert_eq!(exchange.prune(), (1, 0));
+    assert!(!exchange.accounts().contains_key(&1));
+    assert!(exchange.accounts().contains_key(&2));
+
+    // Tracked again with the address kept
+    apply_event(&mut exchange, event_collateral_deposit(1, 1000), &mut None, 5);
+    assert_eq!(exchange.accounts()[&1].address(), address);
+}
+
+#[test]
+fn test_prune_keeps_accounts_with_orders() {
+    let mut exchange = exchange_with_orders(1);
+    assert_eq!(exchange.prune(), (0, 0));
+    assert!(exchange.accounts().contains_key(&1));
+}
+
+#[test]
+fn test_prune_keeps_shared_accounts() {
+    let mut exchange = create_test_exchange();
+    apply_event(&mut exchange, event_account_created(1), &mut None, 0);
+    apply_event(&mut exchange, event_account_created(2), &mut None, 1);
+    apply_event(&mut exchange, event_collateral_deposit(1, 1000), &mut None, 2);
+
+    // Kept accounts are only read, so stay shared with the clone
+    let view = exchange.clone();
+    assert_eq!(exchange.prune(), (1, 0));
+    assert!(!exchange.accounts().contains_key(&2));
+    assert!(view.accounts().contains_key(&2));
+    assert!(Arc::ptr_eq(&view.accounts()[&1], &exchange.accounts()[&1]));
+
+    // Nothing pruned, the map itself stays shared
+    let view = exchange.clone();
+    assert_eq!(exchange.prune(), (0, 0));
+    assert!(std::ptr::eq(view.accounts(), exchange.accounts()));
+}
+
+#[test]
+fn test_apply_events_with_unknown_event() {
+    let mut exchange = create_test_exchange();
+    let unknown =
+        RawExchangeEvent::Unknown { topic0: B256::repeat_byte(0xab), data: Bytes::new() };
+    let block = RawBlockEvents::new(
+        StateInstant::new(1, 1),
+        vec![
+            RawEvent::new(TxHash::ZERO, 0, 0, unknown),
+            RawEvent::new(TxHash::ZERO, 0, 1, event_account_created(1).into()),
+        ],
+    );
+
+    let state_events = exchange.apply_events(&block).expect("UT").expect("UT");
+    assert_eq!(state_events.events().len(), 1);
+    assert_eq!(exchange.instant(), StateInstant::new(1, 1));
+    assert!(exchange.accounts().contains_key(&1));
+}
+
+#[test]
+fn test_apply_events_block_gap() {
+    let block = |number| RawBlockEvents::new(StateInstant::new(number, number), vec![]);
+    let mut exchange = create_test_exchange();
+    exchange.apply_events(&block(1)).expect("UT");
+
+    // Block 2 is skipped
+    assert!(matches!(
+        exchange.apply_events(&block(3)),
+        Err(DexError::BlockGap { expected: 2, got: 3 })
+    ));
+    let event = RawEvent::new(TxHash::ZERO, 0, 0, event_account_created(1).into());
+    assert!(matches!(
+        exchange.apply_event(&event, StateInstant::new(3, 3)),
+        Err(DexError::BlockGap { expected: 2, got: 3 })
+    ));
+    assert_eq!(exchange.instant(), StateInstant::new(1, 1));
+
+    // Tolerating skipped empty blocks
+    exchange.set_max_skipped_blocks(1);
+    exchange.apply_events(&block(3)).expect("UT");
+    assert_eq!(exchange.instant(), StateInstant::new(3, 3));
+    assert!(matches!(
+        exchange.apply_events(&block(6)),
+        Err(DexError::BlockGap { expected: 4, got: 6 })
+    ));
+}
+
+#[test]
+fn test_apply_events_verify_books() {
+    let mut exchange = exchange_with_orders(3);
+    assert!(!exchange.verify_books());
+
+    // Consistent books pass verification of each applied block
+    exchange.set_verify_books(true);
+    for number in 1..=2 {
+        let block = RawBlockEvents::new(StateInstant::new(number, number), vec![]);
+        exchange.apply_events(&block).expect("UT");
+    }
+    assert!(exchange.verify_books());
+    assert_eq!(exchange.perpetuals()[&TEST_PERP_ID].total_orders(), 3);
+}
+
+#[test]
+fn test_apply_events_malformed_data() {
+    let apply = |events: Vec<ExchangeEvents>| {
+        let mut exchange = create_test_exchange();
+        let events = [event_account_created(1)]
+            .into_iter()
+            .chain(events)
+            .enumerate()
+            .map(|(i, event)| RawEvent::new(TxHash::ZERO, 0, i as u64, event.into()))
+            .collect();
+        exchange.apply_events(&RawBlockEvents::new(StateInstant::new(1, 1), events))
+    };
+
+    // Unknown order type
+    let mut request = event_order_request(1, 1, 0, OpenShort, 100, 1);
+    if let ExchangeEvents::OrderRequest(e) = &mut request {
+        e.orderType = 9;
+    }
+    assert!(matches!(apply(vec![request]), Err(DexError::MalformedData(_))));
+
+    // Account id out of range
+    let mut request = event_order_request(1, 1, 0, OpenShort, 100, 1);
+    if let ExchangeEvents::OrderRequest(e) = &mut request {
+        e.accountId = U256::MAX;
+    }
+    assert!(matches!(apply(vec![request]), Err(DexError::MalformedData(_))));
+
+    // Zero order id
+    let request = event_order_request(1, 1, 0, OpenShort, 100, 1);
+    assert!(matches!(apply(vec![request, event_order_placed(0)]), Err(DexError::MalformedData(_))));
+
+    // Unknown position type
+    let mut opened = event_position_opened(1);
+    if let ExchangeEvents::PositionOpened(e) = &mut opened {
+        e.positionType = 2;
+    }
+    assert!(matches!(apply(vec![opened]), Err(DexError::MalformedData(_))));
+
+    // Perpetual id out of range
+    let mut opened = event_position_opened(1);
+    if let ExchangeEvents::PositionOpened(e) = &mut opened {
+        e.perpId = U256::MAX;
+    }
+    assert!(matches!(apply(vec![opened]), Err(DexError::MalformedData(_))));
+
+    // Position account id out of range
+    let mut opened = event_position_opened(1);
+    if let ExchangeEvents::PositionOpened(e) = &mut opened {
+        e.accountId = U256::MAX;
+    }
+    assert!(matches!(apply(vec![opened]), Err(DexError::MalformedData(_))));
+
+    // Created account id out of range
+    assert!(matches!(
+        apply(vec![event_account_created(u32::MAX as u64 + 1)]),
+        Err(DexError::MalformedData(_))
+    ));
+
+    // Order price out of range
+    let request = event_order_request(1, 1, 0, OpenShort, 100, 1);
+    let mut request_price = request.clone();
+    if let ExchangeEvents::OrderRequest(e) = &mut request_price {
+        e.pricePNS = U256::MAX;
+    }
+    assert!(matches!(
+        apply(vec![request_price, event_order_placed(1)]),
+        Err(DexError::MalformedData(_))
+    ));
+
+    // Order lot out of range
+    let mut placed = event_order_placed(1);
+    if let ExchangeEvents::OrderPlaced(e) = &mut placed {
+        e.lotLNS = U256::MAX;
+    }
+    assert!(matches!(apply(vec![request, placed]), Err(DexError::MalformedData(_))));
+
+    // Position price and lot out of range
+    for field in [
+        |e: &mut PositionOpened| e.pricePNS = U256::MAX,
+        |e: &mut PositionOpened| e.lotLNS = U256::MAX,
+    ] {
+        let mut opened = event_position_opened(1);
+        if let ExchangeEvents::PositionOpened(e) = &mut opened {
+            field(e);
+        }
+        assert!(matches!(apply(vec![opened]), Err(DexError::MalformedData(_))));
+    }
+
+    // Collateral amount out of range
+    let mut deposit = event_collateral_deposit(1, 1000);
+    if let ExchangeEvents::CollateralDeposit(e) = &mut deposit {
+        e.balanceCNS = U256::MAX;
+    }
+    assert!(matches!(apply(vec![deposit]), Err(DexError::MalformedData(_))));
+}
+
+#[test]
+fn test_maker_fill_remaining_size() {
+    let mut exchange = create_test_exchange();
+    apply_event(&mut exchange, event_account_created(1), &mut None, 0);
+    let mut maker_context = Some(create_test_order_context(1, None, 1, OpenLong, U256::from(100)));
+    let order_placed = ExchangeEvents::OrderPlaced(OrderPlaced {
+        orderId: U256::from(1),
+        lotLNS: U256::from(5),
+        lockedBalanceCNS: U256::ZERO,
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::ZERO,
+    });
+    apply_event(&mut exchange, order_placed, &mut maker_context, 1);
+
+    let fill = |lots: u64| {
+        ExchangeEvents::MakerOrderFilled(MakerOrderFilled {
+            perpId: U256::from(TEST_PERP_ID),
+            accountId: U256::from(1),
+            orderId: U256::from(1),
+            pricePNS: U256::from(100),
+            lotLNS: U256::from(lots),
+            feeCNS: U256::from(10),
+            lockedBalanceCNS: U256::ZERO,
+            amountCNS: I256::ZERO,
+            balanceCNS: U256::ZERO,
+        })
+    };
+    let maker_fills = |exchange: &mut Exchange, lots, log_index| {
+        let mut taker_context =
+            Some(create_test_order_context(2, None, 2, RequestType::OpenShort, U256::from(100)));
+        let raw_event = RawEvent::new(TxHash::ZERO, 0, log_index, fill(lots).into());
+        exchange
+            .apply_raw_event(StateInstant::new(0, 0), &raw_event, &mut taker_context)
+            .expect("UT")
+            .into_iter()
+            .filter_map(|event| match event {
+                StateEvents::Order(OrderEvent {
+                    account_id: 1,
+                    order_id: Some(order_id),
+                    r#type:
+                        OrderEventType::Filled { fill_price, fill_size, remaining_size, fee, is_maker },
+                    ..
+                }) if order_id.get() == 1 && is_maker => {
+                    Some((fill_price, fill_size, remaining_size, fee))
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Partial fill leaves the rest of the order in the book
+    assert_eq!(
+        maker_fills(&mut exchange, 2, 2),
+        vec![(udec64!(100), udec64!(2), Some(udec64!(3)), udec64!(0.001))]
+    );
+    let perps = exchange.perpetuals();
+    let perp = perps.get(&TEST_PERP_ID).expect("UT");
+    let order = perp.get_order(OrderId::new(1).expect("UT")).expect("UT");
+    assert_eq!(order.size(), udec64!(3).into());
+
+    // Complete fill removes the order
+    assert_eq!(
+        maker_fills(&mut exchange, 3, 3),
+        vec![(udec64!(100), udec64!(3), Some(udec64!(0)), udec64!(0.001))]
+    );
+    let perps = exchange.perpetuals();
+    let perp = perps.get(&TEST_PERP_ID).expect("UT");
+    assert!(perp.get_order(OrderId::new(1).expect("UT")).is_none());
+}
+
+#[test]
+fn test_shared_exchange_readers_and_writer() {
+    const BLOCKS: u64 = 200;
+    let shared = SharedExchange::new(create_test_exchange());
+
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            let reader = shared.clone();
+            scope.spawn(move || {
+                loop {
+                    // Each block creates one account, so a consistent view has
+                    // as many accounts as blocks applied
+                    let (block, num_accounts) = reader.with(|exchange| {
+                        (exchange.instant().block_number(), exchange.accounts().len())
+                    });
+                    assert_eq!(block, num_accounts as u64);
+                    if block == BLOCKS {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let writer = shared.clone();
+        scope.spawn(move || {
+            for block in 1..=BLOCKS {
+                let events = RawBlockEvents::new(
+                    StateInstant::new(block, block),
+                    vec![RawEvent::new(TxHash::ZERO, 0, 0, event_account_created(block).into())],
+                );
+                writer.apply_events(&events).expect("UT").expect("UT");
+            }
+        });
+    });
+
+    assert_eq!(shared.instant(), StateInstant::new(BLOCKS, BLOCKS));
+    assert_eq!(shared.snapshot().accounts().len(), BLOCKS as usize);
+}
+
+#[test]
+fn test_frozen_exchange_unaffected_by_updates() {
+    let shared = SharedExchange::new(create_test_exchange());
+    let block = |block: u64| {
+        RawBlockEvents::new(
+            StateInstant::new(block, block),
+            vec![RawEvent::new(TxHash::ZERO, 0, 0, event_account_created(block).into())],
+        )
+    };
+    shared.apply_events(&block(1)).expect("UT");
+
+    let frozen = shared.freeze();
+    let copy = frozen.clone();
+    shared.apply_events(&block(2)).expect("UT");
+    shared.apply_events(&block(3)).expect("UT");
+
+    for view in [&frozen, &copy] {
+        assert_eq!(view.instant(), StateInstant::new(1, 1));
+        assert_eq!(view.accounts().len(), 1);
+    }
+    assert_eq!(shared.instant(), StateInstant::new(3, 3));
+    assert_eq!(shared.freeze().accounts().len(), 3);
+
+    // Frozen copy of a plain snapshot
+    let mut exchange = shared.snapshot();
+    let frozen = exchange.freeze();
+    exchange.apply_events(&block(4)).expect("UT");
+    assert_eq!(frozen.instant(), StateInstant::new(3, 3));
+    assert_eq!(exchange.accounts().len(), 4);
+}
+
+fn event_order_request(
+    account_id: u64,
+    request_id: RequestId,
+    order_id: u64,
+    request_type: RequestType,
+    price: u64,
+    lot: u64,
+) -> ExchangeEvents {
+    ExchangeEvents::OrderRequest(OrderRequest {
+        perpId: U256::from(TEST_PERP_ID),
+        accountId: U256::from(account_id),
+        orderDescId: U256::from(request_id),
+        orderId: U256::from(order_id),
+        orderType: request_type as u8,
+        pricePNS: U256::from(price),
+        lotLNS: U256::from(lot),
+        expiryBlock: U256::ZERO,
+        postOnly: false,
+        fillOrKill: false,
+        immediateOrCancel: false,
+        maxMatches: U256::ZERO,
+        leverageHdths: U256::from(500),
+        lastExecutionBlock: U256::ZERO,
+        amountCNS: U256::ZERO,
+        maxNegPnlCollatBPS: U256::ZERO,
+        gasLeft: U256::ZERO,
+    })
+}
+
+#[test]
+fn test_change_order_across_spread() {
+    let mut exchange = create_test_exchange();
+    let mut ctx = None;
+    apply_event(&mut exchange, event_account_created(1), &mut ctx, 0);
+    apply_event(&mut exchange, event_account_created(2), &mut ctx, 1);
+
+    // Resting ask of 2 @ 100 and bid of 1 @ 90
+    apply_event(&mut exchange, event_order_request(1, 1, 0, OpenShort, 100, 2), &mut ctx, 2);
+    let order_placed = ExchangeEvents::OrderPlaced(OrderPlaced {
+        orderId: U256::from(1),
+        lotLNS: U256::from(2),
+        lockedBalanceCNS: U256::ZERO,
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::ZERO,
+    });
+    apply_event(&mut exchange, order_placed, &mut ctx, 3);
+    apply_event(&mut exchange, event_order_request(2, 2, 0, OpenLong, 90, 1), &mut ctx, 4);
+    apply_event(&mut exchange, event_order_placed(2), &mut ctx, 5);
+
+    // Bid changed to 105 crosses the ask and gets completely filled as a taker
+    apply_event(&mut exchange, event_order_request(2, 3, 2, Change, 105, 1), &mut ctx, 6);
+    let maker_filled = ExchangeEvents::MakerOrderFilled(MakerOrderFilled {
+        perpId: U256::from(TEST_PERP_ID),
+        accountId: U256::from(1),
+        orderId: U256::from(1),
+        pricePNS: U256::from(100),
+        lotLNS: U256::from(1),
+        feeCNS: U256::ZERO,
+        lockedBalanceCNS: U256::ZERO,
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::ZERO,
+    });
+    apply_event(&mut exchange, maker_filled, &mut ctx, 7);
+    let taker_filled = ExchangeEvents::TakerOrderFilled(TakerOrderFilled {
+        entryPricePNS: U256::from(100),
+        collatPricePNS: U256::from(100),
+        pnlPricePNS: U256::from(100),
+        lotLNS: U256::from(1),
+        feeCNS: U256::from(10),
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::ZERO,
+    });
+    let raw_event = RawEvent::new(TxHash::ZERO, 0, 8, taker_filled.into());
+    let events = exchange
+        .apply_raw_event(StateInstant::new(0, 0), &raw_event, &mut ctx)
+        .expect("UT");
+
+    assert!(events.iter().any(|e| matches!(
+        e,
+        StateEvents::Order(OrderEvent {
+            account_id: 2,
+            order_id: Some(order_id),
+            r#type: OrderEventType::Filled { remaining_size: Some(remaining), is_maker: false, .. },
+            ..
+        }) if order_id.get() == 2 && remaining.is_zero()
+    )));
+    assert!(events.iter().any(|e| matches!(
+        e,
+        StateEvents::Order(OrderEvent {
+            order_id: Some(order_id),
+            r#type: OrderEventType::Removed,
+            ..
+        }) if order_id.get() == 2
+    )));
+    assert!(events.iter().any(|e| matches!(
+        e,
+        StateEvents::Trade(trade) if trade.taker_side == OrderSide::Bid
+    )));
+
+    // Bid does not rest above the market
+    let perps = exchange.perpetuals();
+    let book = perps.get(&TEST_PERP_ID).expect("UT").l3_book();
+    assert!(book.get_order(OrderId::new(2).expect("UT")).is_none());
+    assert_eq!(book.best_bid(), None);
+    assert_eq!(book.best_ask(), Some((num::Price::new(udec64!(100)), num::Size::new(udec64!(1)))));
+}
+
+/// Places `num_orders` resting asks of account 1, the best one is then
+/// filled by account 2, returning the state events of the fill.
+fn apply_book_and_fill(exchange: &mut Exchange, num_orders: u64) -> Vec<StateEvents> {
+    let mut ctx = None;
+    apply_event(exchange, event_account_created(1), &mut ctx, 0);
+    apply_event(exchange, event_account_created(2), &mut ctx, 1);
+    apply_event(exchange, event_collateral_deposit(1, 1_000_000), &mut ctx, 2);
+    apply_event(exchange, event_collateral_deposit(2, 1_000_000), &mut ctx, 3);
+    apply_event(exchange, event_maintenance_margin(500), &mut ctx, 4);
+
+    let mut log_index = 5;
+    for i in 1..=num_orders {
+        let request = event_order_request(1, i, 0, OpenShort, 100 + i, 1);
+        apply_event(exchange, request, &mut ctx, log_index);
+        let order_placed = ExchangeEvents::OrderPlaced(OrderPlaced {
+            orderId: U256::from(i),
+            lotLNS: U256::from(1),
+            lockedBalanceCNS: U256::ZERO,
+            amountCNS: I256::ZERO,
+            balanceCNS: U256::from(1_000_000),
+        });
+        apply_event(exchange, order_placed, &mut ctx, log_index + 1);
+        log_index += 2;
+    }
+
+    let position_opened = |account_id: u64, position_type: u8| {
+        ExchangeEvents::PositionOpened(PositionOpened {
+            perpId: U256::from(TEST_PERP_ID),
+            accountId: U256::from(account_id),
+            positionType: position_type,
+            leverageHdths: U256::from(500),
+            depositCNS: U256::from(202_000),
+            pnlCollateralizedCNS: I256::ZERO,
+            pricePNS: U256::from(101),
+            lotLNS: U256::from(1),
+            insFeeCNS: U256::ZERO,
+            protFeeCNS: U256::ZERO,
+        })
+    };
+    let maker_filled = ExchangeEvents::MakerOrderFilled(MakerOrderFilled {
+        perpId: U256::from(TEST_PERP_ID),
+        accountId: U256::from(1),
+        orderId: U256::from(1),
+        pricePNS: U256::from(101),
+        lotLNS: U256::from(1),
+        feeCNS: U256::from(10),
+        lockedBalanceCNS: U256::ZERO,
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::from(797_990),
+    });
+    let taker_filled = ExchangeEvents::TakerOrderFilled(TakerOrderFilled {
+        entryPricePNS: U256::from(101),
+        collatPricePNS: U256::from(101),
+        pnlPricePNS: U256::from(101),
+        lotLNS: U256::from(1),
+        feeCNS: U256::from(30),
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::from(797_970),
+    });
+    let request = event_order_request(2, num_orders + 1, 0, OpenLong, 101, 1);
+    [request, position_opened(1, 1), maker_filled, position_opened(2, 0), taker_filled]
+        .into_iter()
+        .flat_map(|event| {
+            log_index += 1;
+            let raw_event = RawEvent::new(TxHash::ZERO, 0, log_index, event.into());
+            exchange
+                .apply_raw_event(StateInstant::new(0, 0), &raw_event, &mut ctx)
+                .expect("UT")
+        })
+        .collect()
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_tracking_without_books() {
+    const NUM_ORDERS: u64 = 10_000;
+
+    let mut full = create_test_exchange();
+    let full_events = apply_book_and_fill(&mut full, NUM_ORDERS);
+
+    let tracking = Tracking { books: false, ..Tracking::ALL };
+    let mut book_free = create_test_exchange().with_tracking(tracking);
+    assert_eq!(book_free.tracking(), tracking);
+    let book_free_events = apply_book_and_fill(&mut book_free, NUM_ORDERS);
+
+    // No book maintained, while the cost of it is gone, see
+    // `benches/apply_events.rs`
+    assert_eq!(full.perpetuals()[&TEST_PERP_ID].total_orders(), NUM_ORDERS as usize - 1);
+    assert_eq!(book_free.perpetuals()[&TEST_PERP_ID].total_orders(), 0);
+    assert!(book_free.approximate_memory_bytes() < full.approximate_memory_bytes());
+    assert!(
+        !book_free_events
+            .iter()
+            .any(|e| matches!(e, StateEvents::Order(_)))
+    );
+
+    // Positions, balances and trades are the same
+    assert_eq!(book_free.accounts(), full.accounts());
+    assert_eq!(
+        book_free.perpetuals()[&TEST_PERP_ID].last_price(),
+        full.perpetuals()[&TEST_PERP_ID].last_price()
+    );
+    let trades = |events: &[StateEvents]| {
+        events
+            .iter()
+            .filter_map(StateEvents::as_trade)
+            .map(|t| (t.taker_account_id, t.taker_side, t.maker_fills.len(), t.total_size()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(trades(&book_free_events), vec![(2, OrderSide::Bid, 1, udec64!(1))]);
+    assert_eq!(trades(&book_free_events), trades(&full_events));
+
+    // Trades can be skipped as well
+    let mut positions_only =
+        create_test_exchange().with_tracking(Tracking { trades: false, ..tracking });
+    let events = apply_book_and_fill(&mut positions_only, 1);
+    assert!(trades(&events).is_empty());
+    assert_eq!(positions_only.accounts()[&2].positions(), full.accounts()[&2].positions());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_apply_event_matches_apply_events() {
+    let raw_event = |tx_index, log_index, event: ExchangeEvents| {
+        RawEvent::new(TxHash::ZERO, tx_index, log_index, event.into())
+    };
+    let block = RawBlockEvents::new(
+        StateInstant::new(1, 1),
+        vec![
+            raw_event(0, 0, event_account_created(1)),
+            raw_event(0, 1, event_collateral_deposit(1, 1000)),
+            raw_event(1, 2, event_account_created(2)),
+            raw_event(2, 3, event_order_request(1, 1, 0, OpenLong, 90, 1)),
+            raw_event(2, 4, event_order_placed(1)),
+            raw_event(3, 5, event_maintenance_margin(500)),
+        ],
+    );
+
+    let mut by_block = create_test_exchange();
+    by_block.apply_events(&block).expect("UT");
+
+    let mut by_event = create_test_exchange();
+    let mut num_events = 0;
+    for event in block.events() {
+        num_events += by_event.apply_event(event, block.instant()).expect("UT").len();
+    }
+    by_event.complete_block();
+    assert!(num_events > 0);
+
+    assert_eq!(by_event.instant(), by_block.instant());
+    assert_eq!(by_event.accounts(), by_block.accounts());
+    assert_eq!(by_event.perpetuals(), by_block.perpetuals());
+
+    // Block number survives flattening
+    let state_events = create_test_exchange().apply_events(&block).expect("UT").expect("UT");
+    assert!(
+        state_events
+            .events()
+            .iter()
+            .all(|ctx| ctx.block_number() == block.instant().block_number())
+    );
+
+    // Block applied event by event is not applied again
+    assert!(by_event.apply_events(&block).expect("UT").is_none());
+    let event = &block.events()[1];
+    assert!(by_event.apply_event(event, block.instant()).expect("UT").is_empty());
+}
+
+#[test]
+fn test_apply_events_paired() {
+    let raw_event = |tx_index, log_index, event: ExchangeEvents| {
+        RawEvent::new(TxHash::ZERO, tx_index, log_index, event.into())
+    };
+    let block = RawBlockEvents::new(
+        StateInstant::new(1, 1),
+        vec![
+            raw_event(0, 0, event_account_created(1)),
+            raw_event(0, 1, event_collateral_deposit(1, 1000)),
+            raw_event(1, 2, event_account_created(2)),
+            raw_event(2, 3, event_order_request(1, 1, 0, OpenLong, 90, 1)),
+            raw_event(2, 4, event_order_placed(1)),
+            raw_event(3, 5, event_maintenance_margin(500)),
+        ],
+    );
+    let state_events = create_test_exchange().apply_events(&block).expect("UT").expect("UT");
+
+    let mut exchange = create_test_exchange();
+    let result = exchange
+        .apply_events_paired(&block)
+        .expect("UT")
+        .expect("UT");
+    assert_eq!(result.instant, block.instant());
+    assert_eq!(result.per_event.len(), block.events().len());
+    assert!(result.block_events.is_empty());
+
+    // Each state event maps back to the raw event it was produced from
+    for ((raw, _), expected) in result.per_event.iter().zip(block.events()) {
+        assert_eq!((raw.tx_index(), raw.log_index()), (expected.tx_index(), expected.log_index()));
+    }
+    for ctx in state_events.events() {
+        let (raw, produced) = &result.per_event[ctx.log_index() as usize];
+        assert_eq!((raw.tx_index(), raw.log_index()), (ctx.tx_index(), ctx.log_index()));
+        assert_eq!(format!("{produced:?}"), format!("{:?}", ctx.event()));
+    }
+    let num_paired = result
+        .per_event
+        .iter()
+        .map(|(_, produced)| produced.len())
+        .sum::<usize>();
+    let num_state = state_events
+        .events()
+        .iter()
+        .map(|ctx| ctx.event().len())
+        .sum::<usize>();
+    assert!(num_paired > 0);
+    assert_eq!(num_paired, num_state);
+
+    assert!(exchange.apply_events_paired(&block).expect("UT").is_none());
+}
+
+#[test]
+fn test_rollback_to_fork() {
+    let block = |number, events: Vec<ExchangeEvents>| {
+        RawBlockEvents::new(
+            StateInstant::new(number, number * 2),
+            events
+                .into_iter()
+                .enumerate()
+                .map(|(idx, event)| RawEvent::new(TxHash::ZERO, 0, idx as u64, event.into()))
+                .collect(),
+        )
+    };
+    let balance = |exchange: &Exchange, id| exchange.accounts().get(&id).map(|acc| acc.balance());
+
+    let mut exchange = create_test_exchange();
+    exchange.set_rollback_capacity(2);
+    exchange
+        .apply_events(&block(1, vec![event_account_created(1), event_collateral_deposit(1, 10000)]))
+        .expect("UT");
+    exchange
+        .apply_events(&block(2, vec![event_collateral_deposit(1, 20000)]))
+        .expect("UT");
+    exchange
+        .apply_events(&block(3, vec![event_account_created(2)]))
+        .expect("UT");
+    assert_eq!(balance(&exchange, 1), Some(num::Collateral::new(udec128!(2))));
+    assert!(exchange.accounts().contains_key(&2));
+
+    // Block 1 is beyond the capacity
+    assert!(matches!(
+        exchange.rollback_to(StateInstant::new(0, 0)),
+        Err(DexError::RollbackUnavailable(0))
+    ));
+
+    // Settings changed past the block are retained
+    exchange.set_max_skipped_blocks(5);
+    exchange.set_book_capacity_threshold(0.5);
+    exchange.set_balance_history_capacity(4);
+    exchange.set_book_journal_capacity(8);
+    let tracking = Tracking { trades: false, ..Tracking::ALL };
+    let mut exchange = exchange.with_tracking(tracking);
+
+    exchange.rollback_to(StateInstant::new(1, 2)).expect("UT");
+    assert_eq!(exchange.instant(), StateInstant::new(1, 2));
+    assert_eq!(balance(&exchange, 1), Some(num::Collateral::new(udec128!(1))));
+    assert!(!exchange.accounts().contains_key(&2));
+    assert_eq!(exchange.max_skipped_blocks(), 5);
+    assert_eq!(exchange.book_capacity_threshold(), 0.5);
+    assert_eq!(exchange.balance_history_capacity(), 4);
+    assert_eq!(exchange.book_journal_capacity(), 8);
+    assert_eq!(exchange.tracking(), tracking);
+
+    // Not past the block
+    exchange.rollback_to(StateInstant::new(1, 2)).expect("UT");
+    assert_eq!(exchange.instant(), StateInstant::new(1, 2));
+
+    // Apply the new fork
+    exchange
+        .apply_events(&block(2, vec![event_collateral_deposit(1, 30000)]))
+        .expect("UT")
+        .expect("UT");
+    exchange
+        .apply_events(&block(3, vec![event_account_created(3)]))
+        .expect("UT")
+        .expect("UT");
+    assert_eq!(exchange.instant(), StateInstant::new(3, 6));
+    assert_eq!(balance(&exchange, 1), Some(num::Collateral::new(udec128!(3))));
+    assert!(!exchange.accounts().contains_key(&2));
+    assert!(exchange.accounts().contains_key(&3));
+
+    exchange.rollback_to(StateInstant::new(2, 4)).expect("UT");
+    assert_eq!(balance(&exchange, 1), Some(num::Collateral::new(udec128!(3))));
+    assert!(!exchange.accounts().contains_key(&3));
+}
+
+#[test]
+fn test_rollback_journals_mutated_only() {
+    let block = |number, events: Vec<ExchangeEvents>| {
+        RawBlockEvents::new(
+            StateInstant::new(number, number * 2),
+            events
+                .into_iter()
+                .enumerate()
+                .map(|(idx, event)| RawEvent::new(TxHash::ZERO, 0, idx as u64, event.into()))
+                .collect(),
+        )
+    };
+    let mut order_request = event_order_request(1, 1, 0, OpenLong, 90, 1);
+    if let ExchangeEvents::OrderRequest(request) = &mut order_request {
+        request.expiryBlock = U256::from(4);
+    }
+
+    let mut exchange = Exchange::for_test(
+        4,
+        vec![Perpetual::for_testing(TEST_PERP_ID), Perpetual::for_testing(TEST_PERP_ID + 1)],
+    );
+    exchange.set_rollback_capacity(4);
+    exchange.set_book_journal_capacity(8);
+    exchange
+        .apply_events(&block(1, vec![event_account_created(1), event_collateral_deposit(1, 10000)]))
+        .expect("UT");
+    exchange
+        .apply_events(&block(2, vec![order_request, event_order_placed(1)]))
+        .expect("UT");
+    exchange
+        .apply_events(&block(3, vec![event_collateral_deposit(1, 20000)]))
+        .expect("UT");
+    let book = exchange.perpetuals()[&TEST_PERP_ID].l3_book().clone();
+    exchange.apply_events(&block(4, vec![])).expect("UT");
+
+    // Only the block placing the order journals the perpetual contract, the
+    // rest retain just the order expired
+    assert_eq!(exchange.undo_log_len(), (1, 3, 1));
+    let perp = &exchange.perpetuals()[&TEST_PERP_ID];
+    let order = perp.get_order(OrderId::new(1).unwrap()).expect("UT");
+    assert!(order.is_expired());
+    assert_ne!(perp.l3_book(), &book);
+
+    exchange.rollback_to(StateInstant::new(3, 6)).expect("UT");
+    let perp = &exchange.perpetuals()[&TEST_PERP_ID];
+    assert_eq!(perp.state_instant(), StateInstant::new(3, 6));
+    assert_eq!(perp.l3_book(), &book);
+    assert_eq!(exchange.perpetuals()[&(TEST_PERP_ID + 1)].state_instant(), StateInstant::new(3, 6));
+
+    exchange.rollback_to(StateInstant::new(1, 2)).expect("UT");
+    assert_eq!(exchange.perpetuals()[&TEST_PERP_ID].total_orders(), 0);
+    assert_eq!(exchange.perpetuals()[&(TEST_PERP_ID + 1)].state_instant(), StateInstant::new(1, 2));
+}
+
+#[test]
+fn test_trade_pnl_closing_profitable_long() {
+    let mut exchange = create_test_exchange();
+    let mut ctx = None;
+    apply_event(&mut exchange, event_account_created(1), &mut ctx, 0);
+    apply_event(&mut exchange, event_account_created(2), &mut ctx, 1);
+    apply_event(&mut exchange, event_maintenance_margin(500), &mut ctx, 2);
+
+    // Long of 2 @ 100
+    let position_opened = ExchangeEvents::PositionOpened(PositionOpened {
+        perpId: U256::from(TEST_PERP_ID),
+        accountId: U256::from(1),
+        positionType: 0,
+        leverageHdths: U256::ZERO,
+        depositCNS: U256::ZERO,
+        pnlCollateralizedCNS: Default::default(),
+        pricePNS: U256::from(100),
+        lotLNS: U256::from(2),
+        insFeeCNS: U256::ZERO,
+        protFeeCNS: U256::ZERO,
+    });
+    apply_event(&mut exchange, position_opened, &mut ctx, 3);
+
+    // Taker sells 1.5 to the maker @ 110
+    let mut trade = types::Trade {
+        perpetual_id: TEST_PERP_ID,
+        taker_account_id: 1,
+        taker_request_id: 1,
+        taker_side: OrderSide::Ask,
+        taker_fee: udec64!(0.1),
+        maker_fills: vec![types::MakerFill {
+            log_index: 4,
+            maker_account_id: 2,
+            maker_order_id: OrderId::new(1).expect("UT"),
+            price: udec64!(110),
+            size: udec64!(1.5),
+            fee: udec64!(0.05),
+        }],
+    };
+    assert_eq!(exchange.trade_pnl(&trade, 1), Some(dec256!(15)));
+    // Maker has no position to close
+    assert_eq!(exchange.trade_pnl(&trade, 2), None);
+
+    // Closed size is capped by the position size
+    trade.maker_fills[0].size = udec64!(3);
+    assert_eq!(exchange.trade_pnl(&trade, 1), Some(dec256!(20)));
+
+    // Buying increases the long rather than closing it
+    trade.taker_side = OrderSide::Bid;
+    assert_eq!(exchange.trade_pnl(&trade, 1), None);
+}
+
+#[test]
+fn test_set_mark_price_updates_position_pnl() {
+    let mut exchange = create_test_exchange();
+    let mut ctx = None;
+    apply_event(&mut exchange, event_account_created(1), &mut ctx, 0);
+    apply_event(&mut exchange, event_maintenance_margin(500), &mut ctx, 1);
+
+    // Long of 2 @ 100
+    let position_opened = ExchangeEvents::PositionOpened(PositionOpened {
+        perpId: U256::from(TEST_PERP_ID),
+        accountId: U256::from(1),
+        positionType: 0,
+        leverageHdths: U256::ZERO,
+        depositCNS: U256::ZERO,
+        pnlCollateralizedCNS: Default::default(),
+        pricePNS: U256::from(100),
+        lotLNS: U256::from(2),
+        insFeeCNS: U256::ZERO,
+        protFeeCNS: U256::ZERO,
+    });
+    apply_event(&mut exchange, position_opened, &mut ctx, 2);
+
+    let instant = StateInstant::new(1, 1);
+    let events = exchange
+        .set_mark_price(TEST_PERP_ID, udec64!(107.5).into(), instant)
+        .expect("UT");
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[1], StateEvents::Position(_)));
+
+    let perp = &exchange.perpetuals()[&TEST_PERP_ID];
+    assert_eq!(perp.mark_price(), udec64!(107.5).into());
+    assert_eq!(perp.mark_price_instant(), instant);
+    let pos = &exchange.accounts()[&1].positions()[&TEST_PERP_ID];
+    assert_eq!(pos.delta_pnl(), dec256!(15));
+    assert_eq!(pos.pnl(), dec256!(15));
+
+    assert!(matches!(
+        exchange.set_mark_price(TEST_PERP_ID + 1, udec64!(1).into(), instant),
+        Err(DexError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_raw_account_and_position() {
+    let mut exchange = create_test_exchange();
+    let mut ctx = None;
+    apply_event(&mut exchange, event_account_created(1), &mut ctx, 0);
+    apply_event(&mut exchange, event_collateral_deposit(1, 1_000_000), &mut ctx, 1);
+    apply_event(&mut exchange, event_maintenance_margin(500), &mut ctx, 2);
+
+    let cc = exchange.collateral_converter();
+    let account = &exchange.accounts()[&1];
+    let raw = account.raw(&exchange);
+    assert_eq!(raw.balance_cns, U256::from(1_000_000));
+    assert_eq!(raw.locked_balance_cns, U256::ZERO);
+    assert_eq!(cc.from_unsigned(raw.balance_cns), account.balance());
+
+    // Long of 2 @ 100
+    let position_opened = ExchangeEvents::PositionOpened(PositionOpened {
+        perpId: U256::from(TEST_PERP_ID),
+        accountId: U256::from(1),
+        positionType: 0,
+        leverageHdths: U256::ZERO,
+        depositCNS: U256::from(202_000),
+        pnlCollateralizedCNS: Default::default(),
+        pricePNS: U256::from(100),
+        lotLNS: U256::from(2),
+        insFeeCNS: U256::ZERO,
+        protFeeCNS: U256::ZERO,
+    });
+    apply_event(&mut exchange, position_opened, &mut ctx, 3);
+    exchange
+        .set_mark_price(TEST_PERP_ID, udec64!(107.5).into(), StateInstant::new(1, 1))
+        .expect("UT");
+
+    let sc = exchange.perpetuals()[&TEST_PERP_ID].size_converter();
+    let position = &exchange.accounts()[&1].positions()[&TEST_PERP_ID];
+    let raw = position.raw(&exchange).expect("known perpetual");
+    assert_eq!(raw.lot_lns, U256::from(2));
+    assert_eq!(raw.deposit_cns, U256::from(202_000));
+    assert_eq!(raw.delta_pnl_cns, I256::try_from(150_000).unwrap());
+    assert_eq!(raw.premium_pnl_cns, I256::ZERO);
+    assert_eq!(sc.from_unsigned(raw.lot_lns), position.size());
+    assert_eq!(cc.from_unsigned(raw.deposit_cns), position.deposit());
+    assert_eq!(cc.from_signed::<4>(raw.delta_pnl_cns), position.delta_pnl());
+}
+
+#[test]
+fn test_account_gross_and_net_notional() {
+    let mut exchange =
+        Exchange::for_test(4, vec![Perpetual::for_testing(1), Perpetual::for_testing(2)]);
+    let mut ctx = None;
+    apply_event(&mut exchange, event_account_created(1), &mut ctx, 0);
+    for perp_id in [1, 2] {
+        let maintenance_margin =
+            ExchangeEvents::MaintenanceMarginFractionUpdated(MaintenanceMarginFractionUpdated {
+                perpId: U256::from(perp_id),
+                maintMarginFracHdths: U256::from(500),
+            });
+        apply_event(&mut exchange, maintenance_margin, &mut ctx, perp_id);
+    }
+
+    let position_opened = |perp_id: u64, position_type: u8, price: u64, lot: u64| {
+        ExchangeEvents::PositionOpened(PositionOpened {
+            perpId: U256::from(perp_id),
+            accountId: U256::from(1),
+            positionType: position_type,
+            leverageHdths: U256::ZERO,
+            depositCNS: U256::ZERO,
+            pnlCollateralizedCNS: Default::default(),
+            pricePNS: U256::from(price),
+            lotLNS: U256::from(lot),
+            insFeeCNS: U256::ZERO,
+            protFeeCNS: U256::ZERO,
+        })
+    };
+    // Long of 2 @ 100 and short of 3 @ 50
+    apply_event(&mut exchange, position_opened(1, 0, 100, 2), &mut ctx, 3);
+    apply_event(&mut exchange, position_opened(2, 1, 50, 3), &mut ctx, 4);
+    let instant = StateInstant::new(1, 1);
+    exchange
+        .set_mark_price(1, udec64!(110).into(), instant)
+        .expect("UT");
+    exchange
+        .set_mark_price(2, udec64!(40).into(), instant)
+        .expect("UT");
+
+    let account = &exchange.accounts()[&1];
+    let perpetuals = exchange.perpetuals();
+    assert_eq!(account.gross_notional(perpetuals), udec128!(340).into());
+    assert_eq!(account.net_notional(perpetuals), dec256!(100));
+
+    // Positions of untracked perpetual contracts are skipped
+    let mut perpetuals = perpetuals.clone();
+    perpetuals.remove(&1);
+    assert_eq!(account.gross_notional(&perpetuals), udec128!(120).into());
+    assert_eq!(account.net_notional(&perpetuals), dec256!(-120));
+}
+
+#[test]
+fn test_simulate_order_max_leverage_and_position_limit() {
+    let mut exchange = create_test_exchange();
+    let mut ctx = None;
+    apply_event(&mut exchange, event_account_created(1), &mut ctx, 0);
+    let initial_margin =
+        ExchangeEvents::InitialMarginFractionUpdated(InitialMarginFractionUpdated {
+            perpId: U256::from(TEST_PERP_ID),
+            initMarginFracHdths: U256::from(1000),
+        });
+    apply_event(&mut exchange, initial_margin, &mut ctx, 1);
+    apply_event(&mut exchange, event_maintenance_margin(500), &mut ctx, 2);
+
+    // Long of 2 @ 100 with deposit of 20
+    let position_opened = ExchangeEvents::PositionOpened(PositionOpened {
+        perpId: U256::from(TEST_PERP_ID),
+        accountId: U256::from(1),
+        positionType: 0,
+        leverageHdths: U256::ZERO,
+        depositCNS: U256::from(200_000),
+        pnlCollateralizedCNS: Default::default(),
+        pricePNS: U256::from(100),
+        lotLNS: U256::from(2),
+        insFeeCNS: U256::ZERO,
+        protFeeCNS: U256::ZERO,
+    });
+    apply_event(&mut exchange, position_opened, &mut ctx, 3);
+
+    let request = |r#type, size, leverage| {
+        types::OrderRequest::new(
+            1,
+            TEST_PERP_ID,
+            r#type,
+            None,
+            udec64!(100).into(),
+            size,
+            None,
+            false,
+            false,
+            false,
+            None,
+            leverage,
+            None,
+            None,
+            0,
+        )
+    };
+
+    // Exactly at the max leverage and the position limit
+    let sim = exchange
+        .simulate_order(1, &request(OpenLong, udec64!(2).into(), udec64!(10)), Some(udec64!(4)))
+        .expect("UT");
+    assert_eq!(sim.position_size, dec64!(4));
+    assert_eq!(sim.position_deposit, udec128!(40));
+    assert_eq!(sim.effective_leverage, udec64!(10));
+    assert_eq!(sim.max_leverage, udec64!(10));
+    assert!(!sim.exceeds_max_leverage);
+    assert!(!sim.exceeds_position_limit);
+    assert!(!sim.is_rejected());
+
+    // Over the max leverage and the position limit
+    let sim = exchange
+        .simulate_order(1, &request(OpenLong, udec64!(2).into(), udec64!(11)), Some(udec64!(3)))
+        .expect("UT");
+    assert!(sim.effective_leverage > udec64!(10));
+    assert!(sim.exceeds_max_leverage);
+    assert!(sim.exceeds_position_limit);
+    assert!(sim.is_rejected());
+
+    // Reducing releases deposit proportionally regardless of the leverage
+    let sim = exchange
+        .simulate_order(1, &request(CloseLong, udec64!(1).into(), udec64!(0)), None)
+        .expect("UT");
+    assert_eq!(sim.position_size, dec64!(1));
+    assert_eq!(sim.position_deposit, udec128!(10));
+    assert_eq!(sim.effective_leverage, udec64!(10));
+    assert!(!sim.is_rejected());
+
+    // Flipping opens the remainder at the order leverage
+    let sim = exchange
+        .simulate_order(1, &request(OpenShort, udec64!(3).into(), udec64!(20)), None)
+        .expect("UT");
+    assert_eq!(sim.position_size, dec64!(-1));
+    assert_eq!(sim.position_deposit, udec128!(5));
+    assert_eq!(sim.effective_leverage, udec64!(20));
+    assert!(sim.exceeds_max_leverage);
+
+    assert!(matches!(
+        exchange.simulate_order(1, &request(Cancel, udec64!(1).into(), udec64!(1)), None),
+        Err(DexError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_simulate_order_crosses_book() {
+    let perp = Perpetual::for_test(TEST_PERP_ID)
+        .with_bid(udec64!(99).into(), udec64!(1).into())
+        .with_ask(udec64!(101).into(), udec64!(1).into());
+    let exchange = Exchange::new(
+        Chain::testnet(),
+        StateInstant::new(0, 0),
+        num::CollateralConverter::new(4),
+        100,
+        udec128!(0.001).into(),
+        udec128!(0.001).into(),
+        udec128!(0.001).into(),
+        HashMap::from([(TEST_PERP_ID, perp)]),
+        HashMap::new(),
+        false,
+        true,
+    );
+    let simulate = |r#type, price: fastnum::UD64| {
+        let request = types::OrderRequest::new(
+            1,
+            TEST_PERP_ID,
+            r#type,
+            None,
+            price.into(),
+            udec64!(1).into(),
+            None,
+            false,
+            false,
+            false,
+            None,
+            udec64!(10),
+            None,
+            None,
+            0,
+        );
+        exchange.simulate_order(1, &request, None).expect("UT")
+    };
+
+    // Resting inside the spread
+    assert!(!simulate(OpenLong, udec64!(100)).crosses_book);
+    assert!(!simulate(OpenShort, udec64!(100)).crosses_book);
+
+    // Matched at the opposite best price
+    assert!(simulate(OpenLong, udec64!(101)).crosses_book);
+    assert!(simulate(OpenShort, udec64!(99)).crosses_book);
+}
+
+#[test]
+fn test_verify_account_locked_balance() {
+    let order_placed = |locked_balance_cns: u64| {
+        ExchangeEvents::OrderPlaced(OrderPlaced {
+            orderId: U256::from(1),
+            lotLNS: U256::from(1),
+            lockedBalanceCNS: U256::from(locked_balance_cns),
+            amountCNS: I256::ZERO,
+            balanceCNS: U256::from(500_000),
+        })
+    };
+    let place_order = |r#type, locked_balance_cns| {
+        // All perpetual contracts of the chain are tracked
+        let chain = Chain::custom(1, Address::ZERO, 0, Address::ZERO, vec![TEST_PERP_ID], Chain::DEFAULT_BLOCK_TIME);
+        let mut exchange = Exchange::new(
+            chain,
+            StateInstant::new(0, 0),
+            num::CollateralConverter::new(4),
+            100,
+            udec128!(0.001).into(),
+            udec128!(0.001).into(),
+            udec128!(0.001).into(),
+            HashMap::from([(TEST_PERP_ID, Perpetual::for_testing(TEST_PERP_ID))]),
+            HashMap::new(),
+            false,
+            true,
+        );
+        let mut ctx = None;
+        apply_event(&mut exchange, event_account_created(1), &mut ctx, 0);
+        apply_event(&mut exchange, event_collateral_deposit(1, 500_000), &mut ctx, 1);
+        apply_event(&mut exchange, event_order_request(1, 1, 0, r#type, 90, 1), &mut ctx, 2);
+        apply_event(&mut exchange, order_placed(locked_balance_cns), &mut ctx, 3);
+        exchange
+    };
+
+    // Open order of 1 @ 90 at 5x locks margin of 18 and the recycle fee of 0.001,
+    // give or take a unit of collateral precision
+    assert_eq!(place_order(OpenLong, 180_010).verify_account(1), vec![]);
+    assert_eq!(place_order(OpenLong, 180_011).verify_account(1), vec![]);
+
+    // Locked balance corrupted below the order collateral
+    assert_eq!(
+        place_order(OpenLong, 10_010).verify_account(1),
+        vec![Inconsistency::LockedMismatch {
+            locked_balance: udec128!(1.001).into(),
+            expected: udec128!(18.001).into()
+        }]
+    );
+
+    // Locked balance corrupted above the order collateral
+    assert_eq!(
+        place_order(OpenLong, 200_010).verify_account(1),
+        vec![Inconsistency::LockedMismatch {
+            locked_balance: udec128!(20.001).into(),
+            expected: udec128!(18.001).into()
+        }]
+    );
+
+    // Close order locks only the recycle fee
+    assert_eq!(place_order(CloseShort, 10).verify_account(1), vec![]);
+
+    assert_eq!(place_order(OpenLong, 180_010).verify_account(2), vec![
+        Inconsistency::UnknownAccount
+    ]);
+}
+
+#[test]
+fn test_order_request_price_range() {
+    let exchange = create_test_exchange();
+    let request = |perp_id, r#type, price| {
+        types::OrderRequest::new(
+            1,
+            perp_id,
+            r#type,
+            None,
+            price,
+            udec64!(1).into(),
+            None,
+            false,
+            false,
+            false,
+            None,
+            udec64!(10),
+            None,
+            None,
+            0,
+        )
+    };
+
+    // Zero base price and whole ticks, 2^24 - 1 is the max representable price
+    let max_price = udec64!(16777215);
+    for r#type in [OpenLong, OpenShort, CloseLong, Change] {
+        assert!(
+            request(TEST_PERP_ID, r#type, max_price.into())
+                .validate(&exchange)
+                .is_ok()
+        );
+        assert!(matches!(
+            request(TEST_PERP_ID, r#type, udec64!(16777216).into()).validate(&exchange),
+            Err(DexError::InvalidArgument(_))
+        ));
+    }
+
+    // Price is irrelevant for cancellation
+    assert!(
+        request(TEST_PERP_ID, Cancel, udec64!(16777216).into())
+            .validate(&exchange)
+            .is_ok()
+    );
+
+    assert!(matches!(
+        request(TEST_PERP_ID + 1, OpenLong, udec64!(100).into()).validate(&exchange),
+        Err(DexError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_order_request_max_leverage() {
+    let mut exchange = create_test_exchange();
+    let mut ctx = None;
+    let initial_margin =
+        ExchangeEvents::InitialMarginFractionUpdated(InitialMarginFractionUpdated {
+            perpId: U256::from(TEST_PERP_ID),
+            initMarginFracHdths: U256::from(1000),
+        });
+    apply_event(&mut exchange, initial_margin, &mut ctx, 0);
+    let request = |r#type, leverage| {
+        types::OrderRequest::new(
+            1,
+            TEST_PERP_ID,
+            r#type,
+            None,
+            udec64!(100).into(),
+            udec64!(1).into(),
+            None,
+            false,
+            false,
+            false,
+            None,
+            leverage,
+            None,
+            None,
+            0,
+        )
+    };
+
+    for r#type in [OpenLong, OpenShort, Change] {
+        assert!(request(r#type, udec64!(10)).validate(&exchange).is_ok());
+        assert!(matches!(
+            request(r#type, udec64!(10.01)).validate(&exchange),
+            Err(DexError::InvalidArgument(_))
+        ));
+    }
+
+    // Leverage is irrelevant for closing and cancellation
+    for r#type in [CloseLong, CloseShort, Cancel] {
+        assert!(request(r#type, udec64!(20)).validate(&exchange).is_ok());
+    }
+}
+
+#[test]
+fn test_account_balance_history() {
+    let mut exchange = create_test_exchange();
+    exchange.set_balance_history_capacity(2);
+    let mut ctx = None;
+    apply_event(&mut exchange, event_account_created(1), &mut ctx, 0);
+    apply_event(&mut exchange, event_collateral_deposit(1, 1_000_000), &mut ctx, 1);
+
+    // Recycle fee charged on order placement
+    apply_event(&mut exchange, event_order_request(1, 1, 0, OpenShort, 100, 2), &mut ctx, 2);
+    let order_placed = ExchangeEvents::OrderPlaced(OrderPlaced {
+        orderId: U256::from(1),
+        lotLNS: U256::from(2),
+        lockedBalanceCNS: U256::ZERO,
+        amountCNS: I256::ZERO,
+        balanceCNS: U256::from(999_000),
+    });
+    apply_event(&mut exchange, order_placed, &mut ctx, 3);
+
+    let history = exchange.accounts()[&1].balance_history();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].delta, dec256!(100));
+    assert_eq!(history[0].reason, BalanceChangeReason::Deposit);
+    assert_eq!(history[1].delta, dec256!(-0.1));
+    assert_eq!(history[1].reason, BalanceChangeReason::Fee);
+
+    // The oldest change is evicted
+    apply_event(&mut exchange, event_collateral_withdrawal(1, 499_000), &mut ctx, 4);
+    let history = exchange.accounts()[&1].balance_history();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].reason, BalanceChangeReason::Fee);
+    assert_eq!(history[1].delta, dec256!(-50));
+    assert_eq!(history[1].reason, BalanceChangeReason::Withdrawal);
+}
+
+#[test]
+fn test_protocol_transfers() {
+    let mut exchange = create_test_exchange();
+    exchange.set_balance_history_capacity(1);
+    let mut ctx = None;
+    for (log_index, event) in [
+        event_account_created(1),
+        event_collateral_deposit(1, 1_000_000),
+        event_account_created(2),
+        event_collateral_deposit(2, 1_000_000),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        apply_event(&mut exchange, event, &mut ctx, log_index as u64);
+    }
+
+    // No account-to-account transfers, the amount moves through the protocol
+    let to_protocol = ExchangeEvents::TransferAccountToProtocol(TransferAccountToProtocol {
+        accountId: U256::from(1),
+        amountCNS: U256::from(100_000),
+        balanceCNS: U256::from(900_000),
+    });
+    let from_protocol = ExchangeEvents::TransferProtocolToAccount(TransferProtocolToAccount {
+        accountId: U256::from(2),
+        amountCNS: U256::from(100_000),
+        balanceCNS: U256::from(1_100_000),
+    });
+    apply_event(&mut exchange, to_protocol, &mut ctx, 4);
+    apply_event(&mut exchange, from_protocol, &mut ctx, 5);
+
+    for (account_id, balance, delta) in
+        [(1, udec128!(90), dec256!(-10)), (2, udec128!(110), dec256!(10))]
+    {
+        let account = &exchange.accounts()[&account_id];
+        assert_eq!(account.balance(), balance.into());
+        assert_eq!(account.balance_history()[0].delta, delta);
+        assert_eq!(account.balance_history()[0].reason, BalanceChangeReason::Transfer);
+    }
+}
+
+#[derive(Default)]
+struct RecordingAuditSink(Mutex<Vec<(u64, &'static str)>>);
+
+impl StateAuditSink for RecordingAuditSink {
+    fn record(&self, record: &StateAuditRecord<'_>) {
+        self.0.lock().unwrap().push((record.log_index, record.kind()));
+    }
+}
+
+#[cfg(feature = "display")]
+#[test]
+fn test_account_display_collateral_decimals() {
+    crate::num::set_colors(false);
+    let mut exchange = create_test_exchange();
+    let mut ctx = None;
+    let account_created = ExchangeEvents::AccountCreated(AccountCreated {
+        account: Address::repeat_byte(1),
+        id: U256::from(1),
+    });
+    apply_event(&mut exchange, account_created, &mut ctx, 0);
+    apply_event(&mut exchange, event_collateral_deposit(1, 10_000_000), &mut ctx, 1);
+    let account = exchange.accounts().get(&1).expect("UT");
+
+    // Collateral converter of the test exchange has 4 decimals
+    let rendered = exchange.display_config().scoped(|| account.to_string());
+    assert!(rendered.contains("Balance: 1000.0000 | Available: 1000.0000"), "{rendered}");
+}
+
+fn audit_test_block() -> RawBlockEvents {
+    let raw_event = |tx_index, log_index, event: ExchangeEvents| {
+        RawEvent::new(TxHash::ZERO, tx_index, log_index, event.into())
+    };
+    RawBlockEvents::new(
+        StateInstant::new(1, 1),
+        vec![
+            raw_event(0, 0, event_account_created(1)),
+            raw_event(0, 1, event_collateral_deposit(1, 1000)),
+            raw_event(1, 2, event_order_request(1, 1, 0, OpenLong, 90, 1)),
+            raw_event(1, 3, event_order_placed(1)),
+        ],
+    )
+}
+
+#[test]
+fn test_audit_sink_receives_state_changes() {
+    let block = audit_test_block();
+    let sink = Arc::new(RecordingAuditSink::default());
+    let mut exchange = create_test_exchange();
+    exchange.set_audit_sink(Some(sink.clone()));
+    let state_events = exchange.apply_events(&block).expect("UT").expect("UT");
+
+    // One record per state change, with the originating raw event log index
+    let expected = state_events
+        .events()
+        .iter()
+        .flat_map(|ctx| ctx.event().iter().map(|_| ctx.log_index()))
+        .collect::<Vec<_>>();
+    let records = sink.0.lock().unwrap().clone();
+    assert!(!records.is_empty());
+    assert_eq!(records.iter().map(|(log_index, _)| *log_index).collect::<Vec<_>>(), expected);
+    assert!(records.contains(&(1, "balance")));
+    assert!(records.contains(&(3, "order")));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_audit_json_lines_sink() {
+    let block = audit_test_block();
+    let path = std::env::temp_dir().join(format!("perpl-audit-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut exchange = create_test_exchange();
+    exchange
+        .set_audit_sink(Some(Arc::new(crate::state::JsonLinesAuditSink::open(&path).expect("UT"))));
+    let state_events = exchange.apply_events(&block).expect("UT").expect("UT");
+    let lines = std::fs::read_to_string(&path).expect("UT");
+    let _ = std::fs::remove_file(&path);
+
+    // One line per state change
+    let num_changes = state_events.events().iter().map(|ctx| ctx.event().len()).sum::<usize>();
+    assert_eq!(lines.lines().count(), num_changes);
+    assert!(lines.lines().all(|line| line.starts_with(r#"{"block":1,"#) && line.ends_with('}')));
+    assert!(lines.contains(r#""log_index":3,"kind":"order""#));
+    let record: serde_json::Value =
+        serde_json::from_str(lines.lines().next().expect("UT")).expect("UT");
+    assert_eq!(record["tx_hash"], TxHash::ZERO.to_string());
+    assert_eq!(
+        record["change"],
+        serde_json::json!({"account_id": "1", "request_id": null, "type": "created", "id": "1"})
+    );
+
+    // Amounts are lossless strings along with the IDs of affected entities
+    let records: Vec<serde_json::Value> =
+        lines.lines().map(|line| serde_json::from_str(line).expect("UT")).collect();
+    let deposit = records
+        .iter()
+        .find(|record| record["change"]["type"] == "balance_updated")
+        .expect("UT");
+    assert_eq!(deposit["kind"], "balance");
+    assert_eq!(deposit["change"]["account_id"], "1");
+    assert!(deposit["change"]["balance"].as_str().is_some_and(|b| b.parse::<f64>().is_ok()));
+    let placed = records
+        .iter()
+        .find(|record| record["change"]["type"] == "placed")
+        .expect("UT");
+    assert_eq!(placed["change"]["order_id"], "1");
+    assert_eq!(placed["change"]["order_type"], "Open Long");
+    assert_eq!(placed["change"]["post_only"], false);
+}
+
+#[test]
+fn test_state_event_observers() {
+    let block = RawBlockEvents::new(
+        StateInstant::new(1, 1),
+        [
+            event_account_created(1),
+            event_collateral_deposit(1, 1000),
+            event_order_request(1, 1, 0, OpenLong, 90, 1),
+            event_order_placed(1),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(idx, event)| RawEvent::new(TxHash::ZERO, 0, idx as u64, event.into()))
+        .collect(),
+    );
+
+    let mut exchange = create_test_exchange();
+    let first = Arc::new(Mutex::new(vec![]));
+    let second = Arc::new(Mutex::new(0));
+    exchange.on_state_event({
+        let first = first.clone();
+        move |event| first.lock().unwrap().push(format!("{event:?}"))
+    });
+    exchange.on_state_event({
+        let second = second.clone();
+        move |_| *second.lock().unwrap() += 1
+    });
+    let state_events = exchange.apply_events(&block).expect("UT").expect("UT");
+
+    let expected = state_events
+        .events()
+        .iter()
+        .flat_map(|ctx| ctx.event().iter().map(|event| format!("{event:?}")))
+        .collect::<Vec<_>>();
+    assert!(!expected.is_empty());
+    assert_eq!(*first.lock().unwrap(), expected);
+    assert_eq!(*second.lock().unwrap(), expected.len());
+}
+
+fn mocked_provider(implementation: Address, code: Bytes) -> impl Provider {
+    let asserter = Asserter::new();
+    asserter.push_success(&implementation.into_word());
+    asserter.push_success(&code);
+    ProviderBuilder::new().connect_mocked_client(asserter)
+}
+
+#[tokio::test]
+async fn test_check_revision_matches() {
+    let code: Bytes = ExchangeCalls::SELECTORS.concat().into();
+    let provider = mocked_provider(Address::repeat_byte(0x11), code);
+    Exchange::check_revision(&Chain::testnet(), &provider).await.expect("UT");
+}
+
+#[tokio::test]
+async fn test_check_revision_mismatch() {
+    // Implementation compiled from an older ABI lacking the last selector
+    let selectors = ExchangeCalls::SELECTORS;
+    let code: Bytes = selectors[..selectors.len() - 1].concat().into();
+    let provider = mocked_provider(Address::repeat_byte(0x11), code);
+    let err = Exchange::check_revision(&Chain::testnet(), &provider).await.unwrap_err();
+    assert!(matches!(err, DexError::RevisionMismatch(rev, 1) if rev == Exchange::revision()));
+}
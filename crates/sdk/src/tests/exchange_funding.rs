@@ -4,9 +4,11 @@
 //! the real three-pass `apply_events` wiring, so they are the tests that FAIL if the passes are
 //! reordered. They are fast in-crate synthetic-event tests — no Anvil, no RPC.
 //!
-//! Convention for clean arithmetic: the test perpetual uses `Converter::new(0)` for price/size/
-//! funding (values pass through) and `Converter::new(4)` for collateral; entry = 100, size = 10,
-//! maintenance margin = 20 (so MMR = entry*size/mm = 50), funding payment = 1 per unit.
+//! Convention for clean arithmetic: the test perpetual uses `Converter::new(0)`
+//! for price/size/ funding (values pass through) and
+//! `CollateralConverter::new(4)` for collateral; entry = 100, size = 10,
+//! maintenance margin = 20 (so MMR = entry*size/mm = 50), funding payment = 1
+//! per unit.
 
 use std::collections::HashMap;
 
@@ -19,7 +21,7 @@ use crate::{
         AccountCreated, ExchangeEvents, MaintenanceMarginFractionUpdated, PositionDecreased,
         PositionOpened,
     },
-    num::Converter,
+    num,
     state::{Exchange, Perpetual},
     stream::{RawBlockEvents, RawEvent},
     types::StateInstant,
@@ -35,7 +37,7 @@ const SHORT: u8 = 1;
 fn si(block: u64) -> StateInstant { StateInstant::new(block, block) }
 
 fn ev(event: ExchangeEvents, log_index: u64) -> RawEvent {
-    RawEvent::new(TxHash::ZERO, 0, log_index, event)
+    RawEvent::new(TxHash::ZERO, 0, log_index, event.into())
 }
 
 /// A test perpetual with a nonzero maintenance margin (mm = 20), so `Position::opened` — which
@@ -59,11 +61,11 @@ fn exchange(perps: HashMap<u32, Perpetual>) -> Exchange {
     Exchange::new(
         Chain::testnet(),
         si(0),
-        Converter::new(4), // collateral converter
+        num::CollateralConverter::new(4), // collateral converter
         100,
-        udec128!(0.001),
-        udec128!(0.001),
-        udec128!(0.001),
+        udec128!(0.001).into(),
+        udec128!(0.001).into(),
+        udec128!(0.001).into(),
         perps,
         HashMap::new(),
         false, // is_halted
@@ -239,7 +241,7 @@ fn exchange_pass1_funding_vs_pass3_mmf_ordering() {
         .expect("block 1");
     assert_eq!(
         exchange.accounts().get(&1).unwrap().positions().get(&PERP).unwrap().liquidation_price(),
-        udec64!(95), // 100 + (50 - 100 - 0)/10
+        udec64!(95).into(), // 100 + (50 - 100 - 0)/10
     );
 
     // Block 2: funding effective (Pass 1: premium -10) AND MMF raised to hdths=1000 -> mm 10
@@ -252,7 +254,7 @@ fn exchange_pass1_funding_vs_pass3_mmf_ordering() {
 
     let pos = exchange.accounts().get(&1).unwrap().positions().get(&PERP).unwrap().clone();
     assert_eq!(pos.premium_pnl(), dec256!(-10), "funding applied in Pass 1");
-    assert_eq!(pos.maintenance_margin_requirement(), udec128!(100), "MMF fanned out in Pass 3");
+    assert_eq!(pos.maintenance_margin_requirement(), udec128!(100).into(), "MMF fanned out in Pass 3");
     // liq = entry + (MMR - deposit - premium)/size = 100 + (100 - 100 - (-10))/10 = 101.
-    assert_eq!(pos.liquidation_price(), udec64!(101), "liq composes funding + MMF");
+    assert_eq!(pos.liquidation_price(), udec64!(101).into(), "liq composes funding + MMF");
 }
@@ -10,6 +10,7 @@ pub struct BlockEvents<T> {
 /// Event along with transaction context.
 #[derive(Clone, Debug)]
 pub struct EventContext<T> {
+    pub(crate) block_number: u64,
     pub(crate) tx_hash: TxHash,
     pub(crate) tx_index: u64,
     pub(crate) log_index: u64,
@@ -28,13 +29,23 @@ impl<T> BlockEvents<T> {
 
 impl<T> EventContext<T> {
     pub fn new(tx_hash: TxHash, tx_index: u64, log_index: u64, event: T) -> Self {
-        Self { tx_hash, tx_index, log_index, event }
+        Self { block_number: 0, tx_hash, tx_index, log_index, event }
     }
 
     pub(crate) fn empty(event: T) -> Self {
-        Self { tx_hash: TxHash::ZERO, tx_index: 0, log_index: 0, event }
+        Self { block_number: 0, tx_hash: TxHash::ZERO, tx_index: 0, log_index: 0, event }
     }
 
+    /// Sets the number of the block the event produced at.
+    pub fn with_block(mut self, block_number: u64) -> Self {
+        self.block_number = block_number;
+        self
+    }
+
+    /// Number of the block the event produced at, so events flattened out of
+    /// [`BlockEvents`] can still be located.
+    pub fn block_number(&self) -> u64 { self.block_number }
+
     pub fn tx_hash(&self) -> TxHash { self.tx_hash }
 
     pub fn tx_index(&self) -> u64 { self.tx_index }
@@ -45,6 +56,7 @@ impl<T> EventContext<T> {
 
     pub(crate) fn pass<O>(&self, other: O) -> EventContext<O> {
         EventContext {
+            block_number: self.block_number,
             tx_hash: self.tx_hash,
             tx_index: self.tx_index,
             log_index: self.log_index,
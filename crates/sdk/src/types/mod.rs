@@ -1,17 +1,24 @@
 mod event;
+mod op;
 mod order;
 mod request;
 mod trade;
 
 use std::{fmt::Display, str::FromStr};
 
-use alloy::primitives::Address;
+use alloy::{eips::BlockId, primitives::Address, providers::Provider};
 use chrono::{DateTime, Utc};
 pub use event::*;
+pub use op::Op;
 pub use order::{OrderSide, OrderType};
 pub use request::{OrderRequest, RequestType};
 pub use trade::*;
 
+use crate::{
+    Chain,
+    error::{DexError, ProviderError},
+};
+
 /// ID of perpetual contract.
 pub type PerpetualId = u32;
 
@@ -19,7 +26,7 @@ pub type PerpetualId = u32;
 pub type AccountId = u32;
 
 /// Account address or ID.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum AccountAddressOrID {
     Address(Address),
     ID(AccountId),
@@ -35,6 +42,46 @@ pub type OrderId = std::num::NonZeroU16;
 /// Order request ID.
 pub type RequestId = u64;
 
+/// Metadata of the collateral token, see [`Chain::fetch_collateral_metadata`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollateralMeta {
+    /// Token symbol, e.g. "USDC".
+    pub symbol: String,
+
+    /// Token name.
+    pub name: String,
+
+    /// Number of decimals of the token amounts.
+    pub decimals: u8,
+}
+
+/// Source of the current wall-clock time for the helpers counting down to a
+/// point in time, so tests can pin it, see [`SystemClock`] and [`FixedClock`].
+pub trait Clock {
+    /// Current Unix timestamp, in seconds.
+    fn now(&self) -> u64;
+}
+
+/// [`Clock`] backed by the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+/// [`Clock`] pinned to the Unix timestamp, in seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 { self.0 }
+}
+
 /// Instant in chain history the state/event is up to date with.
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Default)]
 pub struct StateInstant {
@@ -47,6 +94,19 @@ impl StateInstant {
         Self { block_number, block_timestamp }
     }
 
+    /// Instant of the block the exchange smart contract was deployed at.
+    ///
+    /// Use it as a starting point of [`crate::stream::raw`] to replay the
+    /// whole exchange history from the very first contract block.
+    pub async fn genesis<P: Provider>(chain: &Chain, provider: &P) -> Result<Self, DexError> {
+        let block = provider
+            .get_block(BlockId::number(chain.deployed_at_block()))
+            .await
+            .map_err(ProviderError::from)?
+            .ok_or(ProviderError::InvalidRequest("block is not available yet".to_string()))?;
+        Ok(Self::new(chain.deployed_at_block(), block.header.timestamp))
+    }
+
     pub fn block_number(&self) -> u64 { self.block_number }
 
     pub fn block_timestamp(&self) -> u64 { self.block_timestamp }
@@ -54,6 +114,20 @@ impl StateInstant {
     pub fn next(&self) -> Self {
         Self { block_number: self.block_number + 1, block_timestamp: self.block_timestamp }
     }
+
+    /// Compact binary encoding, e.g. to persist resume cursors in a KV store:
+    /// big-endian block number followed by big-endian block timestamp.
+    ///
+    /// Byte order of encoded instants matches their [`Ord`] order.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        (((self.block_number as u128) << 64) | self.block_timestamp as u128).to_be_bytes()
+    }
+
+    /// Decodes the instant encoded with [`Self::to_bytes`].
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        let value = u128::from_be_bytes(bytes);
+        Self::new((value >> 64) as u64, value as u64)
+    }
 }
 
 impl Display for StateInstant {
@@ -70,7 +144,7 @@ impl Display for StateInstant {
 }
 
 impl FromStr for AccountAddressOrID {
-    type Err = crate::error::DexError;
+    type Err = DexError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Ok(address) = Address::from_str(s) {
@@ -79,7 +153,7 @@ impl FromStr for AccountAddressOrID {
         if let Ok(id) = AccountId::from_str(s) {
             return Ok(AccountAddressOrID::ID(id));
         }
-        Err(crate::error::DexError::InvalidArgument(format!(
+        Err(DexError::InvalidArgument(format!(
             "invalid account address or ID: {}",
             s
         )))
@@ -87,7 +161,74 @@ impl FromStr for AccountAddressOrID {
 }
 
 impl TryFrom<String> for AccountAddressOrID {
-    type Error = crate::error::DexError;
+    type Error = DexError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> { AccountAddressOrID::from_str(&value) }
 }
+
+impl Display for AccountAddressOrID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountAddressOrID::Address(address) => write!(f, "{}", address),
+            AccountAddressOrID::ID(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+/// Serialized as a string in the [`Display`] format.
+#[cfg(feature = "serde")]
+impl serde::Serialize for AccountAddressOrID {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserialized from a string in the [`FromStr`] format.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for AccountAddressOrID {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+
+    #[test]
+    fn test_account_address_or_id_round_trip() {
+        let addr = address!("0x000000000000000000000000000000000000dEaD");
+        let by_address = AccountAddressOrID::Address(addr);
+        let text = by_address.to_string();
+        assert_eq!(text, "0x000000000000000000000000000000000000dEaD");
+        assert!(matches!(text.parse(), Ok(AccountAddressOrID::Address(a)) if a == addr));
+
+        let by_id = AccountAddressOrID::ID(42);
+        let text = by_id.to_string();
+        assert_eq!(text, "42");
+        assert!(matches!(text.parse(), Ok(AccountAddressOrID::ID(42))));
+    }
+
+    #[test]
+    fn test_state_instant_bytes_round_trip() {
+        let instant = StateInstant::new(0x0102, 0x0304);
+        let bytes = instant.to_bytes();
+        assert_eq!(bytes, [0, 0, 0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 3, 4]);
+        assert_eq!(StateInstant::from_bytes(bytes), instant);
+
+        for instant in [
+            StateInstant::default(),
+            StateInstant::new(u64::MAX, 0),
+            StateInstant::new(0, u64::MAX),
+            StateInstant::new(u64::MAX, u64::MAX),
+        ] {
+            assert_eq!(StateInstant::from_bytes(instant.to_bytes()), instant);
+        }
+        assert_eq!(StateInstant::new(u64::MAX, u64::MAX).to_bytes(), [0xff; 16]);
+
+        // Byte order matches instant order
+        assert!(StateInstant::new(1, 2).to_bytes() < StateInstant::new(2, 1).to_bytes());
+    }
+}
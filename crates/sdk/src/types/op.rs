@@ -0,0 +1,155 @@
+use alloy::{
+    primitives::{Bytes, U256},
+    sol_types::SolCall,
+};
+use fastnum::D64;
+
+use super::*;
+use crate::{
+    abi::dex::Exchange::{OpDesc, execPerpOpsCall},
+    error::DexError,
+    num, state,
+};
+
+/// Perpetual contract operation other than an order, e.g. mark price or
+/// funding rate update, issued via `execPerpOps`.
+///
+/// The values of the on-chain `OpDescEnum` are not published in the exchange
+/// ABI, so the operation type is kept as is. The state changes of executed
+/// operations are applied by [`state::Exchange::apply_events`] through the
+/// events the exchange emits for them, e.g. `MarkUpdated` and
+/// `FundingEventCompleted`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Op {
+    op_id: RequestId,
+    perp_id: PerpetualId,
+    op_type: u8,
+    price: num::Price,
+    funding_rate: D64,
+    allow_overwrite: bool,
+    report: Bytes,
+}
+
+impl Op {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        op_id: RequestId,
+        perp_id: PerpetualId,
+        op_type: u8,
+        price: num::Price,
+        funding_rate: D64,
+        allow_overwrite: bool,
+        report: Bytes,
+    ) -> Self {
+        Self { op_id, perp_id, op_type, price, funding_rate, allow_overwrite, report }
+    }
+
+    /// Client-provided ID of the operation.
+    pub fn op_id(&self) -> RequestId { self.op_id }
+
+    /// ID of the perpetual contract the operation is for.
+    pub fn perpetual_id(&self) -> PerpetualId { self.perp_id }
+
+    /// Raw `OpDescEnum` value of the operation.
+    pub fn op_type(&self) -> u8 { self.op_type }
+
+    /// Price provided with the operation, e.g. mark price.
+    pub fn price(&self) -> num::Price { self.price }
+
+    /// Funding rate provided with the operation.
+    pub fn funding_rate(&self) -> D64 { self.funding_rate }
+
+    /// If the operation is allowed to overwrite previously set values.
+    pub fn allow_overwrite(&self) -> bool { self.allow_overwrite }
+
+    /// Unverified oracle report provided with the operation.
+    pub fn report(&self) -> &Bytes { &self.report }
+
+    /// Decodes operation from [`OpDesc`] with provided converters.
+    ///
+    /// Fails with [`DexError::MalformedData`] on values out of range.
+    pub fn from_op_desc(
+        desc: &OpDesc,
+        price_converter: num::PriceConverter,
+        funding_rate_converter: num::Converter,
+    ) -> Result<Self, DexError> {
+        Ok(Self {
+            op_id: num::narrow(desc.opDescId, "op id")?,
+            perp_id: num::narrow(desc.perpId, "perpetual id")?,
+            op_type: desc.opType,
+            price: price_converter.from_u64(desc.pricePNS as u64),
+            funding_rate: funding_rate_converter.try_from_signed(desc.fundingRatePct100k)?,
+            allow_overwrite: desc.allowOverwrite,
+            report: desc.unverifiedReport.clone(),
+        })
+    }
+
+    /// Decodes operations from `execPerpOps` call data, using converters of
+    /// the perpetual contracts known to the `exchange`.
+    pub fn from_calldata(input: &[u8], exchange: &state::Exchange) -> Result<Vec<Self>, DexError> {
+        let call = execPerpOpsCall::abi_decode(input)
+            .map_err(|err| DexError::MalformedData(format!("execPerpOps call data: {err}")))?;
+        call.operations
+            .iter()
+            .map(|desc| {
+                let perp = u32::try_from(desc.perpId)
+                    .ok()
+                    .and_then(|perp_id| exchange.perpetuals().get(&perp_id))
+                    .ok_or_else(|| {
+                        DexError::InvalidArgument(format!("unknown perpetual: {}", desc.perpId))
+                    })?;
+                Self::from_op_desc(desc, perp.price_converter(), perp.funding_rate_converter())
+            })
+            .collect()
+    }
+
+    /// Encodes operation to [`OpDesc`] to be passed to `execPerpOps`,
+    /// reverse of [`Self::from_op_desc`].
+    pub fn to_op_desc(
+        &self,
+        price_converter: num::PriceConverter,
+        funding_rate_converter: num::Converter,
+    ) -> Result<OpDesc, DexError> {
+        let price = price_converter.to_unsigned(self.price);
+        Ok(OpDesc {
+            opDescId: U256::from(self.op_id),
+            perpId: U256::from(self.perp_id),
+            opType: self.op_type,
+            pricePNS: price.try_into().map_err(|_| {
+                DexError::InvalidArgument(format!("op price out of range: {}", self.price))
+            })?,
+            fundingRatePct100k: funding_rate_converter.to_signed(self.funding_rate),
+            allowOverwrite: self.allow_overwrite,
+            unverifiedReport: self.report.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::I256;
+    use fastnum::{dec64, udec64};
+
+    use super::*;
+
+    #[test]
+    fn test_op_desc_round_trip() {
+        let (pc, fc) = (num::PriceConverter::new(2), num::Converter::new(5));
+        let op = Op::new(7, 16, 1, num::Price::new(udec64!(1234.56)), dec64!(-0.00125), true, Bytes::from([1, 2]));
+
+        let desc = op.to_op_desc(pc, fc).expect("UT");
+        assert_eq!(desc.pricePNS, 123456);
+        assert_eq!(desc.fundingRatePct100k, I256::try_from(-125).expect("UT"));
+        assert_eq!(Op::from_op_desc(&desc, pc, fc).expect("UT"), op);
+
+        let input = execPerpOpsCall { operations: vec![desc.clone()] }.abi_encode();
+        let call = execPerpOpsCall::abi_decode(&input).expect("UT");
+        assert_eq!(call.operations.len(), 1);
+        assert_eq!(Op::from_op_desc(&call.operations[0], pc, fc).expect("UT"), op);
+
+        let out_of_range = OpDesc { perpId: U256::MAX, ..desc };
+        assert!(matches!(Op::from_op_desc(&out_of_range, pc, fc), Err(DexError::MalformedData(_))));
+        let too_high = Op::new(7, 16, 1, num::Price::new(udec64!(100000000)), dec64!(0), false, Bytes::new());
+        assert!(too_high.to_op_desc(pc, fc).is_err());
+    }
+}
@@ -1,4 +1,6 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
+
+use crate::{error::DexError, num};
 
 /// Type of the placed order.
 ///
@@ -48,16 +50,36 @@ impl OrderSide {
             OrderSide::Bid => OrderSide::Ask,
         }
     }
+
+    /// Whether price `a` is strictly better than price `b` for an order on
+    /// this side: higher for bids, lower for asks.
+    pub fn is_better_price(&self, a: num::Price, b: num::Price) -> bool {
+        match self {
+            OrderSide::Ask => a < b,
+            OrderSide::Bid => a > b,
+        }
+    }
+
+    /// Whether an order on this side at `price` crosses the best price of the
+    /// opposite side of the book, i.e. would be matched immediately.
+    pub fn crosses(&self, price: num::Price, opposite_best: num::Price) -> bool {
+        match self {
+            OrderSide::Ask => price <= opposite_best,
+            OrderSide::Bid => price >= opposite_best,
+        }
+    }
 }
 
-impl From<u8> for OrderType {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for OrderType {
+    type Error = DexError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => OrderType::OpenLong,
-            1 => OrderType::OpenShort,
-            2 => OrderType::CloseLong,
-            3 => OrderType::CloseShort,
-            _ => unreachable!(),
+            0 => Ok(OrderType::OpenLong),
+            1 => Ok(OrderType::OpenShort),
+            2 => Ok(OrderType::CloseLong),
+            3 => Ok(OrderType::CloseShort),
+            _ => Err(DexError::MalformedData(format!("invalid order type: {value}"))),
         }
     }
 }
@@ -82,6 +104,25 @@ impl Display for OrderType {
     }
 }
 
+/// Parses both the long ("Open Long") and the short ("OL") forms of
+/// [`Display`], as well as the variant name ("OpenLong"), case-insensitively.
+impl FromStr for OrderType {
+    type Err = DexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match normalize_name(s).as_str() {
+            "openlong" | "ol" => Ok(OrderType::OpenLong),
+            "openshort" | "os" => Ok(OrderType::OpenShort),
+            "closelong" | "cl" => Ok(OrderType::CloseLong),
+            "closeshort" | "cs" => Ok(OrderType::CloseShort),
+            _ => Err(DexError::InvalidArgument(format!(
+                "invalid order type: {s}, expected one of: Open Long (OL), Open Short (OS), \
+                 Close Long (CL), Close Short (CS)"
+            ))),
+        }
+    }
+}
+
 impl Display for OrderSide {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -90,3 +131,63 @@ impl Display for OrderSide {
         }
     }
 }
+
+/// Lowercase name without whitespace, underscores and hyphens, to match
+/// names regardless of case and word separators.
+pub(super) fn normalize_name(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_whitespace() && *c != '_' && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use fastnum::UD64;
+
+    use super::*;
+
+    fn price(value: u64) -> num::Price { num::Price::new(UD64::from(value)) }
+
+    #[test]
+    fn test_is_better_price() {
+        assert!(OrderSide::Bid.is_better_price(price(101), price(100)));
+        assert!(!OrderSide::Bid.is_better_price(price(99), price(100)));
+        assert!(!OrderSide::Bid.is_better_price(price(100), price(100)));
+
+        assert!(OrderSide::Ask.is_better_price(price(99), price(100)));
+        assert!(!OrderSide::Ask.is_better_price(price(101), price(100)));
+        assert!(!OrderSide::Ask.is_better_price(price(100), price(100)));
+    }
+
+    #[test]
+    fn test_crosses() {
+        // Bid against best ask
+        assert!(OrderSide::Bid.crosses(price(101), price(100)));
+        assert!(OrderSide::Bid.crosses(price(100), price(100)));
+        assert!(!OrderSide::Bid.crosses(price(99), price(100)));
+
+        // Ask against best bid
+        assert!(OrderSide::Ask.crosses(price(99), price(100)));
+        assert!(OrderSide::Ask.crosses(price(100), price(100)));
+        assert!(!OrderSide::Ask.crosses(price(101), price(100)));
+    }
+
+    #[test]
+    fn test_order_type_from_str() {
+        for order_type in (0..=3).map(|t| OrderType::try_from(t).unwrap()) {
+            assert_eq!(order_type.to_string().parse::<OrderType>().unwrap(), order_type);
+            assert_eq!(format!("{order_type:#}").parse::<OrderType>().unwrap(), order_type);
+            assert_eq!(format!("{order_type:?}").parse::<OrderType>().unwrap(), order_type);
+        }
+        assert_eq!("open long".parse::<OrderType>().unwrap(), OrderType::OpenLong);
+        assert_eq!("cs".parse::<OrderType>().unwrap(), OrderType::CloseShort);
+        assert!(matches!("Open".parse::<OrderType>(), Err(DexError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_order_type_try_from() {
+        assert_eq!(OrderType::try_from(3).unwrap(), OrderType::CloseShort);
+        assert!(matches!(OrderType::try_from(4), Err(DexError::MalformedData(_))));
+    }
+}
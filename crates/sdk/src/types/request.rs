@@ -1,8 +1,15 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
 use alloy::primitives::U256;
-use fastnum::{UD64, UD128};
+use fastnum::UD64;
 
 use super::*;
-use crate::{abi::dex::Exchange::OrderDesc, num, state};
+use crate::{
+    Chain,
+    abi::dex::Exchange::{self, OrderDesc},
+    error::DexError,
+    num, state,
+};
 
 /// Type of the order request.
 ///
@@ -25,7 +32,7 @@ use crate::{abi::dex::Exchange::OrderDesc, num, state};
 ///   insufficient margin or the account holder wishes to reduce leverage.
 /// * [`RequestType::Change`] is an operation to change parameters of an
 ///   existing order, gas-efficiently.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RequestType {
     OpenLong,
     OpenShort,
@@ -37,16 +44,14 @@ pub enum RequestType {
 }
 
 /// Request to post/modify an order.
-#[derive(Clone, derive_more::Debug)]
+#[derive(Clone, derive_more::Debug, PartialEq)]
 pub struct OrderRequest {
     request_id: RequestId,
     perp_id: PerpetualId,
     r#type: RequestType,
     order_id: Option<OrderId>,
-    #[debug("{price}")]
-    price: UD64,
-    #[debug("{size}")]
-    size: UD64,
+    price: num::Price,
+    size: num::Size,
     expiry_block: Option<u64>,
     post_only: bool,
     fill_or_kill: bool,
@@ -55,7 +60,7 @@ pub struct OrderRequest {
     #[debug("{leverage}")]
     leverage: UD64,
     last_exec_block: Option<u64>,
-    amount: Option<UD128>,
+    amount: Option<num::Collateral>,
     max_neg_pnl_collat_bps: u16,
 }
 
@@ -74,8 +79,8 @@ impl OrderRequest {
         perp_id: PerpetualId,
         r#type: RequestType,
         order_id: Option<OrderId>,
-        price: UD64,
-        size: UD64,
+        price: num::Price,
+        size: num::Size,
         expiry_block: Option<u64>,
         post_only: bool,
         fill_or_kill: bool,
@@ -83,7 +88,7 @@ impl OrderRequest {
         max_matches: Option<u32>,
         leverage: UD64,
         last_exec_block: Option<u64>,
-        amount: Option<UD128>,
+        amount: Option<num::Collateral>,
         max_neg_pnl_collat_bps: u16,
     ) -> Self {
         Self {
@@ -105,6 +110,18 @@ impl OrderRequest {
         }
     }
 
+    /// Absolute expiry block of an order placed at `current_block` to stay
+    /// on the book for at least the `duration`, to be used as `expiry_block`
+    /// of [`Self::new`].
+    ///
+    /// The duration is converted with [`Chain::block_time`] and rounded up to
+    /// whole blocks.
+    pub fn expiry_in(current_block: u64, duration: Duration, chain: &Chain) -> u64 {
+        let block_time = chain.block_time().as_nanos().max(1);
+        let blocks = duration.as_nanos().div_ceil(block_time);
+        current_block.saturating_add(u64::try_from(blocks).unwrap_or(u64::MAX))
+    }
+
     /// Prepare order request to execution.
     pub fn prepare(&self, exchange: &state::Exchange) -> OrderDesc {
         let perp = exchange
@@ -119,12 +136,139 @@ impl OrderRequest {
         )
     }
 
+    /// Checks the request against the state of the exchange, so invalid
+    /// requests are rejected before getting reverted on-chain.
+    ///
+    /// Limit price of order requests must be representable by the exchange,
+    /// see [`state::Perpetual::price_in_range`], and leverage of orders
+    /// opening positions must not exceed the max leverage permitted by
+    /// [`state::Perpetual::initial_margin`], unless the latter is not set.
+    pub fn validate(&self, exchange: &state::Exchange) -> Result<(), DexError> {
+        let perp = exchange.perpetuals().get(&self.perp_id).ok_or_else(|| {
+            DexError::InvalidArgument(format!("unknown perpetual: {}", self.perp_id))
+        })?;
+        let has_price =
+            !matches!(self.r#type, RequestType::Cancel | RequestType::IncreasePositionCollateral);
+        if has_price && !perp.price_in_range(self.price) {
+            return Err(DexError::InvalidArgument(format!(
+                "price {} is out of range of perpetual {}",
+                self.price, self.perp_id
+            )));
+        }
+        let has_leverage = matches!(
+            self.r#type,
+            RequestType::OpenLong | RequestType::OpenShort | RequestType::Change
+        );
+        let max_leverage = perp.initial_margin();
+        if has_leverage && !max_leverage.is_zero() && self.leverage > max_leverage {
+            return Err(DexError::InvalidArgument(format!(
+                "leverage {} exceeds max leverage {} of perpetual {}",
+                self.leverage, max_leverage, self.perp_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// ID of the perpetual contract the request is for.
+    pub fn perpetual_id(&self) -> PerpetualId { self.perp_id }
+
+    /// Type of the request.
+    pub fn r#type(&self) -> RequestType { self.r#type }
+
+    /// Limit price of the order.
+    pub fn price(&self) -> num::Price { self.price }
+
+    /// Size of the order.
+    pub fn size(&self) -> num::Size { self.size }
+
+    /// Leverage of the order.
+    pub fn leverage(&self) -> UD64 { self.leverage }
+
+    /// Decodes order request from [`OrderDesc`] with provided converters,
+    /// reverse of [`Self::prepare`].
+    ///
+    /// Amount is decoded only if `collateral_converter` is provided, zero
+    /// values of optional parameters are decoded as `None`.
+    ///
+    /// Fails with [`DexError::MalformedData`] on unknown order type or values
+    /// out of range.
+    pub fn from_order_desc(
+        desc: &OrderDesc,
+        price_converter: num::PriceConverter,
+        size_converter: num::SizeConverter,
+        leverage_converter: num::Converter,
+        collateral_converter: Option<num::CollateralConverter>,
+    ) -> Result<Self, DexError> {
+        fn optional<T: TryFrom<U256>>(value: U256, field: &str) -> Result<Option<T>, DexError> {
+            (!value.is_zero())
+                .then(|| num::narrow(value, field))
+                .transpose()
+        }
+        Ok(Self {
+            request_id: num::narrow(desc.orderDescId, "request id")?,
+            perp_id: num::narrow(desc.perpId, "perpetual id")?,
+            r#type: desc.orderType.try_into()?,
+            // Trigger order IDs are not supported by the SDK yet
+            order_id: u16::try_from(desc.orderId).ok().and_then(OrderId::new),
+            price: price_converter.try_from_unsigned(desc.pricePNS)?,
+            size: size_converter.try_from_unsigned(desc.lotLNS)?,
+            expiry_block: optional(desc.expiryBlock, "expiry block")?,
+            post_only: desc.postOnly,
+            fill_or_kill: desc.fillOrKill,
+            immediate_or_cancel: desc.immediateOrCancel,
+            max_matches: optional(desc.maxMatches, "max matches")?,
+            leverage: leverage_converter.try_from_unsigned(desc.leverageHdths)?,
+            last_exec_block: optional(desc.lastExecutionBlock, "last execution block")?,
+            amount: collateral_converter
+                .filter(|_| !desc.amountCNS.is_zero())
+                .map(|conv| conv.try_from_unsigned(desc.amountCNS))
+                .transpose()?,
+            max_neg_pnl_collat_bps: num::narrow(desc.maxNegPnlCollatBPS, "max negative PnL")?,
+        })
+    }
+
+    /// Decodes order request from [`Exchange::OrderRequest`] event emitted
+    /// by the exchange, so it can be compared to the request sent.
+    ///
+    /// See [`Self::from_order_desc`] for details.
+    pub fn from_event(
+        event: &Exchange::OrderRequest,
+        price_converter: num::PriceConverter,
+        size_converter: num::SizeConverter,
+        leverage_converter: num::Converter,
+        collateral_converter: Option<num::CollateralConverter>,
+    ) -> Result<Self, DexError> {
+        Self::from_order_desc(
+            &OrderDesc {
+                orderDescId: event.orderDescId,
+                perpId: event.perpId,
+                orderType: event.orderType,
+                orderId: event.orderId,
+                pricePNS: event.pricePNS,
+                lotLNS: event.lotLNS,
+                expiryBlock: event.expiryBlock,
+                postOnly: event.postOnly,
+                fillOrKill: event.fillOrKill,
+                immediateOrCancel: event.immediateOrCancel,
+                maxMatches: event.maxMatches,
+                leverageHdths: event.leverageHdths,
+                lastExecutionBlock: event.lastExecutionBlock,
+                amountCNS: event.amountCNS,
+                maxNegPnlCollatBPS: event.maxNegPnlCollatBPS,
+            },
+            price_converter,
+            size_converter,
+            leverage_converter,
+            collateral_converter,
+        )
+    }
+
     pub(crate) fn to_order_desc(
         &self,
-        price_converter: num::Converter,
-        size_converter: num::Converter,
+        price_converter: num::PriceConverter,
+        size_converter: num::SizeConverter,
         leverage_converter: num::Converter,
-        collateral_converter: Option<num::Converter>,
+        collateral_converter: Option<num::CollateralConverter>,
     ) -> OrderDesc {
         OrderDesc {
             orderDescId: U256::from(self.request_id),
@@ -143,24 +287,26 @@ impl OrderRequest {
             amountCNS: self
                 .amount
                 .zip(collateral_converter)
-                .map(|(a, conv)| conv.to_unsigned(a))
+                .map(|(amount, conv)| conv.to_unsigned(amount))
                 .unwrap_or_default(),
             maxNegPnlCollatBPS: U256::from(self.max_neg_pnl_collat_bps),
         }
     }
 }
 
-impl From<u8> for RequestType {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for RequestType {
+    type Error = DexError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => RequestType::OpenLong,
-            1 => RequestType::OpenShort,
-            2 => RequestType::CloseLong,
-            3 => RequestType::CloseShort,
-            4 => RequestType::Cancel,
-            5 => RequestType::IncreasePositionCollateral,
-            6 => RequestType::Change,
-            _ => unreachable!(),
+            0 => Ok(RequestType::OpenLong),
+            1 => Ok(RequestType::OpenShort),
+            2 => Ok(RequestType::CloseLong),
+            3 => Ok(RequestType::CloseShort),
+            4 => Ok(RequestType::Cancel),
+            5 => Ok(RequestType::IncreasePositionCollateral),
+            6 => Ok(RequestType::Change),
+            _ => Err(DexError::MalformedData(format!("invalid request type: {value}"))),
         }
     }
 }
@@ -180,14 +326,213 @@ impl RequestType {
     }
 }
 
-impl From<RequestType> for OrderType {
-    fn from(value: RequestType) -> Self {
+impl From<OrderType> for RequestType {
+    fn from(value: OrderType) -> Self {
         match value {
-            RequestType::OpenLong => OrderType::OpenLong,
-            RequestType::OpenShort => OrderType::OpenShort,
-            RequestType::CloseLong => OrderType::CloseLong,
-            RequestType::CloseShort => OrderType::CloseShort,
-            _ => unreachable!(),
+            OrderType::OpenLong => RequestType::OpenLong,
+            OrderType::OpenShort => RequestType::OpenShort,
+            OrderType::CloseLong => RequestType::CloseLong,
+            OrderType::CloseShort => RequestType::CloseShort,
+        }
+    }
+}
+
+impl Display for RequestType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            match self {
+                RequestType::OpenLong => write!(f, "OL"),
+                RequestType::OpenShort => write!(f, "OS"),
+                RequestType::CloseLong => write!(f, "CL"),
+                RequestType::CloseShort => write!(f, "CS"),
+                RequestType::Cancel => write!(f, "CX"),
+                RequestType::IncreasePositionCollateral => write!(f, "IC"),
+                RequestType::Change => write!(f, "CH"),
+            }
+        } else {
+            match self {
+                RequestType::OpenLong => write!(f, "Open Long"),
+                RequestType::OpenShort => write!(f, "Open Short"),
+                RequestType::CloseLong => write!(f, "Close Long"),
+                RequestType::CloseShort => write!(f, "Close Short"),
+                RequestType::Cancel => write!(f, "Cancel"),
+                RequestType::IncreasePositionCollateral => {
+                    write!(f, "Increase Position Collateral")
+                },
+                RequestType::Change => write!(f, "Change"),
+            }
+        }
+    }
+}
+
+/// Parses both the long ("Open Long") and the short ("OL") forms of
+/// [`Display`], as well as the variant name ("OpenLong"), case-insensitively.
+impl FromStr for RequestType {
+    type Err = DexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(order_type) = s.parse::<OrderType>() {
+            return Ok(order_type.into());
+        }
+        match super::order::normalize_name(s).as_str() {
+            "cancel" | "cx" => Ok(RequestType::Cancel),
+            "increasepositioncollateral" | "ic" => Ok(RequestType::IncreasePositionCollateral),
+            "change" | "ch" => Ok(RequestType::Change),
+            _ => Err(DexError::InvalidArgument(format!(
+                "invalid request type: {s}, expected one of: Open Long (OL), Open Short (OS), \
+                 Close Long (CL), Close Short (CS), Cancel (CX), \
+                 Increase Position Collateral (IC), Change (CH)"
+            ))),
+        }
+    }
+}
+
+/// Fails for request types not placing an order.
+impl TryFrom<RequestType> for OrderType {
+    type Error = DexError;
+
+    fn try_from(value: RequestType) -> Result<Self, Self::Error> {
+        match value {
+            RequestType::OpenLong => Ok(OrderType::OpenLong),
+            RequestType::OpenShort => Ok(OrderType::OpenShort),
+            RequestType::CloseLong => Ok(OrderType::CloseLong),
+            RequestType::CloseShort => Ok(OrderType::CloseShort),
+            _ => Err(DexError::MalformedData(format!("request type {value} is not an order"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fastnum::{udec64, udec128};
+
+    use super::*;
+
+    #[test]
+    fn test_order_desc_round_trip() {
+        let (pc, sc, lc, cc) = (
+            num::PriceConverter::new(1),
+            num::SizeConverter::new(5),
+            num::Converter::new(2),
+            num::CollateralConverter::new(6),
+        );
+        let request = OrderRequest::new(
+            42,
+            16,
+            RequestType::Change,
+            OrderId::new(7),
+            udec64!(100123.4).into(),
+            udec64!(0.00125).into(),
+            Some(1000),
+            true,
+            false,
+            false,
+            Some(8),
+            udec64!(12.5),
+            Some(990),
+            Some(udec128!(250.5).into()),
+            300,
+        );
+
+        let desc = request.to_order_desc(pc, sc, lc, Some(cc));
+        assert_eq!(OrderRequest::from_order_desc(&desc, pc, sc, lc, Some(cc)).unwrap(), request);
+
+        let event = Exchange::OrderRequest {
+            perpId: desc.perpId,
+            accountId: U256::from(3),
+            orderDescId: desc.orderDescId,
+            orderId: desc.orderId,
+            orderType: desc.orderType,
+            pricePNS: desc.pricePNS,
+            lotLNS: desc.lotLNS,
+            expiryBlock: desc.expiryBlock,
+            postOnly: desc.postOnly,
+            fillOrKill: desc.fillOrKill,
+            immediateOrCancel: desc.immediateOrCancel,
+            maxMatches: desc.maxMatches,
+            leverageHdths: desc.leverageHdths,
+            lastExecutionBlock: desc.lastExecutionBlock,
+            amountCNS: desc.amountCNS,
+            maxNegPnlCollatBPS: desc.maxNegPnlCollatBPS,
+            gasLeft: U256::from(1_000_000),
+        };
+        assert_eq!(OrderRequest::from_event(&event, pc, sc, lc, Some(cc)).unwrap(), request);
+
+        // Unset optional parameters
+        let request = OrderRequest::new(
+            43,
+            16,
+            RequestType::OpenLong,
+            None,
+            udec64!(100000).into(),
+            udec64!(1).into(),
+            None,
+            false,
+            false,
+            true,
+            None,
+            udec64!(10),
+            None,
+            None,
+            0,
+        );
+        let mut desc = request.to_order_desc(pc, sc, lc, Some(cc));
+        assert_eq!(OrderRequest::from_order_desc(&desc, pc, sc, lc, Some(cc)).unwrap(), request);
+
+        // Value out of range
+        let expiry_block = desc.expiryBlock;
+        desc.expiryBlock = U256::MAX;
+        assert!(matches!(
+            OrderRequest::from_order_desc(&desc, pc, sc, lc, Some(cc)),
+            Err(DexError::MalformedData(_))
+        ));
+        desc.expiryBlock = expiry_block;
+
+        // Amounts out of range of decimals
+        let fields: [fn(&mut OrderDesc) -> &mut U256; 4] = [
+            |desc| &mut desc.pricePNS,
+            |desc| &mut desc.lotLNS,
+            |desc| &mut desc.leverageHdths,
+            |desc| &mut desc.amountCNS,
+        ];
+        for field in fields {
+            let mut desc = desc.clone();
+            *field(&mut desc) = U256::MAX;
+            assert!(matches!(
+                OrderRequest::from_order_desc(&desc, pc, sc, lc, Some(cc)),
+                Err(DexError::MalformedData(_))
+            ));
+        }
+
+        // Unknown order type
+        desc.orderType = 9;
+        assert!(matches!(
+            OrderRequest::from_order_desc(&desc, pc, sc, lc, Some(cc)),
+            Err(DexError::MalformedData(_))
+        ));
+    }
+
+    #[test]
+    fn test_expiry_in() {
+        let chain = Chain::testnet();
+        let ttl = Duration::from_secs(5 * 60);
+        assert_eq!(OrderRequest::expiry_in(1000, ttl, &chain), 1750);
+        assert_eq!(OrderRequest::expiry_in(1000, ttl + Duration::from_millis(1), &chain), 1751);
+        assert_eq!(OrderRequest::expiry_in(1000, Duration::ZERO, &chain), 1000);
+        assert_eq!(OrderRequest::expiry_in(u64::MAX - 1, ttl, &chain), u64::MAX);
+    }
+
+    #[test]
+    fn test_request_type_from_str() {
+        for request_type in (0..=6).map(|t| RequestType::try_from(t).unwrap()) {
+            assert_eq!(request_type.to_string().parse::<RequestType>().unwrap(), request_type);
+            assert_eq!(format!("{request_type:#}").parse::<RequestType>().unwrap(), request_type);
+            assert_eq!(format!("{request_type:?}").parse::<RequestType>().unwrap(), request_type);
         }
+        let request_type: RequestType = "increase_position_collateral".parse().unwrap();
+        assert_eq!(request_type, RequestType::IncreasePositionCollateral);
+        assert!(matches!("Close".parse::<RequestType>(), Err(DexError::InvalidArgument(_))));
+        assert!(matches!(RequestType::try_from(7), Err(DexError::MalformedData(_))));
+        assert!(OrderType::try_from(RequestType::Cancel).is_err());
     }
 }
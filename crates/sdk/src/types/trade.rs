@@ -1,4 +1,7 @@
-use fastnum::UD64;
+use alloy::primitives::TxHash;
+use fastnum::{D256, UD64};
+
+use crate::state::{Position, PositionType};
 
 /// A single maker fill within a taker trade.
 #[derive(Clone, derive_more::Debug)]
@@ -52,6 +55,42 @@ pub struct Trade {
     pub maker_fills: Vec<MakerFill>,
 }
 
+/// Self-contained trade record carrying the transaction context of the taker
+/// fill, e.g. for flattened storage, see [`super::EventContext::flatten`].
+#[derive(Clone, derive_more::Debug)]
+pub struct FlatTrade {
+    /// Number of the block the trade executed at.
+    pub block_number: u64,
+
+    /// Hash of the transaction the trade executed in.
+    pub tx_hash: TxHash,
+
+    /// Index of the transaction within the block.
+    pub tx_index: u64,
+
+    /// Log index of the taker fill event.
+    pub log_index: u64,
+
+    /// Perpetual contract ID.
+    pub perpetual_id: super::PerpetualId,
+
+    /// Taker account ID.
+    pub taker_account_id: super::AccountId,
+
+    /// Taker request ID.
+    pub taker_request_id: super::RequestId,
+
+    /// Taker side (Bid = buying, Ask = selling).
+    pub taker_side: super::OrderSide,
+
+    /// Taker fee paid (normalized decimal, in collateral token).
+    #[debug("{taker_fee}")]
+    pub taker_fee: UD64,
+
+    /// All maker fills matched by this taker order.
+    pub maker_fills: Vec<MakerFill>,
+}
+
 impl Trade {
     /// Total size filled across all makers.
     pub fn total_size(&self) -> UD64 { self.maker_fills.iter().map(|f| f.size).sum() }
@@ -63,14 +102,43 @@ impl Trade {
         if self.maker_fills.is_empty() {
             return None;
         }
-        let total_value: UD64 = self.maker_fills.iter().map(|f| f.price * f.size).sum();
         let total_size = self.total_size();
         if total_size == UD64::ZERO {
             return None;
         }
-        Some(total_value / total_size)
+        Some(self.total_notional() / total_size)
+    }
+
+    /// Total traded amount in collateral token, i.e. sum of price * size of
+    /// all maker fills.
+    pub fn total_notional(&self) -> UD64 { self.maker_fills.iter().map(|f| f.price * f.size).sum() }
+
+    /// Traded amount in collateral token of the fills of a specific maker,
+    /// zero if the maker has no fills in this trade.
+    pub fn maker_notional(&self, account_id: super::AccountId) -> UD64 {
+        self.maker_fills
+            .iter()
+            .filter(|f| f.maker_account_id == account_id)
+            .map(|f| f.price * f.size)
+            .sum()
+    }
+
+    /// Indicates if the taker matched any of its own resting orders.
+    ///
+    /// Self fills are economically a no-op for the account, but still
+    /// reported by the exchange.
+    pub fn is_self_trade(&self) -> bool {
+        self.maker_fills
+            .iter()
+            .any(|f| f.maker_account_id == self.taker_account_id)
     }
 
+    /// Last price resulting from the trade, i.e. the price of the final maker
+    /// fill, so price series can be built from trades alone.
+    ///
+    /// Returns `None` if there are no fills.
+    pub fn last_price(&self) -> Option<UD64> { self.maker_fills.last().map(|f| f.price) }
+
     /// Total maker fees paid across all fills.
     pub fn total_maker_fees(&self) -> UD64 { self.maker_fills.iter().map(|f| f.fee).sum() }
 
@@ -96,4 +164,53 @@ impl Trade {
         }
         Some((total_value / total_size, total_size, total_fee))
     }
+
+    /// Realized PnL of the account from the part of this trade closing the
+    /// `position` it had before the trade, excluding fees.
+    ///
+    /// Returns `None` if the account did not participate in the trade, the
+    /// position belongs to another account or perpetual contract, or the
+    /// trade does not reduce it.
+    pub fn realized_pnl(&self, account_id: super::AccountId, position: &Position) -> Option<D256> {
+        if position.account_id() != account_id || position.perpetual_id() != self.perpetual_id {
+            return None;
+        }
+        let (side, price, size) = if account_id == self.taker_account_id {
+            (self.taker_side, self.avg_price()?, self.total_size())
+        } else {
+            let (price, size, _) = self.maker_total(account_id)?;
+            (self.taker_side.opposite(), price, size)
+        };
+        let price_diff = match (position.r#type(), side) {
+            (PositionType::Long, super::OrderSide::Ask) => {
+                price.resize().to_signed() - position.entry_price().get().resize().to_signed()
+            },
+            (PositionType::Short, super::OrderSide::Bid) => {
+                position.entry_price().get().resize().to_signed() - price.resize().to_signed()
+            },
+            _ => return None,
+        };
+        let closed_size = if size < position.size().get() { size } else { position.size().get() };
+        Some(price_diff * closed_size.resize().to_signed())
+    }
+}
+
+impl super::EventContext<Trade> {
+    /// Self-contained record of the trade along with the block number, tx
+    /// hash, tx index and taker fill log index of its context.
+    pub fn flatten(&self) -> FlatTrade {
+        let trade = &self.event;
+        FlatTrade {
+            block_number: self.block_number,
+            tx_hash: self.tx_hash,
+            tx_index: self.tx_index,
+            log_index: self.log_index,
+            perpetual_id: trade.perpetual_id,
+            taker_account_id: trade.taker_account_id,
+            taker_request_id: trade.taker_request_id,
+            taker_side: trade.taker_side,
+            taker_fee: trade.taker_fee,
+            maker_fills: trade.maker_fills.clone(),
+        }
+    }
 }
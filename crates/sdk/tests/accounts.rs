@@ -0,0 +1,58 @@
+use fastnum::udec128;
+use perpl_sdk::testing;
+
+/// Tests batch account creation and collateral changes of an existing account.
+#[tokio::test]
+async fn test_accounts_deposit_and_withdraw() {
+    let exchange = testing::TestExchange::new().await;
+
+    let accounts = exchange
+        .accounts(&[(0, 1_000), (1, 2_000), (2, 3_000), (3, 4_000), (4, 5_000)])
+        .await;
+    assert_eq!(accounts.len(), 5);
+    let balances = [udec128!(1000), udec128!(2000), udec128!(3000), udec128!(4000), udec128!(5000)];
+    for (acc, balance) in accounts.iter().zip(balances) {
+        assert_eq!(acc.balance().await, balance.into());
+    }
+
+    let acc = &accounts[2];
+    acc.deposit(500).await.get_receipt().await.unwrap();
+    assert_eq!(acc.balance().await, udec128!(3500).into());
+
+    acc.withdraw(1_500).await.get_receipt().await.unwrap();
+    assert_eq!(acc.balance().await, udec128!(2000).into());
+
+    // Other accounts are not affected
+    assert_eq!(accounts[1].balance().await, udec128!(2000).into());
+    assert_eq!(accounts[3].balance().await, udec128!(4000).into());
+}
+
+/// Tests exchanges spawned with the same configuration are reproducible.
+#[tokio::test]
+async fn test_deterministic_config() {
+    const MNEMONIC: &str =
+        "abstract vacuum mammal awkward pudding scene penalty purchase dinner depart evoke puzzle";
+    let config = testing::TestConfig::new()
+        .with_mnemonic(MNEMONIC)
+        .with_block_time(None)
+        .with_accounts(6, 1_000)
+        .with_genesis_timestamp(1_700_000_000);
+    let (first, second) = (
+        testing::TestExchange::with_config(config.clone()).await,
+        testing::TestExchange::with_config(config).await,
+    );
+
+    assert_eq!(first.owner, second.owner);
+    assert_eq!(first.admin, second.admin);
+    assert_eq!(first.price_admin, second.price_admin);
+    assert_eq!(first.exchange.address(), second.exchange.address());
+    assert_eq!(first.token.address(), second.token.address());
+
+    let (first_acc, second_acc) = (first.account(2, 1_000).await, second.account(2, 1_000).await);
+    assert_eq!(first_acc.id, second_acc.id);
+    assert_eq!(first_acc.address, second_acc.address);
+
+    // Differs from the Anvil default accounts
+    let default = testing::TestExchange::new().await;
+    assert_ne!(default.owner, first.owner);
+}
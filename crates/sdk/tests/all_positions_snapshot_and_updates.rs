@@ -68,8 +68,8 @@ async fn test_all_positions_snapshot_and_updates() {
         .flat_map(|a| a.positions().values())
         .next()
         .unwrap();
-    assert!(any_position.size() > UD64::ZERO);
-    assert!(any_position.entry_price() > UD64::ZERO);
+    assert!(any_position.size() > UD64::ZERO.into());
+    assert!(any_position.entry_price() > UD64::ZERO.into());
 
     // Apply the next 1000 blocks of events on top of the snapshot to verify
     // the V0-derived state stays consistent under update. This window covers
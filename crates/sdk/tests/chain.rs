@@ -0,0 +1,29 @@
+use std::collections::HashSet;
+
+use perpl_sdk::{Chain, testing, types::CollateralMeta};
+
+/// Tests that collateral token metadata is read from the token contract.
+#[tokio::test]
+async fn test_fetch_collateral_metadata() {
+    let exchange = testing::TestExchange::new().await;
+    let chain = exchange.chain();
+
+    let meta = chain
+        .fetch_collateral_metadata(&exchange.provider)
+        .await
+        .unwrap();
+    assert_eq!(
+        meta,
+        CollateralMeta { symbol: "USD".to_string(), name: "Test USD".to_string(), decimals: 6 }
+    );
+}
+
+/// Tests that chains can be compared and used as map keys.
+#[test]
+fn test_chain_equality() {
+    assert_eq!(Chain::testnet(), Chain::testnet());
+    assert_ne!(Chain::testnet(), Chain::mainnet());
+
+    let chains: HashSet<_> = [Chain::testnet(), Chain::mainnet(), Chain::testnet()].into();
+    assert_eq!(chains.len(), 2);
+}
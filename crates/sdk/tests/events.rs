@@ -2,6 +2,7 @@ use std::num::NonZeroU16;
 
 use fastnum::{udec64, udec128};
 use perpl_sdk::{
+    num,
     state::{
         self, AccountEvent, AccountEventType, OrderEvent, OrderEventType, PositionEvent,
         PositionEventType,
@@ -30,8 +31,8 @@ async fn test_snapshot_and_events() {
                     btc_perp.id,
                     ot,
                     oid,
-                    p,
-                    s,
+                    num::Price::new(p),
+                    num::Size::new(s),
                     None,
                     false,
                     false,
@@ -63,15 +64,15 @@ async fn test_snapshot_and_events() {
         let snapshot = state.snapshot().clone();
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
         assert_eq!(perp.id(), btc_perp.id);
-        assert_eq!(perp.name(), "BTC".to_string());
-        assert_eq!(perp.symbol(), "BTC".to_string());
-        assert_eq!(perp.is_paused(), false);
+        assert_eq!(perp.name(), "BTC");
+        assert_eq!(perp.symbol(), "BTC");
+        assert!(!perp.is_paused());
         assert_eq!(perp.maker_fee(), udec64!(0.00010));
         assert_eq!(perp.taker_fee(), udec64!(0.00035));
         assert_eq!(perp.initial_margin(), udec64!(10));
         assert_eq!(perp.maintenance_margin(), udec64!(20));
-        assert_eq!(perp.last_price(), udec64!(100000));
-        assert_eq!(perp.mark_price(), udec64!(100000));
+        assert_eq!(perp.last_price(), udec64!(100000).into());
+        assert_eq!(perp.mark_price(), udec64!(100000).into());
         assert_eq!(perp.funding_start_block(), 8571);
         assert_eq!(perp.open_interest(), udec128!(0.1));
 
@@ -79,8 +80,8 @@ async fn test_snapshot_and_events() {
 
         let order = perp.get_order(oid(1)).unwrap();
         assert_eq!(order.r#type(), types::OrderType::OpenShort);
-        assert_eq!(order.price(), udec64!(100000));
-        assert_eq!(order.size(), udec64!(0.9));
+        assert_eq!(order.price(), udec64!(100000).into());
+        assert_eq!(order.size(), udec64!(0.9).into());
         assert_eq!(order.placed_size(), None);
 
         let maker = snapshot.accounts().get(&maker.id).unwrap();
@@ -88,16 +89,16 @@ async fn test_snapshot_and_events() {
 
         let maker_pos = maker.positions().get(&btc_perp.id).unwrap();
         assert_eq!(maker_pos.r#type(), state::PositionType::Short);
-        assert_eq!(maker_pos.entry_price(), udec64!(100000));
-        assert_eq!(maker_pos.size(), udec64!(0.1));
+        assert_eq!(maker_pos.entry_price(), udec64!(100000).into());
+        assert_eq!(maker_pos.size(), udec64!(0.1).into());
 
         let taker = snapshot.accounts().get(&taker.id).unwrap();
         assert_eq!(taker.positions().len(), 1);
 
         let taker_pos = taker.positions().get(&btc_perp.id).unwrap();
         assert_eq!(taker_pos.r#type(), state::PositionType::Long);
-        assert_eq!(taker_pos.entry_price(), udec64!(100000));
-        assert_eq!(taker_pos.size(), udec64!(0.1));
+        assert_eq!(taker_pos.entry_price(), udec64!(100000).into());
+        assert_eq!(taker_pos.size(), udec64!(0.1).into());
     }
 
     // Start processing events
@@ -113,7 +114,7 @@ async fn test_snapshot_and_events() {
 
     // Collect and (partially) validate produced events
     while let Some(block_events) = state.next_state_events().await {
-        for event in block_events.events().iter().map(|e| e.event()).flatten() {
+        for event in block_events.events().iter().flat_map(|e| e.event()) {
             match event {
                 state::StateEvents::Account(AccountEvent {
                     account_id: 1,
@@ -149,12 +150,14 @@ async fn test_snapshot_and_events() {
                     request_id: Some(11),
                     client_order_id: Some(11),
                     order_id: Some(order_id),
-                    r#type: OrderEventType::Filled { fill_price, fill_size, fee, is_maker },
+                    r#type:
+                        OrderEventType::Filled { fill_price, fill_size, remaining_size, fee, is_maker },
                 }) if *order_id == oid(1) => {
                     assert_eq!(*fill_price, udec64!(100100));
                     assert_eq!(*fill_size, udec64!(0.1));
+                    assert_eq!(*remaining_size, Some(udec64!(0.9)));
                     assert_eq!(*fee, udec64!(1.001));
-                    assert_eq!(*is_maker, true);
+                    assert!(*is_maker);
                 },
 
                 state::StateEvents::Position(PositionEvent {
@@ -183,17 +186,17 @@ async fn test_snapshot_and_events() {
     {
         let snapshot = state.snapshot().clone();
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
-        assert_eq!(perp.last_price(), udec64!(100100));
+        assert_eq!(perp.last_price(), udec64!(100100).into());
         assert_eq!(perp.open_interest(), udec128!(0));
 
         assert_eq!(perp.total_orders(), 1);
 
         let order = perp.get_order(oid(1)).unwrap();
         assert_eq!(order.r#type(), types::OrderType::OpenLong);
-        assert_eq!(order.price(), udec64!(100100));
-        assert_eq!(order.size(), udec64!(0.8));
-        assert_eq!(order.placed_size(), Some(udec64!(1)));
-        assert_eq!(order.filled_size(), Some(udec64!(0.2)));
+        assert_eq!(order.price(), udec64!(100100).into());
+        assert_eq!(order.size(), udec64!(0.8).into());
+        assert_eq!(order.placed_size(), Some(udec64!(1).into()));
+        assert_eq!(order.filled_size(), Some(udec64!(0.2).into()));
 
         let maker = snapshot.accounts().get(&maker.id).unwrap();
         assert_eq!(maker.positions().len(), 0);
@@ -0,0 +1,54 @@
+use std::pin::pin;
+
+use futures::StreamExt;
+use perpl_sdk::{abi::dex::Exchange::ExchangeEvents, stream, testing, types::StateInstant};
+
+/// Tests that a stream started at the contract deployment block replays
+/// the very first exchange events.
+#[tokio::test]
+async fn test_stream_from_genesis() {
+    let exchange = testing::TestExchange::new().await;
+    let chain = exchange.chain();
+
+    let genesis = StateInstant::genesis(&chain, &exchange.provider).await.unwrap();
+    assert_eq!(genesis.block_number(), chain.deployed_at_block());
+
+    let stream = stream::raw(&chain, exchange.provider.clone(), genesis, tokio::time::sleep);
+    let mut stream = pin!(stream);
+
+    let first = stream.next().await.unwrap().unwrap();
+    assert_eq!(first.instant(), genesis);
+
+    // Exchange setup performed by `TestExchange::new` is visible in the replay
+    let mut block_num = first.instant().block_number();
+    let mut whitelisting_changed = false;
+    while !whitelisting_changed {
+        let block = stream.next().await.unwrap().unwrap();
+        block_num += 1;
+        assert_eq!(block.instant().block_number(), block_num);
+        // Block number survives flattening the block into separate events
+        assert!(block.events().iter().all(|e| e.block_number() == block_num));
+        whitelisting_changed = block.events().iter().any(|e| {
+            matches!(e.event().known(), Some(ExchangeEvents::WhitelistingEnabledChanged(_)))
+        });
+    }
+}
+
+/// Tests that a bounded stream produces exactly the requested blocks and ends.
+#[tokio::test]
+async fn test_stream_range() {
+    let exchange = testing::TestExchange::new().await;
+    let chain = exchange.chain();
+
+    let genesis = StateInstant::genesis(&chain, &exchange.provider).await.unwrap();
+    let from = StateInstant::new(genesis.block_number() + 2, 0);
+    let to = from.block_number() + 4;
+
+    let stream = stream::raw_range(&chain, exchange.provider.clone(), from, to, tokio::time::sleep);
+    let blocks = stream.collect::<Vec<_>>().await;
+
+    assert_eq!(blocks.len(), 5);
+    for (block, block_num) in blocks.iter().zip(from.block_number()..=to) {
+        assert_eq!(block.as_ref().unwrap().instant().block_number(), block_num);
+    }
+}
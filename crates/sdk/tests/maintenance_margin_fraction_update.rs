@@ -1,5 +1,6 @@
 use fastnum::{udec64, udec128};
 use perpl_sdk::{
+    num,
     state::{PositionEvent, PositionEventType, PositionType, StateEvents},
     testing,
     types::{self, RequestType::*},
@@ -25,8 +26,8 @@ async fn test_maintenance_margin_fraction_update() {
                     btc_perp.id,
                     ot,
                     None,
-                    p,
-                    s,
+                    num::Price::new(p),
+                    num::Size::new(s),
                     None,
                     false,
                     false,
@@ -76,10 +77,10 @@ async fn test_maintenance_margin_fraction_update() {
 
         assert_eq!(maker_pos.r#type(), PositionType::Short);
         assert_eq!(taker_pos.r#type(), PositionType::Long);
-        assert_eq!(maker_pos.size(), udec64!(0.1));
-        assert_eq!(taker_pos.size(), udec64!(0.1));
-        assert_eq!(maker_pos.maintenance_margin_requirement(), udec128!(500));
-        assert_eq!(taker_pos.maintenance_margin_requirement(), udec128!(500));
+        assert_eq!(maker_pos.size(), udec64!(0.1).into());
+        assert_eq!(taker_pos.size(), udec64!(0.1).into());
+        assert_eq!(maker_pos.maintenance_margin_requirement(), udec128!(500).into());
+        assert_eq!(taker_pos.maintenance_margin_requirement(), udec128!(500).into());
 
         (maker_pos.liquidation_price(), taker_pos.liquidation_price())
     };
@@ -145,12 +146,12 @@ async fn test_maintenance_margin_fraction_update() {
         .get(&btc_perp.id)
         .unwrap();
 
-    assert_eq!(maker_pos.maintenance_margin_requirement(), udec128!(250));
-    assert_eq!(taker_pos.maintenance_margin_requirement(), udec128!(250));
+    assert_eq!(maker_pos.maintenance_margin_requirement(), udec128!(250).into());
+    assert_eq!(taker_pos.maintenance_margin_requirement(), udec128!(250).into());
 
     // Lowering the requirement widens the gap to liquidation: each price shifts
     // by (old_mmr - new_mmr) / size = 250 / 0.1 = 2500 away from entry — up for
     // the short (liquidates above entry) and down for the long.
-    assert_eq!(maker_pos.liquidation_price(), maker_liq_before + udec64!(2500));
-    assert_eq!(taker_pos.liquidation_price(), taker_liq_before - udec64!(2500));
+    assert_eq!(maker_pos.liquidation_price(), maker_liq_before + udec64!(2500).into());
+    assert_eq!(taker_pos.liquidation_price(), taker_liq_before - udec64!(2500).into());
 }
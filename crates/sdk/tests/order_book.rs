@@ -2,7 +2,7 @@ use std::{num::NonZeroU16, time::Duration};
 
 use fastnum::udec64;
 use perpl_sdk::{
-    state, testing,
+    num, state, testing,
     types::{self, RequestType::*},
 };
 
@@ -25,8 +25,8 @@ async fn test_order_book() {
                     btc_perp.id,
                     ot,
                     oid,
-                    p,
-                    s,
+                    num::Price::new(p),
+                    num::Size::new(s),
                     exp,
                     false,
                     false,
@@ -60,34 +60,39 @@ async fn test_order_book() {
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
         let book = perp.l3_book();
 
-        assert_eq!(book.best_ask(), Some((udec64!(100000), udec64!(0.9))));
+        assert_eq!(
+            book.best_ask(),
+            Some((num::Price::new(udec64!(100000)), num::Size::new(udec64!(0.9))))
+        );
         assert_eq!(book.best_bid(), None);
 
-        let ask_level = book.ask_level(udec64!(100000)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(100000))).unwrap();
         assert_eq!(ask_level.num_orders(), 1);
-        assert_eq!(ask_level.size(), udec64!(0.9));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(0.9)));
+        assert!(!ask_level.is_empty());
 
         let order = perp.get_order(oid(1)).unwrap();
         assert_eq!(order.r#type(), types::OrderType::OpenShort);
-        assert_eq!(order.price(), udec64!(100000));
-        assert_eq!(order.size(), udec64!(0.9));
+        assert_eq!(order.price(), udec64!(100000).into());
+        assert_eq!(order.size(), udec64!(0.9).into());
 
         let maker = snapshot.accounts().get(&maker.id).unwrap();
         assert_eq!(maker.positions().len(), 1);
 
         let maker_pos = maker.positions().get(&btc_perp.id).unwrap();
         assert_eq!(maker_pos.r#type(), state::PositionType::Short);
-        assert_eq!(maker_pos.entry_price(), udec64!(100000));
-        assert_eq!(maker_pos.size(), udec64!(0.1));
+        assert_eq!(maker_pos.entry_price(), udec64!(100000).into());
+        assert_eq!(maker_pos.size(), udec64!(0.1).into());
+        assert_eq!(maker.position_by_symbol(&snapshot, "BTC"), Some(maker_pos));
+        assert_eq!(maker.position_by_symbol(&snapshot, "ETH"), None);
 
         let taker = snapshot.accounts().get(&taker.id).unwrap();
         assert_eq!(taker.positions().len(), 1);
 
         let taker_pos = taker.positions().get(&btc_perp.id).unwrap();
         assert_eq!(taker_pos.r#type(), state::PositionType::Long);
-        assert_eq!(taker_pos.entry_price(), udec64!(100000));
-        assert_eq!(taker_pos.size(), udec64!(0.1));
+        assert_eq!(taker_pos.entry_price(), udec64!(100000).into());
+        assert_eq!(taker_pos.size(), udec64!(0.1).into());
     }
 
     // Start processing events
@@ -108,23 +113,29 @@ async fn test_order_book() {
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
         let book = perp.l3_book();
 
-        assert_eq!(book.best_ask(), Some((udec64!(99900), udec64!(1))));
-        assert_eq!(book.best_bid(), Some((udec64!(99000), udec64!(0.5))));
+        assert_eq!(
+            book.best_ask(),
+            Some((num::Price::new(udec64!(99900)), num::Size::new(udec64!(1))))
+        );
+        assert_eq!(
+            book.best_bid(),
+            Some((num::Price::new(udec64!(99000)), num::Size::new(udec64!(0.5))))
+        );
 
-        let ask_level = book.ask_level(udec64!(100000)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(100000))).unwrap();
         assert_eq!(ask_level.num_orders(), 2);
-        assert_eq!(ask_level.size(), udec64!(2.9));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(2.9)));
+        assert!(!ask_level.is_empty());
 
-        let ask_level = book.ask_level(udec64!(99900)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(99900))).unwrap();
         assert_eq!(ask_level.num_orders(), 1);
-        assert_eq!(ask_level.size(), udec64!(1));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(1)));
+        assert!(!ask_level.is_empty());
 
-        let bid_level = book.bid_level(udec64!(99000)).unwrap();
+        let bid_level = book.bid_level(num::Price::new(udec64!(99000))).unwrap();
         assert_eq!(bid_level.num_orders(), 1);
-        assert_eq!(bid_level.size(), udec64!(0.5));
-        assert_eq!(bid_level.is_empty(), false);
+        assert_eq!(bid_level.size(), num::Size::new(udec64!(0.5)));
+        assert!(!bid_level.is_empty());
     }
 
     // Orders to expire
@@ -142,28 +153,34 @@ async fn test_order_book() {
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
         let book = perp.l3_book();
 
-        assert_eq!(book.best_ask(), Some((udec64!(99800), udec64!(2))));
-        assert_eq!(book.best_bid(), Some((udec64!(99000), udec64!(1.2))));
+        assert_eq!(
+            book.best_ask(),
+            Some((num::Price::new(udec64!(99800)), num::Size::new(udec64!(2))))
+        );
+        assert_eq!(
+            book.best_bid(),
+            Some((num::Price::new(udec64!(99000)), num::Size::new(udec64!(1.2))))
+        );
 
-        let ask_level = book.ask_level(udec64!(100000)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(100000))).unwrap();
         assert_eq!(ask_level.num_orders(), 3);
-        assert_eq!(ask_level.size(), udec64!(5.9));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(5.9)));
+        assert!(!ask_level.is_empty());
 
-        let ask_level = book.ask_level(udec64!(99900)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(99900))).unwrap();
         assert_eq!(ask_level.num_orders(), 1);
-        assert_eq!(ask_level.size(), udec64!(1));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(1)));
+        assert!(!ask_level.is_empty());
 
-        let ask_level = book.ask_level(udec64!(99800)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(99800))).unwrap();
         assert_eq!(ask_level.num_orders(), 1);
-        assert_eq!(ask_level.size(), udec64!(2));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(2)));
+        assert!(!ask_level.is_empty());
 
-        let bid_level = book.bid_level(udec64!(99000)).unwrap();
+        let bid_level = book.bid_level(num::Price::new(udec64!(99000))).unwrap();
         assert_eq!(bid_level.num_orders(), 2);
-        assert_eq!(bid_level.size(), udec64!(1.2));
-        assert_eq!(bid_level.is_empty(), false);
+        assert_eq!(bid_level.size(), num::Size::new(udec64!(1.2)));
+        assert!(!bid_level.is_empty());
     }
 
     // Wait for expiration
@@ -178,23 +195,29 @@ async fn test_order_book() {
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
         let book = perp.l3_book();
 
-        assert_eq!(book.best_ask(), Some((udec64!(99900), udec64!(1))));
-        assert_eq!(book.best_bid(), Some((udec64!(99000), udec64!(0.5))));
+        assert_eq!(
+            book.best_ask(),
+            Some((num::Price::new(udec64!(99900)), num::Size::new(udec64!(1))))
+        );
+        assert_eq!(
+            book.best_bid(),
+            Some((num::Price::new(udec64!(99000)), num::Size::new(udec64!(0.5))))
+        );
 
-        let ask_level = book.ask_level(udec64!(100000)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(100000))).unwrap();
         assert_eq!(ask_level.num_orders(), 2);
-        assert_eq!(ask_level.size(), udec64!(2.9));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(2.9)));
+        assert!(!ask_level.is_empty());
 
-        let ask_level = book.ask_level(udec64!(99900)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(99900))).unwrap();
         assert_eq!(ask_level.num_orders(), 1);
-        assert_eq!(ask_level.size(), udec64!(1));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(1)));
+        assert!(!ask_level.is_empty());
 
-        let bid_level = book.bid_level(udec64!(99000)).unwrap();
+        let bid_level = book.bid_level(num::Price::new(udec64!(99000))).unwrap();
         assert_eq!(bid_level.num_orders(), 1);
-        assert_eq!(bid_level.size(), udec64!(0.5));
-        assert_eq!(bid_level.is_empty(), false);
+        assert_eq!(bid_level.size(), num::Size::new(udec64!(0.5)));
+        assert!(!bid_level.is_empty());
     }
 
     // Cancel and update expired orders
@@ -211,23 +234,29 @@ async fn test_order_book() {
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
         let book = perp.l3_book();
 
-        assert_eq!(book.best_ask(), Some((udec64!(99900), udec64!(1))));
-        assert_eq!(book.best_bid(), Some((udec64!(99000), udec64!(1.8))));
+        assert_eq!(
+            book.best_ask(),
+            Some((num::Price::new(udec64!(99900)), num::Size::new(udec64!(1))))
+        );
+        assert_eq!(
+            book.best_bid(),
+            Some((num::Price::new(udec64!(99000)), num::Size::new(udec64!(1.8))))
+        );
 
-        let ask_level = book.ask_level(udec64!(100000)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(100000))).unwrap();
         assert_eq!(ask_level.num_orders(), 2);
-        assert_eq!(ask_level.size(), udec64!(2.9));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(2.9)));
+        assert!(!ask_level.is_empty());
 
-        let ask_level = book.ask_level(udec64!(99900)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(99900))).unwrap();
         assert_eq!(ask_level.num_orders(), 1);
-        assert_eq!(ask_level.size(), udec64!(1));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(1)));
+        assert!(!ask_level.is_empty());
 
-        let bid_level = book.bid_level(udec64!(99000)).unwrap();
+        let bid_level = book.bid_level(num::Price::new(udec64!(99000))).unwrap();
         assert_eq!(bid_level.num_orders(), 2);
-        assert_eq!(bid_level.size(), udec64!(1.8));
-        assert_eq!(bid_level.is_empty(), false);
+        assert_eq!(bid_level.size(), num::Size::new(udec64!(1.8)));
+        assert!(!bid_level.is_empty());
     }
 
     // Cancel and update active orders
@@ -244,23 +273,29 @@ async fn test_order_book() {
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
         let book = perp.l3_book();
 
-        assert_eq!(book.best_ask(), Some((udec64!(99900), udec64!(1))));
-        assert_eq!(book.best_bid(), Some((udec64!(99000), udec64!(1.5))));
+        assert_eq!(
+            book.best_ask(),
+            Some((num::Price::new(udec64!(99900)), num::Size::new(udec64!(1))))
+        );
+        assert_eq!(
+            book.best_bid(),
+            Some((num::Price::new(udec64!(99000)), num::Size::new(udec64!(1.5))))
+        );
 
-        let ask_level = book.ask_level(udec64!(100000)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(100000))).unwrap();
         assert_eq!(ask_level.num_orders(), 1);
-        assert_eq!(ask_level.size(), udec64!(0.9));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(0.9)));
+        assert!(!ask_level.is_empty());
 
-        let ask_level = book.ask_level(udec64!(99900)).unwrap();
+        let ask_level = book.ask_level(num::Price::new(udec64!(99900))).unwrap();
         assert_eq!(ask_level.num_orders(), 1);
-        assert_eq!(ask_level.size(), udec64!(1));
-        assert_eq!(ask_level.is_empty(), false);
+        assert_eq!(ask_level.size(), num::Size::new(udec64!(1)));
+        assert!(!ask_level.is_empty());
 
-        let bid_level = book.bid_level(udec64!(99000)).unwrap();
+        let bid_level = book.bid_level(num::Price::new(udec64!(99000))).unwrap();
         assert_eq!(bid_level.num_orders(), 2);
-        assert_eq!(bid_level.size(), udec64!(1.5));
-        assert_eq!(bid_level.is_empty(), false);
+        assert_eq!(bid_level.size(), num::Size::new(udec64!(1.5)));
+        assert!(!bid_level.is_empty());
     }
 
     // Close maker order of reduced size
@@ -278,7 +313,13 @@ async fn test_order_book() {
         let perp = snapshot.perpetuals().get(&btc_perp.id).unwrap();
         let book = perp.l3_book();
 
-        assert_eq!(book.best_ask(), Some((udec64!(100000), udec64!(0.9))));
-        assert_eq!(book.best_bid(), Some((udec64!(99000), udec64!(1.5))));
+        assert_eq!(
+            book.best_ask(),
+            Some((num::Price::new(udec64!(100000)), num::Size::new(udec64!(0.9))))
+        );
+        assert_eq!(
+            book.best_bid(),
+            Some((num::Price::new(udec64!(99000)), num::Size::new(udec64!(1.5))))
+        );
     }
 }
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use alloy::{consensus::Transaction, primitives::Bytes, providers::Provider};
+use fastnum::{D64, dec64, udec64};
+use perpl_sdk::{testing, types};
+
+/// `OpDescEnum` values of the exchange, not published in its ABI.
+const MARK_UPDATE: u8 = 1;
+const FUNDING_UPDATE: u8 = 2;
+
+/// Tests that perpetual operations executed alongside orders are applied to
+/// the indexed snapshot, and decoded back from the transaction input.
+#[tokio::test]
+async fn test_perp_ops() {
+    let exchange = testing::TestExchange::new().await;
+    let maker = exchange.account(0, 1_000_000).await;
+    let btc_perp = exchange.btc_perp().await;
+
+    let (indexer, mut state) = testing::Indexer::new(&exchange).await;
+    tokio::spawn(indexer.run(tokio::time::sleep));
+
+    let bid = |request_id, price| {
+        types::OrderRequest::new(
+            request_id,
+            btc_perp.id,
+            types::RequestType::OpenLong,
+            None,
+            price,
+            udec64!(0.1).into(),
+            None,
+            true,
+            false,
+            false,
+            None,
+            udec64!(10),
+            None,
+            None,
+            1000,
+        )
+    };
+    let ops = vec![
+        types::Op::new(
+            1,
+            btc_perp.id,
+            MARK_UPDATE,
+            udec64!(100500).into(),
+            D64::ZERO,
+            false,
+            Bytes::new(),
+        ),
+        types::Op::new(
+            2,
+            btc_perp.id,
+            FUNDING_UPDATE,
+            udec64!(100500).into(),
+            dec64!(0.0001),
+            true,
+            Bytes::new(),
+        ),
+    ];
+
+    let pending_txs = [
+        btc_perp
+            .orders(maker.id, vec![bid(1, udec64!(99000).into())])
+            .await,
+        btc_perp.exec_ops(ops.clone()).await,
+        btc_perp
+            .orders(maker.id, vec![bid(2, udec64!(99500).into())])
+            .await,
+    ];
+    let mut receipts = Vec::new();
+    for pending_tx in pending_txs {
+        let receipt = pending_tx.get_receipt().await.unwrap();
+        assert!(receipt.status(), "{receipt:#?}");
+        receipts.push(receipt);
+    }
+
+    // Same operations decoded from the transaction input
+    let tx = exchange
+        .provider
+        .get_transaction_by_hash(receipts[1].transaction_hash)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(types::Op::from_calldata(tx.input(), &state.snapshot()).unwrap(), ops);
+
+    assert!(
+        tokio::time::timeout(Duration::from_secs(5), state.wait_for(None, Some(2)))
+            .await
+            .unwrap()
+    );
+
+    let snapshot = state.snapshot();
+    let perp = &snapshot.perpetuals()[&btc_perp.id];
+    assert_eq!(perp.mark_price(), udec64!(100500).into());
+    // Scheduled for the next funding event, or applied if its block is reached
+    assert_eq!(perp.next_funding_rate().unwrap_or(perp.funding_rate()), dec64!(0.0001));
+    assert!(perp.next_funding_event_block().is_some());
+    assert_eq!(perp.total_orders(), 2);
+}
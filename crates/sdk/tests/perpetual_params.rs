@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use fastnum::{UD64, udec64};
+use perpl_sdk::{
+    state::{PerpetualEvent, PerpetualEventType, PerpetualParam, StateEvents},
+    testing,
+};
+
+/// Tests that changes of perpetual contract risk and fee parameters are
+/// reported with both previous and new values.
+#[tokio::test]
+async fn test_perpetual_param_changes() {
+    let exchange = testing::TestExchange::new().await;
+    let btc_perp = exchange.btc_perp().await;
+
+    let (indexer, mut state) = testing::Indexer::new(&exchange).await;
+    let max_age = state.snapshot().perpetuals()[&btc_perp.id].price_max_age_sec();
+    tokio::spawn(indexer.run(tokio::time::sleep));
+
+    let receipts = [
+        btc_perp.set_maker_fee(udec64!(0.00015)).await,
+        btc_perp.set_taker_fee(udec64!(0.0004)).await,
+        btc_perp.set_initial_margin(udec64!(12)).await,
+        btc_perp.set_maintenance_margin(udec64!(40)).await,
+        btc_perp.set_price_max_age(max_age + 30).await,
+    ];
+    for receipt in receipts {
+        assert!(receipt.get_receipt().await.unwrap().status());
+    }
+
+    let mut changes = HashMap::new();
+    while let Some(block_events) = state.next_state_events().await {
+        for event in block_events.events().iter().flat_map(|e| e.event()) {
+            if let StateEvents::Perpetual(PerpetualEvent {
+                perpetual_id,
+                r#type: PerpetualEventType::ParamChanged { param, old, new },
+            }) = event
+            {
+                assert_eq!(*perpetual_id, btc_perp.id);
+                changes.insert(*param, (*old, *new));
+            }
+        }
+        if changes.len() == 5 {
+            break;
+        }
+        assert!(block_events.instant().block_number() < 100, "parameter changes were not reported");
+    }
+
+    assert_eq!(
+        changes,
+        HashMap::from([
+            (PerpetualParam::MakerFee, (udec64!(0.0001), udec64!(0.00015))),
+            (PerpetualParam::TakerFee, (udec64!(0.00035), udec64!(0.0004))),
+            (PerpetualParam::InitialMargin, (udec64!(10), udec64!(12))),
+            (PerpetualParam::MaintenanceMargin, (udec64!(20), udec64!(40))),
+            (PerpetualParam::PriceMaxAge, (UD64::from(max_age), UD64::from(max_age + 30))),
+        ])
+    );
+}
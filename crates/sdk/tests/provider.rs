@@ -0,0 +1,24 @@
+use alloy::{eips::BlockNumberOrTag, providers::Provider};
+use perpl_sdk::{provider, testing};
+
+/// Tests that the recommended provider can fetch blocks, with and without
+/// throttling.
+#[tokio::test]
+async fn test_recommended_provider() {
+    let exchange = testing::TestExchange::new().await;
+    let latest = exchange.provider.get_block_number().await.unwrap();
+
+    for throttle in [None, Some(15)] {
+        let provider = provider::recommended(&exchange.rpc_url, throttle)
+            .await
+            .unwrap();
+        assert_eq!(provider.get_chain_id().await.unwrap(), exchange.chain_id);
+
+        let block = provider
+            .get_block_by_number(BlockNumberOrTag::Number(latest))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(block.header.number, latest);
+    }
+}
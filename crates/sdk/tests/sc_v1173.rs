@@ -16,7 +16,7 @@ use alloy::primitives::U256;
 use fastnum::udec64;
 use perpl_sdk::{
     error::DexError,
-    state, testing,
+    num, state, testing,
     types::{self, RequestType::*},
 };
 
@@ -82,8 +82,8 @@ async fn test_sc_v1173() {
                     btc_perp.id,
                     ot,
                     oid,
-                    p,
-                    s,
+                    num::Price::new(p),
+                    num::Size::new(s),
                     exp,
                     false,
                     false,
@@ -143,10 +143,10 @@ async fn test_sc_v1173() {
             .get(&btc_perp.id)
             .unwrap();
         assert_eq!(taker_pos.r#type(), state::PositionType::Long);
-        assert_eq!(taker_pos.size(), udec64!(0.3));
+        assert_eq!(taker_pos.size(), udec64!(0.3).into());
         assert_eq!(
             taker_pos.entry_price(),
-            udec64!(100001.33333282470703),
+            udec64!(100001.33333282470703).into(),
             "entry_price should reflect non-zero priceResiduePNSQ16",
         );
     }
@@ -187,10 +187,10 @@ async fn test_sc_v1173() {
             .positions()
             .get(&btc_perp.id)
             .unwrap();
-        assert_eq!(taker_pos.size(), udec64!(0.6));
+        assert_eq!(taker_pos.size(), udec64!(0.6).into());
         assert_eq!(
             taker_pos.entry_price(),
-            udec64!(100003.86666412353515),
+            udec64!(100003.86666412353515).into(),
             "entry_price should reflect averaged-fill priceResiduePNSQ16",
         );
     }
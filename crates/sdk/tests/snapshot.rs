@@ -1,7 +1,15 @@
-use std::time::{Duration, Instant};
+use std::{
+    num::NonZeroU16,
+    time::{Duration, Instant},
+};
 
+use alloy::{
+    eips::BlockId,
+    primitives::Address,
+    providers::{MULTICALL3_ADDRESS, Provider},
+};
 use fastnum::{UD64, udec64, udec128};
-use perpl_sdk::{state, testing, types};
+use perpl_sdk::{error::DexError, num, state, testing, types};
 
 /// Tests the creation of exchange snapshot when perpetual order book is full.
 #[tokio::test]
@@ -25,17 +33,17 @@ async fn test_full_book_snapshot() {
         .enumerate()
     {
         let orders = levels
-            .into_iter()
+            .iter()
             .enumerate()
-            .map(|(level, (ask, bid))| {
+            .flat_map(|(level, (ask, bid))| {
                 vec![
                     types::OrderRequest::new(
                         chunk as u64 * 100 + level as u64,
                         btc_perp.id,
                         types::RequestType::OpenShort,
                         None,
-                        *ask,
-                        size,
+                        num::Price::new(*ask),
+                        num::Size::new(size),
                         None,
                         true,
                         false,
@@ -51,8 +59,8 @@ async fn test_full_book_snapshot() {
                         btc_perp.id,
                         types::RequestType::OpenLong,
                         None,
-                        *bid,
-                        size,
+                        num::Price::new(*bid),
+                        num::Size::new(size),
                         None,
                         true,
                         false,
@@ -65,12 +73,11 @@ async fn test_full_book_snapshot() {
                     ),
                 ]
             })
-            .flatten()
             .collect();
 
         pending_txs.push(btc_perp.orders(maker.id, orders).await);
         if chunk % 200 == 0 {
-            pending_txs.push(btc_perp.set_mark_price(udec64!(100000)).await);
+            pending_txs.push(btc_perp.set_mark_price(udec64!(100000).into()).await);
         }
         tokio::time::sleep(Duration::from_millis(100)).await;
     }
@@ -83,7 +90,7 @@ async fn test_full_book_snapshot() {
 
     // Do some trades
     btc_perp
-        .set_mark_price(udec64!(100000))
+        .set_mark_price(udec64!(100000).into())
         .await
         .get_receipt()
         .await
@@ -97,8 +104,8 @@ async fn test_full_book_snapshot() {
                     btc_perp.id,
                     types::RequestType::OpenLong,
                     None,
-                    udec64!(101000),
-                    udec64!(0.05),
+                    udec64!(101000).into(),
+                    udec64!(0.05).into(),
                     None,
                     false,
                     false,
@@ -114,8 +121,8 @@ async fn test_full_book_snapshot() {
                     btc_perp.id,
                     types::RequestType::OpenLong,
                     None,
-                    udec64!(101000),
-                    udec64!(0.05),
+                    udec64!(101000).into(),
+                    udec64!(0.05).into(),
                     None,
                     false,
                     false,
@@ -131,8 +138,8 @@ async fn test_full_book_snapshot() {
                     btc_perp.id,
                     types::RequestType::CloseLong,
                     None,
-                    udec64!(99500),
-                    udec64!(0.01),
+                    udec64!(99500).into(),
+                    udec64!(0.01).into(),
                     None,
                     false,
                     false,
@@ -168,22 +175,22 @@ async fn test_full_book_snapshot() {
         "actual block num: {}",
         snap.instant().block_number()
     );
-    assert_eq!(snap.is_halted(), false);
+    assert!(!snap.is_halted());
     assert_eq!(snap.perpetuals().len(), 1);
     assert_eq!(snap.accounts().len(), 2);
 
     let perp = snap.perpetuals().get(&btc_perp.id).unwrap();
     assert!(perp.instant().block_number() > 200);
     assert_eq!(perp.id(), btc_perp.id);
-    assert_eq!(perp.name(), "BTC".to_string());
-    assert_eq!(perp.symbol(), "BTC".to_string());
-    assert_eq!(perp.is_paused(), false);
+    assert_eq!(perp.name(), "BTC");
+    assert_eq!(perp.symbol(), "BTC");
+    assert!(!perp.is_paused());
     assert_eq!(perp.maker_fee(), udec64!(0.00010));
     assert_eq!(perp.taker_fee(), udec64!(0.00035));
     assert_eq!(perp.initial_margin(), udec64!(10));
     assert_eq!(perp.maintenance_margin(), udec64!(20));
-    assert_eq!(perp.last_price(), udec64!(99900));
-    assert_eq!(perp.mark_price(), udec64!(100000));
+    assert_eq!(perp.last_price(), udec64!(99900).into());
+    assert_eq!(perp.mark_price(), udec64!(100000).into());
     assert_eq!(perp.funding_start_block(), 8571);
     assert_eq!(perp.open_interest(), udec128!(0.09));
 
@@ -192,22 +199,395 @@ async fn test_full_book_snapshot() {
     let all_asks_valid = perp
         .l3_book()
         .ask_orders()
-        .all(|o| o.account_id() == maker.id && o.size() == udec64!(0.001));
+        .all(|o| o.account_id() == maker.id && o.size() == udec64!(0.001).into());
     let all_bids_valid = perp
         .l3_book()
         .bid_orders()
-        .all(|o| o.account_id() == maker.id && o.size() == udec64!(0.001));
+        .all(|o| o.account_id() == maker.id && o.size() == udec64!(0.001).into());
     assert!(all_asks_valid && all_bids_valid);
 
-    assert_eq!(perp.l3_book().best_ask(), Some((udec64!(100200), udec64!(0.099))));
-    assert_eq!(perp.l3_book().best_bid(), Some((udec64!(99900), udec64!(0.089))));
+    assert_eq!(
+        perp.l3_book().best_ask(),
+        Some((num::Price::new(udec64!(100200)), num::Size::new(udec64!(0.099))))
+    );
+    assert_eq!(
+        perp.l3_book().best_bid(),
+        Some((num::Price::new(udec64!(99900)), num::Size::new(udec64!(0.089))))
+    );
+
+    assert_eq!(
+        perp.l3_book().ask_impact(num::Size::new(udec64!(1))),
+        Some((
+            num::Price::new(udec64!(101200)),
+            num::Size::new(udec64!(1)),
+            num::Price::new(udec64!(100651))
+        ))
+    );
+    assert_eq!(
+        perp.l3_book().bid_impact(num::Size::new(udec64!(1))),
+        Some((
+            num::Price::new(udec64!(98900)),
+            num::Size::new(udec64!(1)),
+            num::Price::new(udec64!(99439))
+        ))
+    );
+}
+
+/// Tests the creation of order book only snapshot.
+#[tokio::test]
+async fn test_books_only_snapshot() {
+    let exchange = testing::TestExchange::new().await;
+    let maker = exchange.account(0, 1_000_000).await;
+    let btc_perp = exchange.btc_perp().await;
+
+    let receipt = btc_perp
+        .order(
+            maker.id,
+            types::OrderRequest::new(
+                1,
+                btc_perp.id,
+                types::RequestType::OpenShort,
+                None,
+                udec64!(100000).into(),
+                udec64!(1).into(),
+                None,
+                false,
+                false,
+                false,
+                None,
+                udec64!(10),
+                None,
+                None,
+                1000,
+            ),
+        )
+        .await
+        .get_receipt()
+        .await
+        .unwrap();
+    assert!(receipt.status(), "{:#?}", receipt);
+
+    // Account that does not exist would fail the snapshot if fetched
+    let snap = state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
+        .with_accounts(vec![types::AccountAddressOrID::ID(999_999)])
+        .books_only()
+        .build()
+        .await
+        .unwrap();
+
+    assert!(snap.accounts().is_empty());
+    assert_eq!(snap.perpetuals().len(), 1);
+
+    let perp = snap.perpetuals().get(&btc_perp.id).unwrap();
+    assert_eq!(perp.total_orders(), 1);
+    assert_eq!(
+        perp.l3_book().best_ask(),
+        Some((num::Price::new(udec64!(100000)), num::Size::new(udec64!(1))))
+    );
+}
 
+/// Tests orders expired as of the snapshot block are marked expired right away.
+#[tokio::test]
+async fn test_expired_order_snapshot() {
+    let exchange = testing::TestExchange::new().await;
+    let maker = exchange.account(0, 1_000_000).await;
+    let btc_perp = exchange.btc_perp().await;
+
+    let ask = |request_id, price, expiry_block| {
+        types::OrderRequest::new(
+            request_id,
+            btc_perp.id,
+            types::RequestType::OpenShort,
+            None,
+            num::Price::new(price),
+            udec64!(1).into(),
+            expiry_block,
+            false,
+            false,
+            false,
+            None,
+            udec64!(10),
+            None,
+            None,
+            1000,
+        )
+    };
+    // Each transaction mines a block, so the first order expires before the snapshot
+    let block_num = exchange.provider.get_block_number().await.unwrap();
+    for request in [
+        ask(1, udec64!(100000), Some(block_num + 2)),
+        ask(2, udec64!(101000), None),
+        ask(3, udec64!(102000), None),
+    ] {
+        let receipt = btc_perp
+            .order(maker.id, request)
+            .await
+            .get_receipt()
+            .await
+            .unwrap();
+        assert!(receipt.status(), "{:#?}", receipt);
+    }
+
+    let snap = state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
+        .books_only()
+        .build()
+        .await
+        .unwrap();
+    assert!(snap.instant().block_number() > block_num + 2);
+
+    let book = snap.perpetuals().get(&btc_perp.id).unwrap().l3_book();
+    assert_eq!(book.total_orders(), 3);
+    let expired: Vec<_> = book
+        .all_orders()
+        .values()
+        .filter(|order| order.is_expired())
+        .collect();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].price(), udec64!(100000).into());
     assert_eq!(
-        perp.l3_book().ask_impact(udec64!(1)),
-        Some((udec64!(101200), udec64!(1), udec64!(100651)))
+        book.best_ask(),
+        Some((num::Price::new(udec64!(101000)), num::Size::new(udec64!(1))))
     );
+}
+
+/// Tests perpetual contracts fetched via multicall match the ones fetched
+/// with separate calls, and multicall failures fall back to the latter.
+#[tokio::test]
+async fn test_multicall_snapshot() {
+    let exchange = testing::TestExchange::new().await;
+    let maker = exchange.account(0, 1_000_000).await;
+    let btc_perp = exchange.btc_perp().await;
+
+    let receipt = btc_perp
+        .order(
+            maker.id,
+            types::OrderRequest::new(
+                1,
+                btc_perp.id,
+                types::RequestType::OpenLong,
+                None,
+                udec64!(99000).into(),
+                udec64!(1).into(),
+                None,
+                false,
+                false,
+                false,
+                None,
+                udec64!(10),
+                None,
+                None,
+                1000,
+            ),
+        )
+        .await
+        .get_receipt()
+        .await
+        .unwrap();
+    assert!(receipt.status(), "{:#?}", receipt);
+
+    let block = BlockId::number(receipt.block_number.unwrap());
+    let builder = || {
+        state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
+            .at_block(block)
+            .books_only()
+    };
+    let serial = builder().build().await.unwrap();
+    let multicall = builder()
+        .with_multicall(MULTICALL3_ADDRESS)
+        .with_multicall_fallback(false)
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(multicall.perpetuals().len(), 1);
+    assert_eq!(multicall.perpetuals(), serial.perpetuals());
+
+    let perp = multicall.perpetuals().get(&btc_perp.id).unwrap();
+    assert_eq!(perp.total_orders(), 1);
     assert_eq!(
-        perp.l3_book().bid_impact(udec64!(1)),
-        Some((udec64!(98900), udec64!(1), udec64!(99439)))
+        perp.l3_book().best_bid(),
+        Some((num::Price::new(udec64!(99000)), num::Size::new(udec64!(1))))
     );
+
+    // No multicall contract at the address, snapshot taken before the order
+    // is placed as orders are always fetched via multicall
+    let empty_book = BlockId::number(receipt.block_number.unwrap() - 1);
+    let no_multicall = || {
+        state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
+            .at_block(empty_book)
+            .books_only()
+            .with_multicall(Address::repeat_byte(0xab))
+    };
+    let result = no_multicall().with_multicall_fallback(false).build().await;
+    assert!(result.is_err());
+    let fallback = no_multicall().build().await.unwrap();
+    let serial = state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
+        .at_block(empty_book)
+        .books_only()
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(fallback.perpetuals().len(), 1);
+    assert_eq!(fallback.perpetuals(), serial.perpetuals());
+}
+
+/// Tests the lenient snapshot omitting entities failed to be fetched.
+#[tokio::test]
+async fn test_lenient_snapshot() {
+    let exchange = testing::TestExchange::new().await;
+    let accounts = exchange.accounts(&[(0, 100_000), (1, 100_000)]).await;
+    let btc_perp = exchange.btc_perp().await;
+
+    // Account that does not exist fails to be fetched
+    let builder = || {
+        state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone()).with_accounts(
+            vec![
+                types::AccountAddressOrID::ID(accounts[0].id),
+                types::AccountAddressOrID::ID(999_999),
+                types::AccountAddressOrID::Address(accounts[1].address),
+            ],
+        )
+    };
+    assert!(builder().build().await.is_err());
+
+    let (snap, failures) = builder().build_lenient().await.unwrap();
+    assert_eq!(snap.perpetuals().len(), 1);
+    assert!(snap.perpetuals().contains_key(&btc_perp.id));
+    assert_eq!(snap.accounts().len(), 2);
+    assert!(snap.accounts().contains_key(&accounts[0].id));
+    assert!(snap.accounts().contains_key(&accounts[1].id));
+
+    assert_eq!(failures.len(), 1);
+    assert!(matches!(
+        failures[0],
+        state::SnapshotFailure::Account(types::AccountAddressOrID::ID(999_999), _)
+    ));
+}
+
+/// Tests an account referenced multiple times, by both address and ID, ends up
+/// as a single entry of the snapshot, while zero ID fails the snapshot.
+#[tokio::test]
+async fn test_duplicate_accounts_snapshot() {
+    let exchange = testing::TestExchange::new().await;
+    let accounts = exchange.accounts(&[(0, 100_000), (1, 100_000)]).await;
+    exchange.btc_perp().await;
+
+    let snap = state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
+        .with_accounts(vec![
+            types::AccountAddressOrID::ID(accounts[0].id),
+            types::AccountAddressOrID::Address(accounts[0].address),
+            types::AccountAddressOrID::ID(accounts[0].id),
+        ])
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(snap.accounts().len(), 1);
+    let account = snap.accounts().get(&accounts[0].id).unwrap();
+    assert_eq!(account.address(), accounts[0].address);
+    assert_eq!(account.balance(), udec128!(100000).into());
+
+    let result = state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
+        .with_accounts(vec![
+            types::AccountAddressOrID::ID(accounts[1].id),
+            types::AccountAddressOrID::ID(0),
+        ])
+        .build()
+        .await;
+    assert!(matches!(result, Err(DexError::InvalidArgument(_))));
+}
+
+/// Tests perpetual contracts listed by the exchange are discovered on-chain.
+#[tokio::test]
+async fn test_onchain_perpetuals_snapshot() {
+    let exchange = testing::TestExchange::new().await;
+    let btc_perp = exchange.btc_perp().await;
+
+    // Chain unaware of the perpetual contracts listed
+    let known = exchange.chain();
+    let chain = perpl_sdk::Chain::custom(
+        known.chain_id(),
+        known.collateral_token(),
+        known.deployed_at_block(),
+        known.exchange(),
+        vec![],
+        known.block_time(),
+    );
+    let perpetuals = chain.fetch_perpetuals(&exchange.provider).await.unwrap();
+    assert_eq!(perpetuals, vec![btc_perp.id]);
+
+    let snap = state::SnapshotBuilder::new(&chain, exchange.provider.clone())
+        .books_only()
+        .with_onchain_perpetuals()
+        .build()
+        .await
+        .unwrap();
+    assert!(snap.perpetuals().contains_key(&btc_perp.id));
+    assert_eq!(snap.chain().perpetuals(), &[btc_perp.id]);
+}
+
+/// Tests placed size of partially filled orders is backfilled from the order
+/// events preceding the snapshot.
+#[tokio::test]
+async fn test_placed_size_backfill_snapshot() {
+    let exchange = testing::TestExchange::new().await;
+    let maker = exchange.account(0, 1_000_000).await;
+    let taker = exchange.account(1, 100_000).await;
+    let btc_perp = exchange.btc_perp().await;
+
+    let order = async |acc, r, ot, s| {
+        let receipt = btc_perp
+            .order(
+                acc,
+                types::OrderRequest::new(
+                    r,
+                    btc_perp.id,
+                    ot,
+                    None,
+                    udec64!(100000).into(),
+                    num::Size::new(s),
+                    None,
+                    false,
+                    false,
+                    false,
+                    None,
+                    udec64!(10),
+                    None,
+                    None,
+                    1000,
+                ),
+            )
+            .await
+            .get_receipt()
+            .await
+            .unwrap();
+        assert!(receipt.status(), "{:#?}", receipt);
+        receipt
+    };
+    order(maker.id, 1, types::RequestType::OpenShort, udec64!(1)).await;
+    let receipt = order(taker.id, 2, types::RequestType::OpenLong, udec64!(0.1)).await;
+
+    let block = BlockId::number(receipt.block_number.unwrap());
+    let builder = || {
+        state::SnapshotBuilder::new(&exchange.chain(), exchange.provider.clone())
+            .at_block(block)
+            .books_only()
+    };
+    let order_id = NonZeroU16::new(1).unwrap();
+
+    let snap = builder().build().await.unwrap();
+    let perp = snap.perpetuals().get(&btc_perp.id).unwrap();
+    let order = perp.get_order(order_id).unwrap();
+    assert_eq!(order.size(), udec64!(0.9).into());
+    assert_eq!(order.filled_size(), None);
+
+    let snap = builder()
+        .with_placed_size_backfill(100)
+        .build()
+        .await
+        .unwrap();
+    let perp = snap.perpetuals().get(&btc_perp.id).unwrap();
+    let order = perp.get_order(order_id).unwrap();
+    assert_eq!(order.size(), udec64!(0.9).into());
+    assert_eq!(order.placed_size(), Some(udec64!(1).into()));
+    assert_eq!(order.filled_size(), Some(udec64!(0.1).into()));
 }